@@ -1,19 +1,109 @@
 extern crate clap;
-use clap::{Arg, SubCommand};
+use clap::{AppSettings, Arg, SubCommand};
+use std::{error::Error, fmt::Display, str::FromStr};
+use crate::{constants::*, epoch::{Epoch, TimeScale}, math::DVec3, output::{OutputDevice, OutputError, OutputFrequency}, render::parse_length_vector, repl::Repl, scenario::{Scenario, ScenarioError}, sim::*, sweep::{Sweep, SweepError}, verify::Verification, telemetry::TelemetrySnapshot, units::{Length, Time, UnitParseError}};
+
+/// Validates that an argument parses as a quantity with units, e.g. "1 h" or "29.78 km/s"
+fn validate_quantity<T: FromStr<Err = UnitParseError>>(value: String) -> Result<(), String> {
+    value.parse::<T>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Validates a plot or image size given as columns by rows, e.g. "120x40"
+fn validate_size(value: String) -> Result<(), String> {
+    match value.split_once(['x', 'X']).map(|(w, h)| (w.trim().parse::<usize>(), h.trim().parse::<usize>())) {
+        Some((Ok(w), Ok(h))) if w >= 8 && h >= 4 => Ok(()),
+        _ => Err(format!("invalid size '{}', expected columns x rows of at least 8x4", value)),
+    }
+}
+
+/// Validates a comma separated position, e.g. "0, 0, 2 AU"
+fn validate_length_vector(value: String) -> Result<(), String> {
+    parse_length_vector(&value).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Validates a time warp, a positive multiple of wall clock time
+fn validate_warp(value: String) -> Result<(), String> {
+    match value.trim_end_matches('x').parse::<f64>() {
+        Ok(warp) if warp > 0.0 && warp.is_finite() => Ok(()),
+        _ => Err(format!("invalid time warp '{}', expected a positive factor such as 10 or 10x", value)),
+    }
+}
+
+fn validate_number(value: String) -> Result<(), String> {
+    value.parse::<f64>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Options shared by every subcommand that builds a simulation
+fn simulation_args() -> Vec<Arg<'static, 'static>> {
+    let maxsimtime_option = Arg::with_name("maxsimtime")
+        .long("maxsimtime")
+        .short("t")
+        .required(false)
+        .takes_value(true)
+        .validator(validate_quantity::<Time>);
+    
+    let maxrealtime_option = Arg::with_name("maxrealtime")
+        .long("maxrealtime")
+        .short("r")
+        .required(false)
+        .takes_value(true)
+        .validator(validate_quantity::<Time>);
+
+    let maxframes_option = Arg::with_name("maxframes")
+        .long("maxframes")
+        .short("f")
+        .required(false)
+        .takes_value(true)
+        .validator(|value| value.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()));
+
+    let timestep_option = Arg::with_name("timestep")
+        .long("timestep")
+        .short("d")
+        .required(false)
+        .takes_value(true)
+        .validator(validate_quantity::<Time>);
+
+    let integrator_option = Arg::with_name("integrator")
+        .long("integrator")
+        .short("i")
+        .required(false)
+        .takes_value(true)
+        .possible_values(&["euler", "semiimpliciteuler", "velocityverlet", "blockvelocityverlet"]);
+
+    let epoch_option = Arg::with_name("epoch")
+        .long("epoch")
+        .short("e")
+        .required(false)
+        .takes_value(true)
+        .validator(|value| value.parse::<Epoch>().map(|_| ()).map_err(|e| e.to_string()));
+
+    let scenario_option = Arg::with_name("scenario")
+        .long("scenario")
+        .short("s")
+        .required(false)
+        .takes_value(true);
+
+    vec![timestep_option, integrator_option, maxsimtime_option, maxrealtime_option, maxframes_option, scenario_option, epoch_option]
+}
 
 pub fn parse_command_line() -> clap::ArgMatches<'static> {
+    app().get_matches()
+}
+
+fn app() -> clap::App<'static, 'static> {
     let output_targ_option = Arg::with_name("target")
         .long("target")
         .short("t")
         .required(true)
         .takes_value(true)
-        .possible_values(&["console", "file"]);
+        .possible_values(&["console", "file", "oem", "trajectory", "svg", "image"]);
     
     let output_frequency_option = Arg::with_name("frequency")
         .long("frequency")
-        .short("f")
+        .short("r")
         .required(false)
-        .min_values(1);
+        .takes_value(true)
+        .validator(validate_quantity::<OutputFrequency>);
 
     let track_targ_option = Arg::with_name("target")
         .long("target")
@@ -26,6 +116,7 @@ pub fn parse_command_line() -> clap::ArgMatches<'static> {
         .arg(Arg::with_name("position").long("position").short("p"))
         .arg(Arg::with_name("velocity").long("velocity").short("v"))
         .arg(Arg::with_name("acceleration").long("acceleration").short("a"))
+        .arg(Arg::with_name("sgp4").long("sgp4"))
         .arg(track_targ_option);
 
     let output_subcommand = SubCommand::with_name("output")
@@ -36,31 +127,396 @@ pub fn parse_command_line() -> clap::ArgMatches<'static> {
         .arg(Arg::with_name("potentialenergy").long("potentialenergy").short("p"))
         .arg(Arg::with_name("frames").long("frames").short("f"))
         .arg(Arg::with_name("memoryuse").long("memuse").short("m"))
+        .arg(Arg::with_name("heap").long("heap"))
+        .arg(Arg::with_name("steprate").long("steprate"))
+        .arg(Arg::with_name("phasetimes").long("phasetimes"))
+        .arg(Arg::with_name("rtfactor").long("rtfactor"))
+        .arg(Arg::with_name("time").long("time"))
+        .arg(Arg::with_name("format").long("format").takes_value(true).possible_values(&["pretty", "csv", "jsonl", "plot"]))
+        .arg(Arg::with_name("path").long("path").short("o").takes_value(true))
+        .arg(Arg::with_name("center").long("center").short("c").takes_value(true))
+        .arg(Arg::with_name("accelerations").long("accelerations").short("a"))
+        .arg(Arg::with_name("scale").long("scale").takes_value(true).possible_values(&["auto", "log"]))
+        .arg(Arg::with_name("trail").long("trail").takes_value(true).validator(|v| v.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("plotsize").long("plotsize").takes_value(true).validator(validate_size))
+        .arg(Arg::with_name("noansi").long("noansi"))
+        .arg(Arg::with_name("plane").long("plane").takes_value(true).possible_values(&["xy", "xz", "yz"]))
+        .arg(Arg::with_name("apsides").long("apsides"))
+        .arg(Arg::with_name("image").long("image").takes_value(true).possible_values(&["png", "ppm"]))
+        .arg(Arg::with_name("imagesize").long("imagesize").takes_value(true).validator(validate_size))
+        .arg(Arg::with_name("camera").long("camera").takes_value(true).validator(validate_length_vector))
+        .arg(Arg::with_name("lookat").long("lookat").takes_value(true).validator(validate_length_vector))
+        .arg(Arg::with_name("fov").long("fov").takes_value(true).validator(validate_number))
+        .arg(Arg::with_name("extent").long("extent").takes_value(true).validator(validate_quantity::<Length>))
+        .arg(Arg::with_name("pointsize").long("pointsize").takes_value(true).validator(validate_number))
+        .arg(Arg::with_name("nolabels").long("nolabels"))
         .subcommand(track_subcommand);
     
-    let maxsimtime_option = Arg::with_name("maxsimtime")
-        .long("maxsimtime")
-        .short("t")
-        .required(false)
-        .takes_value(true);
+    let run_subcommand = SubCommand::with_name("run")
+        .alias("simparams")
+        .about("Runs a simulation")
+        .args(&simulation_args())
+        .arg(Arg::with_name("profile").long("profile"))
+        .arg(Arg::with_name("warp").long("warp").short("w").takes_value(true).conflicts_with("realtime").validator(validate_warp))
+        .arg(Arg::with_name("realtime").long("realtime"))
+        .subcommand(output_subcommand);
+
+    let validate_subcommand = SubCommand::with_name("validate")
+        .about("Checks that a scenario file loads")
+        .arg(Arg::with_name("scenario").required(true));
+
+    let list_bodies_subcommand = SubCommand::with_name("list-bodies")
+        .about("Lists the bodies of a scenario, or of the default simulation")
+        .arg(Arg::with_name("scenario").long("scenario").short("s").takes_value(true));
+
+    let repl_subcommand = SubCommand::with_name("repl")
+        .alias("interactive")
+        .about("Steps and inspects a simulation from an interactive console")
+        .args(&simulation_args());
+
+    let bench_subcommand = SubCommand::with_name("bench")
+        .about("Times a simulation without output")
+        .args(&simulation_args())
+        .arg(Arg::with_name("steps").long("steps").short("n").takes_value(true)
+            .validator(|value| value.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("repeat").long("repeat").takes_value(true)
+            .validator(|value| value.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())));
+
+    let sweep_subcommand = SubCommand::with_name("sweep")
+        .about("Compares the accuracy and runtime of timesteps and integrators against a fine reference run")
+        .arg(Arg::with_name("scenario").required(true))
+        .arg(Arg::with_name("timesteps").long("timesteps").short("d").required(true).takes_value(true)
+            .multiple(true).use_delimiter(true).validator(validate_quantity::<Time>))
+        .arg(Arg::with_name("integrators").long("integrators").short("i").takes_value(true)
+            .multiple(true).use_delimiter(true)
+            .possible_values(&["euler", "semiimpliciteuler", "velocityverlet", "blockvelocityverlet"]))
+        .arg(Arg::with_name("duration").long("duration").short("t").takes_value(true).validator(validate_quantity::<Time>))
+        .arg(Arg::with_name("reference").long("reference").takes_value(true).validator(validate_quantity::<Time>));
+
+    let verify_subcommand = SubCommand::with_name("verify")
+        .about("Checks every integrator against analytic two-body orbits")
+        .arg(Arg::with_name("steps").long("steps-per-orbit").short("n").takes_value(true)
+            .validator(|value| value.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("orbits").long("orbits").takes_value(true)
+            .validator(|value| value.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("integrators").long("integrators").short("i").takes_value(true)
+            .multiple(true).use_delimiter(true)
+            .possible_values(&["euler", "semiimpliciteuler", "velocityverlet", "blockvelocityverlet"]))
+        .arg(Arg::with_name("tolerance").long("tolerance").takes_value(true).validator(validate_number));
     
-    let maxrealtime_option = Arg::with_name("maxrealtime")
-        .long("maxrealtime")
-        .short("r")
-        .required(false)
-        .takes_value(true);
+    clap::App::new("ssim").version("1.0").author("Jeremy T. Hatcher")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(run_subcommand)
+        .subcommand(validate_subcommand)
+        .subcommand(list_bodies_subcommand)
+        .subcommand(bench_subcommand)
+        .subcommand(repl_subcommand)
+        .subcommand(sweep_subcommand)
+        .subcommand(verify_subcommand)
+}
 
-    let timestep_option = Arg::with_name("timestep")
-        .long("timestep")
-        .short("d")
-        .required(false)
-        .takes_value(true);
+/// Errors setting up a simulation from the command line
+#[derive(Debug)]
+pub enum CliError {
+    Scenario(String, ScenarioError),
+    Output(OutputError),
+    Write(std::io::Error),
+    Sweep(SweepError),
+    Verification(usize, f64), // runs over the tolerance, and the tolerance
+}
 
-    let simparams_subcommand = SubCommand::with_name("simparams")
-        .arg(timestep_option)
-        .arg(maxsimtime_option)
-        .arg(maxrealtime_option)
-        .subcommand(output_subcommand);
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Scenario(path, inner) => write!(f, "{}: {}", path, inner),
+            Self::Output(inner) => write!(f, "{}", inner),
+            Self::Write(inner) => write!(f, "failed to write output: {}", inner),
+            Self::Sweep(inner) => write!(f, "{}", inner),
+            Self::Verification(failures, tolerance) => write!(f, "{} runs exceed a relative error of {:e}", failures, tolerance),
+        }
+    }
+}
+
+impl Error for CliError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Scenario(_, inner) => Some(inner),
+            Self::Output(inner) => Some(inner),
+            Self::Write(inner) => Some(inner),
+            Self::Sweep(inner) => Some(inner),
+            Self::Verification(..) => None,
+        }
+    }
+}
+
+impl From<OutputError> for CliError {
+    fn from(error: OutputError) -> Self {
+        CliError::Output(error)
+    }
+}
+
+impl From<SweepError> for CliError {
+    fn from(error: SweepError) -> Self {
+        CliError::Sweep(error)
+    }
+}
+
+/// Reads an argument that was already validated by clap
+fn parsed<T: FromStr>(matches: &clap::ArgMatches, name: &str) -> Option<T> {
+    matches.value_of(name).and_then(|value| value.parse().ok())
+}
+
+/// Loads the scenario named by `--scenario` into a new simulation, or the default bodies without one. `--epoch`
+/// replaces the epoch of the scenario before its bodies are added, as horizons tables and element sets are read at it
+fn load_simulation(matches: &clap::ArgMatches) -> Result<Simulation, CliError> {
+    let mut sim = Simulation::new();
+    let epoch = parsed::<Epoch>(matches, "epoch");
+    match matches.value_of("scenario") {
+        Some(path) => Scenario::load(path)
+            .and_then(|mut scenario| {
+                scenario.epoch = epoch.or(scenario.epoch);
+                scenario.apply(&mut sim)
+            })
+            .map_err(|e| CliError::Scenario(path.to_string(), e))?,
+        None => {
+            if let Some(epoch) = epoch {
+                sim.set_epoch(epoch);
+            }
+            add_default_bodies(&mut sim);
+        },
+    }
+    Ok(sim)
+}
+
+/// Builds the simulation described by the arguments of `run` or `bench`. Options given on the command line override
+/// the settings of the scenario, a run without any termination condition is limited to an hour of simulated time
+pub fn configure_simulation(matches: &clap::ArgMatches) -> Result<Simulation, CliError> {
+    let mut sim = load_simulation(matches)?;
+
+    if let Some(timestep) = parsed::<Time>(matches, "timestep") {
+        sim.set_timestep(timestep.seconds());
+    }
+
+    if let Some(integrator) = parsed::<IntegrationMethod>(matches, "integrator") {
+        sim.set_integration_method(integrator);
+    }
+
+    if let Some(maxsimtime) = parsed::<Time>(matches, "maxsimtime") {
+        sim.set_termination_condition(TerminationCondition::ElapsedTime(maxsimtime.seconds()));
+    }
+
+    if let Some(maxrealtime) = parsed::<Time>(matches, "maxrealtime") {
+        sim.set_termination_condition(TerminationCondition::WallTime(maxrealtime.seconds()));
+    }
+
+    if let Some(maxframes) = parsed::<usize>(matches, "maxframes") {
+        sim.set_termination_condition(TerminationCondition::Frames(maxframes));
+    }
+
+    if !sim.has_termination_conditions() {
+        sim.set_termination_condition(TerminationCondition::ElapsedTime(Time::from_hours(1.0).seconds()));
+    }
+
+    Ok(sim)
+}
+
+/// `run`: configures the simulation and its output, then runs it to completion
+pub fn run(matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let mut sim = configure_simulation(matches)?;
+    if let Some(warp) = matches.value_of("warp").and_then(|warp| warp.trim_end_matches('x').parse().ok()) {
+        sim.set_time_warp(warp);
+    } else if matches.is_present("realtime") {
+        sim.set_time_warp(1.0);
+    }
+    let device = OutputDevice::from_cli_config(&sim, matches)?;
+    sim.set_output_device(device);
+    sim.run().map_err(CliError::Write)?;
+
+    // a paced run that could not keep up says so even without --profile
+    if matches.is_present("profile") {
+        eprint!("{}", sim.telemetry().summary(sim.present().sim_time()));
+    }
+    if let Some(pacer) = sim.pacer().filter(|pacer| matches.is_present("profile") || pacer.slowest().is_some()) {
+        eprint!("{}", pacer.summary());
+    }
+    Ok(())
+}
+
+/// `validate`: loads a scenario, including any element sets and ephemerides it refers to, and summarises it
+pub fn validate(matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let path = matches.value_of("scenario").unwrap_or_default();
+    let scenario = Scenario::load(path).map_err(|e| CliError::Scenario(path.to_string(), e))?;
+    let mut sim = Simulation::new();
+    scenario.apply(&mut sim).map_err(|e| CliError::Scenario(path.to_string(), e))?;
+
+    println!("{}: ok, {} bodies", path, scenario.bodies.len());
+    if let Some(epoch) = scenario.epoch {
+        println!("  epoch      {}", epoch.to_iso8601(TimeScale::Tdb));
+    }
+    if let Some(timestep) = scenario.timestep {
+        println!("  timestep   {}", timestep);
+    }
+    if let Some(duration) = scenario.duration {
+        println!("  duration   {}", duration);
+    }
+    if let Some(integrator) = scenario.integrator {
+        println!("  integrator {:?}", integrator);
+    }
+    Ok(())
+}
+
+/// `list-bodies`: prints the id, name, category and initial state of every body
+pub fn list_bodies(matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let sim = load_simulation(matches)?;
+    println!("{}", body_table(sim.present()));
+    Ok(())
+}
+
+/// `repl`: an interactive console for stepping and inspecting the simulation
+pub fn repl(matches: &clap::ArgMatches) -> Result<(), CliError> {
+    Repl::new(configure_simulation(matches)?).run_interactive();
+    Ok(())
+}
+
+/// A table of the id, name, category, mass, radius, distance from the origin and speed of every body
+pub fn body_table(frame: &PhysicsFrame) -> String {
+    let mut table = format!("{:>4}  {:<16} {:<14} {:>12} {:>12} {:>12} {:>12}", "id", "name", "category", "mass kg", "radius m", "distance m", "speed m/s");
+    for body in (0..frame.kinematic_data().len()).filter_map(|id| frame.get_body_ref(id)) {
+        table.push_str(&format!(
+            "\n{:>4}  {:<16} {:<14} {:>12.4e} {:>12.4e} {:>12.4e} {:>12.4e}",
+            body.id(), frame.body_name(body.id()).unwrap_or("-"), format!("{:?}", body.physics_category()),
+            body.mass(), body.bounding_radius(), body.position().magnitude(), body.velocity().magnitude()
+        ));
+    }
+    table
+}
+
+/// `bench`: runs the simulation without output `--repeat` times and prints the timing summary of each run
+pub fn bench(matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let repeat = parsed::<usize>(matches, "repeat").unwrap_or(1).max(1);
+    let mut best: Option<f64> = None;
+    for run in 0..repeat {
+        let mut sim = configure_simulation(matches)?;
+        if let Some(steps) = parsed::<usize>(matches, "steps") {
+            sim.set_termination_condition(TerminationCondition::Frames(steps));
+        }
+        sim.run().map_err(CliError::Write)?;
+
+        let sim_time = sim.present().sim_time();
+        let snapshot = sim.telemetry().snapshot(sim_time);
+        let rate = snapshot.steps_per_second(&TelemetrySnapshot::default());
+        best = Some(best.map_or(rate, |best| best.max(rate)));
+        println!("run {} of {}", run + 1, repeat);
+        print!("{}", sim.telemetry().summary(sim_time));
+    }
+    if let (Some(best), true) = (best, repeat > 1) {
+        println!("best {:.1} steps/s", best);
+    }
+    Ok(())
+}
+
+/// `sweep`: runs a scenario with every timestep and integrator given, all integrators by default, and prints a table
+/// of how each compares with a velocity verlet reference run
+pub fn sweep(matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let path = matches.value_of("scenario").unwrap_or_default();
+    let mut scenario = Scenario::load(path).map_err(|e| CliError::Scenario(path.to_string(), e))?;
+    if let Some(duration) = parsed::<Time>(matches, "duration") {
+        scenario.duration = Some(duration);
+    }
+
+    let timesteps = matches.values_of("timesteps").into_iter().flatten()
+        .filter_map(|value| value.parse::<Time>().ok())
+        .map(|timestep| timestep.seconds())
+        .collect();
+    let integrators = match matches.values_of("integrators") {
+        Some(values) => values.filter_map(|value| value.parse().ok()).collect(),
+        None => vec![IntegrationMethod::Euler, IntegrationMethod::SemiImplicitEuler, IntegrationMethod::VelocityVerlet, IntegrationMethod::BlockVelocityVerlet],
+    };
+
+    let mut sweep = Sweep::new(scenario, timesteps, integrators);
+    if let Some(reference) = parsed::<Time>(matches, "reference") {
+        sweep = sweep.with_reference(reference.seconds(), IntegrationMethod::VelocityVerlet);
+    }
+    print!("{}", sweep.run()?);
+    Ok(())
+}
+
+/// `verify`: runs the integrators on analytic two-body orbits and prints the error after each orbit, failing if any
+/// final relative error is over `--tolerance`
+pub fn verify(matches: &clap::ArgMatches) -> Result<(), CliError> {
+    let mut verification = Verification::default();
+    if let Some(steps) = parsed::<usize>(matches, "steps") {
+        verification.steps_per_orbit = steps.max(1);
+    }
+    if let Some(orbits) = parsed::<usize>(matches, "orbits") {
+        verification.orbits = orbits.max(1);
+    }
+    if let Some(values) = matches.values_of("integrators") {
+        verification.integrators = values.filter_map(|value| value.parse().ok()).collect();
+    }
+
+    let report = verification.run();
+    print!("{}", report);
+    match parsed::<f64>(matches, "tolerance") {
+        Some(tolerance) if !report.failures(tolerance).is_empty() => Err(CliError::Verification(report.failures(tolerance).len(), tolerance)),
+        _ => Ok(()),
+    }
+}
+
+fn add_default_bodies(sim: &mut Simulation) {
+    let sol = sim.make_physics_body()
+        .named("Sol")
+        .with_physics_category(PhysicsCategory::Gravitational)
+        .with_mass(SOL_MASS.kilograms())
+        .with_bounding_radius(SOL_RADIUS.meters() as f32)
+        .with_grav_param(SOL_GRAV_PARAM.cubic_meters_per_second_squared())
+        .add();
     
-    clap::App::new("ssim").version("1.0").author("Jeremy T. Hatcher").subcommand(simparams_subcommand).get_matches()
+    let _earth = sim.make_physics_body()
+        .named("Earth")
+        .with_transform(DVec3::new(EARTH_DIST_TO_SOL.meters(), 0.0, 0.0), None)
+        .with_velocity(DVec3::new(0.0, EARTH_SOL_ORBIT_VEL.meters_per_second(), 0.0))
+        .with_mass(EARTH_MASS.kilograms())
+        .with_bounding_radius(EARTH_RADIUS.meters() as f32)
+        .with_grav_param(EARTH_GRAV_PARAM.cubic_meters_per_second_squared())
+        .relative_to(sol)
+        .with_physics_category(PhysicsCategory::Gravitational)
+        .add();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epoch_option_places_horizons_bodies() {
+        let dir = std::env::temp_dir();
+        let (table, scenario) = (dir.join(format!("ssim_cli_{}.txt", std::process::id())), dir.join(format!("ssim_cli_{}.scn", std::process::id())));
+        std::fs::write(&table, "
+Target body name: Earth (399)
+Center body name: Sun (10)
+Output units    : KM-S
+Reference frame : Ecliptic of J2000.0
+$$SOE
+2451544.500000000 = A.D. 2000-Jan-01 00:00:00.0000 TDB
+ X =-2.521092855899356E+07 Y = 1.449279195838006E+08 Z =-6.164165719002485E+02
+ VX=-2.983983333368269E+01 VY=-5.207633918704476E+00 VZ= 6.169236959167168E-05
+2451545.500000000 = A.D. 2000-Jan-02 00:00:00.0000 TDB
+ X =-2.778811619550782E+07 Y = 1.444503536065063E+08 Z =-6.109325017541647E+02
+ VX=-2.982472107896220E+01 VY=-5.848893648703380E+00 VZ= 6.530040025466127E-05
+$$EOE
+").unwrap();
+        std::fs::write(&scenario, format!("epoch = 2000-01-01T00:00:00 TDB\n[body Earth]\nhorizons = {}", table.display())).unwrap();
+
+        // the body is read from the table at the epoch given on the command line, not at the scenario's
+        let args = vec!["ssim", "run", "--scenario", scenario.to_str().unwrap(), "--epoch", "2000-01-02T00:00:00 TDB"];
+        let matches = app().get_matches_from(args);
+        let configured = configure_simulation(matches.subcommand_matches("run").unwrap());
+        std::fs::remove_file(&table).unwrap();
+        std::fs::remove_file(&scenario).unwrap();
+        let sim = configured.unwrap();
+        assert_eq!(sim.present().epoch(), Some(Epoch::from_julian_date(2451545.5, TimeScale::Tdb)));
+        let earth = sim.present().get_named_bodies("earth")[0].position();
+        assert!((earth.x + 2.778811619550782E+10).abs() < 1.0e-3, "{:?}", earth);
+    }
 }
@@ -1,15 +1,17 @@
-pub const SOL_GRAV_PARAM: f64 = 132712440018000000000.0;
-pub const SOL_MASS: f64 = 1989000000000000000000000000000.0; // kg
-pub const SOL_RADIUS: f32 = 696340000.0; // m
+use crate::units::*;
 
-pub const EARTH_GRAV_PARAM: f64 = 398600441800000.0;
-pub const EARTH_MASS: f64 = 5972000000000000000000000.0; // kg
-pub const EARTH_RADIUS: f32 = 6371000.0;
-pub const EARTH_DIST_TO_SOL: f64 = 149600000000.0; // m
-pub const EARTH_SOL_ORBIT_VEL: f32 = 29780.0;
-pub const EARTH_LEO_ORBIT_VEL: f32 = 7788.25;
+pub const SOL_GRAV_PARAM: GravParam = GravParam::from_cubic_meters_per_second_squared(132712440018000000000.0);
+pub const SOL_MASS: Mass = Mass::from_kilograms(1989000000000000000000000000000.0);
+pub const SOL_RADIUS: Length = Length::from_meters(696340000.0);
 
-pub const G: f64 = 0.0000000000667408;
+pub const EARTH_GRAV_PARAM: GravParam = GravParam::from_cubic_meters_per_second_squared(398600441800000.0);
+pub const EARTH_MASS: Mass = Mass::from_kilograms(5972000000000000000000000.0);
+pub const EARTH_RADIUS: Length = Length::from_meters(6371000.0);
+pub const EARTH_DIST_TO_SOL: Length = Length::from_meters(149600000000.0);
+pub const EARTH_SOL_ORBIT_VEL: Velocity = Velocity::from_meters_per_second(29780.0);
+pub const EARTH_LEO_ORBIT_VEL: Velocity = Velocity::from_meters_per_second(7788.25);
+
+pub const G: f64 = 0.0000000000667408; // m^3 kg^-1 s^-2
 
 //pub const EARTH: PhysicsBodyRef = PhysicsBodyRef {
 //    bounding_radius: EARTH_RADIUS,
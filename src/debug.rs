@@ -1,5 +1,5 @@
 
-use std::{cell::{Cell, RefCell}, sync::{Mutex, MutexGuard, Once, mpsc::{ channel, Sender, Receiver }}, thread::{self, JoinHandle, ThreadId}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use std::{cell::{Cell, RefCell}, collections::{HashMap, VecDeque}, sync::{Mutex, MutexGuard, Once, mpsc::{ channel, Sender, Receiver }}, thread::{self, JoinHandle, ThreadId}, time::{Duration, SystemTime, UNIX_EPOCH}};
 
 // TODO
 //   Implement debug instrumentation and built in profiling
@@ -15,8 +15,165 @@ use std::{cell::{Cell, RefCell}, sync::{Mutex, MutexGuard, Once, mpsc::{ channel
 //     add file support
 
 
-pub trait MemoryUse {
-    fn memory_use_estimate(&self) -> usize;
+/// Memory accounting, the inline size of a value plus everything it owns on the heap
+///
+/// Containers count their capacity rather than their length, since that is what is actually allocated. Types that own
+/// no heap memory just need an empty impl
+pub trait MemUse: Sized {
+    fn memory_use(&self) -> usize {
+        ::std::mem::size_of::<Self>() + self.heap_use()
+    }
+
+    /// Bytes allocated on the heap by this value and everything it owns
+    fn heap_use(&self) -> usize {
+        0
+    }
+}
+
+macro_rules! inline_mem_use {
+    ($($t:ty),*) => { $(impl MemUse for $t {})* };
+}
+
+inline_mem_use!(bool, char, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, crate::math::DVec3, crate::epoch::Epoch);
+
+impl MemUse for String {
+    fn heap_use(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl MemUse for std::path::PathBuf {
+    fn heap_use(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: MemUse> MemUse for Option<T> {
+    fn heap_use(&self) -> usize {
+        self.as_ref().map(|value| value.heap_use()).unwrap_or(0)
+    }
+}
+
+impl<T: MemUse> MemUse for Vec<T> {
+    fn heap_use(&self) -> usize {
+        self.capacity() * ::std::mem::size_of::<T>() + self.iter().map(|value| value.heap_use()).sum::<usize>()
+    }
+}
+
+impl<T: MemUse> MemUse for VecDeque<T> {
+    fn heap_use(&self) -> usize {
+        self.capacity() * ::std::mem::size_of::<T>() + self.iter().map(|value| value.heap_use()).sum::<usize>()
+    }
+}
+
+impl<K: MemUse, V: MemUse, S> MemUse for HashMap<K, V, S> {
+    fn heap_use(&self) -> usize {
+        // the std map is a swiss table, a power of two number of buckets kept at most 7/8 full with one control byte
+        // per bucket plus a group's worth of trailing control bytes
+        let buckets = match self.capacity() {
+            0 => return 0,
+            c if c < 4 => 4,
+            c if c < 8 => 8,
+            c => (c * 8 / 7).next_power_of_two(),
+        };
+        let table = buckets * (::std::mem::size_of::<(K, V)>() + 1) + 16;
+        table + self.iter().map(|(key, value)| key.heap_use() + value.heap_use()).sum::<usize>()
+    }
+}
+
+impl<A: MemUse, B: MemUse> MemUse for (A, B) {
+    fn heap_use(&self) -> usize {
+        self.0.heap_use() + self.1.heap_use()
+    }
+}
+
+impl<A: MemUse, B: MemUse, C: MemUse> MemUse for (A, B, C) {
+    fn heap_use(&self) -> usize {
+        self.0.heap_use() + self.1.heap_use() + self.2.heap_use()
+    }
+}
+
+/// Process wide heap statistics from the counting allocator
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AllocationStats {
+    pub live_bytes: usize, // currently allocated
+    pub allocated_bytes: u64, // allocated over the life of the process, including since freed
+    pub allocations: u64,
+}
+
+/// The heap statistics so far, only available when built with the `counting-allocator` feature
+pub fn allocation_stats() -> Option<AllocationStats> {
+    #[cfg(feature = "counting-allocator")]
+    {
+        Some(counting_allocator::stats())
+    }
+    #[cfg(not(feature = "counting-allocator"))]
+    {
+        None
+    }
+}
+
+#[cfg(feature = "counting-allocator")]
+mod counting_allocator {
+    use std::{alloc::{GlobalAlloc, Layout, System}, sync::atomic::{AtomicU64, AtomicUsize, Ordering}};
+    use super::AllocationStats;
+
+    static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+    static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+    static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+    /// Wraps the system allocator, counting every allocation
+    pub struct CountingAllocator;
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    fn record_allocation(size: usize) {
+        LIVE_BYTES.fetch_add(size, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                record_allocation(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc_zeroed(layout);
+            if !ptr.is_null() {
+                record_allocation(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                // counted as freeing the old block and allocating the new one
+                LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+                record_allocation(new_size);
+            }
+            new_ptr
+        }
+    }
+
+    pub fn stats() -> AllocationStats {
+        AllocationStats {
+            live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -537,6 +694,41 @@ mod test {
         };
     }
 
+    #[test]
+    fn memory_use_counts_capacity() {
+        let mut numbers: Vec<f64> = Vec::with_capacity(100);
+        numbers.push(1.0);
+        assert_eq!(numbers.heap_use(), 800);
+        assert_eq!(numbers.memory_use(), 800 + std::mem::size_of::<Vec<f64>>());
+
+        let names = vec![String::with_capacity(10), String::from("abc")];
+        assert_eq!(names.heap_use(), names.capacity() * std::mem::size_of::<String>() + 10 + names[1].capacity());
+        assert_eq!(Some(String::with_capacity(7)).heap_use(), 7);
+
+        let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+        assert_eq!(index.heap_use(), 0);
+        index.insert(String::from("EARTH"), vec![1, 2]);
+        let entry = std::mem::size_of::<(String, Vec<usize>)>();
+        let owned = index.keys().next().unwrap().capacity() + index.values().next().unwrap().capacity() * 8;
+        assert_eq!(index.heap_use(), 4 * (entry + 1) + 16 + owned);
+        for i in 0..100 {
+            index.insert(i.to_string(), Vec::new());
+        }
+        assert!(index.heap_use() > 128 * entry);
+    }
+
+    #[test]
+    fn allocation_counting() {
+        assert_eq!(allocation_stats().is_some(), cfg!(feature = "counting-allocator"));
+        if let Some(before) = allocation_stats() {
+            let buffer: Vec<u8> = Vec::with_capacity(1 << 20);
+            let after = allocation_stats().unwrap();
+            assert!(after.allocated_bytes >= before.allocated_bytes + (1 << 20));
+            assert!(after.allocations > before.allocations);
+            drop(buffer);
+        }
+    }
+
     #[test]
     #[should_panic]
     fn test_fatal_log() {
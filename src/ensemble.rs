@@ -0,0 +1,487 @@
+use std::{error::Error, fmt::Display, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}};
+
+use crate::{forces::Burn, math::DVec3, observer::{Observer, SimEvent}, scenario::{Scenario, ScenarioError}, sim::*};
+
+// Ensemble runs
+//
+// Runs a base scenario many times with perturbed initial conditions and summarises the spread of the results. Each
+// member draws its perturbations, in the order they were added, from its own generator seeded from the ensemble seed
+// and the member number, so an ensemble is reproducible whatever the number of threads and any member can be rebuilt
+// on its own with `Ensemble::member`.
+//
+//   Position, Velocity  an offset drawn separately for each axis, in m or m/s
+//   Mass                an offset in kg, the gravitational parameter of a gravitational body scales with it
+//   BurnStart           an offset in seconds to the start of the named burn
+//
+// Perturbations are applied to the built simulation, so they apply to bodies whose state comes from an element set
+// or an ephemeris as well, and a body's offset does not carry over to the bodies placed relative to it.
+
+/// xoshiro256**, seeded through splitmix64
+#[derive(Debug, Clone)]
+pub struct Rng([u64; 4]);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut state = seed;
+        Rng([splitmix64(&mut state), splitmix64(&mut state), splitmix64(&mut state), splitmix64(&mut state)])
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.0;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// Uniform on [0, 1)
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, by the Box-Muller transform
+    pub fn gaussian(&mut self) -> f64 {
+        let (u1, u2) = (1.0 - self.uniform(), self.uniform());
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A zero mean distribution of offsets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    Gaussian { sigma: f64 },
+    Uniform { half_width: f64 },
+}
+
+impl Distribution {
+    pub fn sample(&self, rng: &mut Rng) -> f64 {
+        match self {
+            Self::Gaussian { sigma } => rng.gaussian() * sigma,
+            Self::Uniform { half_width } => (2.0 * rng.uniform() - 1.0) * half_width,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    Position,
+    Velocity,
+    Mass,
+    BurnStart,
+}
+
+#[derive(Debug, Clone)]
+pub struct Perturbation {
+    pub target: String, // a body name, or a burn name for BurnStart
+    pub parameter: Parameter,
+    pub distribution: Distribution,
+}
+
+/// A burn added to every member, of a constant force on the named body
+#[derive(Debug, Clone)]
+pub struct BurnPlan {
+    pub name: String,
+    pub body: String,
+    pub start: f64, // seconds of simulation time
+    pub duration: f64,
+    pub force: DVec3, // newtons
+}
+
+/// A quantity recorded from every member, between two named bodies
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tracked {
+    Distance(String, String), // at the end of the run, m
+    ClosestApproach(String, String), // smallest distance over the run, sampled once per frame, m
+    RelativeSpeed(String, String), // at the end of the run, m/s
+}
+
+impl Display for Tracked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Distance(a, b) => write!(f, "distance {}-{} (m)", a, b),
+            Self::ClosestApproach(a, b) => write!(f, "closest approach {}-{} (m)", a, b),
+            Self::RelativeSpeed(a, b) => write!(f, "relative speed {}-{} (m/s)", a, b),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EnsembleError {
+    Scenario(ScenarioError),
+    UnknownBody(String),
+    UnknownBurn(String),
+    NoDuration,
+}
+
+impl Display for EnsembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Scenario(inner) => write!(f, "{}", inner),
+            Self::UnknownBody(name) => write!(f, "unknown body: '{}'", name),
+            Self::UnknownBurn(name) => write!(f, "unknown burn: '{}'", name),
+            Self::NoDuration => write!(f, "the scenario needs a duration to run an ensemble"),
+        }
+    }
+}
+
+impl Error for EnsembleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Scenario(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScenarioError> for EnsembleError {
+    fn from(error: ScenarioError) -> Self {
+        EnsembleError::Scenario(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ensemble {
+    scenario: Scenario,
+    members: usize,
+    seed: u64,
+    threads: usize,
+    perturbations: Vec<Perturbation>,
+    burns: Vec<BurnPlan>,
+    tracked: Vec<Tracked>,
+}
+
+impl Ensemble {
+    pub fn new(scenario: Scenario, members: usize) -> Self {
+        Ensemble {
+            scenario,
+            members,
+            seed: 0,
+            threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            perturbations: Vec::new(),
+            burns: Vec::new(),
+            tracked: Vec::new(),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Runs members on up to `threads` threads, as many as the machine has by default
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn perturb(mut self, target: &str, parameter: Parameter, distribution: Distribution) -> Self {
+        self.perturbations.push(Perturbation { target: String::from(target), parameter, distribution });
+        self
+    }
+
+    pub fn with_burn(mut self, burn: BurnPlan) -> Self {
+        self.burns.push(burn);
+        self
+    }
+
+    pub fn track(mut self, tracked: Tracked) -> Self {
+        self.tracked.push(tracked);
+        self
+    }
+
+    /// The perturbed simulation of one member, ready to run
+    pub fn member(&self, member: usize) -> Result<Simulation, EnsembleError> {
+        let mut sim = self.scenario.build()?;
+        let mut rng = Rng::new(self.seed ^ splitmix64(&mut (member as u64)));
+        let mut burn_offsets = vec![0.0; self.burns.len()];
+
+        for perturbation in self.perturbations.iter() {
+            let mut sample = || perturbation.distribution.sample(&mut rng);
+            let offset = match perturbation.parameter {
+                Parameter::Position => (DVec3::new(sample(), sample(), sample()), DVec3::zero(), 0.0),
+                Parameter::Velocity => (DVec3::zero(), DVec3::new(sample(), sample(), sample()), 0.0),
+                Parameter::Mass => (DVec3::zero(), DVec3::zero(), sample()),
+                Parameter::BurnStart => {
+                    let index = self.burns.iter().position(|burn| burn.name == perturbation.target)
+                        .ok_or_else(|| EnsembleError::UnknownBurn(perturbation.target.clone()))?;
+                    burn_offsets[index] += sample();
+                    continue;
+                },
+            };
+            let (position, velocity, mass) = offset;
+            for id in body_ids(&sim, &perturbation.target)? {
+                sim.perturb_body(id, position, velocity, mass);
+            }
+        }
+
+        for (burn, offset) in self.burns.iter().zip(burn_offsets) {
+            let body = body_ids(&sim, &burn.body)?[0];
+            sim.add_force_model(Box::new(Burn {
+                name: burn.name.clone(),
+                body,
+                start: burn.start + offset,
+                duration: burn.duration,
+                force: burn.force,
+            }));
+        }
+        Ok(sim)
+    }
+
+    /// Runs every member to the end of the scenario and summarises the tracked quantities
+    pub fn run(&self) -> Result<EnsembleReport, EnsembleError> {
+        if self.scenario.duration.is_none() {
+            return Err(EnsembleError::NoDuration)
+        }
+
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(self.members));
+        std::thread::scope(|scope| {
+            for _ in 0..self.threads.min(self.members) {
+                scope.spawn(|| loop {
+                    let member = next.fetch_add(1, Ordering::Relaxed);
+                    if member >= self.members {
+                        break
+                    }
+                    let result = self.run_member(member);
+                    results.lock().unwrap().push((member, result));
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(member, _)| *member);
+        let members = results.into_iter().map(|(_, result)| result).collect::<Result<Vec<MemberResult>, EnsembleError>>()?;
+
+        let tracked = self.tracked.iter().enumerate()
+            .map(|(i, tracked)| (tracked.clone(), Summary::new(members.iter().map(|member| member.values[i]).collect())))
+            .collect();
+        let collisions = members.iter().filter(|member| member.collided).count();
+        Ok(EnsembleReport {
+            seed: self.seed,
+            collision_probability: collisions as f64 / members.len().max(1) as f64,
+            tracked,
+            members,
+        })
+    }
+
+    fn run_member(&self, member: usize) -> Result<MemberResult, EnsembleError> {
+        let mut sim = self.member(member)?;
+        let mut pairs = Vec::new();
+        for tracked in self.tracked.iter() {
+            let (a, b) = match tracked {
+                Tracked::Distance(a, b) | Tracked::ClosestApproach(a, b) | Tracked::RelativeSpeed(a, b) => (a, b),
+            };
+            pairs.push((body_ids(&sim, a)?[0], body_ids(&sim, b)?[0]));
+        }
+
+        let record = Arc::new(Mutex::new(MemberRecord {
+            closest: pairs.iter().map(|(a, b)| distance(sim.present(), *a, *b)).collect(),
+            collided: false,
+        }));
+        sim.add_observer(Box::new(MemberObserver { pairs: pairs.clone(), record: record.clone() }));
+        sim.run().expect("ensemble members only have in memory observers");
+
+        let record = record.lock().unwrap();
+        let frame = sim.present();
+        let values = self.tracked.iter().zip(pairs.iter()).enumerate().map(|(i, (tracked, (a, b)))| match tracked {
+            Tracked::Distance(..) => distance(frame, *a, *b),
+            Tracked::ClosestApproach(..) => record.closest[i],
+            Tracked::RelativeSpeed(..) => {
+                let (a, b) = (frame.get_body_ref(*a).unwrap(), frame.get_body_ref(*b).unwrap());
+                (a.velocity() - b.velocity()).magnitude()
+            },
+        }).collect();
+        Ok(MemberResult { member, values, collided: record.collided })
+    }
+}
+
+fn body_ids(sim: &Simulation, name: &str) -> Result<Vec<usize>, EnsembleError> {
+    let ids: Vec<usize> = sim.present().get_named_bodies(name).iter().map(|body| body.id()).collect();
+    match ids.is_empty() {
+        true => Err(EnsembleError::UnknownBody(String::from(name))),
+        false => Ok(ids),
+    }
+}
+
+fn distance(frame: &PhysicsFrame, a: usize, b: usize) -> f64 {
+    match (frame.get_body_ref(a), frame.get_body_ref(b)) {
+        (Some(a), Some(b)) => a.centers_distance_to(&b),
+        _ => f64::NAN,
+    }
+}
+
+#[derive(Debug)]
+struct MemberRecord {
+    closest: Vec<f64>,
+    collided: bool,
+}
+
+/// Follows the closest approaches and collisions of one member
+struct MemberObserver {
+    pairs: Vec<(usize, usize)>,
+    record: Arc<Mutex<MemberRecord>>,
+}
+
+impl Observer for MemberObserver {
+    fn frame(&mut self, sim: &Simulation) {
+        let mut record = self.record.lock().unwrap();
+        for (closest, (a, b)) in record.closest.iter_mut().zip(self.pairs.iter()) {
+            *closest = closest.min(distance(sim.present(), *a, *b));
+        }
+    }
+
+    fn event(&mut self, _sim: &Simulation, event: &SimEvent) {
+        if let SimEvent::Collision { .. } = event {
+            self.record.lock().unwrap().collided = true;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemberResult {
+    pub member: usize,
+    pub values: Vec<f64>, // one per tracked quantity
+    pub collided: bool,
+}
+
+/// Statistics of one tracked quantity over the members
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub mean: f64,
+    pub std_dev: f64,
+    sorted: Vec<f64>,
+}
+
+impl Summary {
+    pub fn new(mut values: Vec<f64>) -> Self {
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let n = values.len().max(1) as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - 1.0).max(1.0);
+        Summary { mean, std_dev: variance.sqrt(), sorted: values }
+    }
+
+    /// The value below which `p` percent of the members fall, interpolated between members
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.sorted.is_empty() {
+            return f64::NAN
+        }
+        let rank = (p / 100.0).clamp(0.0, 1.0) * (self.sorted.len() - 1) as f64;
+        let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+        self.sorted[below] + (self.sorted[above] - self.sorted[below]) * (rank - below as f64)
+    }
+
+    pub fn min(&self) -> f64 {
+        self.percentile(0.0)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.percentile(100.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnsembleReport {
+    pub seed: u64,
+    pub members: Vec<MemberResult>,
+    pub tracked: Vec<(Tracked, Summary)>,
+    pub collision_probability: f64, // fraction of members in which any two bodies collided
+}
+
+impl Display for EnsembleReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} members, seed {}", self.members.len(), self.seed)?;
+        writeln!(f, "collision probability {:.2} %", self.collision_probability * 100.0)?;
+        for (tracked, summary) in self.tracked.iter() {
+            writeln!(f, "{}", tracked)?;
+            writeln!(f, "  mean {:.6e}, std dev {:.6e}", summary.mean, summary.std_dev)?;
+            writeln!(f, "  p5 {:.6e}, median {:.6e}, p95 {:.6e}", summary.percentile(5.0), summary.percentile(50.0), summary.percentile(95.0))?;
+            writeln!(f, "  min {:.6e}, max {:.6e}", summary.min(), summary.max())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A probe passing a 1 m target at 100 m, on course for a dead centre hit
+    fn flyby() -> Scenario {
+        Scenario::parse("
+            timestep = 1 s
+            duration = 200 s
+            [body Target]
+            radius = 1 m
+            [body Probe]
+            radius = 1 m
+            position = 100 m, 0 m, 0 m
+            velocity = -1 m/s, 0 m/s, 0 m/s
+        ").unwrap()
+    }
+
+    #[test]
+    fn generator_is_deterministic_and_normal() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        assert!((0..100).all(|_| a.next_u64() == b.next_u64()));
+
+        let samples: Vec<f64> = (0..100_000).map(|_| a.gaussian()).collect();
+        let summary = Summary::new(samples);
+        assert!(summary.mean.abs() < 0.02, "{}", summary.mean);
+        assert!((summary.std_dev - 1.0).abs() < 0.02, "{}", summary.std_dev);
+        assert!((summary.percentile(97.5) - 1.96).abs() < 0.05);
+
+        let uniform = Distribution::Uniform { half_width: 3.0 };
+        assert!((0..1000).map(|_| uniform.sample(&mut a)).all(|x| (-3.0..3.0).contains(&x)));
+    }
+
+    #[test]
+    fn collision_probability_of_a_scattered_flyby() {
+        // offsets across the track of sigma 2 m miss by a Rayleigh distributed distance, and the bodies touch
+        // within 2 m, so 1 - exp(-1/2) or about 39 % of members collide
+        let ensemble = Ensemble::new(flyby(), 400).with_seed(42)
+            .perturb("Probe", Parameter::Position, Distribution::Gaussian { sigma: 2.0 })
+            .track(Tracked::ClosestApproach(String::from("Probe"), String::from("Target")));
+        let report = ensemble.run().unwrap();
+        assert!((report.collision_probability - 0.393).abs() < 0.07, "{}", report.collision_probability);
+        let median = report.tracked[0].1.percentile(50.0);
+        assert!(median > 1.5 && median < 3.5, "{}", median);
+
+        // the same seed gives the same ensemble, whatever the threads
+        let again = ensemble.with_threads(1).run().unwrap();
+        assert_eq!(again.collision_probability, report.collision_probability);
+        assert_eq!(again.members[123].values, report.members[123].values);
+    }
+
+    #[test]
+    fn burn_timing_spreads_the_arrival() {
+        // a 10 s, 1 N burn on the unit mass probe adds 10 m/s along x, starting anywhere from 40 s to 60 s
+        let ensemble = Ensemble::new(flyby(), 50).with_seed(1)
+            .with_burn(BurnPlan { name: String::from("kick"), body: String::from("Probe"), start: 50.0, duration: 10.0, force: DVec3::new(1.0, 0.0, 0.0) })
+            .perturb("kick", Parameter::BurnStart, Distribution::Uniform { half_width: 10.0 })
+            .track(Tracked::RelativeSpeed(String::from("Probe"), String::from("Target")))
+            .track(Tracked::Distance(String::from("Probe"), String::from("Target")));
+        let report = ensemble.run().unwrap();
+
+        let (speed, distance) = (&report.tracked[0].1, &report.tracked[1].1);
+        assert!((speed.mean - 9.0).abs() < 1.0e-9);
+        assert!(distance.min() >= 1250.0 - 1.0e-6 && distance.max() <= 1450.0 + 1.0e-6, "{}", report);
+        assert!(distance.max() - distance.min() > 100.0);
+
+        let missing = Ensemble::new(flyby(), 1).perturb("kick", Parameter::BurnStart, Distribution::Gaussian { sigma: 1.0 });
+        assert!(matches!(missing.run(), Err(EnsembleError::UnknownBurn(_))));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{debug::MemUse, epoch::Epoch, math::{DVec3, propagate_kepler}};
+
+// Tabulated ephemerides
+//
+// A time ordered table of position and velocity states for a single body, interpolated with cubic Hermite
+// polynomials which use both the positions and velocities at the bracketing records. Tables are produced by the
+// ephemeris importers and consumed by anything that needs a body state at an arbitrary epoch
+//
+// Chebyshev series are the compact alternative, piecewise polynomial fits of the positions in the style of the JPL
+// development ephemerides. Either, or an analytic two-body orbit, can be the `Rails` a scripted body follows
+
+/// Obliquity of the ecliptic at J2000 in degrees, IAU 2006
+pub const OBLIQUITY_J2000: f64 = 23.439279444444445;
+
+/// Rotates a vector from the ICRF (equator of J2000) into the ecliptic of J2000, the frame the simulation uses
+pub fn equatorial_to_ecliptic(v: DVec3) -> DVec3 {
+    let (sin, cos) = OBLIQUITY_J2000.to_radians().sin_cos();
+    DVec3::new(v.x, cos * v.y + sin * v.z, -sin * v.y + cos * v.z)
+}
+
+/// Rotates a vector from the ecliptic of J2000 into the ICRF
+pub fn ecliptic_to_equatorial(v: DVec3) -> DVec3 {
+    let (sin, cos) = OBLIQUITY_J2000.to_radians().sin_cos();
+    DVec3::new(v.x, cos * v.y - sin * v.z, sin * v.y + cos * v.z)
+}
+
+/// A single tabulated state, in metres and metres per second
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EphemerisRecord {
+    pub epoch: Epoch,
+    pub position: DVec3,
+    pub velocity: DVec3,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EphemerisTable {
+    records: Vec<EphemerisRecord>,
+}
+
+impl EphemerisTable {
+    pub fn new(mut records: Vec<EphemerisRecord>) -> Self {
+        records.sort_by(|a, b| a.epoch.partial_cmp(&b.epoch).unwrap_or(std::cmp::Ordering::Equal));
+        EphemerisTable { records }
+    }
+
+    pub fn records(&self) -> &[EphemerisRecord] {
+        &self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn start(&self) -> Option<Epoch> {
+        self.records.first().map(|record| record.epoch)
+    }
+
+    pub fn end(&self) -> Option<Epoch> {
+        self.records.last().map(|record| record.epoch)
+    }
+
+    pub fn covers(&self, epoch: Epoch) -> bool {
+        match (self.start(), self.end()) {
+            (Some(start), Some(end)) => start <= epoch && epoch <= end,
+            _ => false,
+        }
+    }
+
+    /// Interpolated position and velocity at `epoch`, or `None` if the epoch is outside the table
+    pub fn state_at(&self, epoch: Epoch) -> Option<(DVec3, DVec3)> {
+        if !self.covers(epoch) {
+            return None
+        }
+
+        // index of the first record after the epoch, the bracketing records are either side of it
+        let upper = self.records.partition_point(|record| record.epoch <= epoch);
+        if upper == 0 {
+            return None
+        } else if upper == self.records.len() {
+            let last = self.records[upper - 1];
+            return Some((last.position, last.velocity)) // exactly on the final record
+        }
+
+        let (a, b) = (&self.records[upper - 1], &self.records[upper]);
+        let h = b.epoch.seconds_since(&a.epoch);
+        if h <= 0.0 {
+            return Some((a.position, a.velocity))
+        }
+        Some(hermite(a.position, a.velocity, b.position, b.velocity, h, epoch.seconds_since(&a.epoch) / h))
+    }
+}
+
+/// Cubic Hermite interpolation over an interval of length `h` seconds at the normalised time `s` in [0, 1]
+pub fn hermite(p0: DVec3, v0: DVec3, p1: DVec3, v1: DVec3, h: f64, s: f64) -> (DVec3, DVec3) {
+    let (s2, s3) = (s * s, s * s * s);
+
+    let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+    let h10 = s3 - 2.0 * s2 + s;
+    let h01 = -2.0 * s3 + 3.0 * s2;
+    let h11 = s3 - s2;
+    let position = p0 * h00 + v0 * (h10 * h) + p1 * h01 + v1 * (h11 * h);
+
+    // derivatives of the basis functions with respect to s, divided by h for time derivatives
+    let d00 = 6.0 * s2 - 6.0 * s;
+    let d10 = 3.0 * s2 - 4.0 * s + 1.0;
+    let d01 = -6.0 * s2 + 6.0 * s;
+    let d11 = 3.0 * s2 - 2.0 * s;
+    let velocity = p0 * (d00 / h) + v0 * d10 + p1 * (d01 / h) + v1 * d11;
+
+    (position, velocity)
+}
+
+/// Chebyshev polynomial fits of each position component over `start` to `end`, velocities are their derivatives
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChebyshevSegment {
+    pub start: Epoch,
+    pub end: Epoch,
+    pub coefficients: [Vec<f64>; 3], // metres
+}
+
+impl ChebyshevSegment {
+    /// Fits `degree` + 1 coefficients per component to positions sampled at the Chebyshev nodes of the interval
+    pub fn fit(start: Epoch, end: Epoch, degree: usize, position_at: impl Fn(Epoch) -> DVec3) -> Self {
+        let n = degree + 1;
+        let span = end.seconds_since(&start);
+        let samples: Vec<(f64, DVec3)> = (0..n).map(|k| {
+            let angle = std::f64::consts::PI * (k as f64 + 0.5) / n as f64;
+            (angle, position_at(start.offset_by(0.5 * (angle.cos() + 1.0) * span)))
+        }).collect();
+
+        let mut coefficients = [vec![0.0; n], vec![0.0; n], vec![0.0; n]];
+        for (axis, axis_coefficients) in coefficients.iter_mut().enumerate() {
+            for (j, coefficient) in axis_coefficients.iter_mut().enumerate() {
+                let scale = if j == 0 { 1.0 } else { 2.0 } / n as f64;
+                // T_j(cos(angle)) = cos(j angle)
+                *coefficient = samples.iter().map(|(angle, position)| scale * (j as f64 * angle).cos() * position[axis]).sum();
+            }
+        }
+
+        ChebyshevSegment {
+            start,
+            end,
+            coefficients,
+        }
+    }
+
+    pub fn covers(&self, epoch: Epoch) -> bool {
+        self.start <= epoch && epoch <= self.end
+    }
+
+    /// Position and velocity at `epoch`, the polynomials are extrapolated outside the segment
+    pub fn state_at(&self, epoch: Epoch) -> (DVec3, DVec3) {
+        let span = self.end.seconds_since(&self.start);
+        let x = 2.0 * epoch.seconds_since(&self.start) / span - 1.0;
+        let (px, vx) = chebyshev(&self.coefficients[0], x);
+        let (py, vy) = chebyshev(&self.coefficients[1], x);
+        let (pz, vz) = chebyshev(&self.coefficients[2], x);
+        (DVec3::new(px, py, pz), DVec3::new(vx, vy, vz) * (2.0 / span))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChebyshevSeries {
+    segments: Vec<ChebyshevSegment>,
+}
+
+impl ChebyshevSeries {
+    pub fn new(mut segments: Vec<ChebyshevSegment>) -> Self {
+        segments.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap_or(std::cmp::Ordering::Equal));
+        ChebyshevSeries { segments }
+    }
+
+    /// Fits segments of `segment_length` seconds, the last may be shorter, to the positions of a table
+    pub fn from_table(table: &EphemerisTable, segment_length: f64, degree: usize) -> Self {
+        let (start, end) = match (table.start(), table.end()) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return ChebyshevSeries::default(),
+        };
+
+        let mut segments = Vec::new();
+        let mut segment_start = start;
+        while segment_start < end {
+            let segment_end = match segment_start.offset_by(segment_length) {
+                next if next < end => next,
+                _ => end,
+            };
+            let position_at = |epoch| table.state_at(epoch).map_or(DVec3::zero(), |(position, _)| position);
+            segments.push(ChebyshevSegment::fit(segment_start, segment_end, degree, position_at));
+            segment_start = segment_end;
+        }
+        ChebyshevSeries { segments }
+    }
+
+    pub fn segments(&self) -> &[ChebyshevSegment] {
+        &self.segments
+    }
+
+    /// Position and velocity at `epoch` from the segment covering it, or `None` if no segment does
+    pub fn state_at(&self, epoch: Epoch) -> Option<(DVec3, DVec3)> {
+        let index = self.segments.partition_point(|segment| segment.end < epoch);
+        self.segments.get(index).filter(|segment| segment.covers(epoch)).map(|segment| segment.state_at(epoch))
+    }
+}
+
+/// Value and derivative with respect to `x` of a Chebyshev series at `x` in [-1, 1]
+fn chebyshev(coefficients: &[f64], x: f64) -> (f64, f64) {
+    let (mut value, mut derivative) = (0.0, 0.0);
+    let (mut t0, mut t1, mut d0, mut d1) = (1.0, x, 0.0, 1.0); // T_k-1, T_k and their derivatives
+    for (k, c) in coefficients.iter().enumerate() {
+        let (t, d) = match k {
+            0 => (t0, d0),
+            1 => (t1, d1),
+            _ => {
+                let (t, d) = (2.0 * x * t1 - t0, 2.0 * t1 + 2.0 * x * d1 - d0);
+                t0 = t1; t1 = t; d0 = d1; d1 = d;
+                (t, d)
+            },
+        };
+        value += c * t;
+        derivative += c * d;
+    }
+    (value, derivative)
+}
+
+/// The trajectory a scripted body follows in place of being integrated
+#[derive(Debug, Clone)]
+pub enum Rails {
+    Table(EphemerisTable),
+    Chebyshev(ChebyshevSeries),
+    Kepler { position: DVec3, velocity: DVec3, grav_param: f64, sim_time: f64 }, // the two-body orbit through a state at sim_time
+}
+
+impl Rails {
+    /// Position and velocity at `sim_time` seconds, whose epoch is `epoch` if the simulation has one. `None` where the
+    /// rails do not reach, tables and series need an epoch
+    pub fn state_at(&self, sim_time: f64, epoch: Option<Epoch>) -> Option<(DVec3, DVec3)> {
+        match self {
+            Self::Table(table) => table.state_at(epoch?),
+            Self::Chebyshev(series) => series.state_at(epoch?),
+            Self::Kepler { position, velocity, grav_param, sim_time: start } => Some(propagate_kepler(*position, *velocity, *grav_param, sim_time - start)),
+        }
+    }
+}
+
+impl MemUse for EphemerisRecord {}
+
+impl MemUse for EphemerisTable {
+    fn heap_use(&self) -> usize {
+        self.records.heap_use()
+    }
+}
+
+impl MemUse for ChebyshevSegment {
+    fn heap_use(&self) -> usize {
+        self.coefficients.iter().map(|coefficients| coefficients.heap_use()).sum()
+    }
+}
+
+impl MemUse for ChebyshevSeries {
+    fn heap_use(&self) -> usize {
+        self.segments.heap_use()
+    }
+}
+
+impl MemUse for Rails {
+    fn heap_use(&self) -> usize {
+        match self {
+            Self::Table(table) => table.heap_use(),
+            Self::Chebyshev(series) => series.heap_use(),
+            Self::Kepler { .. } => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MU: f64 = 3.986004418e14;
+
+    #[test]
+    fn chebyshev_series_follow_the_table() {
+        // a day of a low orbit tabulated every minute, fitted with half hour segments
+        let (p0, v0) = (DVec3::new(7.0e6, 0.0, 0.0), DVec3::new(0.0, (MU / 7.0e6).sqrt(), 1000.0));
+        let records = (0..=1440).map(|i| {
+            let (position, velocity) = propagate_kepler(p0, v0, MU, i as f64 * 60.0);
+            EphemerisRecord { epoch: Epoch::J2000.offset_by(i as f64 * 60.0), position, velocity }
+        }).collect();
+        let table = EphemerisTable::new(records);
+        let series = ChebyshevSeries::from_table(&table, 1800.0, 12);
+        assert_eq!(series.segments().len(), 48);
+
+        for seconds in [0.0, 1234.5, 3600.0, 50000.0, 86400.0].iter() {
+            let (exact_position, exact_velocity) = propagate_kepler(p0, v0, MU, *seconds);
+            let (position, velocity) = series.state_at(Epoch::J2000.offset_by(*seconds)).unwrap();
+            assert!(position.length_to(&exact_position) < 1.0, "{}: {}", seconds, position.length_to(&exact_position));
+            assert!(velocity.length_to(&exact_velocity) < 0.05, "{}: {}", seconds, velocity.length_to(&exact_velocity));
+        }
+        assert!(series.state_at(Epoch::J2000.offset_by(-1.0)).is_none());
+        assert!(Rails::Chebyshev(series).state_at(0.0, None).is_none());
+    }
+}
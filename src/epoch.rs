@@ -0,0 +1,426 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use crate::units::SECONDS_PER_DAY;
+
+// Astronomical epochs and time scales
+//
+// An epoch is stored as TT seconds past J2000.0 (2000-01-01T12:00:00 TT). Simulation time is uniform, so sim time
+// offsets are applied in TT and converted to other scales on demand.
+//
+//   TAI = TT - 32.184s
+//   UTC = TAI - (TAI - UTC), where TAI - UTC comes from the leap second table below
+//   TDB = TT + periodic terms under 2ms, using the usual two term approximation
+
+pub const J2000_JULIAN_DATE: f64 = 2451545.0;
+pub const MJD_OFFSET: f64 = 2400000.5;
+pub const TT_MINUS_TAI: f64 = 32.184;
+
+/// TAI - UTC in seconds, taking effect at 00:00:00 UTC on the given date
+///
+/// Updated through the leap second introduced at the end of 2016. Dates before 1972 use the first entry
+const LEAP_SECONDS: [(i32, u32, u32, f64); 28] = [
+    (1972, 1, 1, 10.0),
+    (1972, 7, 1, 11.0),
+    (1973, 1, 1, 12.0),
+    (1974, 1, 1, 13.0),
+    (1975, 1, 1, 14.0),
+    (1976, 1, 1, 15.0),
+    (1977, 1, 1, 16.0),
+    (1978, 1, 1, 17.0),
+    (1979, 1, 1, 18.0),
+    (1980, 1, 1, 19.0),
+    (1981, 7, 1, 20.0),
+    (1982, 7, 1, 21.0),
+    (1983, 7, 1, 22.0),
+    (1985, 7, 1, 23.0),
+    (1988, 1, 1, 24.0),
+    (1990, 1, 1, 25.0),
+    (1991, 1, 1, 26.0),
+    (1992, 7, 1, 27.0),
+    (1993, 7, 1, 28.0),
+    (1994, 7, 1, 29.0),
+    (1996, 1, 1, 30.0),
+    (1997, 7, 1, 31.0),
+    (1999, 1, 1, 32.0),
+    (2006, 1, 1, 33.0),
+    (2009, 1, 1, 34.0),
+    (2012, 7, 1, 35.0),
+    (2015, 7, 1, 36.0),
+    (2017, 1, 1, 37.0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeScale {
+    Utc,
+    Tai,
+    Tt,
+    Tdb,
+}
+
+impl Display for TimeScale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Utc => write!(f, "UTC"),
+            Self::Tai => write!(f, "TAI"),
+            Self::Tt => write!(f, "TT"),
+            Self::Tdb => write!(f, "TDB"),
+        }
+    }
+}
+
+impl FromStr for TimeScale {
+    type Err = EpochParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "UTC" | "Z" => Ok(Self::Utc),
+            "TAI" => Ok(Self::Tai),
+            "TT" | "TDT" => Ok(Self::Tt),
+            "TDB" => Ok(Self::Tdb),
+            _ => Err(EpochParseError::UnknownTimeScale(String::from(s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EpochParseError {
+    Malformed(String),
+    UnknownTimeScale(String),
+    OutOfRange(String),
+}
+
+impl Display for EpochParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(s) => write!(f, "malformed epoch: '{}', expected an ISO-8601 date or 'JD <julian date>'", s),
+            Self::UnknownTimeScale(s) => write!(f, "unknown time scale: '{}'", s),
+            Self::OutOfRange(s) => write!(f, "calendar field out of range: '{}'", s),
+        }
+    }
+}
+
+impl Error for EpochParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+/// A broken down Gregorian calendar date and time of day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalendarDate {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: f64,
+}
+
+/// Julian day number of the Gregorian date, the julian date at noon of that day
+fn julian_day_number(year: i32, month: u32, day: u32) -> i64 {
+    let (y, m, d) = (year as i64, month as i64, day as i64);
+    let a = (14 - m) / 12;
+    let y = y + 4800 - a;
+    let m = m + 12 * a - 3;
+    d + (153 * m + 2) / 5 + 365 * y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400) - 32045
+}
+
+/// Gregorian date of a julian day number
+fn civil_from_julian_day_number(jdn: i64) -> (i32, u32, u32) {
+    let a = jdn + 32044;
+    let b = (4 * a + 3).div_euclid(146097);
+    let c = a - (146097 * b).div_euclid(4);
+    let d = (4 * c + 3) / 1461;
+    let e = c - (1461 * d) / 4;
+    let m = (5 * e + 2) / 153;
+    let day = e - (153 * m + 2) / 5 + 1;
+    let month = m + 3 - 12 * (m / 10);
+    let year = 100 * b + d - 4800 + m / 10;
+    (year as i32, month as u32, day as u32)
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Whether the UTC day ends with a leap second, that is the table has a step at 00:00:00 the following day.
+/// The first entry is where the table starts rather than a leap second
+fn ends_with_leap_second(year: i32, month: u32, day: u32) -> bool {
+    let (next_year, next_month, next_day) = civil_from_julian_day_number(julian_day_number(year, month, day) + 1);
+    LEAP_SECONDS[1..].iter().any(|(y, m, d, _)| (*y, *m, *d) == (next_year, next_month, next_day))
+}
+
+/// Seconds past J2000.0 of a calendar date, counting every day as 86400 seconds in whatever scale it is given in
+fn calendar_seconds_past_j2000(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: f64) -> f64 {
+    let days = (julian_day_number(year, month, day) as f64 - 0.5) - J2000_JULIAN_DATE;
+    days * SECONDS_PER_DAY + (hour as f64) * 3600.0 + (minute as f64) * 60.0 + second
+}
+
+/// TAI - UTC at a UTC instant given in seconds past J2000.0 UTC
+fn tai_minus_utc_at_utc(utc_seconds: f64) -> f64 {
+    let mut offset = LEAP_SECONDS[0].3;
+    for (year, month, day, tai_utc) in LEAP_SECONDS.iter() {
+        if utc_seconds >= calendar_seconds_past_j2000(*year, *month, *day, 0, 0, 0.0) {
+            offset = *tai_utc;
+        } else {
+            break;
+        }
+    }
+    offset
+}
+
+/// TAI - UTC at a TAI instant given in seconds past J2000.0 TAI
+fn tai_minus_utc_at_tai(tai_seconds: f64) -> f64 {
+    let mut offset = LEAP_SECONDS[0].3;
+    for (year, month, day, tai_utc) in LEAP_SECONDS.iter() {
+        if tai_seconds >= calendar_seconds_past_j2000(*year, *month, *day, 0, 0, 0.0) + *tai_utc {
+            offset = *tai_utc;
+        } else {
+            break;
+        }
+    }
+    offset
+}
+
+/// TDB - TT in seconds at a TT instant
+fn tdb_minus_tt(tt_seconds: f64) -> f64 {
+    let days = tt_seconds / SECONDS_PER_DAY;
+    let g = (357.53 + 0.98560028 * days).to_radians();
+    0.001657 * g.sin() + 0.00001385 * (2.0 * g).sin()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct Epoch {
+    tt_seconds: f64, // seconds past J2000.0 in terrestrial time
+}
+
+impl Epoch {
+    pub const J2000: Epoch = Epoch { tt_seconds: 0.0 };
+
+    pub fn from_seconds_past_j2000(seconds: f64, scale: TimeScale) -> Self {
+        let tt_seconds = match scale {
+            TimeScale::Tt => seconds,
+            TimeScale::Tai => seconds + TT_MINUS_TAI,
+            TimeScale::Utc => seconds + tai_minus_utc_at_utc(seconds) + TT_MINUS_TAI,
+            TimeScale::Tdb => seconds - tdb_minus_tt(seconds), // the periodic term barely changes across the difference
+        };
+        Epoch { tt_seconds }
+    }
+
+    pub fn seconds_past_j2000(&self, scale: TimeScale) -> f64 {
+        match scale {
+            TimeScale::Tt => self.tt_seconds,
+            TimeScale::Tai => self.tt_seconds - TT_MINUS_TAI,
+            TimeScale::Utc => {
+                // UTC seconds can't represent 23:59:60, an instant inside a leap second maps onto the following 00:00:00
+                let tai = self.tt_seconds - TT_MINUS_TAI;
+                tai - tai_minus_utc_at_tai(tai)
+            },
+            TimeScale::Tdb => self.tt_seconds + tdb_minus_tt(self.tt_seconds),
+        }
+    }
+
+    pub fn from_julian_date(julian_date: f64, scale: TimeScale) -> Self {
+        Epoch::from_seconds_past_j2000((julian_date - J2000_JULIAN_DATE) * SECONDS_PER_DAY, scale)
+    }
+
+    pub fn julian_date(&self, scale: TimeScale) -> f64 {
+        J2000_JULIAN_DATE + self.seconds_past_j2000(scale) / SECONDS_PER_DAY
+    }
+
+    pub fn modified_julian_date(&self, scale: TimeScale) -> f64 {
+        self.julian_date(scale) - MJD_OFFSET
+    }
+
+    /// The epoch of a calendar date. Second 60 is only accepted in UTC, at the end of a day ending with a leap second
+    pub fn from_calendar(date: CalendarDate, scale: TimeScale) -> Result<Self, EpochParseError> {
+        let out_of_range = || EpochParseError::OutOfRange(format!("{:?}", date));
+        if !(1..=12).contains(&date.month) || date.day < 1 || date.day > days_in_month(date.year, date.month) || date.hour > 23 || date.minute > 59 {
+            return Err(out_of_range());
+        }
+
+        let leap_second = scale == TimeScale::Utc && date.hour == 23 && date.minute == 59 && ends_with_leap_second(date.year, date.month, date.day);
+        match date.second {
+            second if (0.0..60.0).contains(&second) => (),
+            second if leap_second && (60.0..61.0).contains(&second) => {
+                // UTC seconds past J2000 can't hold 23:59:60, count on from 23:59:59 before TAI - UTC steps up
+                let last_second = calendar_seconds_past_j2000(date.year, date.month, date.day, 23, 59, 59.0);
+                return Ok(Epoch::from_seconds_past_j2000(last_second, scale).offset_by(second - 59.0))
+            },
+            _ => return Err(out_of_range()),
+        }
+
+        let seconds = calendar_seconds_past_j2000(date.year, date.month, date.day, date.hour, date.minute, date.second);
+        Ok(Epoch::from_seconds_past_j2000(seconds, scale))
+    }
+
+    pub fn calendar(&self, scale: TimeScale) -> CalendarDate {
+        let seconds = self.seconds_past_j2000(scale);
+        let days_past_midnight = seconds / SECONDS_PER_DAY + 0.5; // J2000.0 is at noon
+        let day_number = days_past_midnight.floor();
+        let mut second_of_day = seconds - (day_number - 0.5) * SECONDS_PER_DAY;
+        if second_of_day >= SECONDS_PER_DAY {
+            second_of_day -= SECONDS_PER_DAY; // guard against rounding at midnight
+        }
+
+        let (year, month, day) = civil_from_julian_day_number(day_number as i64 + J2000_JULIAN_DATE as i64);
+        let hour = (second_of_day / 3600.0).floor();
+        let minute = ((second_of_day - hour * 3600.0) / 60.0).floor();
+        CalendarDate {
+            year,
+            month,
+            day,
+            hour: hour as u32,
+            minute: minute as u32,
+            second: second_of_day - hour * 3600.0 - minute * 60.0,
+        }
+    }
+
+    /// Formats the epoch as an ISO-8601 timestamp in the given scale, to the millisecond
+    pub fn to_iso8601(&self, scale: TimeScale) -> String {
+        self.to_iso8601_with_precision(scale, 3)
+    }
+
+    /// ISO 8601 timestamp with `decimals` digits of fractional seconds, up to microseconds
+    pub fn to_iso8601_with_precision(&self, scale: TimeScale, decimals: usize) -> String {
+        let decimals = decimals.min(6);
+        let unit = 10f64.powi(decimals as i32);
+
+        // round before breaking down so that 59.9999s doesn't print as 60.000s
+        let rounded = Epoch { tt_seconds: self.tt_seconds + 0.5 / unit };
+        let date = rounded.calendar(scale);
+        let whole = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", date.year, date.month, date.day, date.hour, date.minute, date.second.floor());
+        if decimals == 0 {
+            return whole
+        }
+        let fraction = ((date.second - date.second.floor()) * unit).floor();
+        format!("{}.{:0width$}", whole, fraction, width = decimals)
+    }
+
+    /// The epoch offset by a number of uniform (TT) seconds, such as the elapsed simulation time
+    pub fn offset_by(&self, seconds: f64) -> Epoch {
+        Epoch { tt_seconds: self.tt_seconds + seconds }
+    }
+
+    /// Uniform (TT) seconds from `other` to this epoch
+    pub fn seconds_since(&self, other: &Epoch) -> f64 {
+        self.tt_seconds - other.tt_seconds
+    }
+}
+
+impl FromStr for Epoch {
+    type Err = EpochParseError;
+
+    /// Parses "2021-03-14T12:00:00Z", "2021-03-14 12:00:00.5 TDB", "2021-03-14" or "JD 2451545.0 TT"
+    ///
+    /// Calendar dates without a scale are UTC, julian dates without a scale are TT
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || EpochParseError::Malformed(String::from(s));
+        let s = s.trim();
+
+        let (value, scale) = match s.rsplit_once(char::is_whitespace) {
+            Some((value, scale)) if scale.parse::<TimeScale>().is_ok() => (value.trim(), Some(scale.parse::<TimeScale>()?)),
+            _ => (s, None),
+        };
+
+        if let Some(jd) = value.strip_prefix("JD").or_else(|| value.strip_prefix("jd")) {
+            let jd = jd.trim().parse::<f64>().map_err(|_| malformed())?;
+            return Ok(Epoch::from_julian_date(jd, scale.unwrap_or(TimeScale::Tt)));
+        }
+
+        let (value, scale) = match value.strip_suffix('Z') {
+            Some(value) => (value, scale.or(Some(TimeScale::Utc))),
+            None => (value, scale),
+        };
+
+        let (date, time) = match value.split_once(['T', ' ']) {
+            Some((date, time)) => (date, time.trim()),
+            None => (value, "00:00:00"),
+        };
+
+        let date_fields: Vec<&str> = date.split('-').collect();
+        let time_fields: Vec<&str> = time.split(':').collect();
+        if date_fields.len() != 3 || time_fields.is_empty() || time_fields.len() > 3 {
+            return Err(malformed())
+        }
+
+        let calendar = CalendarDate {
+            year: date_fields[0].parse().map_err(|_| malformed())?,
+            month: date_fields[1].parse().map_err(|_| malformed())?,
+            day: date_fields[2].parse().map_err(|_| malformed())?,
+            hour: time_fields[0].parse().map_err(|_| malformed())?,
+            minute: time_fields.get(1).map(|m| m.parse()).unwrap_or(Ok(0)).map_err(|_| malformed())?,
+            second: time_fields.get(2).map(|s| s.parse()).unwrap_or(Ok(0.0)).map_err(|_| malformed())?,
+        };
+
+        Epoch::from_calendar(calendar, scale.unwrap_or(TimeScale::Utc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn j2000() {
+        let epoch: Epoch = "2000-01-01T12:00:00 TT".parse().unwrap();
+        assert_eq!(epoch, Epoch::J2000);
+        assert_eq!(epoch.julian_date(TimeScale::Tt), J2000_JULIAN_DATE);
+        assert_eq!("JD 2451545.0".parse::<Epoch>().unwrap(), Epoch::J2000);
+        assert_eq!(Epoch::J2000.to_iso8601(TimeScale::Utc), "2000-01-01T11:58:55.816");
+        assert_eq!(Epoch::J2000.to_iso8601(TimeScale::Tai), "2000-01-01T11:59:27.816");
+    }
+
+    #[test]
+    fn leap_seconds() {
+        let before: Epoch = "2016-12-31T23:59:59Z".parse().unwrap();
+        let after: Epoch = "2017-01-01T00:00:00Z".parse().unwrap();
+        assert_eq!(after.seconds_since(&before), 2.0); // 23:59:60 sits between them
+        assert_eq!(after.seconds_past_j2000(TimeScale::Tai) - after.seconds_past_j2000(TimeScale::Utc), 37.0);
+        assert_eq!(after.to_iso8601(TimeScale::Utc), "2017-01-01T00:00:00.000");
+
+        let leap: Epoch = "2016-12-31T23:59:60.5Z".parse().unwrap();
+        assert_eq!(leap.seconds_since(&before), 1.5);
+        assert_eq!(after.seconds_since(&leap), 0.5);
+        assert!("1972-06-30T23:59:60Z".parse::<Epoch>().is_ok());
+
+        // only at the end of a day before a step in the table, and only in UTC
+        assert!(matches!("2015-12-31T23:59:60Z".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!(matches!("2016-12-31T23:58:60Z".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!(matches!("2016-12-31T23:59:60 TAI".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!(matches!("1971-12-31T23:59:60Z".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+    }
+
+    #[test]
+    fn calendar_round_trip() {
+        let epoch: Epoch = "2021-03-14T07:26:53.25 TDB".parse().unwrap();
+        assert_eq!(epoch.to_iso8601(TimeScale::Tdb), "2021-03-14T07:26:53.250");
+        assert!((epoch.seconds_past_j2000(TimeScale::Tdb) - epoch.seconds_past_j2000(TimeScale::Tt)).abs() < 0.002);
+
+        let date = epoch.offset_by(86400.0 * 365.0).calendar(TimeScale::Tdb);
+        assert_eq!((date.year, date.month, date.day), (2022, 3, 14));
+        assert_eq!(Epoch::from_julian_date(2459287.5, TimeScale::Utc).to_iso8601(TimeScale::Utc), "2021-03-14T00:00:00.000");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!("2021-13-01".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!(matches!("2021-02-31".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!(matches!("2021-04-31".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!(matches!("2021-02-29".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!(matches!("1900-02-29".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!(matches!("2021-01-00".parse::<Epoch>(), Err(EpochParseError::OutOfRange(_))));
+        assert!("2020-02-29".parse::<Epoch>().is_ok());
+        assert!("2000-02-29".parse::<Epoch>().is_ok());
+        assert!("2021-12-31".parse::<Epoch>().is_ok());
+        assert!(matches!("yesterday".parse::<Epoch>(), Err(EpochParseError::Malformed(_))));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{math::DVec3, observer::id_after_removal, sim::*};
+
+// Force models
+//
+// Every force acting on the bodies comes from a model registered with the simulation, gravity included. A model
+// declares what its force depends on, which decides the `PhysDynamic` accumulator it is added to and how often the
+// integrators evaluate it:
+//
+//   Independent  evaluated once at the start of each step, from the present state. Thrust, scheduled burns
+//   Spatial      evaluated every time the integrator moves the bodies. Gravity, springs, electrostatics
+//   Velocity     evaluated alongside the spatial models, from the velocities at that point of the step. Drag
+//
+// Models read the frame being integrated and add their forces into a buffer parallel to the targets, so they can
+// never write into another model's accumulator. Bodies exerting a force are read from a separate sources frame, the
+// present frame for the single step integrators and the frame being integrated for the block integrator
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForceDependency {
+    Independent,
+    Spatial,
+    Velocity,
+}
+
+pub trait ForceModel: Send {
+    fn name(&self) -> &str;
+
+    fn dependency(&self) -> ForceDependency;
+
+    /// Adds the force on each body in `targets` to the matching entry of `forces`, in newtons, with the bodies exerting
+    /// it read from `sources`. `frame.sim_time()` is the time at the start of the step being integrated
+    fn accumulate(&self, sources: &PhysicsFrame, frame: &PhysicsFrame, targets: &[usize], forces: &mut [DVec3]);
+
+    /// Called when a body is removed, the ids of the bodies after it shift down by one. Returns false when the model
+    /// no longer applies and should be dropped
+    fn remove_body(&mut self, _id: usize) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Debug for dyn ForceModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ForceModel({})", self.name())
+    }
+}
+
+/// Newtonian gravity from every gravitational body, registered with every new simulation
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gravity;
+
+impl ForceModel for Gravity {
+    fn name(&self) -> &str {
+        "gravity"
+    }
+
+    fn dependency(&self) -> ForceDependency {
+        ForceDependency::Spatial
+    }
+
+    fn accumulate(&self, sources: &PhysicsFrame, frame: &PhysicsFrame, targets: &[usize], forces: &mut [DVec3]) {
+        let sources: Vec<(usize, DVec3, f64)> = (0..sources.kinematic_data().len())
+            .filter_map(|id| sources.get_body_ref(id))
+            .filter(|body| body.physics_category().is_gravitational())
+            .map(|body| (body.id(), body.position(), body.grav_param()))
+            .collect();
+
+        for (&i, force) in targets.iter().zip(forces.iter_mut()) {
+            let body = match frame.get_body_ref(i) {
+                Some(body) => body,
+                None => continue,
+            };
+            let (position, mass) = (body.position(), body.mass());
+            for (k, source_position, grav_param) in sources.iter() {
+                // don't impart forces on yourself
+                if i != *k {
+                    // F = G ((m1 * m2) / r^2)
+                    let r = source_position.length_to(&position);
+                    *force += position.normal_vector_toward(source_position) * ((grav_param * mass) / (r * r));
+                }
+            }
+        }
+    }
+}
+
+/// A constant force on one body from `start` for `duration` seconds of simulation time, for engine burns.
+/// Independent forces are held for a whole step, so burns are resolved to the timestep
+#[derive(Debug, Clone)]
+pub struct Burn {
+    pub name: String,
+    pub body: usize,
+    pub start: f64,
+    pub duration: f64,
+    pub force: DVec3, // newtons
+}
+
+impl ForceModel for Burn {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn dependency(&self) -> ForceDependency {
+        ForceDependency::Independent
+    }
+
+    fn accumulate(&self, _sources: &PhysicsFrame, frame: &PhysicsFrame, targets: &[usize], forces: &mut [DVec3]) {
+        let t = frame.sim_time();
+        if t < self.start || t >= self.start + self.duration {
+            return
+        }
+        for (&i, force) in targets.iter().zip(forces.iter_mut()) {
+            if i == self.body {
+                *force += self.force;
+            }
+        }
+    }
+
+    /// A burn ends with its body
+    fn remove_body(&mut self, id: usize) -> bool {
+        match id_after_removal(self.body, id) {
+            Some(body) => {
+                self.body = body;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A spring of stiffness `k` tying body `a` to body `b`
+    struct Spring { a: usize, b: usize, k: f64 }
+
+    impl ForceModel for Spring {
+        fn name(&self) -> &str { "spring" }
+        fn dependency(&self) -> ForceDependency { ForceDependency::Spatial }
+        fn accumulate(&self, _sources: &PhysicsFrame, frame: &PhysicsFrame, targets: &[usize], forces: &mut [DVec3]) {
+            let (a, b) = (frame.get_body_ref(self.a).unwrap().position(), frame.get_body_ref(self.b).unwrap().position());
+            for (&i, force) in targets.iter().zip(forces.iter_mut()) {
+                if i == self.a { *force += (b - a) * self.k; }
+                if i == self.b { *force += (a - b) * self.k; }
+            }
+        }
+    }
+
+    /// Linear drag, opposing every bodies velocity
+    struct Drag(f64);
+
+    impl ForceModel for Drag {
+        fn name(&self) -> &str { "drag" }
+        fn dependency(&self) -> ForceDependency { ForceDependency::Velocity }
+        fn accumulate(&self, _sources: &PhysicsFrame, frame: &PhysicsFrame, targets: &[usize], forces: &mut [DVec3]) {
+            for (&i, force) in targets.iter().zip(forces.iter_mut()) {
+                *force -= frame.get_body_ref(i).unwrap().velocity() * self.0;
+            }
+        }
+    }
+
+    /// A constant push along x for the first `until` seconds
+    struct Thrust { until: f64 }
+
+    impl ForceModel for Thrust {
+        fn name(&self) -> &str { "thrust" }
+        fn dependency(&self) -> ForceDependency { ForceDependency::Independent }
+        fn accumulate(&self, _sources: &PhysicsFrame, frame: &PhysicsFrame, _targets: &[usize], forces: &mut [DVec3]) {
+            if frame.sim_time() < self.until {
+                for force in forces.iter_mut() {
+                    *force += DVec3::new(1.0, 0.0, 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spring_oscillates_with_its_natural_period() {
+        // two unit masses on a spring of stiffness 1 oscillate about their centre with period 2 pi / sqrt(2)
+        let mut sim = Simulation::new();
+        let a = sim.make_physics_body().with_transform(DVec3::new(-1.0, 0.0, 0.0), None).add();
+        let b = sim.make_physics_body().with_transform(DVec3::new(1.0, 0.0, 0.0), None).add();
+        sim.add_force_model(Box::new(Spring { a, b, k: 1.0 }));
+        sim.set_timestep(1.0e-3);
+
+        let period = 2.0 * std::f64::consts::PI / 2f64.sqrt();
+        sim.run_until(period - 1.0e-4);
+        assert!((sim.present().get_body_ref(b).unwrap().position().x - 1.0).abs() < 1.0e-5);
+        sim.run_until(1.5 * period);
+        assert!((sim.present().get_body_ref(b).unwrap().position().x + 1.0).abs() < 1.0e-2);
+    }
+
+    #[test]
+    fn each_dependency_feeds_its_own_accumulator() {
+        let mut sim = Simulation::new();
+        sim.make_physics_body().add();
+        sim.add_force_model(Box::new(Thrust { until: 10.0 }));
+        sim.add_force_model(Box::new(Drag(0.5)));
+        assert_eq!(sim.force_model_names(), vec!["gravity", "thrust", "drag"]);
+
+        // drag builds until it balances the thrust at 2 m/s, then slows the body once the thrust stops
+        sim.step_n(10);
+        let body = sim.present().get_body_ref(0).unwrap().velocity().x;
+        assert!(body > 1.9 && body < 2.0, "{}", body);
+        sim.step_n(10);
+        assert!(sim.present().get_body_ref(0).unwrap().velocity().x < 0.1);
+
+        assert!(sim.remove_force_model("drag").is_some());
+        assert!(sim.remove_force_model("drag").is_none());
+    }
+
+    #[test]
+    fn burns_follow_their_body_when_another_is_removed() {
+        let mut sim = Simulation::new();
+        for y in [0.0, 10.0, 20.0] {
+            sim.make_physics_body().with_transform(DVec3::new(0.0, y, 0.0), None).add();
+        }
+        sim.add_force_model(Box::new(Burn { name: String::from("burn"), body: 2, start: 0.0, duration: 10.0, force: DVec3::new(1.0, 0.0, 0.0) }));
+        sim.set_timestep(1.0);
+
+        // the burned body is now the second, the first stays put
+        assert!(sim.remove_body(0));
+        sim.step_n(10);
+        assert!((sim.present().get_body_ref(1).unwrap().velocity().x - 10.0).abs() < 1.0e-6);
+        assert!(sim.present().get_body_ref(0).unwrap().velocity().x.abs() < 1.0e-6);
+
+        // and the burn goes with it
+        assert!(sim.remove_body(1));
+        assert_eq!(sim.force_model_names(), vec!["gravity"]);
+    }
+}
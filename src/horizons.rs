@@ -0,0 +1,370 @@
+use std::{error::Error, fmt::Display, path::Path};
+
+use crate::{ephemeris::*, epoch::*, math::DVec3, sim::*, units::*};
+
+// JPL Horizons vector table import
+//
+// Reads the text saved from a Horizons "VECTORS" ephemeris, either the default multi-line record layout or the CSV
+// layout. The header supplies the target and centre names, the output units and the reference frame, and the state
+// records are read from between the $$SOE and $$EOE markers.
+//
+// The simulation looks down on the ecliptic (see the coordinate notes in lib.rs), so tables given in the ICRF or
+// another equatorial frame are rotated into the ecliptic of J2000 when they are read
+
+#[derive(Debug)]
+pub enum HorizonsError {
+    Io(std::io::Error),
+    MissingMarker(&'static str),
+    MissingHeader(&'static str),
+    UnsupportedUnits(String),
+    Malformed { line: usize, message: String },
+    EpochOutOfRange(Epoch),
+}
+
+impl Display for HorizonsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(inner) => write!(f, "failed to read horizons file: {}", inner),
+            Self::MissingMarker(marker) => write!(f, "missing {} marker", marker),
+            Self::MissingHeader(header) => write!(f, "missing '{}' header", header),
+            Self::UnsupportedUnits(units) => write!(f, "unsupported output units: '{}'", units),
+            Self::Malformed { line, message } => write!(f, "line {}: {}", line, message),
+            Self::EpochOutOfRange(epoch) => write!(f, "epoch {} TDB is outside the table", epoch.to_iso8601(TimeScale::Tdb)),
+        }
+    }
+}
+
+impl Error for HorizonsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for HorizonsError {
+    fn from(error: std::io::Error) -> Self {
+        HorizonsError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReferenceFrame {
+    EclipticJ2000,
+    Equatorial(String), // ICRF, FK5/J2000 and friends, all treated as the ICRF
+}
+
+/// The parsed contents of a Horizons vector table
+#[derive(Debug, Clone)]
+pub struct HorizonsVectors {
+    pub target: String,
+    pub center: String,
+    pub source_frame: ReferenceFrame,
+    pub grav_param: Option<GravParam>,
+    pub radius: Option<Length>,
+    pub table: EphemerisTable, // in the ecliptic of J2000, metres and metres per second
+}
+
+impl HorizonsVectors {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, HorizonsError> {
+        let text = std::fs::read_to_string(path)?;
+        HorizonsVectors::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, HorizonsError> {
+        let lines: Vec<&str> = text.lines().collect();
+        let soe = lines.iter().position(|l| l.trim() == "$$SOE").ok_or(HorizonsError::MissingMarker("$$SOE"))?;
+        let eoe = lines.iter().position(|l| l.trim() == "$$EOE").ok_or(HorizonsError::MissingMarker("$$EOE"))?;
+        let header = &lines[..soe];
+
+        let target = header_value(header, "Target body name").map(strip_body_id).ok_or(HorizonsError::MissingHeader("Target body name"))?;
+        let center = header_value(header, "Center body name").map(strip_body_id).ok_or(HorizonsError::MissingHeader("Center body name"))?;
+        let units = header_value(header, "Output units").unwrap_or("KM-S");
+        let (length_scale, time_scale) = match units.split_whitespace().next().unwrap_or("").to_ascii_uppercase().as_str() {
+            "KM-S" => (METERS_PER_KILOMETER, 1.0),
+            "KM-D" => (METERS_PER_KILOMETER, SECONDS_PER_DAY),
+            "AU-D" => (METERS_PER_AU, SECONDS_PER_DAY),
+            _ => return Err(HorizonsError::UnsupportedUnits(String::from(units))),
+        };
+
+        let frame = header_value(header, "Reference frame").or_else(|| header_value(header, "Coordinate systm")).unwrap_or("ICRF");
+        let source_frame = if frame.to_ascii_uppercase().contains("ECLIPTIC") {
+            ReferenceFrame::EclipticJ2000
+        } else {
+            ReferenceFrame::Equatorial(String::from(frame.split_whitespace().next().unwrap_or(frame)))
+        };
+
+        // the time column is JDTDB unless the table says otherwise
+        let scale = if header.iter().any(|l| l.contains("JDUT")) { TimeScale::Utc } else { TimeScale::Tdb };
+
+        let body = &lines[soe + 1..eoe];
+        let raw = if body.iter().any(|l| l.contains(" X =") || l.trim_start().starts_with("X =")) {
+            parse_records(body, soe + 2, scale)?
+        } else {
+            parse_csv_records(body, header, soe + 2, scale)?
+        };
+
+        let records = raw.into_iter().map(|(epoch, position, velocity)| {
+            let (position, velocity) = (position * length_scale, velocity * (length_scale / time_scale));
+            match source_frame {
+                ReferenceFrame::EclipticJ2000 => EphemerisRecord { epoch, position, velocity },
+                ReferenceFrame::Equatorial(_) => EphemerisRecord { epoch, position: equatorial_to_ecliptic(position), velocity: equatorial_to_ecliptic(velocity) },
+            }
+        }).collect();
+
+        Ok(HorizonsVectors {
+            target,
+            center,
+            source_frame,
+            grav_param: physical_value(header, "GM").map(GravParam::from_cubic_kilometers_per_second_squared),
+            radius: physical_value(header, "radius").map(Length::from_kilometers),
+            table: EphemerisTable::new(records),
+        })
+    }
+
+    /// Position and velocity relative to the centre body at `epoch`, interpolating between records as needed
+    pub fn state_at(&self, epoch: Epoch) -> Result<(DVec3, DVec3), HorizonsError> {
+        self.table.state_at(epoch).ok_or(HorizonsError::EpochOutOfRange(epoch))
+    }
+
+    /// Fills in a body builder with the targets name, state at `epoch`, and physical data when the file had any
+    pub fn apply_to<'a>(&self, builder: PhysicsBodyBuilder<'a>, epoch: Epoch) -> Result<PhysicsBodyBuilder<'a>, HorizonsError> {
+        let (position, velocity) = self.state_at(epoch)?;
+        let mut builder = builder
+            .named(&self.target)
+            .with_transform(position, None)
+            .with_velocity(velocity);
+
+        if let Some(grav_param) = self.grav_param {
+            builder = builder
+                .with_grav_param(grav_param.cubic_meters_per_second_squared())
+                .with_mass(grav_param.cubic_meters_per_second_squared() / crate::constants::G);
+        }
+        if let Some(radius) = self.radius {
+            builder = builder.with_bounding_radius(radius.meters() as f32);
+        }
+        Ok(builder)
+    }
+
+    /// Adds the target to the simulation at the simulations epoch, relative to the centre body if it has been added.
+    /// A simulation without an epoch is anchored to the first record of the table
+    pub fn add_to(&self, sim: &mut Simulation, category: PhysicsCategory) -> Result<usize, HorizonsError> {
+        let epoch = match (sim.present().epoch(), self.table.start()) {
+            (Some(epoch), _) => epoch,
+            (None, Some(start)) => {
+                sim.set_epoch(start);
+                start
+            },
+            (None, None) => Epoch::J2000, // an empty table, which has no state at any epoch
+        };
+        let center_id = sim.present().get_named_bodies(&self.center).first().map(|body| body.id());
+
+        let mut builder = self.apply_to(sim.make_physics_body(), epoch)?.with_physics_category(category);
+        if let Some(id) = center_id {
+            builder = builder.relative_to(id);
+        }
+        Ok(builder.add())
+    }
+}
+
+/// The value of a "Key : value" header line
+fn header_value<'a>(header: &[&'a str], key: &str) -> Option<&'a str> {
+    header.iter()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(k, _)| k.trim() == key)
+        .map(|(_, value)| value.split('{').next().unwrap_or(value).trim())
+}
+
+/// Turns "Earth (399)" into "Earth"
+fn strip_body_id(name: &str) -> String {
+    String::from(name.split('(').next().unwrap_or(name).trim())
+}
+
+/// Finds a number in the physical data block, e.g. "GM, km^3/s^2 = 398600.435436" or "Vol. Mean Radius (km) = 6371.01"
+fn physical_value(header: &[&str], key: &str) -> Option<f64> {
+    for line in header.iter() {
+        // physical data is laid out in two columns of "name = value", so each segment between the '=' signs holds a
+        // value followed by the name of the next column
+        let segments: Vec<&str> = line.split('=').collect();
+        for i in 0..segments.len().saturating_sub(1) {
+            let name = match i {
+                0 => segments[0].trim(),
+                _ => segments[i].trim().split_once(char::is_whitespace).map(|(_, rest)| rest).unwrap_or("").trim(),
+            };
+            let matches = match key {
+                "GM" => name.starts_with("GM,") || name == "GM (km^3/s^2)",
+                _ => name.to_ascii_lowercase().contains(key),
+            };
+            if matches {
+                let value = segments[i + 1].split_whitespace().next().unwrap_or("");
+                if let Ok(value) = value.split("+-").next().unwrap_or(value).parse::<f64>() {
+                    return Some(value);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn malformed(line: usize, message: &str) -> HorizonsError {
+    HorizonsError::Malformed { line, message: String::from(message) }
+}
+
+fn finish_record(current: Option<(Epoch, [Option<f64>; 6], usize)>, records: &mut Vec<(Epoch, DVec3, DVec3)>) -> Result<(), HorizonsError> {
+    if let Some((epoch, values, line)) = current {
+        match values {
+            [Some(x), Some(y), Some(z), Some(vx), Some(vy), Some(vz)] => {
+                records.push((epoch, DVec3::new(x, y, z), DVec3::new(vx, vy, vz)));
+            },
+            _ => return Err(malformed(line, "record is missing position or velocity components")),
+        }
+    }
+    Ok(())
+}
+
+/// Parses the default record layout, a "JD = A.D. date" line followed by lines of "KEY = value" pairs
+fn parse_records(body: &[&str], first_line: usize, scale: TimeScale) -> Result<Vec<(Epoch, DVec3, DVec3)>, HorizonsError> {
+    let mut records = Vec::new();
+    let mut current: Option<(Epoch, [Option<f64>; 6], usize)> = None;
+
+    for (i, line) in body.iter().enumerate() {
+        let line_number = first_line + i;
+        if line.contains("A.D.") || line.contains("B.C.") {
+            finish_record(current.take(), &mut records)?;
+            let jd = line.split('=').next().unwrap_or("").trim().parse::<f64>().map_err(|_| malformed(line_number, "expected a julian date"))?;
+            current = Some((Epoch::from_julian_date(jd, scale), [None; 6], line_number));
+            continue;
+        }
+
+        let values = match current.as_mut() {
+            Some((_, values, _)) => values,
+            None => continue,
+        };
+
+        let spaced = line.replace('=', " = ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        for pair in tokens.windows(3).filter(|w| w[1] == "=") {
+            let slot = match pair[0] {
+                "X" => 0, "Y" => 1, "Z" => 2, "VX" => 3, "VY" => 4, "VZ" => 5,
+                _ => continue,
+            };
+            values[slot] = Some(pair[2].parse::<f64>().map_err(|_| malformed(line_number, "expected a number"))?);
+        }
+    }
+    finish_record(current.take(), &mut records)?;
+    Ok(records)
+}
+
+/// Parses the CSV layout, using the column names line in the header to locate each component
+fn parse_csv_records(body: &[&str], header: &[&str], first_line: usize, scale: TimeScale) -> Result<Vec<(Epoch, DVec3, DVec3)>, HorizonsError> {
+    let columns: Vec<String> = header.iter().rev()
+        .find(|line| line.contains("JDTDB") || line.contains("JDUT"))
+        .ok_or(HorizonsError::MissingHeader("JDTDB"))?
+        .split(',')
+        .map(|c| c.trim().to_ascii_uppercase())
+        .collect();
+
+    let column = |name: &'static str| columns.iter().position(|c| c == name).ok_or(HorizonsError::MissingHeader(name));
+    let indices = [column("X")?, column("Y")?, column("Z")?, column("VX")?, column("VY")?, column("VZ")?];
+
+    let mut records = Vec::new();
+    for (i, line) in body.iter().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let line_number = first_line + i;
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let number = |index: usize| -> Result<f64, HorizonsError> {
+            fields.get(index).and_then(|f| f.parse::<f64>().ok()).ok_or_else(|| malformed(line_number, "expected a number"))
+        };
+
+        let epoch = Epoch::from_julian_date(number(0)?, scale);
+        let position = DVec3::new(number(indices[0])?, number(indices[1])?, number(indices[2])?);
+        let velocity = DVec3::new(number(indices[3])?, number(indices[4])?, number(indices[5])?);
+        records.push((epoch, position, velocity));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VECTORS: &str = "
+*******************************************************************************
+ Revised: April 12, 2021                 Earth                              399
+ Vol. Mean Radius (km)    = 6371.01+-0.02   Mass x10^24 (kg)= 5.97219+-0.0006
+ GM, km^3/s^2             = 398600.435436   Equ. radius, km = 6378.137
+*******************************************************************************
+Ephemeris / WWW_USER Fri Mar 19 12:00:00 2021 Pasadena, USA      / Horizons
+*******************************************************************************
+Target body name: Earth (399)                     {source: DE441}
+Center body name: Sun (10)                        {source: DE441}
+Center-site name: BODY CENTER
+*******************************************************************************
+Output units    : KM-S
+Reference frame : Ecliptic of J2000.0
+*******************************************************************************
+$$SOE
+2451544.500000000 = A.D. 2000-Jan-01 00:00:00.0000 TDB
+ X =-2.521092855899356E+07 Y = 1.449279195838006E+08 Z =-6.164165719002485E+02
+ VX=-2.983983333368269E+01 VY=-5.207633918704476E+00 VZ= 6.169236959167168E-05
+2451545.500000000 = A.D. 2000-Jan-02 00:00:00.0000 TDB
+ X =-2.778811619550782E+07 Y = 1.444503536065063E+08 Z =-6.109325017541647E+02
+ VX=-2.982472107896220E+01 VY=-5.848893648703380E+00 VZ= 6.530040025466127E-05
+$$EOE
+";
+
+    #[test]
+    fn parse_vectors() {
+        let vectors = HorizonsVectors::parse(VECTORS).unwrap();
+        assert_eq!(vectors.target, "Earth");
+        assert_eq!(vectors.center, "Sun");
+        assert_eq!(vectors.source_frame, ReferenceFrame::EclipticJ2000);
+        assert_eq!(vectors.grav_param, Some(GravParam::from_cubic_kilometers_per_second_squared(398600.435436)));
+        assert_eq!(vectors.radius, Some(Length::from_kilometers(6371.01)));
+        assert_eq!(vectors.table.records().len(), 2);
+        assert_eq!(vectors.table.records()[0].position.x, -2.521092855899356E+10);
+        assert!((vectors.table.records()[1].velocity.y + 5.84889364870338E+03).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parse_csv_vectors() {
+        let csv = "
+Target body name: Moon (301)
+Center body name: Earth (399)
+Output units    : AU-D
+Reference frame : ICRF
+            JDTDB,            Calendar Date (TDB),                      X,                      Y,                      Z,                     VX,                     VY,                     VZ,
+$$SOE
+2451545.000000000, A.D. 2000-Jan-01 12:00:00.0000,  1.0E+00,  0.0E+00,  0.0E+00,  0.0E+00,  1.0E+00,  0.0E+00,
+$$EOE
+";
+        let vectors = HorizonsVectors::parse(csv).unwrap();
+        assert_eq!(vectors.target, "Moon");
+        let (position, velocity) = vectors.state_at(Epoch::from_julian_date(2451545.0, TimeScale::Tdb)).unwrap();
+        assert_eq!(position.x, METERS_PER_AU);
+        // the ICRF y axis tips out of the ecliptic by the obliquity
+        assert!((velocity.z / velocity.magnitude() + OBLIQUITY_J2000.to_radians().sin()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn interpolate_and_build() {
+        let vectors = HorizonsVectors::parse(VECTORS).unwrap();
+        let midday: Epoch = "2000-01-01T12:00:00 TDB".parse().unwrap();
+        let (position, _) = vectors.state_at(midday).unwrap();
+        assert!(position.x < -2.521092855899356E+10 && position.x > -2.778811619550782E+10);
+        assert!(matches!(vectors.state_at(Epoch::from_julian_date(2451600.0, TimeScale::Tdb)), Err(HorizonsError::EpochOutOfRange(_))));
+
+        let mut sim = Simulation::new();
+        sim.set_epoch(midday);
+        let sun = sim.make_physics_body().named("Sun").with_physics_category(PhysicsCategory::Gravitational).add();
+        let earth = vectors.add_to(&mut sim, PhysicsCategory::Gravitational).unwrap();
+        assert_ne!(sun, earth);
+        assert_eq!(sim.present().get_named_bodies("earth")[0].position(), position);
+
+        // without an epoch the simulation starts at the first record rather than wherever J2000 falls in the table
+        let mut sim = Simulation::new();
+        vectors.add_to(&mut sim, PhysicsCategory::Gravitational).unwrap();
+        assert_eq!(sim.present().epoch(), vectors.table.start());
+        assert_eq!(sim.present().get_named_bodies("earth")[0].position().x, -2.521092855899356E+10);
+    }
+}
@@ -4,6 +4,27 @@ pub mod sim;
 pub mod output;
 pub mod cli;
 pub mod constants;
+pub mod units;
+pub mod epoch;
+pub mod ephemeris;
+pub mod horizons;
+pub mod tle;
+pub mod sgp4;
+pub mod oem;
+pub mod trajectory;
+pub mod plot;
+pub mod svg;
+pub mod render;
+pub mod telemetry;
+pub mod pacing;
+pub mod observer;
+pub mod forces;
+pub mod threads;
+pub mod ensemble;
+pub mod sweep;
+pub mod verify;
+pub mod scenario;
+pub mod repl;
 pub mod identity;
 pub mod collections;
 pub mod systems;
@@ -8,16 +8,60 @@
 // -mul, mulassign
 // -div, divassign
 
-/// Single precsion 3D vector
-#[derive(Debug, Clone, Copy, Default)]
-pub struct SVec3 {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32
+use std::ops::{ Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg };
+
+/// A floating point type which can be used as the component of a vector
+///
+/// Implemented for `f32` and `f64` so that physics data can pick the precision it needs
+pub trait Scalar: Copy + Default + PartialOrd + std::fmt::Debug
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self>
+    + AddAssign + SubAssign + MulAssign + DivAssign
+{
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn sqrt(self) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn from_f64(n: f64) -> Self;
+    fn to_f64(self) -> f64;
+}
+
+impl Scalar for f32 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+
+    fn sqrt(self) -> Self { f32::sqrt(self) }
+    fn powf(self, n: Self) -> Self { f32::powf(self, n) }
+    fn from_f64(n: f64) -> Self { n as f32 }
+    fn to_f64(self) -> f64 { self as f64 }
+}
+
+impl Scalar for f64 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+
+    fn sqrt(self) -> Self { f64::sqrt(self) }
+    fn powf(self, n: Self) -> Self { f64::powf(self, n) }
+    fn from_f64(n: f64) -> Self { n }
+    fn to_f64(self) -> f64 { self }
+}
+
+/// 3D vector, generic over the precision of its components
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Vec3<T> {
+    pub x: T,
+    pub y: T,
+    pub z: T
 }
 
-impl SVec3 {
-    pub fn new(x: f32, y: f32, z: f32) -> Self {
+/// Single precsion 3D vector
+pub type SVec3 = Vec3<f32>;
+
+/// Double precision 3D vector
+pub type DVec3 = Vec3<f64>;
+
+impl<T: Scalar> Vec3<T> {
+    pub fn new(x: T, y: T, z: T) -> Self {
         Self {
             x: x,
             y: y,
@@ -26,14 +70,14 @@ impl SVec3 {
     }
     
     pub const fn zero() -> Self {
-        Self { x: 0.0, y: 0.0, z: 0.0 }
+        Self { x: T::ZERO, y: T::ZERO, z: T::ZERO }
     }
 
-    pub fn sum(&self) -> f32 {
+    pub fn sum(&self) -> T {
         self.x + self.y + self.z
     }
 
-    pub fn dot(&self, rhs: &Self) -> f32 {
+    pub fn dot(&self, rhs: &Self) -> T {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 
@@ -45,15 +89,15 @@ impl SVec3 {
         }
     }
 
-    pub fn length_to(&self, rhs: &Self) -> f32 {
+    pub fn length_to(&self, rhs: &Self) -> T {
         // ((x2 - x1)2 + (y2 - y1)2 + (z2 - z1)2)1/2
         ( (self.x - rhs.x) * (self.x - rhs.x)
         + (self.y - rhs.y) * (self.y - rhs.y)
         + (self.z - rhs.z) * (self.z - rhs.z)
-        ).powf(0.5)
+        ).sqrt()
     }
     
-    pub fn magnitude(&self) -> f32 {
+    pub fn magnitude(&self) -> T {
         self.dot(&self).sqrt()
     }
 
@@ -61,6 +105,22 @@ impl SVec3 {
         let m = self.magnitude();
         Self { x: self.x / m, y: self.y / m, z: self.z / m }
     }
+
+    pub fn normal_vector_toward(&self, rhs: &Self) -> Self {
+        (rhs - self).normalize()
+    }
+
+    /// Converts the vector to another precision
+    pub fn cast<U: Scalar>(&self) -> Vec3<U> {
+        Vec3 { x: U::from_f64(self.x.to_f64()), y: U::from_f64(self.y.to_f64()), z: U::from_f64(self.z.to_f64()) }
+    }
+}
+
+impl DVec3 {
+    pub fn rotate_by(&self, rotation: &Quat) -> Self {
+        let result: Quat = *rotation * Quat { w: 0.0, v: self.into() } * rotation.inverse_unit();
+        result.v.into()
+    }
 }
 
 impl std::convert::From<DVec3> for SVec3 {
@@ -75,216 +135,122 @@ impl std::convert::From<&DVec3> for SVec3 {
     }
 }
 
-impl std::ops::Index<usize> for &SVec3 {
-    type Output = f32;
+impl std::convert::From<SVec3> for DVec3 {
+    fn from(svec: SVec3) -> Self {
+        Self { x: svec.x as f64, y: svec.y as f64, z: svec.z as f64 }
+    }
+}
+
+impl std::convert::From<&SVec3> for DVec3 {
+    fn from(svec: &SVec3) -> Self {
+        Self { x: svec.x as f64, y: svec.y as f64, z: svec.z as f64 }
+    }
+}
+
+impl<T: Scalar> std::convert::From<(T, T, T)> for Vec3<T> {
+    fn from(tuple: (T, T, T)) -> Self {
+        Self { x: tuple.0, y: tuple.1, z: tuple.2 }
+    }
+}
+
+impl<T: Scalar> std::ops::Index<usize> for &Vec3<T> {
+    type Output = T;
     fn index(&self, index: usize) -> &Self::Output {
         match index {
             0 => &self.x,
             1 => &self.y,
             2 => &self.z,
-            _ => panic!("SVec3::index out of bounds")
+            _ => panic!("Vec3::index out of bounds")
         }
     }
 }
 
-impl std::ops::Add for SVec3 {
+impl<T: Scalar> std::ops::Add for Vec3<T> {
     type Output = Self;
     fn add(self, rhs: Self) -> Self::Output {
         Self::Output { x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z }
     }
 }
 
-impl std::ops::AddAssign for SVec3 {
+impl std::ops::Add<SVec3> for DVec3 {
+    type Output = Self;
+    fn add(self, rhs: SVec3) -> Self {
+        Self { x: self.x + rhs.x as f64, y: self.y + rhs.y as f64, z: self.z + rhs.z as f64 }
+    }
+}
+
+impl<T: Scalar> std::ops::AddAssign for Vec3<T> {
     fn add_assign(&mut self, rhs: Self) {
         *self = Self { x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z }
     }
 }
 
-impl std::ops::Sub for SVec3 {
+impl<T: Scalar> std::ops::Sub for Vec3<T> {
     type Output = Self;
     fn sub(self, rhs: Self) -> Self::Output {
         Self::Output { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z }
     }
 }
 
-impl std::ops::Mul<f32> for SVec3 {
-    type Output = Self;
-    fn mul(self, rhs: f32) -> Self::Output {
-        let rhs = rhs as f32;
-        Self::Output { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs }
+impl<'a, 'b, T: Scalar> std::ops::Sub<&'b Vec3<T>> for &'a Vec3<T> {
+    type Output = Vec3<T>;
+    fn sub(self, rhs: &'b Vec3<T>) -> Vec3<T> {
+        Vec3 { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z }
     }
 }
 
-impl std::ops::Mul<f64> for SVec3 {
-    type Output = Self;
-    fn mul(self, rhs: f64) -> Self::Output {
-        let rhs = rhs as f32;
-        Self::Output { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs }
-    }
-}
-
-impl std::ops::Mul<SVec3> for f64 {
-    type Output = SVec3;
-    fn mul(self, rhs: SVec3) -> Self::Output {
-        let scalar = self as f32;
-        Self::Output { x: scalar * rhs.x, y: scalar * rhs.y, z: scalar * rhs.z }
+impl<T: Scalar> std::ops::SubAssign for Vec3<T> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = Self { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z }
     }
 }
 
-impl std::ops::Div<f32> for SVec3 {
+impl<T: Scalar> std::ops::Neg for Vec3<T> {
     type Output = Self;
-    fn div(self, rhs: f32) -> Self::Output {
-        Self::Output { x: self.x / rhs, y: self.y / rhs, z: self.z / rhs }
+    fn neg(self) -> Self::Output {
+        Self::Output { x: -self.x, y: -self.y, z: -self.z }
     }
 }
 
-impl std::ops::Div<f64> for SVec3 {
+impl<T: Scalar> std::ops::Mul<T> for Vec3<T> {
     type Output = Self;
-    fn div(self, rhs: f64) -> Self::Output {
-        let rhs = rhs as f32;
-        Self::Output { x: self.x / rhs, y: self.y / rhs, z: self.z / rhs }
-    }
-}
-
-/// Double precision 3D vector
-#[derive(Debug, Clone, Copy, Default)]
-pub struct DVec3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64
-}
-
-impl DVec3 {
-    pub fn new(x: f64, y: f64, z: f64) -> DVec3 {
-        DVec3 {
-            x: x,
-            y: y,
-            z: z
-        }
-    }
-    
-    pub const fn zero() -> DVec3 {
-        DVec3 { x: 0.0, y: 0.0, z: 0.0 }
-    }
-
-    pub fn sum(&self) -> f64 {
-        self.x + self.y + self.z
-    }
-
-    pub fn dot(&self, rhs: &Self) -> f64 {
-        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
-    }
-
-    pub fn cross(&self, rhs: &Self) -> DVec3 {
-        DVec3 { 
-            x: (self.y * rhs.z) - (self.z * rhs.y),
-            y: (self.z * rhs.x) - (self.x * rhs.z),
-            z: (self.x * rhs.y) - (self.y * rhs.x)
-        }
-    }
-
-    pub fn length_to(&self, rhs: &Self) -> f64 {
-        // ((x2 - x1)2 + (y2 - y1)2 + (z2 - z1)2)1/2
-        ( (self.x - rhs.x) * (self.x - rhs.x)
-        + (self.y - rhs.y) * (self.y - rhs.y)
-        + (self.z - rhs.z) * (self.z - rhs.z)
-        ).powf(0.5)
-    }
-
-    pub fn magnitude(&self) -> f64 {
-        self.dot(&self).sqrt()
-    }
-
-    pub fn normalize(&self) -> Self {
-        let m = self.magnitude();
-        DVec3 { x: self.x / m, y: self.y / m, z: self.z / m }
-    }
-
-    pub fn normal_vector_toward(&self, rhs: &DVec3) -> DVec3 {
-        (rhs - self).normalize()
-    }
-
-    pub fn rotate_by(&self, rotation: &Quat) -> Self {
-        let result: Quat = *rotation * Quat { w: 0.0, v: self.into() } * rotation.inverse_unit();
-        result.v.into()
-    }
-}
-
-impl std::convert::From<SVec3> for DVec3 {
-    fn from(svec: SVec3) -> Self {
-        Self { x: svec.x as f64, y: svec.y as f64, z: svec.z as f64 }
-    }
-}
-
-impl std::convert::From<&SVec3> for DVec3 {
-    fn from(svec: &SVec3) -> Self {
-        Self { x: svec.x as f64, y: svec.y as f64, z: svec.z as f64 }
-    }
-}
-
-impl std::convert::From<(f64, f64, f64)> for DVec3 {
-    fn from(tuple: (f64, f64, f64)) -> DVec3 {
-        DVec3 { x: tuple.0, y: tuple.1, z: tuple.2 }
-    }
-}
-
-impl std::ops::Index<usize> for &DVec3 {
-    type Output = f64;
-    fn index(&self, index: usize) -> &Self::Output {
-        match index {
-            0 => &self.x,
-            1 => &self.y,
-            2 => &self.z,
-            _ => panic!("SVec3::index out of bounds")
-        }
+    fn mul(self, rhs: T) -> Self::Output {
+        Self::Output { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs }
     }
 }
 
-impl std::ops::Mul<f64> for DVec3 {
+impl std::ops::Mul<f64> for SVec3 {
     type Output = Self;
     fn mul(self, rhs: f64) -> Self::Output {
+        let rhs = rhs as f32;
         Self::Output { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs }
     }
 }
 
-impl std::ops::Div<f64> for DVec3 {
-    type Output = Self;
-    fn div(self, rhs: f64) -> Self {
-        Self { x: self.x / rhs, y: self.y / rhs, z: self.z / rhs }
-    }
-}
-
-impl std::ops::Sub for DVec3 {
-    type Output = DVec3;
-    fn sub(self, rhs: Self) -> Self {
-        DVec3 { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z }
+impl<T: Scalar> std::ops::MulAssign<T> for Vec3<T> {
+    fn mul_assign(&mut self, rhs: T) {
+        *self = Self { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs }
     }
 }
 
-impl<'a, 'b> std::ops::Sub<&'b DVec3> for &'a DVec3 {
-    type Output = DVec3;
-    fn sub(self, rhs: &'b DVec3) -> DVec3 {
-        DVec3 { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z }
-    }
-}
-
-impl std::ops::Add for DVec3 {
+impl<T: Scalar> std::ops::Div<T> for Vec3<T> {
     type Output = Self;
-    fn add(self, rhs: Self) -> Self {
-        Self { x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z }
+    fn div(self, rhs: T) -> Self::Output {
+        Self::Output { x: self.x / rhs, y: self.y / rhs, z: self.z / rhs }
     }
 }
 
-impl std::ops::Add<SVec3> for DVec3 {
+impl std::ops::Div<f64> for SVec3 {
     type Output = Self;
-    fn add(self, rhs: SVec3) -> Self {
-        Self { x: self.x + rhs.x as f64, y: self.y + rhs.y as f64, z: self.z + rhs.z as f64 }
+    fn div(self, rhs: f64) -> Self::Output {
+        let rhs = rhs as f32;
+        Self::Output { x: self.x / rhs, y: self.y / rhs, z: self.z / rhs }
     }
 }
 
-impl std::ops::AddAssign for DVec3 {
-    fn add_assign(&mut self, rhs: Self) {
-        *self = Self { x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z }
+impl<T: Scalar> std::ops::DivAssign<T> for Vec3<T> {
+    fn div_assign(&mut self, rhs: T) {
+        *self = Self { x: self.x / rhs, y: self.y / rhs, z: self.z / rhs }
     }
 }
 
@@ -447,9 +413,107 @@ impl std::ops::Mul<DVec3> for f64 {
     }
 }
 
+impl std::ops::Mul<SVec3> for f64 {
+    type Output = SVec3;
+    fn mul(self, rhs: SVec3) -> Self::Output {
+        let scalar = self as f32;
+        Self::Output { x: scalar * rhs.x, y: scalar * rhs.y, z: scalar * rhs.z }
+    }
+}
+
 impl std::ops::Mul<SVec3> for f32 {
     type Output = SVec3;
     fn mul(self, rhs: Self::Output) -> Self::Output {
         Self::Output { x: self * rhs.x, y: self * rhs.y, z: self * rhs.z }
     }
 }
+
+// Two-body propagation
+
+/// The state `t` seconds on of a body moving from `position` and `velocity` under a point mass of gravitational
+/// parameter `mu` alone, by the universal variable formulation. Exact for elliptic, parabolic and hyperbolic orbits,
+/// and `t` may be negative
+pub fn propagate_kepler(position: DVec3, velocity: DVec3, mu: f64, t: f64) -> (DVec3, DVec3) {
+    let sqrt_mu = mu.sqrt();
+    let r0 = position.magnitude();
+    let rv = position.dot(&velocity) / sqrt_mu; // r0 . v0 / sqrt(mu)
+    let alpha = 2.0 / r0 - velocity.dot(&velocity) / mu; // reciprocal of the semi-major axis
+
+    // starting guesses for the universal anomaly from Vallado, Fundamentals of Astrodynamics and Applications
+    let mut chi = if alpha * r0 > 1.0e-9 {
+        sqrt_mu * t * alpha
+    } else if alpha * r0 < -1.0e-9 {
+        let a = 1.0 / alpha;
+        let sign = t.signum();
+        let guess = sign * (-a).sqrt() * ((-2.0 * mu * alpha * t) / (position.dot(&velocity) + sign * (-mu * a).sqrt() * (1.0 - r0 * alpha))).ln();
+        if guess.is_finite() { guess } else { sqrt_mu * t / r0 }
+    } else {
+        let p = position.cross(&velocity).dot(&position.cross(&velocity)) / mu;
+        let s = 0.5 * (std::f64::consts::FRAC_PI_2 - (3.0 * (mu / (p * p * p)).sqrt() * t).atan());
+        let w = s.tan().cbrt().atan();
+        p.sqrt() * 2.0 / (2.0 * w).tan()
+    };
+
+    // newton iteration on the universal Kepler equation
+    for _ in 0..100 {
+        let z = alpha * chi * chi;
+        let (c, s) = (stumpff_c(z), stumpff_s(z));
+        let f = rv * chi * chi * c + (1.0 - alpha * r0) * chi * chi * chi * s + r0 * chi - sqrt_mu * t;
+        let df = rv * chi * (1.0 - z * s) + (1.0 - alpha * r0) * chi * chi * c + r0;
+        let step = f / df;
+        chi -= step;
+        if step.abs() <= 1.0e-14 * chi.abs().max(1.0) {
+            break
+        }
+    }
+
+    // lagrange coefficients
+    let z = alpha * chi * chi;
+    let (c, s) = (stumpff_c(z), stumpff_s(z));
+    let f = 1.0 - chi * chi / r0 * c;
+    let g = t - chi * chi * chi / sqrt_mu * s;
+    let new_position = position * f + velocity * g;
+    let r = new_position.magnitude();
+    let f_dot = sqrt_mu / (r * r0) * (z * s - 1.0) * chi;
+    let g_dot = 1.0 - chi * chi / r * c;
+    (new_position, position * f_dot + velocity * g_dot)
+}
+
+/// Stumpff function C(z) = (1 - cos sqrt z) / z, by its series near zero where the closed form cancels
+fn stumpff_c(z: f64) -> f64 {
+    if z > 1.0 {
+        (1.0 - z.sqrt().cos()) / z
+    } else if z < -1.0 {
+        ((-z).sqrt().cosh() - 1.0) / -z
+    } else {
+        stumpff_series(z, 2)
+    }
+}
+
+/// Stumpff function S(z) = (sqrt z - sin sqrt z) / sqrt z^3
+fn stumpff_s(z: f64) -> f64 {
+    if z > 1.0 {
+        let root = z.sqrt();
+        (root - root.sin()) / (root * root * root)
+    } else if z < -1.0 {
+        let root = (-z).sqrt();
+        (root.sinh() - root) / (root * root * root)
+    } else {
+        stumpff_series(z, 3)
+    }
+}
+
+/// The sum over k of (-z)^k / (2k + first)!
+fn stumpff_series(z: f64, first: u32) -> f64 {
+    let mut term = 1.0 / (1..=first).map(f64::from).product::<f64>();
+    let mut sum = term;
+    for k in 1..30 {
+        let n = f64::from(2 * k + first);
+        term *= -z / ((n - 1.0) * n);
+        sum += term;
+        if term.abs() < 1.0e-17 * sum.abs() {
+            break
+        }
+    }
+    sum
+}
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{debug::MemUse, sim::*};
+
+// Observers
+//
+// Anything that wants to watch a simulation step by step registers an observer, which is passed every committed frame
+// and the events raised by it. Observers run on the thread driving the simulation, they must be Send so that a
+// simulation can be moved to a background thread along with them. Results are read out through whatever the observer
+// shares with its creator, a channel or an Arc<Mutex<_>>
+//
+// Events are only detected while at least one observer is registered:
+//
+//   Collision   the bounding spheres of two bodies start to overlap, reported once until they separate again
+//   SoiChange   a body moves into the sphere of influence of a different gravitational body, the smallest sphere of
+//               influence containing a body is the one it is in. The heaviest body's sphere is unbounded
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimEvent {
+    Collision { bodies: (usize, usize), relative_speed: f64 },
+    SoiChange { body: usize, from: Option<usize>, to: Option<usize> },
+}
+
+pub trait Observer: Send {
+    /// Called with every committed frame
+    fn frame(&mut self, sim: &Simulation);
+
+    /// Called with each event raised by a frame, before `frame`
+    fn event(&mut self, _sim: &Simulation, _event: &SimEvent) {}
+
+    /// Called once when a run finishes
+    fn finish(&mut self, _sim: &Simulation) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether frames may be dropped when a paced simulation falls behind, for output that only shows the present
+    fn may_skip_frames(&self) -> bool {
+        false
+    }
+
+    /// Heap memory held by the observer, for `MemUse`
+    fn heap_use(&self) -> usize {
+        0
+    }
+
+    /// Called when a body is removed, the ids of the bodies after it shift down by one
+    fn remove_body(&mut self, _id: usize) {}
+}
+
+/// The id of the body `id` once the body `removed` is gone, None for the removed body itself
+pub fn id_after_removal(id: usize, removed: usize) -> Option<usize> {
+    match id {
+        id if id == removed => None,
+        id if id > removed => Some(id - 1),
+        id => Some(id),
+    }
+}
+
+/// Moves a focus and the focus relative trails of the bodies past the removal of the body `removed`. The trail of the
+/// removed body is dropped, and every trail when it was the focus since they were relative to it
+pub fn trails_after_removal<T>(focus: &mut Option<usize>, trails: &mut HashMap<usize, T>, removed: usize) {
+    if *focus == Some(removed) {
+        trails.clear();
+    }
+    *focus = focus.and_then(|focus| id_after_removal(focus, removed));
+    *trails = trails.drain().filter_map(|(body, trail)| Some((id_after_removal(body, removed)?, trail))).collect();
+}
+
+impl std::fmt::Debug for dyn Observer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Observer")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EventDetector {
+    touching: HashSet<(usize, usize)>, // pairs of bodies whose bounding spheres overlapped in the last frame
+    soi: Vec<Option<usize>>, // the body whose sphere of influence each body was in during the last frame
+}
+
+impl EventDetector {
+    /// The events raised by moving from the previously detected frame to `frame`
+    pub fn detect(&mut self, frame: &PhysicsFrame) -> Vec<SimEvent> {
+        let bodies: Vec<PhysicsBodyRef> = (0..frame.kinematic_data().len()).filter_map(|id| frame.get_body_ref(id)).collect();
+        let mut events = Vec::new();
+
+        let mut touching = HashSet::new();
+        for (i, a) in bodies.iter().enumerate() {
+            for b in bodies[i + 1..].iter() {
+                if a.bounding_radius() + b.bounding_radius() > 0.0 && a.bounding_distance_to(b) < 0.0 {
+                    touching.insert((a.id(), b.id()));
+                    if !self.touching.contains(&(a.id(), b.id())) {
+                        events.push(SimEvent::Collision { bodies: (a.id(), b.id()), relative_speed: (a.velocity() - b.velocity()).magnitude() });
+                    }
+                }
+            }
+        }
+        self.touching = touching;
+
+        let spheres = spheres_of_influence(&bodies);
+        let soi: Vec<Option<usize>> = bodies.iter().map(|body| {
+            spheres.iter()
+                .filter(|(id, radius)| *id != body.id() && body.centers_distance_to(&bodies[*id]) < *radius)
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|(id, _)| *id)
+        }).collect();
+        // bodies new since the last frame start out where they are without an event
+        for (body, (previous, current)) in self.soi.iter().zip(soi.iter()).enumerate() {
+            if previous != current {
+                events.push(SimEvent::SoiChange { body, from: *previous, to: *current });
+            }
+        }
+        self.soi = soi;
+
+        events
+    }
+
+    /// Forgets a removed body, the ids of the bodies after it shift down by one
+    pub fn remove_body(&mut self, id: usize) {
+        self.touching = self.touching.iter()
+            .filter_map(|(a, b)| Some((id_after_removal(*a, id)?, id_after_removal(*b, id)?)))
+            .collect();
+        if id < self.soi.len() {
+            self.soi.remove(id);
+        }
+        for soi in self.soi.iter_mut() {
+            *soi = soi.and_then(|other| id_after_removal(other, id));
+        }
+    }
+}
+
+/// The sphere of influence radius of each gravitational body, relative to the heavier body pulling on it hardest
+fn spheres_of_influence(bodies: &[PhysicsBodyRef]) -> Vec<(usize, f64)> {
+    let gravitational: Vec<&PhysicsBodyRef> = bodies.iter().filter(|body| body.physics_category().is_gravitational()).collect();
+    gravitational.iter().map(|body| {
+        let primary = gravitational.iter()
+            .filter(|other| other.mass() > body.mass())
+            .map(|other| (other, other.mass() / body.centers_distance_to(other).powi(2)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        let radius = match primary {
+            Some((primary, _)) => body.centers_distance_to(primary) * (body.mass() / primary.mass()).powf(0.4),
+            None => f64::INFINITY,
+        };
+        (body.id(), radius)
+    }).collect()
+}
+
+impl MemUse for EventDetector {
+    fn heap_use(&self) -> usize {
+        self.touching.capacity() * std::mem::size_of::<(usize, usize)>() + self.soi.heap_use()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{constants::*, math::DVec3};
+    use std::sync::{Arc, Mutex};
+
+    /// Records every event it sees into a shared list
+    struct EventLog(Arc<Mutex<Vec<SimEvent>>>);
+
+    impl Observer for EventLog {
+        fn frame(&mut self, _sim: &Simulation) {}
+
+        fn event(&mut self, _sim: &Simulation, event: &SimEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn collisions_are_reported_once() {
+        let mut sim = Simulation::new();
+        sim.make_physics_body().with_bounding_radius(1.0).add();
+        sim.make_physics_body().with_bounding_radius(1.0).with_transform(DVec3::new(10.0, 0.0, 0.0), None)
+            .with_velocity(DVec3::new(-1.0, 0.0, 0.0)).add();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        sim.add_observer(Box::new(EventLog(log.clone())));
+
+        sim.step_n(20); // overlapping from about 8 s until they pass through each other around 12 s
+        let events = log.lock().unwrap().clone();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], SimEvent::Collision { bodies: (0, 1), .. }));
+    }
+
+    #[test]
+    fn probe_escapes_the_earth() {
+        let mut sim = Simulation::new();
+        let sol = sim.make_physics_body().named("Sol").with_physics_category(PhysicsCategory::Gravitational)
+            .with_mass(SOL_MASS.kilograms()).with_grav_param(SOL_GRAV_PARAM.cubic_meters_per_second_squared()).add();
+        let earth = sim.make_physics_body().named("Earth").with_physics_category(PhysicsCategory::Gravitational)
+            .with_transform(DVec3::new(EARTH_DIST_TO_SOL.meters(), 0.0, 0.0), None)
+            .with_velocity(DVec3::new(0.0, EARTH_SOL_ORBIT_VEL.meters_per_second(), 0.0))
+            .with_mass(EARTH_MASS.kilograms()).with_grav_param(EARTH_GRAV_PARAM.cubic_meters_per_second_squared()).relative_to(sol).add();
+        // well over escape speed from 100 000 km, so it leaves the earths 925 000 km sphere within a few days
+        let probe = sim.make_physics_body().with_transform(DVec3::new(1.0e8, 0.0, 0.0), None)
+            .with_velocity(DVec3::new(5000.0, 0.0, 0.0)).relative_to(earth).add();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        sim.add_observer(Box::new(EventLog(log.clone())));
+
+        sim.set_timestep(600.0);
+        sim.run_until(5.0 * 86400.0);
+        let events = log.lock().unwrap().clone();
+        assert_eq!(events, vec![SimEvent::SoiChange { body: probe, from: Some(earth), to: Some(sol) }]);
+    }
+    #[test]
+    fn trails_follow_removed_bodies() {
+        let mut trails: HashMap<usize, &str> = vec![(0, "sun"), (1, "earth"), (2, "moon")].into_iter().collect();
+        let mut focus = Some(0);
+        trails_after_removal(&mut focus, &mut trails, 1);
+        assert_eq!(focus, Some(0));
+        assert_eq!(trails, vec![(0, "sun"), (1, "moon")].into_iter().collect());
+
+        // the trails were relative to the focus, so they all go with it
+        trails_after_removal(&mut focus, &mut trails, 0);
+        assert_eq!(focus, None);
+        assert!(trails.is_empty());
+    }
+}
@@ -0,0 +1,443 @@
+use std::{error::Error, fmt::Display, io::Write, path::{Path, PathBuf}};
+
+use crate::{debug::MemUse, ephemeris::*, epoch::*, horizons::ReferenceFrame, math::DVec3};
+
+// CCSDS Orbit Ephemeris Messages
+//
+// Reads and writes the keyword = value notation (KVN) of OEM version 2.0 (CCSDS 502.0-B-2). A message is a header
+// followed by one segment per object, each a metadata block and a list of ephemeris data lines
+//
+//   CCSDS_OEM_VERS = 2.0
+//   CREATION_DATE = 2021-03-14T12:00:00.000
+//   ORIGINATOR = CRATE
+//
+//   META_START
+//   OBJECT_NAME = EARTH
+//   OBJECT_ID = EARTH
+//   CENTER_NAME = SOL
+//   REF_FRAME = EME2000
+//   TIME_SYSTEM = UTC
+//   START_TIME = 2021-03-14T12:00:00.000000
+//   STOP_TIME = 2021-03-15T12:00:00.000000
+//   META_STOP
+//
+//   2021-03-14T12:00:00.000000 -148930371.225812 3231200.127133 1400418.334720 -1.017101331 -27.441230210 -11.896615337
+//
+// Data lines are in km and km/s, with optional acceleration columns in km/s^2. Covariance blocks are skipped when
+// reading and never written. States are held in the simulation frame (ecliptic of J2000) and rotated on the way in
+// and out when the message uses an equatorial frame
+
+pub const OEM_VERSION: &str = "2.0";
+
+#[derive(Debug)]
+pub enum OemError {
+    Io(std::io::Error),
+    MissingVersion,
+    Malformed { line: usize, message: String },
+    UnsupportedFrame(String),
+    UnsupportedTimeSystem(String),
+}
+
+impl Display for OemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(inner) => write!(f, "failed to access orbit ephemeris message: {}", inner),
+            Self::MissingVersion => write!(f, "not an orbit ephemeris message, missing CCSDS_OEM_VERS"),
+            Self::Malformed { line, message } => write!(f, "line {}: {}", line, message),
+            Self::UnsupportedFrame(frame) => write!(f, "unsupported reference frame '{}'", frame),
+            Self::UnsupportedTimeSystem(system) => write!(f, "unsupported time system '{}'", system),
+        }
+    }
+}
+
+impl Error for OemError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for OemError {
+    fn from(error: std::io::Error) -> Self {
+        OemError::Io(error)
+    }
+}
+
+/// A single ephemeris data line, in the simulation frame in SI units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OemState {
+    pub epoch: Epoch,
+    pub position: DVec3,
+    pub velocity: DVec3,
+    pub acceleration: Option<DVec3>,
+}
+
+/// The metadata and ephemeris of a single object
+#[derive(Debug, Clone, PartialEq)]
+pub struct OemSegment {
+    pub object_name: String,
+    pub object_id: String,
+    pub center_name: String,
+    pub ref_frame: ReferenceFrame,
+    pub time_system: TimeScale,
+    pub states: Vec<OemState>,
+}
+
+impl OemSegment {
+    pub fn new(object_name: &str, center_name: &str) -> Self {
+        OemSegment {
+            object_name: String::from(object_name),
+            object_id: String::from(object_name),
+            center_name: String::from(center_name),
+            ref_frame: ReferenceFrame::Equatorial(String::from("EME2000")),
+            time_system: TimeScale::Utc,
+            states: Vec::new(),
+        }
+    }
+
+    /// The segment as an interpolable ephemeris, the states are relative to `center_name`
+    pub fn table(&self) -> EphemerisTable {
+        EphemerisTable::new(self.states.iter().map(|state| EphemerisRecord { epoch: state.epoch, position: state.position, velocity: state.velocity }).collect())
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        let epoch = |epoch: Epoch| epoch.to_iso8601_with_precision(self.time_system, 6);
+        let rotate = |v: DVec3| match self.ref_frame {
+            ReferenceFrame::EclipticJ2000 => v,
+            ReferenceFrame::Equatorial(_) => ecliptic_to_equatorial(v),
+        };
+
+        writeln!(out, "META_START")?;
+        writeln!(out, "OBJECT_NAME = {}", self.object_name)?;
+        writeln!(out, "OBJECT_ID = {}", self.object_id)?;
+        writeln!(out, "CENTER_NAME = {}", self.center_name)?;
+        writeln!(out, "REF_FRAME = {}", frame_name(&self.ref_frame))?;
+        writeln!(out, "TIME_SYSTEM = {}", self.time_system)?;
+        if let (Some(first), Some(last)) = (self.states.first(), self.states.last()) {
+            writeln!(out, "START_TIME = {}", epoch(first.epoch))?;
+            writeln!(out, "STOP_TIME = {}", epoch(last.epoch))?;
+        }
+        writeln!(out, "META_STOP")?;
+        writeln!(out)?;
+
+        for state in self.states.iter() {
+            let (p, v) = (rotate(state.position) * 0.001, rotate(state.velocity) * 0.001);
+            write!(out, "{} {:.6} {:.6} {:.6} {:.9} {:.9} {:.9}", epoch(state.epoch), p.x, p.y, p.z, v.x, v.y, v.z)?;
+            if let Some(a) = state.acceleration.map(|a| rotate(a) * 0.001) {
+                write!(out, " {:.12} {:.12} {:.12}", a.x, a.y, a.z)?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out)
+    }
+}
+
+/// A complete orbit ephemeris message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Oem {
+    pub creation_date: Option<String>,
+    pub originator: String,
+    pub segments: Vec<OemSegment>,
+}
+
+impl Oem {
+    pub fn new(originator: &str) -> Self {
+        Oem { creation_date: None, originator: String::from(originator), segments: Vec::new() }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Oem, OemError> {
+        let text = std::fs::read_to_string(path)?;
+        Oem::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Oem, OemError> {
+        let mut oem = Oem::new("");
+        let mut version = false;
+        let mut in_meta = false;
+        let mut in_covariance = false;
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            let malformed = |message: String| OemError::Malformed { line: line_number, message };
+
+            if line.is_empty() || line.starts_with("COMMENT") {
+                continue;
+            }
+
+            match line {
+                "META_START" => {
+                    oem.segments.push(OemSegment::new("", ""));
+                    in_meta = true;
+                    continue;
+                },
+                "META_STOP" => {
+                    in_meta = false;
+                    continue;
+                },
+                "COVARIANCE_START" => {
+                    in_covariance = true;
+                    continue;
+                },
+                "COVARIANCE_STOP" => {
+                    in_covariance = false;
+                    continue;
+                },
+                _ if in_covariance => continue,
+                _ => {},
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                let (key, value) = (key.trim(), value.trim());
+                match (in_meta, oem.segments.last_mut()) {
+                    (true, Some(segment)) => match key {
+                        "OBJECT_NAME" => segment.object_name = String::from(value),
+                        "OBJECT_ID" => segment.object_id = String::from(value),
+                        "CENTER_NAME" => segment.center_name = String::from(value),
+                        "REF_FRAME" => segment.ref_frame = parse_frame(value)?,
+                        "TIME_SYSTEM" => segment.time_system = value.parse().map_err(|_| OemError::UnsupportedTimeSystem(String::from(value)))?,
+                        _ => {}, // START_TIME, USEABLE_START_TIME, INTERPOLATION and friends are implied by the data
+                    },
+                    _ => match key {
+                        "CCSDS_OEM_VERS" => version = true,
+                        "CREATION_DATE" => oem.creation_date = Some(String::from(value)),
+                        "ORIGINATOR" => oem.originator = String::from(value),
+                        _ => {},
+                    },
+                }
+                continue;
+            }
+
+            // an ephemeris data line
+            let segment = match (in_meta, oem.segments.last_mut()) {
+                (false, Some(segment)) => segment,
+                _ => return Err(malformed(String::from("data line outside of a segment"))),
+            };
+
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() != 7 && columns.len() != 10 {
+                return Err(malformed(format!("expected 7 or 10 columns, found {}", columns.len())))
+            }
+            let epoch = parse_epoch(columns[0], segment.time_system).ok_or_else(|| malformed(format!("malformed epoch '{}'", columns[0])))?;
+            let mut values = [0.0f64; 9];
+            for (i, column) in columns[1..].iter().enumerate() {
+                values[i] = column.parse().map_err(|_| malformed(format!("malformed number '{}'", column)))?;
+            }
+
+            let rotate = |v: DVec3| match segment.ref_frame {
+                ReferenceFrame::EclipticJ2000 => v,
+                ReferenceFrame::Equatorial(_) => equatorial_to_ecliptic(v),
+            };
+            let vector = |i: usize| rotate(DVec3::new(values[i], values[i + 1], values[i + 2]) * 1000.0);
+            segment.states.push(OemState {
+                epoch,
+                position: vector(0),
+                velocity: vector(3),
+                acceleration: if columns.len() == 10 { Some(vector(6)) } else { None },
+            });
+        }
+
+        if !version {
+            return Err(OemError::MissingVersion)
+        }
+        Ok(oem)
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(out, "CCSDS_OEM_VERS = {}", OEM_VERSION)?;
+        match &self.creation_date {
+            Some(date) => writeln!(out, "CREATION_DATE = {}", date)?,
+            None => writeln!(out, "CREATION_DATE = {}", creation_date())?,
+        }
+        writeln!(out, "ORIGINATOR = {}", self.originator)?;
+        writeln!(out)?;
+
+        for segment in self.segments.iter() {
+            segment.write(out)?;
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()
+    }
+}
+
+/// Collects tracked body states during a run and saves them as an orbit ephemeris message when the run ends
+#[derive(Debug, Clone)]
+pub struct OemWriter {
+    pub path: PathBuf,
+    pub accelerations: bool,
+    pub oem: Oem,
+}
+
+impl OemWriter {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        OemWriter { path: path.as_ref().to_path_buf(), accelerations: false, oem: Oem::new("CRATE") }
+    }
+
+    pub fn with_accelerations(mut self, accelerations: bool) -> Self {
+        self.accelerations = accelerations;
+        self
+    }
+
+    /// The segment for an object, created on first use
+    pub fn segment(&mut self, object_name: &str, center_name: &str) -> &mut OemSegment {
+        let index = match self.oem.segments.iter().position(|s| s.object_name == object_name && s.center_name == center_name) {
+            Some(index) => index,
+            None => {
+                self.oem.segments.push(OemSegment::new(object_name, center_name));
+                self.oem.segments.len() - 1
+            },
+        };
+        &mut self.oem.segments[index]
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        self.oem.save(&self.path)
+    }
+}
+
+fn frame_name(frame: &ReferenceFrame) -> &str {
+    match frame {
+        ReferenceFrame::EclipticJ2000 => "ECLIPJ2000",
+        ReferenceFrame::Equatorial(name) => name,
+    }
+}
+
+fn parse_frame(name: &str) -> Result<ReferenceFrame, OemError> {
+    match name {
+        "ECLIPJ2000" => Ok(ReferenceFrame::EclipticJ2000),
+        "EME2000" | "ICRF" | "GCRF" => Ok(ReferenceFrame::Equatorial(String::from(name))),
+        _ => Err(OemError::UnsupportedFrame(String::from(name))),
+    }
+}
+
+/// Parses both the calendar "YYYY-MM-DDThh:mm:ss" and day of year "YYYY-DDDThh:mm:ss" epoch formats
+fn parse_epoch(text: &str, scale: TimeScale) -> Option<Epoch> {
+    let (date, time) = text.split_once('T').unwrap_or((text, "00:00:00"));
+    let fields: Vec<&str> = date.split('-').collect();
+    match fields.len() {
+        3 => format!("{} {}", text, scale).parse().ok(),
+        2 => {
+            let new_year: Epoch = format!("{}-01-01T{} {}", fields[0], time, scale).parse().ok()?;
+            let day: f64 = fields[1].parse().ok()?;
+            Some(Epoch::from_seconds_past_j2000(new_year.seconds_past_j2000(scale) + (day - 1.0) * 86400.0, scale))
+        },
+        _ => None,
+    }
+}
+
+/// The current wall clock time as an OEM creation date
+fn creation_date() -> String {
+    let unix = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    let epoch = Epoch::from_julian_date(2440587.5 + unix / 86400.0, TimeScale::Utc);
+    epoch.to_iso8601(TimeScale::Utc)
+}
+
+impl MemUse for OemState {}
+
+impl MemUse for OemSegment {
+    fn heap_use(&self) -> usize {
+        self.object_name.heap_use() + self.object_id.heap_use() + self.center_name.heap_use() + self.states.heap_use()
+    }
+}
+
+impl MemUse for OemWriter {
+    fn heap_use(&self) -> usize {
+        self.path.heap_use() + self.oem.creation_date.heap_use() + self.oem.originator.heap_use() + self.oem.segments.heap_use()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "CCSDS_OEM_VERS = 2.0
+COMMENT an example message
+CREATION_DATE = 1996-11-04T17:22:31
+ORIGINATOR = NASA/JPL
+
+META_START
+OBJECT_NAME = MARS GLOBAL SURVEYOR
+OBJECT_ID = 1996-062A
+CENTER_NAME = MARS BARYCENTER
+REF_FRAME = EME2000
+TIME_SYSTEM = UTC
+START_TIME = 1996-12-18T12:00:00.331
+STOP_TIME = 1996-12-28T21:28:00.331
+INTERPOLATION = HERMITE
+INTERPOLATION_DEGREE = 7
+META_STOP
+
+1996-12-18T12:00:00.331 2789.619 -280.045 -1746.755 4.73372 -2.49586 -1.04195
+1996-12-18T12:01:00.331 2783.419 -308.143 -1877.071 5.18604 -2.42124 -1.99608
+1996-353T12:02:00.331 2776.033 -336.859 -2008.682 5.63678 -2.33951 -1.94687
+
+COVARIANCE_START
+EPOCH = 1996-12-28T21:29:07.267
+COV_REF_FRAME = EME2000
+3.3313494e-04
+COVARIANCE_STOP
+";
+
+    #[test]
+    fn parse_message() {
+        let oem = Oem::parse(SAMPLE).unwrap();
+        assert_eq!(oem.originator, "NASA/JPL");
+        assert_eq!(oem.segments.len(), 1);
+
+        let segment = &oem.segments[0];
+        assert_eq!(segment.object_id, "1996-062A");
+        assert_eq!(segment.center_name, "MARS BARYCENTER");
+        assert_eq!(segment.states.len(), 3);
+        assert!((segment.states[0].position.magnitude() - 3.30327e6).abs() < 1.0e1);
+
+        // the day of year epoch is a minute after the one before it
+        assert!((segment.states[2].epoch.seconds_since(&segment.states[1].epoch) - 60.0).abs() < 1.0e-6);
+        assert!(segment.table().covers(segment.states[1].epoch));
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let mut oem = Oem::new("TEST");
+        let mut segment = OemSegment::new("EARTH", "SOL");
+        segment.time_system = TimeScale::Tdb;
+        for i in 0..3 {
+            segment.states.push(OemState {
+                epoch: Epoch::J2000.offset_by(i as f64 * 3600.0),
+                position: DVec3::new(1.496e11, i as f64 * 1.0e8, 1.0e6),
+                velocity: DVec3::new(-10.0, 29780.0, 1.5),
+                acceleration: Some(DVec3::new(-5.9e-3, 0.0, 0.0)),
+            });
+        }
+        oem.segments.push(segment);
+
+        let mut buffer = Vec::new();
+        oem.write(&mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.contains("REF_FRAME = EME2000"));
+        assert!(text.contains("TIME_SYSTEM = TDB"));
+
+        let read = Oem::parse(&text).unwrap();
+        let (written, read) = (&oem.segments[0].states, &read.segments[0].states);
+        assert_eq!(read.len(), 3);
+        for (a, b) in written.iter().zip(read.iter()) {
+            assert!(b.epoch.seconds_since(&a.epoch).abs() < 1.0e-5);
+            assert!((a.position - b.position).magnitude() < 1.0);
+            assert!((a.velocity - b.velocity).magnitude() < 1.0e-5);
+            assert!((a.acceleration.unwrap() - b.acceleration.unwrap()).magnitude() < 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn malformed_messages() {
+        assert!(matches!(Oem::parse("META_START\nMETA_STOP\n"), Err(OemError::MissingVersion)));
+        assert!(matches!(Oem::parse("CCSDS_OEM_VERS = 2.0\nMETA_START\nREF_FRAME = ITRF\n"), Err(OemError::UnsupportedFrame(_))));
+        assert!(matches!(Oem::parse("CCSDS_OEM_VERS = 2.0\nMETA_START\nMETA_STOP\n2000-01-01T00:00:00 1 2 3\n"), Err(OemError::Malformed { line: 4, .. })));
+    }
+}
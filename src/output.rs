@@ -1,13 +1,17 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
-use std::ops::{ AddAssign, SubAssign };
-use crate::sim::*;
+use std::{ fs::File, io::{ BufWriter, Write }, ops::{ AddAssign, SubAssign } };
+use crate::{ sim::*, debug::{ MemUse, allocation_stats, AllocationStats }, math::DVec3, units::{ Length, Time, UnitParseError }, epoch::{ Epoch, TimeScale }, oem::{ OemState, OemWriter }, trajectory::*, plot::{ PlotScaling, TerminalPlot }, svg::SvgPlot, render::*, telemetry::{ Phase, TelemetrySnapshot }, observer::{ Observer, id_after_removal } };
 
 #[derive(Debug, Clone)]
 enum OutputTarget {
     Console,
     File,
+    Oem,
+    Trajectory,
+    Svg,
+    Image,
 }
 
 impl Default for OutputTarget { fn default() -> Self { Self::Console } }
@@ -16,6 +20,8 @@ impl Default for OutputTarget { fn default() -> Self { Self::Console } }
 enum OutputFormat {
     Pretty,
     Csv,
+    Jsonl,
+    Plot,
 }
 
 impl Default for OutputFormat { fn default() -> Self { Self::Pretty } }
@@ -32,14 +38,19 @@ enum OutputField {
     Acceleration,
     Time,
     MemoryUse,
+    HeapAllocation,
+    StepRate,
+    PhaseTimes,
+    RealTimeFactor,
+    Sgp4Deviation,
 }
 
-#[derive(Debug, Clone)]
-enum OutputFrequency {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFrequency {
     EveryFrame,
-    Hertz(f64),
-    Frames(f64),
-    SimTime(f64),
+    Hertz(f64), // wall clock outputs per second
+    Frames(usize),
+    SimTime(f64), // seconds of simulation time between outputs
 }
 
 impl Default for OutputFrequency {
@@ -48,13 +59,115 @@ impl Default for OutputFrequency {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+impl std::str::FromStr for OutputFrequency {
+    type Err = UnitParseError;
+
+    /// Parses "every", "10 Hz", "100 frames" or a simulation time interval such as "1 h"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let lower = s.to_ascii_lowercase();
+        let positive = |value: &str| -> Result<f64, UnitParseError> {
+            match value.trim().parse::<f64>() {
+                Ok(n) if n > 0.0 && n.is_finite() => Ok(n),
+                _ => Err(UnitParseError::InvalidNumber(String::from(s))),
+            }
+        };
+
+        if lower.is_empty() {
+            Err(UnitParseError::Empty)
+        } else if lower == "every" || lower == "every frame" {
+            Ok(OutputFrequency::EveryFrame)
+        } else if let Some(rate) = lower.strip_suffix("hz") {
+            Ok(OutputFrequency::Hertz(positive(rate)?))
+        } else if let Some(count) = lower.strip_suffix("frames").or_else(|| lower.strip_suffix("frame")) {
+            match count.trim().parse::<usize>() {
+                Ok(count) if count > 0 => Ok(OutputFrequency::Frames(count)),
+                _ => Err(UnitParseError::InvalidNumber(String::from(s))),
+            }
+        } else {
+            let interval: Time = s.parse()?;
+            positive(&interval.seconds().to_string()).map(OutputFrequency::SimTime)
+        }
+    }
+}
+
+/// Where formatted output is written, stdout or a buffered file
+pub struct OutputSink {
+    writer: Box<dyn Write + Send>,
+    buffer_capacity: usize, // of the writer's buffer, for memory accounting
+    description: String,
+}
+
+impl OutputSink {
+    pub fn stdout() -> Self {
+        OutputSink { writer: Box::new(std::io::stdout()), buffer_capacity: 0, description: String::from("stdout") }
+    }
+
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        let file = File::create(path)?;
+        let writer = BufWriter::new(file);
+        Ok(OutputSink { buffer_capacity: writer.capacity(), writer: Box::new(writer), description: String::from(path) })
+    }
+}
+
+impl std::fmt::Debug for OutputSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OutputSink({})", self.description)
+    }
+}
+
+/// Errors configuring an output device
+#[derive(Debug)]
+pub enum OutputError {
+    Io(std::io::Error),
+    UnknownBody(String),
+}
+
+impl std::fmt::Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(inner) => write!(f, "failed to open output: {}", inner),
+            Self::UnknownBody(name) => write!(f, "unknown body: '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for OutputError {
+    fn from(error: std::io::Error) -> Self {
+        OutputError::Io(error)
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct OutputDevice {
     target: OutputTarget,
     format: OutputFormat,
     tracked_bodies: Vec<(String, usize, Vec<OutputField>)>,
     global_fields: Vec<OutputField>,
     frequency: OutputFrequency,
+    next_output: u64, // for SimTime, the index of the next interval boundary to output at
+    last_output: Option<std::time::Instant>, // for Hertz, the wall clock time of the last output
+    sink: Option<OutputSink>,
+    header_written: bool,
+    error: Option<std::io::Error>, // the first write error, output stops once one occurs
+    oem: Option<OemWriter>,
+    oem_center: Option<(String, usize)>, // tracked states are written relative to this body, the origin if None
+    trajectory: Option<TrajectoryWriter<BufWriter<File>>>,
+    trajectory_ids: Vec<Option<usize>>, // the present id of each body in the trajectory header, None once removed
+    plot: Option<TerminalPlot>,
+    svg: Option<SvgPlot>,
+    images: Option<FrameRenderer>,
+    last_allocation: std::cell::Cell<AllocationStats>, // heap statistics at the previous output, for per output deltas
+    telemetry: (TelemetrySnapshot, TelemetrySnapshot), // counters at the previous and the current output
 }
 
 // system energy in J/kg = (system_kinetic_energy + system_potential_energy) / system_total_mass
@@ -63,86 +176,482 @@ impl OutputDevice {
 
     // TODO: ISOLATE CLI STUFF TO CLI.RS
 
-    pub fn from_cli_config(sim: &Simulation, matches: &clap::ArgMatches) -> OutputDevice {
+    /// Configures a device from the `output` subcommand of `run`, naming a body the simulation lacks is an error
+    pub fn from_cli_config(sim: &Simulation, matches: &clap::ArgMatches) -> Result<OutputDevice, OutputError> {
         let mut device = OutputDevice::default();
-        if let Some(matches) = matches.subcommand_matches("simparams") {
-            if let Some(matches) = matches.subcommand_matches("output") {
-                device.target = matches.value_of("target").map(|t| match t.to_ascii_uppercase().as_str() {
-                    "CONSOLE" => OutputTarget::Console,
-                    "FILE" => OutputTarget::File,
-                    _ => OutputTarget::Console,
-                }).unwrap_or(OutputTarget::default());
-
-                device.format = matches.value_of("format").map(|f| match f.to_ascii_uppercase().as_str() {
-                    "PRETTY" => OutputFormat::Pretty,
-                    "CSV" => OutputFormat::Csv,
-                    _ => OutputFormat::Pretty,
-                }).unwrap_or(OutputFormat::default());
-
-                if matches.is_present("totalenergy") { device.global_fields.push(OutputField::TotalEnergy); }
-                if matches.is_present("kineticenergy") { device.global_fields.push(OutputField::KineticEnergy); }
-                if matches.is_present("potentialenergy") { device.global_fields.push(OutputField::PotentialEnergy); }
-                if matches.is_present("frames") { device.global_fields.push(OutputField::Frames); }
-                if matches.is_present("time") { device.global_fields.push(OutputField::Time); }
-                if matches.is_present("memoryuse") { device.global_fields.push(OutputField::MemoryUse); }
-
-                if let Some(matches) = matches.subcommand_matches("track") {
-                    let mut tracked_fields = Vec::new();
-
-                    if matches.is_present("kineticenergy") { tracked_fields.push(OutputField::KineticEnergy); }
-                    if matches.is_present("position") { tracked_fields.push(OutputField::Position); }
-                    if matches.is_present("velocity") { tracked_fields.push(OutputField::Velocity); }
-                    if matches.is_present("acceleration") { tracked_fields.push(OutputField::Acceleration); }
-
-                    if let Some(targets) = matches.values_of("target") {
-                        for target in targets {
-                            for body in sim.present().get_named_bodies(target) {
-                                println!("{:#?}", body);
-                                device.tracked_bodies.push((target.to_ascii_uppercase(), body.id(), tracked_fields.clone()));
-                            }
+        let find_body = |name: &str| -> Result<usize, OutputError> {
+            sim.present().get_named_bodies(name).first().map(|body| body.id()).ok_or_else(|| OutputError::UnknownBody(name.to_string()))
+        };
+        let center = match matches.subcommand_matches("output").and_then(|matches| matches.value_of("center")) {
+            Some(name) => Some((name.to_ascii_uppercase(), find_body(name)?)),
+            None => None,
+        };
+
+        if let Some(matches) = matches.subcommand_matches("output") {
+            device.target = matches.value_of("target").map(|t| match t.to_ascii_uppercase().as_str() {
+                "CONSOLE" => OutputTarget::Console,
+                "FILE" => OutputTarget::File,
+                "OEM" => OutputTarget::Oem,
+                "TRAJECTORY" => OutputTarget::Trajectory,
+                "SVG" => OutputTarget::Svg,
+                "IMAGE" => OutputTarget::Image,
+                _ => OutputTarget::Console,
+            }).unwrap_or(OutputTarget::default());
+
+            if let OutputTarget::Oem = device.target {
+                let path = matches.value_of("path").unwrap_or("output.oem");
+                device.oem = Some(OemWriter::new(path).with_accelerations(matches.is_present("accelerations")));
+                device.oem_center = center.clone();
+            }
+
+            if let Some(frequency) = matches.value_of("frequency").and_then(|f| f.parse().ok()) {
+                device.set_frequency(frequency);
+            }
+
+            device.format = matches.value_of("format").map(|f| match f.to_ascii_uppercase().as_str() {
+                "PRETTY" => OutputFormat::Pretty,
+                "CSV" => OutputFormat::Csv,
+                "JSONL" => OutputFormat::Jsonl,
+                "PLOT" => OutputFormat::Plot,
+                _ => OutputFormat::Pretty,
+            }).unwrap_or(OutputFormat::default());
+
+            if let OutputFormat::Plot = device.format {
+                let mut plot = TerminalPlot::default();
+                plot.focus = center.as_ref().map(|(_, id)| *id);
+                plot.scaling = matches.value_of("scale").and_then(|s| s.parse().ok()).unwrap_or(PlotScaling::Auto);
+                plot.ansi = !matches.is_present("noansi");
+                if let Some(trail) = matches.value_of("trail").and_then(|t| t.parse().ok()) {
+                    plot.trail_length = trail;
+                }
+                if let Some((w, h)) = matches.value_of("plotsize").and_then(|s| s.split_once(['x', 'X'])) {
+                    plot.width = w.trim().parse().unwrap_or(plot.width);
+                    plot.height = h.trim().parse().unwrap_or(plot.height);
+                }
+                device.plot = Some(plot);
+            }
+
+            if let OutputTarget::File = device.target {
+                let default_path = match device.format {
+                    OutputFormat::Pretty | OutputFormat::Plot => "output.txt",
+                    OutputFormat::Csv => "output.csv",
+                    OutputFormat::Jsonl => "output.jsonl",
+                };
+                device.sink = Some(OutputSink::create(matches.value_of("path").unwrap_or(default_path))?);
+            }
+
+            if matches.is_present("totalenergy") { device.global_fields.push(OutputField::TotalEnergy); }
+            if matches.is_present("kineticenergy") { device.global_fields.push(OutputField::KineticEnergy); }
+            if matches.is_present("potentialenergy") { device.global_fields.push(OutputField::PotentialEnergy); }
+            if matches.is_present("frames") { device.global_fields.push(OutputField::Frames); }
+            if matches.is_present("time") { device.global_fields.push(OutputField::Time); }
+            if matches.is_present("memoryuse") { device.global_fields.push(OutputField::MemoryUse); }
+            if matches.is_present("heap") { device.global_fields.push(OutputField::HeapAllocation); }
+            if matches.is_present("steprate") { device.global_fields.push(OutputField::StepRate); }
+            if matches.is_present("phasetimes") { device.global_fields.push(OutputField::PhaseTimes); }
+            if matches.is_present("rtfactor") { device.global_fields.push(OutputField::RealTimeFactor); }
+
+            if let Some(matches) = matches.subcommand_matches("track") {
+                let mut tracked_fields = Vec::new();
+
+                if matches.is_present("kineticenergy") { tracked_fields.push(OutputField::KineticEnergy); }
+                if matches.is_present("position") { tracked_fields.push(OutputField::Position); }
+                if matches.is_present("velocity") { tracked_fields.push(OutputField::Velocity); }
+                if matches.is_present("acceleration") { tracked_fields.push(OutputField::Acceleration); }
+                if matches.is_present("sgp4") { tracked_fields.push(OutputField::Sgp4Deviation); }
+
+                if let Some(targets) = matches.values_of("target") {
+                    for target in targets {
+                        find_body(target)?;
+                        for body in sim.present().get_named_bodies(target) {
+                            device.tracked_bodies.push((target.to_ascii_uppercase(), body.id(), tracked_fields.clone()));
                         }
                     }
                 }
             }
+
+            if let OutputTarget::Trajectory = device.target {
+                let header = TrajectoryHeader::new(sim.present().start_epoch(), device.trajectory_bodies());
+                device.trajectory_ids = header.bodies.iter().map(|body| Some(body.id)).collect();
+                device.trajectory = Some(TrajectoryWriter::create(matches.value_of("path").unwrap_or("output.traj"), header)?);
+            }
+
+            if let OutputTarget::Svg = device.target {
+                let tracked: Vec<(String, usize)> = device.tracked_bodies.iter().map(|(name, id, _)| (name.clone(), *id)).collect();
+                device.svg = Some(SvgPlot::new(matches.value_of("path").unwrap_or("output.svg"), &tracked)
+                    .with_plane(matches.value_of("plane").and_then(|p| p.parse().ok()).unwrap_or_default())
+                    .with_reference(center.clone())
+                    .with_apsides(matches.is_present("apsides")));
+            }
+
+            if let OutputTarget::Image = device.target {
+                let format = matches.value_of("image").and_then(|f| f.parse().ok()).unwrap_or(ImageFormat::Png);
+                let mut images = FrameRenderer::new(matches.value_of("path").unwrap_or("frame_"), format);
+                if let Some((w, h)) = matches.value_of("imagesize").and_then(|s| s.split_once(['x', 'X'])) {
+                    images.width = w.trim().parse().unwrap_or(images.width);
+                    images.height = h.trim().parse().unwrap_or(images.height);
+                }
+                images.focus = center.as_ref().map(|(_, id)| *id);
+                images.camera.position = matches.value_of("camera").and_then(|v| parse_length_vector(v).ok());
+                if let Some(look_at) = matches.value_of("lookat").and_then(|v| parse_length_vector(v).ok()) {
+                    images.camera.look_at = look_at;
+                }
+                if images.camera.position.is_some() {
+                    images.camera.up = DVec3::new(0.0, 0.0, 1.0); // a free camera keeps the ecliptic level
+                }
+                if let Some(fov) = matches.value_of("fov").and_then(|f| f.parse::<f64>().ok()) {
+                    images.camera.projection = Projection::Perspective { fov: fov.to_radians() };
+                } else if let Some(extent) = matches.value_of("extent").and_then(|e| e.parse::<Length>().ok()) {
+                    images.camera.projection = Projection::Orthographic { extent: Some(extent.meters()) };
+                }
+                if let Some(size) = matches.value_of("pointsize").and_then(|p| p.parse().ok()) {
+                    images.min_point_radius = size;
+                }
+                if let Some(trail) = matches.value_of("trail").and_then(|t| t.parse().ok()) {
+                    images.trail_length = trail;
+                }
+                images.labels = !matches.is_present("nolabels");
+                device.images = Some(images);
+            }
         }
-        device
+        Ok(device)
+    }
+
+    /// Live heap bytes, then bytes and allocations since the previous output, None without the counting allocator
+    fn heap_allocation(&self) -> Option<(usize, u64, u64)> {
+        let stats = allocation_stats()?;
+        let previous = self.last_allocation.replace(stats);
+        Some((stats.live_bytes, stats.allocated_bytes - previous.allocated_bytes, stats.allocations - previous.allocations))
+    }
+
+    /// The tracked bodies and their vector fields, bodies tracking none are recorded by position
+    fn trajectory_bodies(&self) -> Vec<TrajectoryBody> {
+        self.tracked_bodies.iter().map(|(name, body_id, fields)| {
+            let mut vectors: Vec<TrajectoryField> = fields.iter().filter_map(|field| match field {
+                OutputField::Position => Some(TrajectoryField::Position),
+                OutputField::Velocity => Some(TrajectoryField::Velocity),
+                OutputField::Acceleration => Some(TrajectoryField::Acceleration),
+                _ => None,
+            }).collect();
+            if vectors.is_empty() {
+                vectors.push(TrajectoryField::Position);
+            }
+            TrajectoryBody { id: *body_id, name: name.clone(), fields: vectors }
+        }).collect()
     }
     
-    pub fn output(&self, sim: &Simulation) {
+    /// Stops writing a removed body and passes the removal on to the plot, svg and image targets. The columns of a
+    /// trajectory stay and hold NaN from then on, and OEM states relative to the removed body continue relative to
+    /// the origin
+    pub fn remove_body(&mut self, id: usize) {
+        self.tracked_bodies = std::mem::take(&mut self.tracked_bodies).into_iter()
+            .filter_map(|(name, body, fields)| Some((name, id_after_removal(body, id)?, fields)))
+            .collect();
+        self.oem_center = self.oem_center.take().and_then(|(name, center)| Some((name, id_after_removal(center, id)?)));
+        for trajectory_id in self.trajectory_ids.iter_mut() {
+            *trajectory_id = trajectory_id.and_then(|body| id_after_removal(body, id));
+        }
+        if let Some(plot) = self.plot.as_mut() {
+            plot.remove_body(id);
+        }
+        if let Some(svg) = self.svg.as_mut() {
+            svg.remove_body(id);
+        }
+        if let Some(images) = self.images.as_mut() {
+            images.remove_body(id);
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: OutputFrequency) {
+        self.frequency = frequency;
+        self.next_output = 0;
+        self.last_output = None;
+    }
+
+    /// Whether the frame at `frame_number` and `sim_time` should be output, updating the schedule if so
+    fn due(&mut self, frame_number: usize, sim_time: f64) -> bool {
         match self.frequency {
-            OutputFrequency::EveryFrame => {
-                // do nothing
-            },
-            OutputFrequency::Hertz(f) => {
-                todo!();
+            OutputFrequency::EveryFrame => true,
+            OutputFrequency::Hertz(rate) => {
+                let now = std::time::Instant::now();
+                match self.last_output {
+                    Some(last) if now.duration_since(last).as_secs_f64() < 1.0 / rate => false,
+                    _ => {
+                        self.last_output = Some(now);
+                        true
+                    },
+                }
             },
-            OutputFrequency::Frames(f) => {
-                if sim.present().frame_number() % f as usize > 0 {
-                    return
+            OutputFrequency::Frames(n) => frame_number % n == 0,
+            OutputFrequency::SimTime(interval) => {
+                // boundaries are computed from their index rather than accumulated, and a step spanning several of
+                // them still only outputs once
+                if sim_time < self.next_output as f64 * interval {
+                    return false
                 }
+                self.next_output = (self.next_output + 1).max((sim_time / interval).floor() as u64 + 1);
+                true
             },
-            OutputFrequency::SimTime(f) => {
-                if sim.present().sim_time() % f >= (sim.present().time_step()) {
-                    return
+        }
+    }
+
+    pub fn output(&mut self, sim: &Simulation) {
+        if !self.due(sim.present().frame_number(), sim.present().sim_time()) {
+            return
+        }
+
+        // rates are over the interval since the previous output
+        self.telemetry = (self.telemetry.1, sim.telemetry().snapshot(sim.present().sim_time()));
+
+        match self.target {
+            OutputTarget::Oem => self.record_oem_states(sim),
+            OutputTarget::Trajectory => self.record_trajectory_frame(sim),
+            OutputTarget::Svg => if let Some(svg) = self.svg.as_mut() { svg.record(sim) },
+            OutputTarget::Image => self.render_image(sim),
+            _ => self.write_frame(sim),
+        }
+    }
+
+    /// Flushes buffered output and writes out anything held back until the end of the run
+    pub fn finish(&mut self, sim: &Simulation) -> std::io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error)
+        }
+        if let Some(sink) = self.sink.as_mut() {
+            sink.writer.flush()?;
+        }
+        if let Some(trajectory) = self.trajectory.as_mut() {
+            trajectory.finish()?;
+        }
+        if let Some(svg) = &self.svg {
+            svg.save()?;
+        }
+        match &self.oem {
+            Some(oem) => oem.save(),
+            None => Ok(()),
+        }
+    }
+
+    fn record_trajectory_frame(&mut self, sim: &Simulation) {
+        let trajectory = match (self.trajectory.as_mut(), &self.error) {
+            (Some(trajectory), None) => trajectory,
+            _ => return,
+        };
+
+        let nan = DVec3::new(f64::NAN, f64::NAN, f64::NAN);
+        let mut vectors = Vec::new();
+        for (body, id) in trajectory.header().bodies.iter().zip(self.trajectory_ids.iter()) {
+            let body_ref = id.and_then(|id| sim.present().get_body_ref(id));
+            for field in body.fields.iter() {
+                vectors.push(match (&body_ref, field) {
+                    (Some(b), TrajectoryField::Position) => b.position(),
+                    (Some(b), TrajectoryField::Velocity) => b.velocity(),
+                    (Some(b), TrajectoryField::Acceleration) => b.acceleration(),
+                    (None, _) => nan,
+                });
+            }
+        }
+
+        if let Err(error) = trajectory.write_frame(sim.present().frame_number(), sim.present().sim_time(), &vectors) {
+            self.error = Some(error);
+        }
+    }
+
+    fn write_frame(&mut self, sim: &Simulation) {
+        if self.error.is_some() {
+            return
+        }
+
+        // the sink is taken out while writing, the formatters need to borrow the rest of the device
+        let mut sink = self.sink.take().unwrap_or_else(OutputSink::stdout);
+        let result = match self.format {
+            OutputFormat::Pretty => self.write_pretty(sim, &mut sink.writer),
+            OutputFormat::Csv => self.write_csv_row(sim, &mut sink.writer),
+            OutputFormat::Jsonl => self.write_json_line(sim, &mut sink.writer),
+            OutputFormat::Plot => self.write_plot(sim, &mut sink.writer),
+        };
+        self.sink = Some(sink);
+
+        // reported when the run finishes
+        if let Err(error) = result {
+            self.error = Some(error);
+        }
+    }
+
+    /// The values of the global fields, with the column name of each
+    fn global_values(&self, sim: &Simulation) -> Vec<(&'static str, Value)> {
+        let mut values = Vec::new();
+        for field in self.global_fields.iter() {
+            match field {
+                OutputField::Frames => values.push(("frame", Value::Integer(sim.present().frame_number()))),
+                OutputField::Time => {
+                    values.push(("time", Value::Number(sim.present().sim_time())));
+                    values.push(("utc", Value::Text(sim.present().epoch().map(|epoch| epoch.to_iso8601(TimeScale::Utc)))));
+                },
+                OutputField::TotalEnergy => values.push(("total_energy", Value::Number((sim.system_kinetic_energy() + sim.system_potential_energy()) / sim.system_total_mass()))),
+                OutputField::KineticEnergy => values.push(("kinetic_energy", Value::Number(sim.system_kinetic_energy()))),
+                OutputField::PotentialEnergy => values.push(("potential_energy", Value::Number(sim.system_potential_energy()))),
+                OutputField::MemoryUse => values.push(("memory_use", Value::Integer(sim.memory_use()))),
+                OutputField::StepRate => values.push(("steps_per_second", Value::Number(self.telemetry.1.steps_per_second(&self.telemetry.0)))),
+                OutputField::RealTimeFactor => values.push(("real_time_factor", Value::Number(self.telemetry.1.real_time_factor(&self.telemetry.0)))),
+                OutputField::PhaseTimes => {
+                    for phase in Phase::ALL.iter() {
+                        values.push((phase_column(*phase), Value::Number(self.telemetry.1.phase_ms_per_step(&self.telemetry.0, *phase))));
+                    }
+                },
+                OutputField::HeapAllocation => {
+                    let heap = self.heap_allocation();
+                    values.push(("heap_live", heap.map(|(live, _, _)| Value::Integer(live)).unwrap_or(Value::Text(None))));
+                    values.push(("heap_allocated", heap.map(|(_, bytes, _)| Value::Integer(bytes as usize)).unwrap_or(Value::Text(None))));
+                    values.push(("heap_allocations", heap.map(|(_, _, count)| Value::Integer(count as usize)).unwrap_or(Value::Text(None))));
+                },
+                _ => {},
+            }
+        }
+        values
+    }
+
+    /// The values of the fields tracked for one body, NaN when the body no longer exists
+    fn tracked_values(sim: &Simulation, body_id: usize, fields: &[OutputField]) -> Vec<(&'static str, Value)> {
+        let body = sim.present().get_body_ref(body_id);
+        let nan = DVec3::new(f64::NAN, f64::NAN, f64::NAN);
+        let mut values = Vec::new();
+        for field in fields.iter() {
+            match field {
+                OutputField::KineticEnergy => values.push(("kinetic_energy", Value::Number(body.as_ref().map(|b| b.kinetic_energy() / b.mass()).unwrap_or(f64::NAN)))),
+                OutputField::Position => values.push(("position", Value::Vector(body.as_ref().map(|b| b.position()).unwrap_or(nan)))),
+                OutputField::Velocity => values.push(("velocity", Value::Vector(body.as_ref().map(|b| b.velocity()).unwrap_or(nan)))),
+                OutputField::Acceleration => values.push(("acceleration", Value::Vector(body.as_ref().map(|b| b.acceleration()).unwrap_or(nan)))),
+                OutputField::Sgp4Deviation => values.push(("sgp4_deviation", Value::Number(sim.sgp4_deviation(body_id).unwrap_or(f64::NAN)))),
+                _ => {},
+            }
+        }
+        values
+    }
+
+    /// One row per output frame, preceded by a header naming every column. Tracked body columns are prefixed with the
+    /// body name and vectors are split into x, y and z columns. Values are in SI units
+    fn write_csv_row(&mut self, sim: &Simulation, out: &mut dyn Write) -> std::io::Result<()> {
+        let mut columns: Vec<(String, Value)> = self.global_values(sim).into_iter().map(|(name, value)| (String::from(name), value)).collect();
+        for (name, body_id, fields) in self.tracked_bodies.iter() {
+            for (field, value) in OutputDevice::tracked_values(sim, *body_id, fields) {
+                columns.push((format!("{}.{}", name, field), value));
+            }
+        }
+
+        if !self.header_written {
+            let header: Vec<String> = columns.iter().flat_map(|(name, value)| match value {
+                Value::Vector(_) => vec![format!("{}.x", name), format!("{}.y", name), format!("{}.z", name)],
+                _ => vec![name.clone()],
+            }).map(|name| csv_escape(&name)).collect();
+            writeln!(out, "{}", header.join(","))?;
+            self.header_written = true;
+        }
+
+        let row: Vec<String> = columns.iter().map(|(_, value)| value.to_csv()).collect();
+        writeln!(out, "{}", row.join(","))
+    }
+
+    /// One JSON object per output frame, tracked bodies are nested under "bodies" by name
+    fn write_json_line(&self, sim: &Simulation, out: &mut dyn Write) -> std::io::Result<()> {
+        let mut members: Vec<String> = self.global_values(sim).iter().map(|(name, value)| format!("\"{}\":{}", name, value.to_json())).collect();
+        if !self.tracked_bodies.is_empty() {
+            let bodies: Vec<String> = self.tracked_bodies.iter().map(|(name, body_id, fields)| {
+                let values: Vec<String> = OutputDevice::tracked_values(sim, *body_id, fields).iter().map(|(field, value)| format!("\"{}\":{}", field, value.to_json())).collect();
+                format!("{}:{{{}}}", json_string(name), values.join(","))
+            }).collect();
+            members.push(format!("\"bodies\":{{{}}}", bodies.join(",")));
+        }
+        writeln!(out, "{{{}}}", members.join(","))
+    }
+
+    /// Draws a top down plot of every body, labelling the tracked ones
+    fn write_plot(&mut self, sim: &Simulation, out: &mut dyn Write) -> std::io::Result<()> {
+        let plot = self.plot.get_or_insert_with(TerminalPlot::default);
+        let tracked: Vec<(String, usize)> = self.tracked_bodies.iter().map(|(name, id, _)| (name.clone(), *id)).collect();
+        write!(out, "{}", plot.render(sim, &tracked))?;
+        out.flush()
+    }
+
+    fn render_image(&mut self, sim: &Simulation) {
+        let images = match (self.images.as_mut(), &self.error) {
+            (Some(images), None) => images,
+            _ => return,
+        };
+        let tracked: Vec<(String, usize)> = self.tracked_bodies.iter().map(|(name, id, _)| (name.clone(), *id)).collect();
+        if let Err(error) = images.write_frame(sim, &tracked) {
+            self.error = Some(error);
+        }
+    }
+
+    fn record_oem_states(&mut self, sim: &Simulation) {
+        let oem = match self.oem.as_mut() {
+            Some(oem) => oem,
+            None => return,
+        };
+
+        // unanchored simulations are written as if they started at J2000
+        let epoch = sim.present().epoch().unwrap_or(Epoch::J2000.offset_by(sim.present().sim_time()));
+        let center = self.oem_center.as_ref().and_then(|(name, id)| sim.present().get_body_ref(*id).map(|body| (name.as_str(), body)));
+        let center_name = center.as_ref().map(|(name, _)| *name).unwrap_or("ORIGIN");
+        let accelerations = oem.accelerations;
+
+        for (name, body_id, _) in self.tracked_bodies.iter() {
+            if let Some(body) = sim.present().get_body_ref(*body_id) {
+                let mut state = OemState { epoch, position: body.position(), velocity: body.velocity(), acceleration: None };
+                if let Some((_, center)) = &center {
+                    state.position -= center.position();
+                    state.velocity -= center.velocity();
+                }
+                if accelerations {
+                    state.acceleration = Some(body.acceleration() - center.as_ref().map(|(_, c)| c.acceleration()).unwrap_or_default());
                 }
+                oem.segment(name, center_name).states.push(state);
             }
         }
+    }
 
-        println!("------------------------------------------");
+    fn write_pretty(&self, sim: &Simulation, out: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(out, "------------------------------------------")?;
         let mut indent = 0;
         let indent_str = "  ";
         for (i, field) in self.global_fields.iter().enumerate() {
             match field {
                 OutputField::TotalEnergy => {
                     let (e, ep) = format_si_value((sim.system_kinetic_energy() + sim.system_potential_energy()) / sim.system_total_mass());
-                    println!("{}System Total Energy: {:09.04}{}J/Kg", indent_str.repeat(indent), e, ep);
+                    writeln!(out, "{}System Total Energy: {:09.04}{}J/Kg", indent_str.repeat(indent), e, ep)?;
                 },
                 OutputField::Frames => {
-                    println!("{}Frame: {}", indent_str.repeat(indent), sim.present().frame_number());
+                    writeln!(out, "{}Frame: {}", indent_str.repeat(indent), sim.present().frame_number())?;
+                },
+                OutputField::Time => {
+                    let (t, tp) = format_si_value(sim.present().sim_time());
+                    match sim.present().epoch() {
+                        Some(epoch) => writeln!(out, "{}Time: {:.04}{}s ({} UTC)", indent_str.repeat(indent), t, tp, epoch.to_iso8601(TimeScale::Utc))?,
+                        None => writeln!(out, "{}Time: {:.04}{}s", indent_str.repeat(indent), t, tp)?,
+                    }
                 },
                 OutputField::MemoryUse => {
                     let (m, mp) = format_mem_value(sim.memory_use());
-                    println!("{}Memory Use: {:.04}{}B", indent_str.repeat(indent), m, mp);
+                    writeln!(out, "{}Memory Use: {:.04}{}B", indent_str.repeat(indent), m, mp)?;
+                },
+                OutputField::StepRate => {
+                    writeln!(out, "{}Step Rate: {:.1} steps/s", indent_str.repeat(indent), self.telemetry.1.steps_per_second(&self.telemetry.0))?;
+                },
+                OutputField::RealTimeFactor => {
+                    writeln!(out, "{}Real Time Factor: {:.4e}", indent_str.repeat(indent), self.telemetry.1.real_time_factor(&self.telemetry.0))?;
+                },
+                OutputField::PhaseTimes => {
+                    let phases: Vec<String> = Phase::ALL.iter().map(|phase| format!("{} {:.6}", phase.name(), self.telemetry.1.phase_ms_per_step(&self.telemetry.0, *phase))).collect();
+                    writeln!(out, "{}Phase Times (ms/step): {}", indent_str.repeat(indent), phases.join(", "))?;
+                },
+                OutputField::HeapAllocation => match self.heap_allocation() {
+                    Some((live, bytes, count)) => {
+                        let (l, lp) = format_mem_value(live);
+                        let (b, bp) = format_mem_value(bytes as usize);
+                        writeln!(out, "{}Heap: {:.04}{}B live, {:.04}{}B in {} allocations since last output", indent_str.repeat(indent), l, lp, b, bp, count)?;
+                    },
+                    None => writeln!(out, "{}Heap: not counted, build with the counting-allocator feature", indent_str.repeat(indent))?,
                 },
                 _ => {
                     continue; // unhandled/not applicable field type
@@ -150,16 +659,16 @@ impl OutputDevice {
             }
         }
 
-        println!("{}Tracked Bodies:", indent_str.repeat(indent));
+        writeln!(out, "{}Tracked Bodies:", indent_str.repeat(indent))?;
         indent.add_assign(3);
         for (name, body_id, fields) in self.tracked_bodies.iter() {
-            print!("{}{}", indent_str.repeat(indent), name);
+            write!(out, "{}{}", indent_str.repeat(indent), name)?;
             
             if let Some(body) = sim.present().get_body_ref(*body_id) {
-                println!(" ({:?})", body.physics_category());
+                writeln!(out, " ({:?})", body.physics_category())?;
                 
                 if fields.is_empty() {
-                    println!("No Fields Tracked");
+                    writeln!(out, "No Fields Tracked")?;
                 } else {
                     indent.add_assign(1);
                     for field in fields.iter() {
@@ -167,25 +676,34 @@ impl OutputDevice {
                         match field {
                             OutputField::KineticEnergy => {
                                 let (e, ep) = format_si_value(body.kinetic_energy() / body.mass());
-                                println!("{}KIN={:+09.04}{}J/kg", i, e, ep);
+                                writeln!(out, "{}KIN={:+09.04}{}J/kg", i, e, ep)?;
                             },
                             OutputField::Position => {
                                 let (x, xp) = format_si_value(body.position().x);
                                 let (y, yp) = format_si_value(body.position().y);
                                 let (z, zp) = format_si_value(body.position().z);
-                                println!("{}POS={:+09.04}{}m, {:+09.04}{}m, {:+09.04}{}m", i, x, xp, y, yp, z, zp);
+                                writeln!(out, "{}POS={:+09.04}{}m, {:+09.04}{}m, {:+09.04}{}m", i, x, xp, y, yp, z, zp)?;
                             },
                             OutputField::Velocity => {
-                                let (x, xp) = format_si_value(body.velocity().x as f64);
-                                let (y, yp) = format_si_value(body.velocity().y as f64);
-                                let (z, zp) = format_si_value(body.velocity().z as f64);
-                                println!("{}VEL={:+09.04}{}m/s, {:+09.04}{}m/s, {:+09.04}{}m/s", i, x, xp, y, yp, z, zp);
+                                let (x, xp) = format_si_value(body.velocity().x);
+                                let (y, yp) = format_si_value(body.velocity().y);
+                                let (z, zp) = format_si_value(body.velocity().z);
+                                writeln!(out, "{}VEL={:+09.04}{}m/s, {:+09.04}{}m/s, {:+09.04}{}m/s", i, x, xp, y, yp, z, zp)?;
                             },
                             OutputField::Acceleration => {
-                                let (x, xp) = format_si_value(body.acceleration().x as f64);
-                                let (y, yp) = format_si_value(body.acceleration().y as f64);
-                                let (z, zp) = format_si_value(body.acceleration().z as f64);
-                                println!("{}ACC={:+09.04}{}m/s^2, {:+09.04}{}m/s^2, {:+09.04}{}m/s^2", i, x, xp, y, yp, z, zp);
+                                let (x, xp) = format_si_value(body.acceleration().x);
+                                let (y, yp) = format_si_value(body.acceleration().y);
+                                let (z, zp) = format_si_value(body.acceleration().z);
+                                writeln!(out, "{}ACC={:+09.04}{}m/s^2, {:+09.04}{}m/s^2, {:+09.04}{}m/s^2", i, x, xp, y, yp, z, zp)?;
+                            },
+                            OutputField::Sgp4Deviation => {
+                                match sim.sgp4_deviation(*body_id) {
+                                    Some(deviation) => {
+                                        let (d, dp) = format_si_value(deviation);
+                                        writeln!(out, "{}SGP4 DEV={:09.04}{}m", i, d, dp)?;
+                                    },
+                                    None => writeln!(out, "{}SGP4 DEV=N/A", i)?,
+                                }
                             },
                             _ => {
                                 continue; // unhandled/not applicable field type
@@ -197,11 +715,78 @@ impl OutputDevice {
             }
         }
         indent.sub_assign(3);
-        println!();
+        writeln!(out)?;
+        Ok(())
     }
 }
 
-fn format_si_value(n: f64) -> (f64, &'static str) {
+/// A single value in a row of tabular output
+enum Value {
+    Integer(usize),
+    Number(f64),
+    Vector(DVec3),
+    Text(Option<String>),
+}
+
+impl Value {
+    fn to_csv(&self) -> String {
+        let number = |n: f64| if n.is_finite() { n.to_string() } else { String::new() };
+        match self {
+            Value::Integer(n) => n.to_string(),
+            Value::Number(n) => number(*n),
+            Value::Vector(v) => format!("{},{},{}", number(v.x), number(v.y), number(v.z)),
+            Value::Text(text) => text.as_deref().map(csv_escape).unwrap_or_default(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        // json has no representation for NaN or infinity
+        let number = |n: f64| if n.is_finite() { n.to_string() } else { String::from("null") };
+        match self {
+            Value::Integer(n) => n.to_string(),
+            Value::Number(n) => number(*n),
+            Value::Vector(v) => format!("[{},{},{}]", number(v.x), number(v.y), number(v.z)),
+            Value::Text(text) => text.as_deref().map(json_string).unwrap_or_else(|| String::from("null")),
+        }
+    }
+}
+
+fn csv_escape(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        String::from(text)
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn phase_column(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Clone => "clone_ms",
+        Phase::Clear => "clear_ms",
+        Phase::Forces => "forces_ms",
+        Phase::Integration => "integration_ms",
+        Phase::Commit => "commit_ms",
+        Phase::Output => "output_ms",
+    }
+}
+
+pub(crate) fn format_si_value(n: f64) -> (f64, &'static str) {
     if n == 0.0 {
         return (0.0, "")
     } else if n.is_nan() {
@@ -246,19 +831,150 @@ fn format_mem_value(n: usize) -> (f64, &'static str) {
     }
 }
 
+impl Observer for OutputDevice {
+    fn frame(&mut self, sim: &Simulation) {
+        self.output(sim);
+    }
+
+    fn finish(&mut self, sim: &Simulation) -> std::io::Result<()> {
+        OutputDevice::finish(self, sim)
+    }
+
+    /// Output only ever shows the present, frames that are behind schedule are dropped to catch up
+    fn may_skip_frames(&self) -> bool {
+        true
+    }
+
+    fn heap_use(&self) -> usize {
+        MemUse::heap_use(self)
+    }
+
+    fn remove_body(&mut self, id: usize) {
+        OutputDevice::remove_body(self, id)
+    }
+}
+
 impl MemUse for OutputDevice {
-    fn memory_use(&self) -> usize {
+    fn heap_use(&self) -> usize {
         let mut total = 0;
-        total += ::std::mem::size_of_val(&self.target);
-        total += ::std::mem::size_of_val(&self.format);
-        total += ::std::mem::size_of_val(&self.tracked_bodies);
-        total += ::std::mem::size_of_val(&self.global_fields);
+        total += self.tracked_bodies.heap_use();
+        total += self.global_fields.heap_use();
+        total += self.sink.heap_use();
+        total += self.oem.heap_use();
+        total += self.oem_center.heap_use();
+        total += self.trajectory.heap_use();
+        total += self.trajectory_ids.heap_use();
+        total += self.plot.heap_use();
+        total += self.svg.heap_use();
+        total += self.images.heap_use();
         total
     }
 }
 
-pub trait MemUse: Sized {
-    fn memory_use(&self) -> usize {
-        ::std::mem::size_of::<Self>()
+impl MemUse for OutputField {}
+
+impl MemUse for OutputSink {
+    fn heap_use(&self) -> usize {
+        ::std::mem::size_of_val(&*self.writer) + self.buffer_capacity + self.description.heap_use()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked_device(sim: &mut Simulation, format: OutputFormat) -> OutputDevice {
+        let id = sim.make_physics_body().named("Probe, 1").with_transform(DVec3::new(1.0, 2.0, 3.0), None).add();
+        OutputDevice {
+            format,
+            global_fields: vec![OutputField::Frames, OutputField::Time],
+            tracked_bodies: vec![(String::from("Probe, 1"), id, vec![OutputField::Position, OutputField::Sgp4Deviation])],
+            ..OutputDevice::default()
+        }
+    }
+
+    /// A writer appending to a buffer the test keeps a handle on
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_frequencies() {
+        assert_eq!("every".parse(), Ok(OutputFrequency::EveryFrame));
+        assert_eq!("10 Hz".parse(), Ok(OutputFrequency::Hertz(10.0)));
+        assert_eq!("100 frames".parse(), Ok(OutputFrequency::Frames(100)));
+        assert_eq!("1 h".parse(), Ok(OutputFrequency::SimTime(3600.0)));
+        assert!("0 Hz".parse::<OutputFrequency>().is_err());
+        assert_eq!("0 frames".parse::<OutputFrequency>(), Err(UnitParseError::InvalidNumber(String::from("0 frames"))));
+        assert!("-1 h".parse::<OutputFrequency>().is_err());
+        assert!("fortnightly".parse::<OutputFrequency>().is_err());
+
+        // a timestep which doesn't divide the interval still outputs once per interval, the first frame and then
+        // once at or after each whole second up to 30
+        let mut device = OutputDevice::default();
+        device.set_frequency(OutputFrequency::SimTime(1.0));
+        let outputs = (1..=100).filter(|&frame| device.due(frame, frame as f64 * 0.3)).count();
+        assert_eq!(outputs, 31);
+
+        // and a timestep longer than the interval outputs every frame, not once per boundary crossed
+        device.set_frequency(OutputFrequency::SimTime(1.0));
+        assert_eq!((1..=10).filter(|&frame| device.due(frame, frame as f64 * 2.5)).count(), 10);
+
+        device.set_frequency(OutputFrequency::Frames(4));
+        assert_eq!((1..=100).filter(|&frame| device.due(frame, 0.0)).count(), 25);
+    }
+
+    #[test]
+    fn csv_rows() {
+        let mut sim = Simulation::new();
+        let mut device = tracked_device(&mut sim, OutputFormat::Csv);
+
+        let mut buffer = Vec::new();
+        device.write_csv_row(&sim, &mut buffer).unwrap();
+        device.write_csv_row(&sim, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        // the header is only written once, and every row has a value (possibly empty) for each column
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "frame,time,utc,\"Probe, 1.position.x\",\"Probe, 1.position.y\",\"Probe, 1.position.z\",\"Probe, 1.sgp4_deviation\"");
+        assert_eq!(lines[1], "0,0,,1,2,3,");
+    }
+
+    #[test]
+    fn tracking_follows_removed_bodies() {
+        let mut sim = Simulation::new();
+        let other = sim.make_physics_body().named("Other").with_transform(DVec3::new(-1.0e6, 0.0, 0.0), None).add();
+        let mut device = tracked_device(&mut sim, OutputFormat::Jsonl);
+        let buffer = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        device.sink = Some(OutputSink { writer: Box::new(SharedBuffer(std::sync::Arc::clone(&buffer))), buffer_capacity: 0, description: String::from("buffer") });
+        sim.add_observer(Box::new(device));
+
+        // the probe was added after the removed body, output keeps following it rather than whatever takes its id
+        assert!(sim.remove_body(other));
+        sim.make_physics_body().named("Newcomer").with_transform(DVec3::new(7.0, 8.0, 9.0), None).add();
+        sim.step();
+        let text = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        assert!(text.contains("\"Probe, 1\":{\"position\":[1,2,3]"), "{}", text);
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut sim = Simulation::new();
+        sim.set_epoch(crate::epoch::Epoch::J2000);
+        let device = tracked_device(&mut sim, OutputFormat::Jsonl);
+
+        let mut buffer = Vec::new();
+        device.write_json_line(&sim, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert_eq!(text, "{\"frame\":0,\"time\":0,\"utc\":\"2000-01-01T11:58:55.816\",\"bodies\":{\"Probe, 1\":{\"position\":[1,2,3],\"sgp4_deviation\":null}}}\n");
     }
 }
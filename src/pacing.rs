@@ -0,0 +1,237 @@
+use std::{sync::{Arc, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
+
+use crate::debug::MemUse;
+
+// Real time pacing
+//
+// Holds simulated time to a multiple of wall clock time, the time warp. Pacing is measured from an anchor, the wall
+// and simulated time at which the current warp took effect, so rounding in individual sleeps never accumulates.
+//
+//   ahead of the anchor line       sleep until the step is due
+//   behind by up to `max_lag`      step back to back and skip output until caught up
+//   behind by more than `max_lag`  count the warp as missed and re-anchor, dropping the backlog
+//
+// A warp of zero holds the simulation paused. The warp is shared through `TimeWarp` so that a front end or another
+// thread can change it while the simulation runs, each change re-anchors
+
+/// A shared handle to a time warp, in simulated seconds per wall clock second
+#[derive(Debug, Clone)]
+pub struct TimeWarp(Arc<AtomicU64>);
+
+impl TimeWarp {
+    pub fn new(warp: f64) -> Self {
+        TimeWarp(Arc::new(AtomicU64::new(warp.max(0.0).to_bits())))
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Negative warps are taken as zero, pausing the simulation
+    pub fn set(&self, warp: f64) {
+        self.0.store(warp.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Where the next step stands against the pacing schedule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    Ahead(Duration), // wait this long before stepping
+    OnTime,
+    Behind, // step without output to catch up
+}
+
+#[derive(Debug)]
+pub struct Pacer {
+    warp: TimeWarp,
+    pub max_lag: Duration, // lag beyond which the pacer gives up on catching up
+    pub output_lag: Duration, // lag beyond which output is skipped
+    anchor: Option<(Instant, f64, f64)>, // wall time, sim time and warp pacing is measured from
+    skipped_outputs: u64,
+    overruns: u64,
+    slowest: Option<f64>, // lowest warp achieved before giving up on catching up
+    slept: Duration,
+}
+
+const PAUSED_POLL: Duration = Duration::from_millis(10);
+
+impl Pacer {
+    pub fn new(warp: f64) -> Self {
+        Pacer::with_handle(TimeWarp::new(warp))
+    }
+
+    pub fn with_handle(warp: TimeWarp) -> Self {
+        Pacer {
+            warp,
+            max_lag: Duration::from_secs(1),
+            output_lag: Duration::from_millis(20),
+            anchor: None,
+            skipped_outputs: 0,
+            overruns: 0,
+            slowest: None,
+            slept: Duration::default(),
+        }
+    }
+
+    /// The shared warp, changes made through it apply from the next step
+    pub fn time_warp(&self) -> TimeWarp {
+        self.warp.clone()
+    }
+
+    /// Where a step from `sim_time` of length `timestep` stands against the schedule
+    pub fn schedule(&mut self, sim_time: f64, timestep: f64) -> Schedule {
+        self.schedule_at(Instant::now(), sim_time, timestep)
+    }
+
+    /// As `schedule`, with the wall clock reading `now`
+    pub fn schedule_at(&mut self, now: Instant, sim_time: f64, timestep: f64) -> Schedule {
+        let warp = self.warp.get();
+        let (anchor_wall, anchor_sim) = match self.anchor {
+            Some((wall, sim, anchor_warp)) if anchor_warp == warp => (wall, sim),
+            _ => {
+                self.anchor = Some((now, sim_time, warp));
+                (now, sim_time)
+            },
+        };
+        if warp == 0.0 {
+            return Schedule::Ahead(PAUSED_POLL)
+        }
+
+        let due = anchor_wall + Duration::from_secs_f64(((sim_time + timestep - anchor_sim) / warp).max(0.0));
+        if due > now {
+            return Schedule::Ahead(due - now)
+        }
+
+        let lag = now - due;
+        if lag > self.max_lag {
+            let achieved = (sim_time - anchor_sim) / (now - anchor_wall).as_secs_f64();
+            self.slowest = Some(self.slowest.map_or(achieved, |slowest| slowest.min(achieved)));
+            self.overruns += 1;
+            self.anchor = Some((now, sim_time, warp));
+            Schedule::Behind
+        } else if lag > self.output_lag {
+            Schedule::Behind
+        } else {
+            Schedule::OnTime
+        }
+    }
+
+    /// Drops the anchor, so pacing restarts from the next step instead of trying to make up for a pause
+    pub fn restart(&mut self) {
+        self.anchor = None;
+    }
+
+    /// Blocks until a step from `sim_time` is due, returning false if output should be skipped to catch up
+    pub fn pace(&mut self, sim_time: f64, timestep: f64) -> bool {
+        loop {
+            match self.schedule(sim_time, timestep) {
+                Schedule::Ahead(wait) => self.sleep(wait),
+                Schedule::OnTime => return true,
+                Schedule::Behind => {
+                    self.skipped_outputs += 1;
+                    return false
+                },
+            }
+        }
+    }
+
+    pub fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+        self.slept += duration;
+    }
+
+    /// Steps that skipped output and times the pacer gave up on catching up
+    pub fn missed(&self) -> (u64, u64) {
+        (self.skipped_outputs, self.overruns)
+    }
+
+    /// The lowest warp achieved when the pacer gave up on catching up, None if it never fell that far behind
+    pub fn slowest(&self) -> Option<f64> {
+        self.slowest
+    }
+
+    pub fn summary(&self) -> String {
+        let slowest = self.slowest.map(|slowest| format!(", running at {:.3}x at worst", slowest)).unwrap_or_default();
+        format!(
+            "pacing: {}x, slept {:.3} s, skipped output on {} steps, fell behind {} times{}\n",
+            self.warp.get(), self.slept.as_secs_f64(), self.skipped_outputs, self.overruns, slowest
+        )
+    }
+}
+
+impl MemUse for Pacer {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_sim_time_to_the_warp() {
+        let mut pacer = Pacer::new(10.0);
+        let start = Instant::now();
+        let mut now = start;
+        for step in 0..20 {
+            loop {
+                match pacer.schedule_at(now, step as f64 * 0.1, 0.1) {
+                    Schedule::Ahead(wait) => now += wait,
+                    schedule => {
+                        assert_eq!(schedule, Schedule::OnTime);
+                        break
+                    },
+                }
+            }
+        }
+        // two simulated seconds at 10x take a fifth of a second
+        assert!(((now - start).as_secs_f64() - 0.2).abs() < 1.0e-6, "{:?}", now - start);
+        assert_eq!(pacer.missed(), (0, 0));
+
+        // pace sleeps until each step is due, however much longer it may take
+        let mut pacer = Pacer::new(10.0);
+        let start = Instant::now();
+        for step in 0..5 {
+            pacer.pace(step as f64 * 0.1, 0.1);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn skips_output_then_gives_up_when_behind() {
+        let mut pacer = Pacer::new(1000.0);
+        pacer.max_lag = Duration::from_millis(50);
+        let start = Instant::now();
+        assert!(matches!(pacer.schedule_at(start, 0.0, 0.001), Schedule::Ahead(_)));
+
+        // steps that take far longer than the 1 us they are due in
+        assert_eq!(pacer.schedule_at(start + Duration::from_millis(30), 0.0, 0.001), Schedule::Behind);
+        assert_eq!(pacer.missed(), (0, 0));
+        assert_eq!(pacer.schedule_at(start + Duration::from_millis(100), 0.001, 0.001), Schedule::Behind);
+        assert_eq!(pacer.missed(), (0, 1));
+        assert!(pacer.slowest().is_some_and(|slowest| (slowest - 0.01).abs() < 1.0e-9));
+
+        // re-anchored, so the next step is on schedule again
+        assert!(matches!(pacer.schedule_at(start + Duration::from_millis(100), 0.001, 1.0), Schedule::Ahead(_)));
+
+        // output is skipped while behind, the lag here is at least the sleep and far short of a minute
+        let mut pacer = Pacer::new(1000.0);
+        pacer.max_lag = Duration::from_secs(60);
+        assert!(matches!(pacer.schedule(0.0, 0.001), Schedule::Ahead(_)));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!pacer.pace(0.0, 0.001));
+        assert_eq!(pacer.missed(), (1, 0));
+    }
+
+    #[test]
+    fn warp_changes_at_runtime() {
+        let mut pacer = Pacer::new(1.0);
+        let warp = pacer.time_warp();
+        assert!(matches!(pacer.schedule(0.0, 60.0), Schedule::Ahead(wait) if wait > Duration::from_secs(59)));
+
+        warp.set(1.0e6);
+        assert!(matches!(pacer.schedule(0.0, 60.0), Schedule::Ahead(wait) if wait < Duration::from_millis(1)));
+
+        warp.set(0.0);
+        assert_eq!(pacer.schedule(0.0, 60.0), Schedule::Ahead(PAUSED_POLL));
+        warp.set(-5.0);
+        assert_eq!(warp.get(), 0.0);
+    }
+}
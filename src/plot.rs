@@ -0,0 +1,261 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{debug::MemUse, math::DVec3, observer::trails_after_removal, sim::*};
+
+// Terminal plots
+//
+// A top down character plot of the X-Y plane (+X right, +Y up, per the coordinate notes in lib.rs) centred on a focus
+// body or the origin. Every body is drawn, tracked bodies are labelled with their names and leave short trails
+//
+//   +-------------------------------+
+//   |              . .              |
+//   |          .        EARTH       |
+//   |              +                |
+//   +--------------------- 1.50e11 m+
+//
+// Terminal cells are roughly twice as tall as they are wide, so the x axis is stretched to keep orbits round
+
+/// How distances from the focus are mapped onto the plot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlotScaling {
+    /// Linear, fitted to the furthest body
+    Auto,
+    /// Logarithmic in distance from the focus, so that close satellites and distant planets share a plot
+    LogRadial,
+}
+
+impl std::str::FromStr for PlotScaling {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "AUTO" | "LINEAR" => Ok(PlotScaling::Auto),
+            "LOG" | "LOGRADIAL" => Ok(PlotScaling::LogRadial),
+            _ => Err(()),
+        }
+    }
+}
+
+const COLORS: [&str; 6] = ["\x1b[36m", "\x1b[33m", "\x1b[32m", "\x1b[35m", "\x1b[31m", "\x1b[34m"];
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone)]
+pub struct TerminalPlot {
+    pub width: usize,
+    pub height: usize,
+    pub scaling: PlotScaling,
+    pub focus: Option<usize>,
+    pub trail_length: usize,
+    pub ansi: bool,
+    trails: HashMap<usize, VecDeque<DVec3>>, // focus relative positions of tracked bodies, newest last
+}
+
+impl Default for TerminalPlot {
+    fn default() -> Self {
+        TerminalPlot { width: 79, height: 31, scaling: PlotScaling::Auto, focus: None, trail_length: 24, ansi: true, trails: HashMap::new() }
+    }
+}
+
+/// A single character cell of the plot
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cell {
+    glyph: char,
+    color: Option<usize>,
+}
+
+impl TerminalPlot {
+    /// Drops the trail of a removed body. A plot focused on it is centred on the origin again, with fresh trails
+    pub fn remove_body(&mut self, id: usize) {
+        trails_after_removal(&mut self.focus, &mut self.trails, id);
+    }
+
+    /// Records the current positions of the tracked bodies and draws the plot
+    pub fn render(&mut self, sim: &Simulation, tracked: &[(String, usize)]) -> String {
+        let frame = sim.present();
+        let origin = self.focus.and_then(|id| frame.get_body_ref(id)).map(|body| body.position()).unwrap_or_default();
+
+        for (_, id) in tracked.iter() {
+            if let Some(body) = frame.get_body_ref(*id) {
+                let trail = self.trails.entry(*id).or_default();
+                trail.push_back(body.position() - origin);
+                while trail.len() > self.trail_length + 1 {
+                    trail.pop_front();
+                }
+            }
+        }
+
+        let bodies: Vec<(usize, DVec3, PhysicsCategory)> = (0..frame.kinematic_data().len())
+            .filter_map(|id| frame.get_body_ref(id))
+            .map(|body| (body.id(), body.position() - origin, body.physics_category()))
+            .collect();
+
+        // the furthest and closest distances drawn set the scale
+        let distances = bodies.iter().map(|(_, p, _)| planar_distance(p)).chain(self.trails.values().flatten().map(planar_distance));
+        let (nearest, furthest) = distances.filter(|d| *d > 0.0).fold((f64::INFINITY, 0.0f64), |(lo, hi), d| (lo.min(d), hi.max(d)));
+        let extent = if furthest > 0.0 { furthest * 1.05 } else { 1.0 };
+        let inner = if nearest.is_finite() { nearest * 0.5 } else { 1.0 };
+
+        let (w, h) = (self.width.max(8), self.height.max(4));
+        let mut grid = vec![vec![Cell { glyph: ' ', color: None }; w]; h];
+        let (centre_column, centre_row) = ((w - 1) / 2, (h - 1) / 2); // whole cells, so the focus never straddles two
+        let to_cell = |p: &DVec3| -> Option<(usize, usize)> {
+            let (x, y) = self.project(p, extent, inner);
+            let column = centre_column as f64 + (x * centre_column as f64).round();
+            let row = centre_row as f64 - (y * centre_row as f64).round();
+            if (0.0..w as f64).contains(&column) && (0.0..h as f64).contains(&row) {
+                Some((column as usize, row as usize))
+            } else {
+                None
+            }
+        };
+
+        // trails first so that bodies are drawn over them
+        for (index, (_, id)) in tracked.iter().enumerate() {
+            if let Some(trail) = self.trails.get(id) {
+                for point in trail.iter().rev().skip(1) {
+                    if let Some((c, r)) = to_cell(point) {
+                        grid[r][c] = Cell { glyph: '.', color: Some(index) };
+                    }
+                }
+            }
+        }
+
+        if let Some((c, r)) = to_cell(&DVec3::zero()) {
+            grid[r][c] = Cell { glyph: '+', color: None };
+        }
+
+        for (id, position, category) in bodies.iter() {
+            if let Some((c, r)) = to_cell(position) {
+                let glyph = match category {
+                    PhysicsCategory::Gravitational | PhysicsCategory::Scripted => '*',
+                    _ => 'o',
+                };
+                let color = tracked.iter().position(|(_, tracked_id)| tracked_id == id);
+                grid[r][c] = Cell { glyph, color };
+
+                // labels go to the right of the marker, clipped at the border
+                if let Some(index) = color {
+                    for (i, ch) in tracked[index].0.chars().enumerate() {
+                        match grid[r].get_mut(c + 2 + i) {
+                            Some(cell) => *cell = Cell { glyph: ch, color },
+                            None => break,
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut out = String::new();
+        if self.ansi {
+            out.push_str("\x1b[H\x1b[2J"); // home the cursor and clear, so successive frames animate in place
+        }
+        out.push('+');
+        out.push_str(&"-".repeat(w));
+        out.push_str("+\n");
+        for row in grid.iter() {
+            out.push('|');
+            for cell in row.iter() {
+                match (self.ansi, cell.color) {
+                    (true, Some(color)) => {
+                        out.push_str(COLORS[color % COLORS.len()]);
+                        out.push(cell.glyph);
+                        out.push_str(RESET);
+                    },
+                    _ => out.push(cell.glyph),
+                }
+            }
+            out.push_str("|\n");
+        }
+
+        let scale = match self.scaling {
+            PlotScaling::Auto => format!(" {:.2e} m", extent),
+            PlotScaling::LogRadial => format!(" log {:.2e}..{:.2e} m", inner, extent),
+        };
+        let rule = w.saturating_sub(scale.chars().count());
+        out.push('+');
+        out.push_str(&"-".repeat(rule));
+        out.push_str(&scale[scale.len().saturating_sub(w)..]);
+        out.push_str("+\n");
+        out
+    }
+
+    /// Maps a focus relative position into [-1, 1] plot coordinates
+    fn project(&self, p: &DVec3, extent: f64, inner: f64) -> (f64, f64) {
+        let aspect = self.height.max(4) as f64 * 2.0 / self.width.max(8) as f64; // cells are about twice as tall as wide
+        let (x, y) = (p.x * aspect, p.y);
+        match self.scaling {
+            PlotScaling::Auto => (x / extent, y / extent),
+            PlotScaling::LogRadial => {
+                let r = planar_distance(p);
+                if r == 0.0 {
+                    return (0.0, 0.0)
+                }
+                let scaled = log_radius(r, inner, extent);
+                (x / r * scaled, y / r * scaled)
+            },
+        }
+    }
+}
+
+fn planar_distance(p: &DVec3) -> f64 {
+    (p.x * p.x + p.y * p.y).sqrt()
+}
+
+/// Maps a distance in [inner, extent] logarithmically onto (0, 1], distances under `inner` are pulled to the centre
+fn log_radius(r: f64, inner: f64, extent: f64) -> f64 {
+    (1.0 + r / inner).ln() / (1.0 + extent / inner).ln()
+}
+
+impl MemUse for TerminalPlot {
+    fn heap_use(&self) -> usize {
+        self.trails.heap_use()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sun_and_planet() -> (Simulation, usize, usize) {
+        let mut sim = Simulation::new();
+        let sun = sim.make_physics_body().named("Sun").with_physics_category(PhysicsCategory::Gravitational).add();
+        let planet = sim.make_physics_body().named("Planet").with_transform(DVec3::new(1.0e11, 0.0, 0.0), None).add();
+        (sim, sun, planet)
+    }
+
+    #[test]
+    fn draws_bodies_and_labels() {
+        let (sim, _, planet) = sun_and_planet();
+        let mut plot = TerminalPlot { width: 41, height: 11, ansi: false, ..TerminalPlot::default() };
+        let text = plot.render(&sim, &[(String::from("PLANET"), planet)]);
+        let rows: Vec<&str> = text.lines().collect();
+
+        // border, 11 rows, border. the sun is drawn over the origin marker in the middle row
+        assert_eq!(rows.len(), 13);
+        assert_eq!(rows[6].chars().nth(21), Some('*'));
+        assert!(rows[6].contains("o PLANET"));
+        assert!(rows[12].ends_with("m+"));
+    }
+
+    #[test]
+    fn focus_and_trails() {
+        let (sim, sun, planet) = sun_and_planet();
+        let mut plot = TerminalPlot { width: 41, height: 11, ansi: false, focus: Some(planet), trail_length: 3, ..TerminalPlot::default() };
+        for _ in 0..5 {
+            plot.render(&sim, &[(String::from("PLANET"), planet)]);
+        }
+        assert_eq!(plot.trails[&planet].len(), 4);
+
+        // centred on the planet, the sun is now to the left of the middle
+        let text = plot.render(&sim, &[(String::from("PLANET"), planet), (String::from("SUN"), sun)]);
+        let middle = text.lines().nth(6).unwrap();
+        assert!(middle.find('*').unwrap() < middle.find('o').unwrap());
+    }
+
+    #[test]
+    fn log_scaling() {
+        assert!((log_radius(1.0e11, 1.0e7, 1.0e11) - 1.0).abs() < 1e-12);
+        // a satellite ten thousand times closer than the planet still lands well away from the centre
+        assert!(log_radius(1.0e7, 1.0e7, 1.0e11) > 0.05);
+        assert!(log_radius(2.0e7, 1.0e7, 1.0e11) > log_radius(1.0e7, 1.0e7, 1.0e11));
+    }
+}
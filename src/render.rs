@@ -0,0 +1,579 @@
+use std::{ collections::{HashMap, VecDeque}, fs::File, io::{ BufWriter, Write } };
+
+use crate::{ debug::MemUse, math::DVec3, observer::trails_after_removal, sim::*, units::{ Length, UnitParseError } };
+
+// Offscreen frame rendering
+//
+// A small software rasteriser for assembling animations of runs on machines without a GPU. Bodies are drawn as discs
+// scaled by their bounding radius, never smaller than a minimum point size, tracked bodies get coloured trails and
+// labels. Images are written as binary PPM or as PNG through a minimal built-in encoder (uncompressed deflate blocks,
+// so files are about as large as PPM but open anywhere)
+//
+// The default camera looks down the -Z axis at the origin, giving the same top-down view as the terminal plot with +X
+// to the right and +Y up, and zooms to fit every body
+
+pub type Rgb = [u8; 3];
+
+const BACKGROUND: Rgb = [4, 4, 16];
+const UNTRACKED: Rgb = [200, 200, 200];
+const COLORS: [Rgb; 6] = [[80, 200, 255], [255, 200, 60], [100, 230, 100], [240, 110, 240], [255, 90, 80], [120, 140, 255]];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "PPM" => Ok(ImageFormat::Ppm),
+            "PNG" => Ok(ImageFormat::Png),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Parallel projection, `extent` is the height of the view in metres or None to fit every body
+    Orthographic { extent: Option<f64> },
+    /// Pinhole projection with a vertical field of view in radians
+    Perspective { fov: f64 },
+}
+
+/// Where the scene is viewed from, positions are relative to the focus body when one is set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub position: Option<DVec3>, // None places the camera above the look at point on +Z, far enough to see every body
+    pub look_at: DVec3,
+    pub up: DVec3,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera { position: None, look_at: DVec3::zero(), up: DVec3::new(0.0, 1.0, 0.0), projection: Projection::Orthographic { extent: None } }
+    }
+}
+
+/// A camera resolved for one frame, mapping scene positions to pixels
+#[derive(Debug, Clone, Copy)]
+struct View {
+    position: DVec3,
+    right: DVec3,
+    up: DVec3,
+    forward: DVec3,
+    projection: Projection,
+    scale: f64, // metres per pixel when orthographic, focal length in pixels when perspective
+    width: f64,
+    height: f64,
+}
+
+impl View {
+    /// Fits the view to the scene, given as the position and bounding radius of each body
+    fn new(camera: &Camera, scene: &[(DVec3, f64)], width: usize, height: usize) -> Self {
+        let (width, height) = (width as f64, height as f64);
+        let furthest = scene.iter().map(|(p, r)| p.length_to(&camera.look_at) + r).fold(0.0, f64::max).max(1.0);
+        let position = camera.position.unwrap_or(camera.look_at + DVec3::new(0.0, 0.0, furthest * 3.0));
+
+        let forward = (camera.look_at - position).normalize();
+        let mut right = forward.cross(&camera.up);
+        if right.magnitude() < 1e-9 {
+            // looking along the up vector, fall back to whichever axis isn't
+            let fallback = if forward.y.abs() < 0.9 { DVec3::new(0.0, 1.0, 0.0) } else { DVec3::new(1.0, 0.0, 0.0) };
+            right = forward.cross(&fallback);
+        }
+        let right = right.normalize();
+        let up = right.cross(&forward);
+
+        let mut view = View { position, right, up, forward, projection: camera.projection, scale: 1.0, width, height };
+        view.scale = match camera.projection {
+            Projection::Orthographic { extent: Some(extent) } => extent / height,
+            Projection::Orthographic { extent: None } => {
+                // fit the largest offset from the centre of the view, in either direction, with a margin
+                let fit = scene.iter().map(|(p, r)| {
+                    let d = *p - camera.look_at;
+                    ((d.dot(&right).abs() + r) * height / width).max(d.dot(&up).abs() + r)
+                }).fold(0.0, f64::max);
+                (fit * 2.2).max(1.0) / height
+            },
+            Projection::Perspective { fov } => height * 0.5 / (fov * 0.5).tan(),
+        };
+        view
+    }
+
+    /// Pixel coordinates and the size of one metre in pixels at that depth, None when behind a perspective camera
+    fn project(&self, p: &DVec3) -> Option<(f64, f64, f64)> {
+        let d = *p - self.position;
+        let (x, y, z) = (d.dot(&self.right), d.dot(&self.up), d.dot(&self.forward));
+        let (x, y, pixels_per_meter) = match self.projection {
+            Projection::Orthographic { .. } => (x / self.scale, y / self.scale, 1.0 / self.scale),
+            Projection::Perspective { .. } => {
+                if z <= 0.0 {
+                    return None
+                }
+                (x / z * self.scale, y / z * self.scale, self.scale / z)
+            },
+        };
+        Some((self.width * 0.5 + x, self.height * 0.5 - y, pixels_per_meter))
+    }
+
+    fn depth(&self, p: &DVec3) -> f64 {
+        (*p - self.position).dot(&self.forward)
+    }
+}
+
+/// An RGB raster
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, fill: Rgb) -> Self {
+        Image { width, height, pixels: vec![fill; width * height] }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x < self.width && y < self.height { Some(self.pixels[y * self.width + x]) } else { None }
+    }
+
+    pub fn set(&mut self, x: i64, y: i64, color: Rgb) {
+        if (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y) {
+            self.pixels[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    pub fn fill_disc(&mut self, cx: f64, cy: f64, radius: f64, color: Rgb) {
+        // clamped so that enormous discs close to the camera don't loop over millions of off-screen pixels
+        let x0 = (cx - radius).floor().max(-1.0) as i64;
+        let x1 = (cx + radius).ceil().min(self.width as f64) as i64;
+        let y0 = (cy - radius).floor().max(-1.0) as i64;
+        let y1 = (cy + radius).ceil().min(self.height as f64) as i64;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+                if dx * dx + dy * dy <= radius * radius {
+                    self.set(x, y, color);
+                }
+            }
+        }
+    }
+
+    /// Bresenham line, segments entirely off-screen are skipped
+    pub fn line(&mut self, from: (f64, f64), to: (f64, f64), color: Rgb) {
+        let (w, h) = (self.width as f64, self.height as f64);
+        if (from.0 < 0.0 && to.0 < 0.0) || (from.0 >= w && to.0 >= w) || (from.1 < 0.0 && to.1 < 0.0) || (from.1 >= h && to.1 >= h) {
+            return
+        }
+        let (mut x, mut y) = (from.0 as i64, from.1 as i64);
+        let (x1, y1) = (to.0 as i64, to.1 as i64);
+        if (x1 - x).abs() + (y1 - y).abs() > 4 * (self.width + self.height) as i64 {
+            return // wildly long segments only happen when a point is near a perspective camera's plane
+        }
+        let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+        let (sx, sy) = (if x < x1 { 1 } else { -1 }, if y < y1 { 1 } else { -1 });
+        let mut error = dx + dy;
+        loop {
+            self.set(x, y, color);
+            if x == x1 && y == y1 {
+                break
+            }
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Draws text in the built-in 5x7 font with its top left corner at (x, y), each font pixel `scale` pixels square
+    pub fn text(&mut self, x: i64, y: i64, text: &str, scale: i64, color: Rgb) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as i64 * 6 * scale;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..5 {
+                    if bits & (0x10 >> column) != 0 {
+                        for sy in 0..scale {
+                            for sx in 0..scale {
+                                self.set(left + column * scale + sx, y + row as i64 * scale + sy, color);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn rgb_bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.pixels.iter().flat_map(|p| p.iter().copied())
+    }
+
+    pub fn write_ppm(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.rgb_bytes().collect::<Vec<u8>>())
+    }
+
+    pub fn write_png(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        out.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bits per channel, RGB, deflate, adaptive filtering, no interlace
+        write_png_chunk(out, b"IHDR", &header)?;
+
+        // every scanline starts with its filter type, 0 for none
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            raw.push(0);
+            raw.extend(row.iter().flat_map(|p| p.iter().copied()));
+        }
+        write_png_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+        write_png_chunk(out, b"IEND", &[])
+    }
+
+    pub fn write(&self, format: ImageFormat, out: &mut dyn Write) -> std::io::Result<()> {
+        match format {
+            ImageFormat::Ppm => self.write_ppm(out),
+            ImageFormat::Png => self.write_png(out),
+        }
+    }
+}
+
+fn write_png_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()).copied());
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps data in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&(!length).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(bytes: impl Iterator<Item = u8>) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b can overflow
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Renders the simulation every output frame and writes each image to `<prefix><frame number>.<extension>`
+#[derive(Debug, Clone)]
+pub struct FrameRenderer {
+    pub prefix: String,
+    pub format: ImageFormat,
+    pub width: usize,
+    pub height: usize,
+    pub camera: Camera,
+    pub focus: Option<usize>,
+    pub min_point_radius: f64, // in pixels
+    pub trail_length: usize,
+    pub labels: bool,
+    trails: HashMap<usize, VecDeque<DVec3>>, // focus relative positions of tracked bodies, newest last
+}
+
+impl FrameRenderer {
+    pub fn new(prefix: &str, format: ImageFormat) -> Self {
+        FrameRenderer {
+            prefix: String::from(prefix),
+            format,
+            width: 640,
+            height: 480,
+            camera: Camera::default(),
+            focus: None,
+            min_point_radius: 2.0,
+            trail_length: 200,
+            labels: true,
+            trails: HashMap::new(),
+        }
+    }
+
+    /// Keeps the camera and trails on the same bodies when one is removed. If the camera followed the removed body it
+    /// looks at the origin from then on, and the trails start over
+    pub fn remove_body(&mut self, id: usize) {
+        trails_after_removal(&mut self.focus, &mut self.trails, id);
+    }
+
+    pub fn path_for(&self, frame_number: usize) -> String {
+        format!("{}{:06}.{}", self.prefix, frame_number, self.format.extension())
+    }
+
+    /// Renders the current frame and writes it out
+    pub fn write_frame(&mut self, sim: &Simulation, tracked: &[(String, usize)]) -> std::io::Result<()> {
+        let image = self.render(sim, tracked);
+        let mut out = BufWriter::new(File::create(self.path_for(sim.present().frame_number()))?);
+        image.write(self.format, &mut out)?;
+        out.flush()
+    }
+
+    /// Records the current positions of the tracked bodies and draws the scene
+    pub fn render(&mut self, sim: &Simulation, tracked: &[(String, usize)]) -> Image {
+        let frame = sim.present();
+        let origin = self.focus.and_then(|id| frame.get_body_ref(id)).map(|body| body.position()).unwrap_or_default();
+
+        for (_, id) in tracked.iter() {
+            if let Some(body) = frame.get_body_ref(*id) {
+                let trail = self.trails.entry(*id).or_default();
+                trail.push_back(body.position() - origin);
+                while trail.len() > self.trail_length + 1 {
+                    trail.pop_front();
+                }
+            }
+        }
+
+        let mut bodies: Vec<(usize, DVec3, f64)> = (0..frame.kinematic_data().len())
+            .filter_map(|id| frame.get_body_ref(id))
+            .map(|body| (body.id(), body.position() - origin, body.bounding_radius()))
+            .collect();
+
+        let scene: Vec<(DVec3, f64)> = bodies.iter().map(|(_, p, r)| (*p, *r)).collect();
+        let view = View::new(&self.camera, &scene, self.width, self.height);
+        let mut image = Image::new(self.width, self.height, BACKGROUND);
+
+        for (index, (_, id)) in tracked.iter().enumerate() {
+            let color = COLORS[index % COLORS.len()];
+            let faded = [color[0] / 2, color[1] / 2, color[2] / 2];
+            if let Some(trail) = self.trails.get(id) {
+                let points: Vec<Option<(f64, f64, f64)>> = trail.iter().map(|p| view.project(p)).collect();
+                for pair in points.windows(2) {
+                    if let (Some(a), Some(b)) = (pair[0], pair[1]) {
+                        image.line((a.0, a.1), (b.0, b.1), faded);
+                    }
+                }
+            }
+        }
+
+        // painter's algorithm, furthest first
+        bodies.sort_by(|a, b| view.depth(&b.1).partial_cmp(&view.depth(&a.1)).unwrap_or(std::cmp::Ordering::Equal));
+        let text_scale = (self.height as i64 / 480).max(1);
+        for (id, position, radius) in bodies.iter() {
+            if let Some((x, y, pixels_per_meter)) = view.project(position) {
+                let index = tracked.iter().position(|(_, tracked_id)| tracked_id == id);
+                let color = index.map(|i| COLORS[i % COLORS.len()]).unwrap_or(UNTRACKED);
+                let r = (radius * pixels_per_meter).max(self.min_point_radius);
+                image.fill_disc(x, y, r, color);
+                if let (true, Some(index)) = (self.labels, index) {
+                    // above right of the disc, or above left when that would run off the image
+                    let name = &tracked[index].0;
+                    let offset = r.min(self.height as f64) + 3.0;
+                    let text_width = (name.chars().count() * 6) as f64 * text_scale as f64;
+                    let left = if x + offset + text_width > self.width as f64 { x - offset - text_width } else { x + offset };
+                    image.text(left as i64, (y - offset) as i64 - 7 * text_scale, name, text_scale, color);
+                }
+            }
+        }
+        image
+    }
+}
+
+/// Parses a comma separated position with length units on each component, e.g. "0, 0, 2 AU"
+pub fn parse_length_vector(value: &str) -> Result<DVec3, UnitParseError> {
+    let components: Vec<&str> = value.split(',').collect();
+    if components.len() != 3 {
+        return Err(UnitParseError::InvalidNumber(String::from(value)))
+    }
+    let mut result = [0.0; 3];
+    for (i, component) in components.iter().enumerate() {
+        result[i] = component.trim().parse::<Length>()?.meters();
+    }
+    Ok(DVec3::new(result[0], result[1], result[2]))
+}
+
+/// Rows of the 5x7 font, the high bit of the low five is the leftmost column
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1e],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        ' ' => [0x00; 7],
+        _ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+impl MemUse for FrameRenderer {
+    fn heap_use(&self) -> usize {
+        self.prefix.heap_use() + self.trails.heap_use()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789".iter().copied()), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(&vec![0xff; 100_000]), {
+            let (a, b) = (0..100_000u64).fold((1u64, 0u64), |(a, b), _| ((a + 255) % 65521, (b + a + 255) % 65521));
+            ((b << 16) | a) as u32
+        });
+    }
+
+    #[test]
+    fn png_structure() {
+        let mut image = Image::new(300, 300, [1, 2, 3]); // 270300 bytes of scanlines, five stored blocks
+        image.set(299, 299, [9, 8, 7]);
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 1, 44, 0, 0, 1, 44]);
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+
+        // the stored blocks hold the scanlines verbatim, the last pixel sits just before the adler checksum
+        let idat_length = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(idat_length, 2 + 270_300 + 5 * 5 + 4);
+        let idat_end = 41 + idat_length;
+        assert_eq!(&png[idat_end - 7..idat_end - 4], &[9, 8, 7]);
+
+        let mut ppm = Vec::new();
+        image.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n300 300\n255\n"));
+        assert_eq!(ppm.len(), 15 + 270_000);
+    }
+
+    #[test]
+    fn default_camera_looks_down() {
+        let scene = [(DVec3::new(10.0, 0.0, 0.0), 0.0), (DVec3::new(0.0, -10.0, 0.0), 0.0)];
+        let view = View::new(&Camera::default(), &scene, 200, 100);
+        let (cx, cy, _) = view.project(&DVec3::zero()).unwrap();
+        let (x, y, _) = view.project(&DVec3::new(10.0, 0.0, 0.0)).unwrap();
+        assert!((cx - 100.0).abs() < 1e-9 && (cy - 50.0).abs() < 1e-9);
+        assert!(x > cx && (y - cy).abs() < 1e-9); // +X to the right
+        let (x, y, _) = view.project(&DVec3::new(0.0, 10.0, 0.0)).unwrap();
+        assert!(y < cy && (x - cx).abs() < 1e-9); // +Y up
+        assert!(y > 0.0 && view.project(&DVec3::new(10.0, 0.0, 0.0)).unwrap().0 < 200.0); // everything fits
+    }
+
+    #[test]
+    fn perspective_camera() {
+        let camera = Camera { position: Some(DVec3::new(-10.0, 0.0, 0.0)), look_at: DVec3::zero(), up: DVec3::new(0.0, 0.0, 1.0), projection: Projection::Perspective { fov: std::f64::consts::FRAC_PI_2 } };
+        let view = View::new(&camera, &[], 100, 100);
+
+        // a 90 degree field of view puts a point as far off axis as it is deep on the edge of the image
+        let (x, y, near) = view.project(&DVec3::new(0.0, 0.0, 10.0)).unwrap();
+        assert!((x - 50.0).abs() < 1e-9 && y.abs() < 1e-9);
+        let (_, _, far) = view.project(&DVec3::new(10.0, 0.0, 0.0)).unwrap();
+        assert!((near / far - 2.0).abs() < 1e-9);
+        assert!(view.project(&DVec3::new(-20.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn renders_bodies() {
+        let mut sim = Simulation::new();
+        sim.make_physics_body().named("Sun").with_bounding_radius(1.0e10).add();
+        let probe = sim.make_physics_body().named("Probe").with_transform(DVec3::new(1.0e11, 0.0, 0.0), None).add();
+
+        let mut renderer = FrameRenderer::new("unused_", ImageFormat::Png);
+        renderer.width = 200;
+        renderer.height = 100;
+        let image = renderer.render(&sim, &[(String::from("PROBE"), probe)]);
+
+        // the sun is a disc at the centre, the probe a minimum size point in its trail colour on the right
+        assert_eq!(image.get(100, 50), Some(UNTRACKED));
+        assert_eq!(image.get(100, 50 - 3), Some(UNTRACKED));
+        let probe_x = (0..200).rev().find(|x| image.get(*x, 50) == Some(COLORS[0])).unwrap();
+        assert!(probe_x > 150);
+        assert!(image.pixels.iter().filter(|p| **p == COLORS[0]).count() > 20); // the label
+        assert_eq!(renderer.path_for(42), "unused_000042.png");
+    }
+
+    #[test]
+    fn vectors_with_units() {
+        assert_eq!(parse_length_vector("0, 1 km, 2 AU").unwrap(), DVec3::new(0.0, 1000.0, 2.0 * 149_597_870_700.0));
+        assert!(parse_length_vector("1, 2").is_err());
+        assert!(parse_length_vector("1, 2, 3 kg").is_err());
+    }
+}
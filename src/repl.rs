@@ -0,0 +1,463 @@
+use std::{error::Error, fmt::Display, io::{BufRead, Write}, str::FromStr, sync::mpsc::{self, TryRecvError}, time::{Duration, Instant}};
+
+use crate::{cli::body_table, pacing::Schedule, scenario::{BodySpec, Scenario, ScenarioError}, sim::*, units::Time};
+
+// Interactive console
+//
+// Commands are read from stdin on a separate thread so that they are still accepted while the simulation runs, a
+// running simulation steps in short slices between checking for input. Bodies are added with the builder style
+//
+//   add Probe .mass(500 kg) .position(7000 km, 0 m, 0 m) .velocity(0 m/s, 7.5 km/s, 0 m/s) .relative_to(Earth)
+//
+// where each call takes a key and value of a scenario [body] section, and checkpoints are saved as scenario files
+
+const HELP: &str = "\
+commands:
+  run | resume          run until paused or a termination condition is met
+  pause                 stop running
+  step [n]              step n frames, 1 by default
+  until <time>          step until the simulation time reaches <time>, e.g. until 2 h
+  status                frame, time, timestep, integrator and termination conditions
+  list                  list every body
+  get <name> [<name>]   state of the named bodies, relative to the second name if given
+  timestep <time>       change the timestep
+  integrator <name>     euler, semiimpliciteuler, velocityverlet or blockvelocityverlet
+  warp <factor> | off   pace running to a multiple of wall clock time, or run flat out
+  add <name> .<key>(<value>) ...
+                        add a body, keys are those of a scenario [body] section
+  save [path]           write a checkpoint scenario, checkpoint.scn by default
+  profile               time spent in each phase of the step
+  quit";
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Help,
+    Run,
+    Pause,
+    Step(usize),
+    Until(Time),
+    Status,
+    List,
+    Get(String, Option<String>),
+    Timestep(Time),
+    Integrator(IntegrationMethod),
+    Warp(Option<f64>),
+    Add(BodySpec),
+    Save(String),
+    Profile,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (word, rest) = match s.split_once(char::is_whitespace) {
+            Some((word, rest)) => (word, rest.trim()),
+            None => (s, ""),
+        };
+
+        match word.to_ascii_lowercase().as_str() {
+            "help" | "?" => Ok(Command::Help),
+            "run" | "resume" | "continue" => Ok(Command::Run),
+            "pause" => Ok(Command::Pause),
+            "step" if rest.is_empty() => Ok(Command::Step(1)),
+            "step" => rest.parse().map(Command::Step).map_err(|_| format!("expected a number of frames, found '{}'", rest)),
+            "until" => rest.parse().map(Command::Until).map_err(|e| e.to_string()),
+            "status" | "time" => Ok(Command::Status),
+            "list" | "bodies" => Ok(Command::List),
+            "get" | "print" => {
+                let mut names = rest.split_whitespace();
+                match (names.next(), names.next()) {
+                    (Some(name), relative_to) => Ok(Command::Get(String::from(name), relative_to.map(String::from))),
+                    (None, _) => Err(String::from("expected a body name")),
+                }
+            },
+            "timestep" | "dt" => match rest.parse::<Time>() {
+                Ok(timestep) if timestep.seconds() > 0.0 => Ok(Command::Timestep(timestep)),
+                Ok(_) => Err(String::from("the timestep must be positive")),
+                Err(e) => Err(e.to_string()),
+            },
+            "integrator" => rest.parse().map(Command::Integrator),
+            "warp" if rest.eq_ignore_ascii_case("off") => Ok(Command::Warp(None)),
+            "warp" => match rest.trim_end_matches('x').parse::<f64>() {
+                Ok(warp) if warp >= 0.0 && warp.is_finite() => Ok(Command::Warp(Some(warp))),
+                _ => Err(format!("expected a time warp factor or off, found '{}'", rest)),
+            },
+            "add" => parse_body(rest).map(Command::Add),
+            "save" | "checkpoint" if rest.is_empty() => Ok(Command::Save(String::from("checkpoint.scn"))),
+            "save" | "checkpoint" => Ok(Command::Save(String::from(rest))),
+            "profile" => Ok(Command::Profile),
+            "quit" | "exit" => Ok(Command::Quit),
+            _ => Err(format!("unknown command '{}', try help", word)),
+        }
+    }
+}
+
+/// Parses `Name .key(value) .key(value) ...` into the spec of a new body
+fn parse_body(text: &str) -> Result<BodySpec, String> {
+    let (name, mut rest) = match text.find('.') {
+        Some(i) => (text[..i].trim(), &text[i..]),
+        None => (text.trim(), ""),
+    };
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(String::from("expected a single word body name before the properties"))
+    }
+
+    let mut body = BodySpec { name: String::from(name), ..BodySpec::default() };
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok(body)
+        }
+        let call = rest.strip_prefix('.').ok_or_else(|| format!("expected .<key>(<value>), found '{}'", rest))?;
+        let (key, after) = call.split_once('(').ok_or_else(|| format!("expected ( after '{}'", call))?;
+        let (value, after) = after.split_once(')').ok_or_else(|| format!("unclosed ( after '{}'", key))?;
+        let key = key.trim().to_ascii_lowercase();
+        body.set(&key, value.trim()).map_err(|e| format!("{}: {}", key, e))?;
+        rest = after;
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplError {
+    Command(String),
+    UnknownBody(String),
+    Scenario(ScenarioError),
+    Io(std::io::Error),
+}
+
+impl Display for ReplError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Command(message) => write!(f, "{}", message),
+            Self::UnknownBody(name) => write!(f, "unknown body: '{}'", name),
+            Self::Scenario(inner) => write!(f, "{}", inner),
+            Self::Io(inner) => write!(f, "{}", inner),
+        }
+    }
+}
+
+impl Error for ReplError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Scenario(inner) => Some(inner),
+            Self::Io(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScenarioError> for ReplError {
+    fn from(error: ScenarioError) -> Self {
+        ReplError::Scenario(error)
+    }
+}
+
+impl From<std::io::Error> for ReplError {
+    fn from(error: std::io::Error) -> Self {
+        ReplError::Io(error)
+    }
+}
+
+#[derive(Debug)]
+pub struct Repl {
+    sim: Simulation,
+    running: bool,
+}
+
+impl Repl {
+    pub fn new(sim: Simulation) -> Self {
+        Repl { sim, running: false }
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.sim
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Carries out a command, returning the text to print
+    pub fn execute(&mut self, command: Command) -> Result<String, ReplError> {
+        match command {
+            Command::Help => Ok(String::from(HELP)),
+            Command::Run if self.sim.finished() => {
+                Err(ReplError::Command(String::from("a termination condition has been met, use step or until to go further")))
+            },
+            Command::Run => {
+                self.running = true;
+                Ok(String::from("running, type pause to stop"))
+            },
+            Command::Pause if !self.running => Ok(String::from("not running")),
+            Command::Pause => {
+                self.running = false;
+                Ok(format!("paused at {}", self.time()))
+            },
+            Command::Step(frames) => {
+                for _ in 0..frames {
+                    self.sim.step();
+                }
+                Ok(self.time())
+            },
+            Command::Until(time) => {
+                if time.seconds() <= self.sim.present().sim_time() {
+                    return Err(ReplError::Command(format!("already at {}", self.time())))
+                }
+                while self.sim.present().sim_time() < time.seconds() {
+                    self.sim.step();
+                }
+                Ok(self.time())
+            },
+            Command::Status => Ok(format!(
+                "{}, timestep {} s, {:?}, {}, {}\nstops at: {:?}",
+                self.time(), self.sim.timestep(), self.sim.integration_method(),
+                self.sim.pacer().map(|pacer| format!("{}x", pacer.time_warp().get())).unwrap_or_else(|| String::from("flat out")),
+                if self.running { "running" } else { "paused" }, self.sim.termination_conditions()
+            )),
+            Command::List => Ok(body_table(self.sim.present())),
+            Command::Get(name, relative_to) => self.body_state(&name, relative_to.as_deref()),
+            Command::Timestep(timestep) => {
+                self.sim.set_timestep(timestep.seconds());
+                Ok(format!("timestep {} s", timestep.seconds()))
+            },
+            Command::Integrator(method) => {
+                self.sim.set_integration_method(method);
+                Ok(format!("integrator {:?}", method))
+            },
+            Command::Warp(None) => {
+                self.sim.set_pacer(None);
+                Ok(String::from("running flat out"))
+            },
+            Command::Warp(Some(warp)) => {
+                self.sim.set_time_warp(warp);
+                Ok(format!("time warp {}x", warp))
+            },
+            Command::Add(body) => {
+                let name = body.name.clone();
+                Scenario { bodies: vec![body], ..Scenario::default() }.apply(&mut self.sim)?;
+                self.body_state(&name, None)
+            },
+            Command::Save(path) => {
+                Scenario::from_simulation(&self.sim).save(&path)?;
+                Ok(format!("saved {} at {}", path, self.time()))
+            },
+            Command::Profile => Ok(self.sim.telemetry().summary(self.sim.present().sim_time())),
+            Command::Quit => Ok(String::new()),
+        }
+    }
+
+    /// Steps a running simulation for up to `slice` of wall time, returning a message if a termination condition stops it.
+    /// A paced simulation waits out its schedule within the slice, so commands are still read at a low time warp
+    pub fn advance(&mut self, slice: Duration) -> Option<String> {
+        let start = Instant::now();
+        while self.running && start.elapsed() < slice {
+            if self.sim.finished() {
+                self.running = false;
+                return Some(format!("stopped at {}", self.time()))
+            }
+
+            let (sim_time, timestep) = (self.sim.present().sim_time(), self.sim.timestep());
+            if let Some(pacer) = self.sim.pacer_mut() {
+                if let Schedule::Ahead(wait) = pacer.schedule(sim_time, timestep) {
+                    pacer.sleep(wait.min(slice.saturating_sub(start.elapsed())));
+                    continue;
+                }
+            }
+            self.sim.step();
+        }
+        None
+    }
+
+    /// Reads commands from stdin until quit or the end of input
+    pub fn run_interactive(mut self) {
+        let (sender, commands) = mpsc::channel();
+        std::thread::spawn(move || {
+            let stdin = std::io::stdin();
+            for line in stdin.lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break
+                }
+            }
+        });
+
+        println!("{}", self.time());
+        prompt();
+        loop {
+            let line = if self.running {
+                match commands.try_recv() {
+                    Ok(line) => Some(line),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match commands.recv() {
+                    Ok(line) => Some(line),
+                    Err(_) => break,
+                }
+            };
+
+            if let Some(line) = line {
+                if !line.trim().is_empty() {
+                    match line.parse::<Command>() {
+                        Ok(Command::Quit) => break,
+                        Ok(command) => match self.execute(command) {
+                            Ok(output) if output.is_empty() => (),
+                            Ok(output) => println!("{}", output),
+                            Err(e) => println!("error: {}", e),
+                        },
+                        Err(e) => println!("error: {}", e),
+                    }
+                }
+                if !self.running {
+                    prompt();
+                }
+            }
+
+            if let Some(message) = self.advance(Duration::from_millis(50)) {
+                println!("{}", message);
+                prompt();
+            }
+        }
+    }
+
+    fn time(&self) -> String {
+        let frame = self.sim.present();
+        match frame.epoch() {
+            Some(epoch) => format!("frame {}, t = {} s ({} UTC)", frame.frame_number(), frame.sim_time(), epoch.to_iso8601(crate::epoch::TimeScale::Utc)),
+            None => format!("frame {}, t = {} s", frame.frame_number(), frame.sim_time()),
+        }
+    }
+
+    fn body_state(&self, name: &str, relative_to: Option<&str>) -> Result<String, ReplError> {
+        let frame = self.sim.present();
+        let origin = match relative_to {
+            Some(other) => match frame.get_named_bodies(other).first() {
+                Some(body) => Some((body.position(), body.velocity())),
+                None => return Err(ReplError::UnknownBody(String::from(other))),
+            },
+            None => None,
+        };
+        let (p0, v0) = origin.unwrap_or_default();
+
+        let bodies = frame.get_named_bodies(name);
+        if bodies.is_empty() {
+            return Err(ReplError::UnknownBody(String::from(name)))
+        }
+
+        let mut text = String::new();
+        for body in bodies.iter() {
+            let (p, v, a) = (body.position() - p0, body.velocity() - v0, body.acceleration());
+            text.push_str(&format!("{} #{} {:?}, {:e} kg\n", name.to_ascii_uppercase(), body.id(), body.physics_category(), body.mass()));
+            text.push_str(&format!("  r = ({:.9e}, {:.9e}, {:.9e}) m, |r| = {:.9e} m\n", p.x, p.y, p.z, p.magnitude()));
+            text.push_str(&format!("  v = ({:.9e}, {:.9e}, {:.9e}) m/s, |v| = {:.9e} m/s\n", v.x, v.y, v.z, v.magnitude()));
+            text.push_str(&format!("  a = ({:.9e}, {:.9e}, {:.9e}) m/s^2", a.x, a.y, a.z));
+        }
+        if let Some(other) = relative_to {
+            text.push_str(&format!("\n  relative to {}", other.to_ascii_uppercase()));
+        }
+        Ok(text)
+    }
+}
+
+fn prompt() {
+    print!("> ");
+    let _ = std::io::stdout().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{Length, Velocity};
+
+    fn earth() -> Repl {
+        let scenario = Scenario::parse("
+            timestep = 10 s
+            [body Earth]
+            category = gravitational
+            mass = 1 Mearth
+            radius = 6371 km
+        ").unwrap();
+        Repl::new(scenario.build().unwrap())
+    }
+
+    #[test]
+    fn builder_syntax() {
+        let command = "add Probe .mass(500 kg) .position(7000 km, 0 m, 0 m) .velocity(0 m/s, 7.5 km/s, 0 m/s) .relative_to(Earth)".parse();
+        let body = match command {
+            Ok(Command::Add(body)) => body,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(body.name, "Probe");
+        assert_eq!(body.position[0], Length::from_kilometers(7000.0));
+        assert_eq!(body.velocity[1], Velocity::from_kilometers_per_second(7.5));
+        assert_eq!(body.relative_to.as_deref(), Some("Earth"));
+
+        assert!("add Probe .mass(1 AU)".parse::<Command>().unwrap_err().starts_with("mass:"));
+        assert!("add Probe .mass(1 kg".parse::<Command>().is_err());
+        assert!("add .mass(1 kg)".parse::<Command>().is_err());
+        assert!("engage 9".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn step_inspect_and_save() {
+        let mut repl = earth();
+        repl.execute("add Probe .position(7000 km, 0 m, 0 m) .velocity(0 m/s, 7.5 km/s, 0 m/s) .relative_to(Earth)".parse().unwrap()).unwrap();
+        repl.execute("step 3".parse().unwrap()).unwrap();
+        assert_eq!(repl.simulation().present().frame_number(), 3);
+        repl.execute("until 2 min".parse().unwrap()).unwrap();
+        assert_eq!(repl.simulation().present().sim_time(), 120.0);
+        assert!(repl.execute("until 1 min".parse().unwrap()).is_err());
+
+        repl.execute("timestep 1 s".parse().unwrap()).unwrap();
+        repl.execute("integrator euler".parse().unwrap()).unwrap();
+        repl.execute("step".parse().unwrap()).unwrap();
+        assert_eq!(repl.simulation().present().sim_time(), 121.0);
+        assert_eq!(repl.simulation().integration_method(), IntegrationMethod::Euler);
+
+        let state = repl.execute("get probe earth".parse().unwrap()).unwrap();
+        assert!(state.starts_with("PROBE #1"));
+        assert!(matches!(repl.execute("get moon".parse().unwrap()), Err(ReplError::UnknownBody(_))));
+
+        let path = std::env::temp_dir().join(format!("ssim_repl_{}.scn", std::process::id()));
+        repl.execute(Command::Save(path.to_string_lossy().into_owned())).unwrap();
+        let resumed = Scenario::load(&path).unwrap().build().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let probe = resumed.present().get_named_bodies("probe")[0].position();
+        assert_eq!(probe, repl.simulation().present().get_named_bodies("probe")[0].position());
+    }
+
+    #[test]
+    fn run_until_terminated() {
+        let mut repl = earth();
+        assert!(repl.execute(Command::Pause).unwrap().contains("not running"));
+        repl.sim.set_termination_condition(TerminationCondition::Frames(20));
+        repl.execute(Command::Run).unwrap();
+        assert!(repl.is_running());
+        while repl.advance(Duration::from_millis(1)).is_none() {}
+        assert!(!repl.is_running());
+        assert_eq!(repl.simulation().present().frame_number(), 20);
+        assert!(repl.execute(Command::Run).is_err());
+    }
+
+    #[test]
+    fn paced_running() {
+        let mut repl = earth();
+        repl.execute("warp 1000x".parse().unwrap()).unwrap();
+        repl.execute(Command::Run).unwrap();
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(100) {
+            repl.advance(Duration::from_millis(20));
+        }
+        let elapsed = start.elapsed();
+
+        // 10 s steps at 1000x are due every 10 ms, a slow machine may fall behind but the run is never ahead
+        let frames = repl.simulation().present().frame_number();
+        assert!(frames >= 1 && frames as u128 <= elapsed.as_millis() / 10, "{} in {:?}", frames, elapsed);
+
+        repl.execute("warp off".parse().unwrap()).unwrap();
+        assert!(repl.simulation().pacer().is_none());
+        assert!("warp fast".parse::<Command>().is_err());
+    }
+}
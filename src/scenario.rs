@@ -0,0 +1,653 @@
+use std::{error::Error, fmt::Display, path::Path, str::FromStr};
+
+use crate::{ephemeris::Rails, epoch::{Epoch, EpochParseError, TimeScale}, horizons::{HorizonsError, HorizonsVectors}, math::DVec3, sgp4::Sgp4, sim::*, tle::{Tle, TleError}, units::*};
+
+// Scenario files
+//
+// A plain text description of the bodies and settings of a simulation. Every value carries its own units
+//
+//   # the earth and the sun
+//   epoch = 2000-01-01T12:00:00 TDB
+//   timestep = 1 h
+//   duration = 1 yr
+//   integrator = velocityverlet
+//
+//   [body Sol]
+//   category = gravitational
+//   mass = 1 Msun
+//   radius = 696340 km
+//   gravparam = 1.32712440018e20 m^3/s^2
+//
+//   [body Earth]
+//   relative_to = Sol
+//   position = 1 AU, 0 m, 0 m
+//   velocity = 0 m/s, 29.78 km/s, 0 m/s
+//
+//   [body Mars]
+//   horizons = mars_vectors.txt # initial state from a saved JPL Horizons vector table, at the scenario epoch
+//
+//   [body Moon]
+//   category = scripted # never integrated, follows its horizons table or else the two-body orbit of its state
+//   relative_to = Earth
+//   position = 384400 km, 0 m, 0 m
+//   velocity = 0 m/s, 1.022 km/s, 0 m/s
+//   orbit_gravparam = 4.035e14 m^3/s^2 # of the two-body orbit, the body's and its centre's together by default
+//
+//   [body ISS]
+//   tle = stations.txt # initial state from the element set of the same name propagated with SGP4, relative to Earth
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Syntax { line: usize, message: String },
+    Unit { line: usize, error: UnitParseError },
+    UnknownBody(String),
+    Horizons(String, HorizonsError),
+    Tle(String, TleError),
+}
+
+impl Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(inner) => write!(f, "failed to read scenario: {}", inner),
+            Self::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            Self::Unit { line, error } => write!(f, "line {}: {}", line, error),
+            Self::UnknownBody(name) => write!(f, "unknown body: '{}'", name),
+            Self::Horizons(path, inner) => write!(f, "{}: {}", path, inner),
+            Self::Tle(path, inner) => write!(f, "{}: {}", path, inner),
+        }
+    }
+}
+
+impl Error for ScenarioError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(inner) => Some(inner),
+            Self::Unit { error, .. } => Some(error),
+            Self::Horizons(_, inner) => Some(inner),
+            Self::Tle(_, inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(error: std::io::Error) -> Self {
+        ScenarioError::Io(error)
+    }
+}
+
+/// The initial state of a single body in a scenario
+#[derive(Debug, Clone, Default)]
+pub struct BodySpec {
+    pub name: String,
+    pub category: PhysicsCategory,
+    pub mass: Option<Mass>,
+    pub radius: Option<Length>,
+    pub grav_param: Option<GravParam>,
+    pub position: [Length; 3],
+    pub velocity: [Velocity; 3],
+    pub relative_to: Option<String>,
+    pub orbit_grav_param: Option<GravParam>, // of the two-body orbit a scripted body follows
+    pub horizons: Option<String>, // path of a horizons vector table to take the initial state from
+    pub tle: Option<String>, // path of a two-line element set file to take the initial state from
+}
+
+impl BodySpec {
+    /// Sets a property from its scenario file key and value
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), PropertyError> {
+        match key {
+            "category" => self.category = value.parse().map_err(PropertyError::Syntax)?,
+            "mass" => self.mass = Some(value.parse().map_err(PropertyError::Unit)?),
+            "radius" => self.radius = Some(value.parse().map_err(PropertyError::Unit)?),
+            "gravparam" => self.grav_param = Some(value.parse().map_err(PropertyError::Unit)?),
+            "position" => self.position = parse_vector(value)?,
+            "velocity" => self.velocity = parse_vector(value)?,
+            "relative_to" => self.relative_to = Some(String::from(value)),
+            "orbit_gravparam" => self.orbit_grav_param = Some(value.parse().map_err(PropertyError::Unit)?),
+            "horizons" => self.horizons = Some(String::from(value)),
+            "tle" => self.tle = Some(String::from(value)),
+            _ => return Err(PropertyError::Syntax(format!("unknown body property '{}'", key))),
+        }
+        Ok(())
+    }
+
+    pub fn position(&self) -> DVec3 {
+        DVec3::new(self.position[0].meters(), self.position[1].meters(), self.position[2].meters())
+    }
+
+    pub fn velocity(&self) -> DVec3 {
+        DVec3::new(self.velocity[0].meters_per_second(), self.velocity[1].meters_per_second(), self.velocity[2].meters_per_second())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub epoch: Option<Epoch>,
+    pub timestep: Option<Time>,
+    pub duration: Option<Time>,
+    pub integrator: Option<IntegrationMethod>,
+    pub bodies: Vec<BodySpec>,
+}
+
+impl Scenario {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scenario, ScenarioError> {
+        let text = std::fs::read_to_string(path)?;
+        Scenario::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Scenario, ScenarioError> {
+        let mut scenario = Scenario::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let syntax = |message: &str| ScenarioError::Syntax { line: line_number, message: String::from(message) };
+            let unit = |error: UnitParseError| ScenarioError::Unit { line: line_number, error };
+
+            if line.starts_with('[') {
+                let header = line.strip_prefix('[').and_then(|h| h.strip_suffix(']')).ok_or_else(|| syntax("unterminated section header"))?;
+                let mut words = header.split_whitespace();
+                match (words.next(), words.next()) {
+                    (Some("body"), Some(name)) => {
+                        scenario.bodies.push(BodySpec { name: String::from(name), ..BodySpec::default() });
+                    },
+                    _ => return Err(syntax("expected a section of the form [body <name>]")),
+                }
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+                None => return Err(syntax("expected <key> = <value>")),
+            };
+
+            match scenario.bodies.last_mut() {
+                None => match key.as_str() {
+                    "epoch" => scenario.epoch = Some(value.parse().map_err(|e: EpochParseError| syntax(&e.to_string()))?),
+                    "timestep" => scenario.timestep = Some(value.parse().map_err(unit)?),
+                    "duration" => scenario.duration = Some(value.parse().map_err(unit)?),
+                    "integrator" => scenario.integrator = Some(value.parse().map_err(|_| syntax("unknown integrator"))?),
+                    _ => return Err(syntax(&format!("unknown setting '{}'", key))),
+                },
+                Some(body) => body.set(&key, value).map_err(|e| e.at(line_number))?,
+            }
+        }
+
+        Ok(scenario)
+    }
+
+    /// Adds the scenarios bodies to the simulation and applies its settings
+    pub fn apply(&self, sim: &mut Simulation) -> Result<(), ScenarioError> {
+        if let Some(epoch) = self.epoch {
+            sim.set_epoch(epoch);
+        }
+
+        if let Some(timestep) = self.timestep {
+            sim.set_timestep(timestep.seconds());
+        }
+
+        if let Some(integrator) = self.integrator {
+            sim.set_integration_method(integrator);
+        }
+
+        if let Some(duration) = self.duration {
+            sim.set_termination_condition(TerminationCondition::ElapsedTime(duration.seconds()));
+        }
+
+        for body in self.bodies.iter() {
+            let vectors = match &body.horizons {
+                Some(path) => Some(HorizonsVectors::load(path).map_err(|e| ScenarioError::Horizons(path.clone(), e))?),
+                None => None,
+            };
+            let element_set = match &body.tle {
+                Some(path) => Some(load_element_set(path, &body.name).map_err(|e| ScenarioError::Tle(path.clone(), e))?),
+                None => None,
+            };
+
+            // horizons states are relative to their centre body when it is part of the scenario, element sets are
+            // always relative to the earth
+            let relative_to = body.relative_to.as_ref()
+                .or_else(|| vectors.as_ref().map(|v| &v.center))
+                .cloned()
+                .or_else(|| element_set.as_ref().map(|_| String::from("Earth")));
+            let relative_id = match &relative_to {
+                Some(name) => match sim.present().get_named_bodies(name).first() {
+                    Some(relative) => Some(relative.id()),
+                    None if body.relative_to.is_none() && element_set.is_none() => None,
+                    None => return Err(ScenarioError::UnknownBody(name.clone())),
+                },
+                None => None,
+            };
+
+            // horizons tables and element sets are only meaningful at their own epochs, so an unanchored scenario is
+            // anchored to the first table record or element set
+            if sim.present().epoch().is_none() {
+                let start = vectors.as_ref().and_then(|vectors| vectors.table.start()).or_else(|| element_set.as_ref().map(|(tle, _)| tle.epoch));
+                if let Some(start) = start {
+                    sim.set_epoch(start);
+                }
+            }
+
+            let epoch = sim.present().epoch().unwrap_or(Epoch::J2000);
+            let mut builder = match (&vectors, &element_set) {
+                (Some(vectors), _) => vectors.apply_to(sim.make_physics_body(), epoch).map_err(|e| ScenarioError::Horizons(body.horizons.clone().unwrap_or_default(), e))?,
+                (None, Some((tle, sgp4))) => tle.apply_to(sim.make_physics_body(), sgp4, epoch).map_err(|e| ScenarioError::Tle(body.tle.clone().unwrap_or_default(), e))?,
+                _ => sim.make_physics_body().with_transform(body.position(), None).with_velocity(body.velocity()),
+            };
+            builder = builder.named(&body.name).with_physics_category(body.category);
+
+            if let Some(mass) = body.mass { builder = builder.with_mass(mass.kilograms()); }
+            if let Some(radius) = body.radius { builder = builder.with_bounding_radius(radius.meters() as f32); }
+            if let Some(grav_param) = body.grav_param { builder = builder.with_grav_param(grav_param.cubic_meters_per_second_squared()); }
+            if let Some(id) = relative_id { builder = builder.relative_to(id); }
+
+            let id = builder.add();
+            if let (Some((_, sgp4)), Some(earth_id)) = (element_set, relative_id) {
+                sim.set_sgp4_reference(id, earth_id, sgp4);
+            }
+
+            // scripted bodies follow their horizons table, else the orbit of their state relative to their centre as
+            // placed, which for an element set is only known once propagated
+            if body.category == PhysicsCategory::Scripted {
+                let rails = match (&vectors, relative_id) {
+                    (Some(vectors), _) => Some(Rails::Table(vectors.table.clone())),
+                    (None, Some(center_id)) => {
+                        let frame = sim.present();
+                        frame.get_body_ref(id).zip(frame.get_body_ref(center_id)).map(|(own, center)| {
+                            let grav_param = body.orbit_grav_param.map(|grav_param| grav_param.cubic_meters_per_second_squared())
+                                .unwrap_or_else(|| own.grav_param() + center.grav_param());
+                            Rails::Kepler {
+                                position: own.position() - center.position(),
+                                velocity: own.velocity() - center.velocity(),
+                                grav_param,
+                                sim_time: frame.sim_time(),
+                            }
+                        })
+                    },
+                    // without a centre a body only has an orbit, about the origin, when given its parameter
+                    (None, None) => body.orbit_grav_param.map(|grav_param| Rails::Kepler {
+                        position: body.position(),
+                        velocity: body.velocity(),
+                        grav_param: grav_param.cubic_meters_per_second_squared(),
+                        sim_time: sim.present().sim_time(),
+                    }),
+                };
+                if let Some(rails) = rails {
+                    sim.set_rails(id, relative_id, rails);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A checkpoint of the present state of a simulation. Bodies are written by absolute position and velocity and
+    /// the epoch is moved on to the present, so the remaining duration is what is left of the elapsed time limit.
+    /// Scripted bodies following a two-body orbit keep it, those following a table or series are written as
+    /// gravitational bodies
+    pub fn from_simulation(sim: &Simulation) -> Scenario {
+        let frame = sim.present();
+        let remaining = sim.termination_conditions().iter().find_map(|condition| match condition {
+            TerminationCondition::ElapsedTime(t) if *t > frame.sim_time() => Some(Time::from_seconds(t - frame.sim_time())),
+            _ => None,
+        });
+
+        // section headers are split on whitespace, so names are written as a single word
+        let name_of = |id: usize| frame.body_name(id).map(|name| name.split_whitespace().collect::<Vec<_>>().join("_")).unwrap_or_else(|| format!("BODY_{}", id));
+
+        // a body on rails is written after its centre, which is looked up by name when the checkpoint is loaded
+        let center_of = |id: usize| sim.rails(id).and_then(|(center, _)| center);
+        let depth = |id: usize| std::iter::successors(Some(id), |id| center_of(*id)).take(frame.kinematic_data().len()).count();
+        let mut ids: Vec<usize> = (0..frame.kinematic_data().len()).collect();
+        ids.sort_by_key(|id| depth(*id));
+
+        let bodies = ids.into_iter().filter_map(|id| frame.get_body_ref(id)).map(|body| {
+            let (p, v) = (body.position(), body.velocity());
+            let mut spec = BodySpec {
+                name: name_of(body.id()),
+                category: body.physics_category(),
+                mass: Some(Mass::from_kilograms(body.mass())),
+                radius: Some(Length::from_meters(body.bounding_radius())),
+                grav_param: Some(GravParam::from_cubic_meters_per_second_squared(body.grav_param())),
+                position: [Length::from_meters(p.x), Length::from_meters(p.y), Length::from_meters(p.z)],
+                velocity: [Velocity::from_meters_per_second(v.x), Velocity::from_meters_per_second(v.y), Velocity::from_meters_per_second(v.z)],
+                ..BodySpec::default()
+            };
+
+            // a two-body orbit is written as the state on it relative to its centre, the tables and series of other
+            // rails can't be, so those bodies carry on from their present state, still pulling on the others
+            match sim.rails(body.id()) {
+                Some((center, rails @ Rails::Kepler { grav_param, .. })) => {
+                    if let Some((p, v)) = rails.state_at(frame.sim_time(), frame.epoch()) {
+                        spec.position = [Length::from_meters(p.x), Length::from_meters(p.y), Length::from_meters(p.z)];
+                        spec.velocity = [Velocity::from_meters_per_second(v.x), Velocity::from_meters_per_second(v.y), Velocity::from_meters_per_second(v.z)];
+                    }
+                    spec.relative_to = center.map(name_of);
+                    spec.orbit_grav_param = Some(GravParam::from_cubic_meters_per_second_squared(*grav_param));
+                },
+                Some(_) => spec.category = PhysicsCategory::Gravitational,
+                None => {},
+            }
+            spec
+        }).collect();
+
+        Scenario {
+            epoch: frame.epoch(),
+            timestep: Some(Time::from_seconds(sim.timestep())),
+            duration: remaining,
+            integrator: Some(sim.integration_method()),
+            bodies,
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    pub fn build(&self) -> Result<Simulation, ScenarioError> {
+        let mut sim = Simulation::new();
+        self.apply(&mut sim)?;
+        Ok(sim)
+    }
+}
+
+/// Writes the scenario file format, values are written in SI units at full precision so that a checkpoint resumes exactly
+impl Display for Scenario {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(epoch) = self.epoch {
+            writeln!(f, "epoch = {} TT", epoch.to_iso8601_with_precision(TimeScale::Tt, 6))?;
+        }
+        if let Some(timestep) = self.timestep {
+            writeln!(f, "timestep = {}", timestep)?;
+        }
+        if let Some(duration) = self.duration {
+            writeln!(f, "duration = {}", duration)?;
+        }
+        if let Some(integrator) = self.integrator {
+            writeln!(f, "integrator = {}", format!("{:?}", integrator).to_ascii_lowercase())?;
+        }
+
+        for body in self.bodies.iter() {
+            writeln!(f, "\n[body {}]", body.name)?;
+            writeln!(f, "category = {}", format!("{:?}", body.category).to_ascii_lowercase())?;
+            if let Some(mass) = body.mass { writeln!(f, "mass = {:e} kg", mass.kilograms())?; }
+            if let Some(radius) = body.radius { writeln!(f, "radius = {:e} m", radius.meters())?; }
+            if let Some(grav_param) = body.grav_param { writeln!(f, "gravparam = {:e} m^3/s^2", grav_param.cubic_meters_per_second_squared())?; }
+            if let Some(relative_to) = &body.relative_to { writeln!(f, "relative_to = {}", relative_to)?; }
+            if let Some(grav_param) = body.orbit_grav_param { writeln!(f, "orbit_gravparam = {:e} m^3/s^2", grav_param.cubic_meters_per_second_squared())?; }
+            if let Some(horizons) = &body.horizons { writeln!(f, "horizons = {}", horizons)?; }
+            if let Some(tle) = &body.tle { writeln!(f, "tle = {}", tle)?; }
+            if body.horizons.is_none() && body.tle.is_none() {
+                let (p, v) = (body.position(), body.velocity());
+                writeln!(f, "position = {:e} m, {:e} m, {:e} m", p.x, p.y, p.z)?;
+                writeln!(f, "velocity = {:e} m/s, {:e} m/s, {:e} m/s", v.x, v.y, v.z)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Scenario {
+    type Err = ScenarioError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scenario::parse(s)
+    }
+}
+
+/// The element set named `name` in a file, or its only element set, with its propagator
+fn load_element_set(path: &str, name: &str) -> Result<(Tle, Sgp4), TleError> {
+    let sets = Tle::load(path)?;
+    let tle = match sets.iter().find(|tle| tle.label().eq_ignore_ascii_case(name)) {
+        Some(tle) => tle.clone(),
+        None if sets.len() == 1 => sets[0].clone(),
+        None => return Err(TleError::NotFound(String::from(name))),
+    };
+    let sgp4 = Sgp4::new(&tle)?;
+    Ok((tle, sgp4))
+}
+
+/// Error from parsing a body property, before the line number is known
+#[derive(Debug)]
+pub enum PropertyError {
+    Syntax(String),
+    Unit(UnitParseError),
+}
+
+impl PropertyError {
+    fn at(self, line: usize) -> ScenarioError {
+        match self {
+            Self::Syntax(message) => ScenarioError::Syntax { line, message },
+            Self::Unit(error) => ScenarioError::Unit { line, error },
+        }
+    }
+}
+
+impl Display for PropertyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Syntax(message) => write!(f, "{}", message),
+            Self::Unit(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for PropertyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Unit(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+fn parse_vector<T: FromStr<Err = UnitParseError> + Copy + Default>(value: &str) -> Result<[T; 3], PropertyError> {
+    let components: Vec<&str> = value.split(',').collect();
+    if components.len() != 3 {
+        return Err(PropertyError::Syntax(format!("expected 3 vector components, found {}", components.len())))
+    }
+
+    let mut result = [T::default(); 3];
+    for (i, component) in components.iter().enumerate() {
+        result[i] = component.parse().map_err(PropertyError::Unit)?;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL_EARTH: &str = "
+        # the earth and the sun
+        timestep = 1 h
+        duration = 1 yr
+        integrator = velocityverlet
+
+        [body Sol]
+        category = gravitational
+        mass = 1 Msun
+        radius = 696340 km
+
+        [body Earth]
+        category = gravitational
+        relative_to = Sol
+        position = 1 AU, 0 m, 0 m
+        velocity = 0 m/s, 29.78 km/s, 0 m/s
+    ";
+
+    #[test]
+    fn parse_scenario() {
+        let scenario = Scenario::parse(SOL_EARTH).unwrap();
+        assert_eq!(scenario.timestep, Some(Time::from_hours(1.0)));
+        assert_eq!(scenario.integrator, Some(IntegrationMethod::VelocityVerlet));
+        assert_eq!(scenario.bodies.len(), 2);
+        assert_eq!(scenario.bodies[1].position().x, METERS_PER_AU);
+        assert_eq!(scenario.bodies[1].velocity().y, 29780.0);
+
+        let sim = scenario.build().unwrap();
+        assert_eq!(sim.present().get_named_bodies("earth").len(), 1);
+    }
+
+    #[test]
+    fn checkpoint_resumes_exactly() {
+        let mut sim = Scenario::parse(SOL_EARTH).unwrap().build().unwrap();
+        sim.set_epoch(Epoch::J2000);
+        for _ in 0..10 {
+            sim.step_simulation();
+        }
+
+        let checkpoint = Scenario::parse(&Scenario::from_simulation(&sim).to_string()).unwrap();
+        assert_eq!(checkpoint.duration, Some(Time::from_years(1.0) - Time::from_hours(10.0)));
+        assert_eq!(checkpoint.integrator, Some(IntegrationMethod::VelocityVerlet));
+        let resumed = checkpoint.build().unwrap();
+        assert!((resumed.present().epoch().unwrap().seconds_since(&Epoch::J2000) - 36000.0).abs() < 1e-6);
+
+        let (original, resumed) = (sim.present().get_named_bodies("earth")[0].clone(), resumed.present().get_named_bodies("earth")[0].clone());
+        assert_eq!(original.position(), resumed.position());
+        assert_eq!(original.velocity(), resumed.velocity());
+        assert_eq!(original.grav_param(), resumed.grav_param());
+    }
+
+    #[test]
+    fn scripted_bodies_are_put_on_rails() {
+        let scenario = Scenario::parse("
+            timestep = 1 h
+            [body Earth]
+            category = gravitational
+            mass = 1 Mearth
+            [body Moon]
+            category = scripted
+            mass = 7.342e22 kg
+            relative_to = Earth
+            position = 384400 km, 0 m, 0 m
+            velocity = 0 m/s, 1.022 km/s, 0 m/s
+        ").unwrap();
+        let mut sim = scenario.build().unwrap();
+        let (earth, moon) = (sim.present().get_named_bodies("earth")[0].id(), sim.present().get_named_bodies("moon")[0].id());
+        assert!(matches!(sim.rails(moon), Some((Some(center), Rails::Kepler { .. })) if center == earth));
+
+        // the moon keeps its distance from the earth on its near circular orbit however far the earth is pulled
+        sim.step_n(24 * 7);
+        let (earth, moon) = (sim.present().get_body_ref(earth).unwrap(), sim.present().get_body_ref(moon).unwrap());
+        assert!(earth.position().magnitude() > 1.0e6);
+        assert!((moon.centers_distance_to(&earth) - 3.844e8).abs() < 5.0e6);
+    }
+
+    #[test]
+    fn unanchored_horizons_scenarios_start_at_the_table() {
+        let path = std::env::temp_dir().join(format!("ssim_scenario_{}.txt", std::process::id()));
+        std::fs::write(&path, "
+Target body name: Earth (399)
+Center body name: Sun (10)
+Output units    : KM-S
+Reference frame : Ecliptic of J2000.0
+$$SOE
+2451544.500000000 = A.D. 2000-Jan-01 00:00:00.0000 TDB
+ X =-2.521092855899356E+07 Y = 1.449279195838006E+08 Z =-6.164165719002485E+02
+ VX=-2.983983333368269E+01 VY=-5.207633918704476E+00 VZ= 6.169236959167168E-05
+2451545.500000000 = A.D. 2000-Jan-02 00:00:00.0000 TDB
+ X =-2.778811619550782E+07 Y = 1.444503536065063E+08 Z =-6.109325017541647E+02
+ VX=-2.982472107896220E+01 VY=-5.848893648703380E+00 VZ= 6.530040025466127E-05
+$$EOE
+").unwrap();
+        let built = Scenario::parse(&format!("[body Earth]\nhorizons = {}", path.display())).unwrap().build();
+        std::fs::remove_file(&path).unwrap();
+        let sim = built.unwrap();
+
+        // the table covers J2000 too, but the scenario starts at its first record
+        assert_eq!(sim.present().epoch(), Some(Epoch::from_julian_date(2451544.5, TimeScale::Tdb)));
+        assert_eq!(sim.present().get_named_bodies("earth")[0].position().x, -2.521092855899356E+10);
+    }
+
+    #[test]
+    fn scripted_element_sets_orbit_their_propagated_state() {
+        let path = std::env::temp_dir().join(format!("ssim_scenario_{}.tle", std::process::id()));
+        std::fs::write(&path, "ISS (ZARYA)
+1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927
+2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537
+").unwrap();
+        let scenario = Scenario::parse(&format!("
+            timestep = 1 min
+            [body Earth]
+            category = gravitational
+            mass = 1 Mearth
+            gravparam = 3.986004418e14 m^3/s^2
+            [body ISS]
+            category = scripted
+            tle = {}
+        ", path.display())).unwrap();
+        let built = scenario.build();
+        std::fs::remove_file(&path).unwrap();
+        let mut sim = built.unwrap();
+
+        // the orbit is that of the element set's state about the earth, not of the zero state of the spec
+        let (earth, iss) = (sim.present().get_named_bodies("earth")[0].id(), sim.present().get_named_bodies("iss")[0].id());
+        assert!(matches!(sim.rails(iss), Some((Some(center), Rails::Kepler { .. })) if center == earth));
+        sim.step_n(90);
+        let (earth, iss) = (sim.present().get_body_ref(earth).unwrap(), sim.present().get_body_ref(iss).unwrap());
+        assert!((iss.centers_distance_to(&earth) - 6.72e6).abs() < 5.0e4, "{}", iss.centers_distance_to(&earth));
+    }
+
+    #[test]
+    fn checkpoint_keeps_scripted_orbits() {
+        let mut sim = Scenario::parse("
+            timestep = 1 h
+            [body Tender]
+            [body Earth]
+            category = gravitational
+            mass = 1 Mearth
+            [body Moon]
+            category = scripted
+            mass = 7.342e22 kg
+            relative_to = Earth
+            position = 384400 km, 0 m, 0 m
+            velocity = 0 m/s, 1.022 km/s, 0 m/s
+            [body Probe]
+            position = 0 m, 10000 km, 0 m
+            velocity = 6 km/s, 0 m/s, 0 m/s
+        ").unwrap().build().unwrap();
+        sim.set_epoch(Epoch::J2000);
+        let id = |sim: &Simulation, name: &str| sim.present().get_named_bodies(name)[0].id();
+
+        // the tender circles the moon added after it, the marker the origin, and a jupiter sized beacon sits on a table
+        let (tender, moon) = (id(&sim, "tender"), id(&sim, "moon"));
+        let orbit = Rails::Kepler { position: DVec3::new(2.0e6, 0.0, 0.0), velocity: DVec3::new(0.0, 1565.0, 0.0), grav_param: 4.9e12, sim_time: 0.0 };
+        assert!(sim.set_rails(tender, Some(moon), orbit));
+        let marker = sim.make_physics_body().named("Marker").add();
+        let orbit = Rails::Kepler { position: DVec3::new(1.0e9, 0.0, 0.0), velocity: DVec3::new(0.0, 3.0e5, 0.0), grav_param: 1.0e20, sim_time: 0.0 };
+        assert!(sim.set_rails(marker, None, orbit));
+        let beacon = sim.make_physics_body().named("Beacon").with_mass(1.898e27).with_grav_param(1.26687e17).add();
+        let record = |days: f64| crate::ephemeris::EphemerisRecord { epoch: Epoch::J2000.offset_by(days * 86400.0), position: DVec3::new(0.0, 0.0, 1.0e11), velocity: DVec3::zero() };
+        assert!(sim.set_rails(beacon, None, Rails::Table(crate::ephemeris::EphemerisTable::new(vec![record(0.0), record(10.0)]))));
+        sim.step_n(24);
+
+        let mut resumed = Scenario::parse(&Scenario::from_simulation(&sim).to_string()).unwrap().build().unwrap();
+        let (moon, earth) = (id(&resumed, "moon"), id(&resumed, "earth"));
+        assert!(matches!(resumed.rails(moon), Some((Some(center), Rails::Kepler { .. })) if center == earth));
+        assert!(matches!(resumed.rails(id(&resumed, "tender")), Some((Some(center), Rails::Kepler { grav_param, .. })) if center == moon && *grav_param == 4.9e12));
+        assert!(matches!(resumed.rails(id(&resumed, "marker")), Some((None, Rails::Kepler { grav_param, .. })) if *grav_param == 1.0e20));
+        assert!(resumed.rails(id(&resumed, "beacon")).is_none());
+        assert_eq!(resumed.present().get_named_bodies("beacon")[0].physics_category(), PhysicsCategory::Gravitational);
+
+        // the scripted bodies carry on along the same orbits, and pull the probe the same way. The beacon pulls it
+        // about 50 km a day, and only drifts a few hundred metres now that it is integrated
+        sim.step_n(24);
+        resumed.step_n(24);
+        for name in ["earth", "moon", "tender", "marker", "probe"] {
+            let (original, resumed) = (sim.present().get_named_bodies(name)[0].position(), resumed.present().get_named_bodies(name)[0].position());
+            assert!(original.length_to(&resumed) < 1.0e-3, "{}: {:?} {:?}", name, original, resumed);
+        }
+    }
+
+    #[test]
+    fn scenario_errors() {
+        assert!(matches!(Scenario::parse("[body A]\nposition = 1 AU, 0 m"), Err(ScenarioError::Syntax { line: 2, .. })));
+        assert!(matches!(Scenario::parse("[body A]\nmass = 1 AU"), Err(ScenarioError::Unit { line: 2, .. })));
+        assert!(matches!(Scenario::parse("[body A]\nrelative_to = B").unwrap().build(), Err(ScenarioError::UnknownBody(_))));
+    }
+}
@@ -0,0 +1,995 @@
+use std::{error::Error, f64::consts::PI, fmt::Display};
+
+use crate::{debug::MemUse, ephemeris::equatorial_to_ecliptic, epoch::{Epoch, TimeScale}, math::DVec3, tle::Tle};
+
+// SGP4
+//
+// The simplified general perturbations model, following Vallado, Crawford, Hujsak and Kelso, "Revisiting Spacetrack
+// Report #3" (2006) with the WGS-72 constants the element sets are fitted against. Element sets with periods of 225
+// minutes or more add the deep space (SDP4) terms: secular and long period lunar and solar perturbations, and the
+// geopotential resonances of geosynchronous and 12 hour orbits, integrated numerically from the epoch
+//
+// Propagated states are in the TEME frame (true equator, mean equinox of the element set epoch) in km and km/s.
+// `state_at` rotates them into the ecliptic of J2000 used by the simulation
+
+const RADIUS_EARTH_KM: f64 = 6378.135;
+const XKE: f64 = 0.07436691613317342; // sqrt(mu) in earth radii^1.5 per minute, 60 / sqrt(6378.135^3 / 398600.8)
+const J2: f64 = 0.001082616;
+const J3: f64 = -0.00000253881;
+const J4: f64 = -0.00000165597;
+const J3OJ2: f64 = J3 / J2;
+const VKMPERSEC: f64 = RADIUS_EARTH_KM * XKE / 60.0;
+const TWO_PI: f64 = 2.0 * PI;
+
+/// Element sets with a period at or above this many minutes need the deep space model
+pub const DEEP_SPACE_PERIOD_MINUTES: f64 = 225.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sgp4Error {
+    Eccentricity(f64),
+    MeanMotion(f64),
+    SemiLatusRectum(f64),
+    Decayed { minutes: f64 },
+}
+
+impl Display for Sgp4Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Eccentricity(e) => write!(f, "mean eccentricity {} is out of range", e),
+            Self::MeanMotion(n) => write!(f, "mean motion {} is not positive", n),
+            Self::SemiLatusRectum(p) => write!(f, "semi-latus rectum {} is negative", p),
+            Self::Decayed { minutes } => write!(f, "satellite has decayed {:.1} min after epoch", minutes),
+        }
+    }
+}
+
+impl Error for Sgp4Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+/// An initialised SGP4 propagator for a single element set
+#[derive(Debug, Clone)]
+pub struct Sgp4 {
+    epoch: Epoch,
+
+    // mean elements at epoch, radians and radians per minute
+    bstar: f64,
+    ecco: f64,
+    inclo: f64,
+    nodeo: f64,
+    argpo: f64,
+    mo: f64,
+    no_unkozai: f64,
+
+    // secular rates and drag coefficients
+    isimp: bool,
+    con41: f64,
+    x1mth2: f64,
+    x7thm1: f64,
+    cc1: f64,
+    cc4: f64,
+    cc5: f64,
+    d2: f64,
+    d3: f64,
+    d4: f64,
+    delmo: f64,
+    eta: f64,
+    argpdot: f64,
+    omgcof: f64,
+    sinmao: f64,
+    t2cof: f64,
+    t3cof: f64,
+    t4cof: f64,
+    t5cof: f64,
+    xlcof: f64,
+    aycof: f64,
+    xmcof: f64,
+    mdot: f64,
+    nodecf: f64,
+    nodedot: f64,
+
+    deep_space: Option<Box<DeepSpace>>,
+}
+
+impl Sgp4 {
+    pub fn new(tle: &Tle) -> Result<Sgp4, Sgp4Error> {
+        let no_kozai = tle.mean_motion * TWO_PI / 1440.0;
+        let ecco = tle.eccentricity;
+        let inclo = tle.inclination.to_radians();
+        let argpo = tle.arg_perigee.to_radians();
+        let mo = tle.mean_anomaly.to_radians();
+        let bstar = tle.bstar;
+
+        if no_kozai <= 0.0 {
+            return Err(Sgp4Error::MeanMotion(no_kozai))
+        }
+        if !(0.0..1.0).contains(&ecco) {
+            return Err(Sgp4Error::Eccentricity(ecco))
+        }
+
+        // recover the original mean motion and semi-major axis from the kozai mean motion in the element set
+        let eccsq = ecco * ecco;
+        let omeosq = 1.0 - eccsq;
+        let rteosq = omeosq.sqrt();
+        let cosio = inclo.cos();
+        let cosio2 = cosio * cosio;
+
+        let ak = (XKE / no_kozai).powf(2.0 / 3.0);
+        let d1 = 0.75 * J2 * (3.0 * cosio2 - 1.0) / (rteosq * omeosq);
+        let del = d1 / (ak * ak);
+        let adel = ak * (1.0 - del * del - del * (1.0 / 3.0 + 134.0 * del * del / 81.0));
+        let del = d1 / (adel * adel);
+        let no = no_kozai / (1.0 + del);
+
+        let ao = (XKE / no).powf(2.0 / 3.0);
+        let sinio = inclo.sin();
+        let po = ao * omeosq;
+        let con42 = 1.0 - 5.0 * cosio2;
+        let con41 = -con42 - cosio2 - cosio2;
+        let posq = po * po;
+        let rp = ao * (1.0 - ecco);
+
+        // low perigees use a modified atmospheric density fit, below 220 km the drag terms are truncated as they are for
+        // every deep space orbit
+        let deep = TWO_PI / no >= DEEP_SPACE_PERIOD_MINUTES;
+        let isimp = deep || rp < 220.0 / RADIUS_EARTH_KM + 1.0;
+        let mut sfour = 78.0 / RADIUS_EARTH_KM + 1.0;
+        let mut qzms24 = ((120.0 - 78.0) / RADIUS_EARTH_KM).powi(4);
+        let perigee = (rp - 1.0) * RADIUS_EARTH_KM;
+        if perigee < 156.0 {
+            sfour = if perigee < 98.0 { 20.0 } else { perigee - 78.0 };
+            qzms24 = ((120.0 - sfour) / RADIUS_EARTH_KM).powi(4);
+            sfour = sfour / RADIUS_EARTH_KM + 1.0;
+        }
+
+        let pinvsq = 1.0 / posq;
+        let tsi = 1.0 / (ao - sfour);
+        let eta = ao * ecco * tsi;
+        let etasq = eta * eta;
+        let eeta = ecco * eta;
+        let psisq = (1.0 - etasq).abs();
+        let coef = qzms24 * tsi.powi(4);
+        let coef1 = coef / psisq.powf(3.5);
+        let cc2 = coef1 * no * (ao * (1.0 + 1.5 * etasq + eeta * (4.0 + etasq))
+            + 0.375 * J2 * tsi / psisq * con41 * (8.0 + 3.0 * etasq * (8.0 + etasq)));
+        let cc1 = bstar * cc2;
+        let cc3 = if ecco > 1.0e-4 { -2.0 * coef * tsi * J3OJ2 * no * sinio / ecco } else { 0.0 };
+        let x1mth2 = 1.0 - cosio2;
+        let cc4 = 2.0 * no * coef1 * ao * omeosq * (eta * (2.0 + 0.5 * etasq) + ecco * (0.5 + 2.0 * etasq)
+            - J2 * tsi / (ao * psisq) * (-3.0 * con41 * (1.0 - 2.0 * eeta + etasq * (1.5 - 0.5 * eeta))
+            + 0.75 * x1mth2 * (2.0 * etasq - eeta * (1.0 + etasq)) * (2.0 * argpo).cos()));
+        let cc5 = 2.0 * coef1 * ao * omeosq * (1.0 + 2.75 * (etasq + eeta) + eeta * etasq);
+
+        // secular rates of the mean anomaly, argument of perigee and node from the zonal harmonics
+        let cosio4 = cosio2 * cosio2;
+        let temp1 = 1.5 * J2 * pinvsq * no;
+        let temp2 = 0.5 * temp1 * J2 * pinvsq;
+        let temp3 = -0.46875 * J4 * pinvsq * pinvsq * no;
+        let mdot = no + 0.5 * temp1 * rteosq * con41 + 0.0625 * temp2 * rteosq * (13.0 - 78.0 * cosio2 + 137.0 * cosio4);
+        let argpdot = -0.5 * temp1 * con42 + 0.0625 * temp2 * (7.0 - 114.0 * cosio2 + 395.0 * cosio4)
+            + temp3 * (3.0 - 36.0 * cosio2 + 49.0 * cosio4);
+        let xhdot1 = -temp1 * cosio;
+        let nodedot = xhdot1 + (0.5 * temp2 * (4.0 - 19.0 * cosio2) + 2.0 * temp3 * (3.0 - 7.0 * cosio2)) * cosio;
+
+        let omgcof = bstar * cc3 * argpo.cos();
+        let xmcof = if ecco > 1.0e-4 { -2.0 / 3.0 * coef * bstar / eeta } else { 0.0 };
+        let nodecf = 3.5 * omeosq * xhdot1 * cc1;
+        let t2cof = 1.5 * cc1;
+        // avoids a divide by zero for inclinations of 180 degrees
+        let xlcof = if (cosio + 1.0).abs() > 1.5e-12 {
+            -0.25 * J3OJ2 * sinio * (3.0 + 5.0 * cosio) / (1.0 + cosio)
+        } else {
+            -0.25 * J3OJ2 * sinio * (3.0 + 5.0 * cosio) / 1.5e-12
+        };
+        let aycof = -0.5 * J3OJ2 * sinio;
+        let delmo = (1.0 + eta * mo.cos()).powi(3);
+        let sinmao = mo.sin();
+        let x7thm1 = 7.0 * cosio2 - 1.0;
+
+        let (mut d2, mut d3, mut d4, mut t3cof, mut t4cof, mut t5cof) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        if !isimp {
+            let cc1sq = cc1 * cc1;
+            d2 = 4.0 * ao * tsi * cc1sq;
+            let temp = d2 * tsi * cc1 / 3.0;
+            d3 = (17.0 * ao + sfour) * temp;
+            d4 = 0.5 * temp * ao * tsi * (221.0 * ao + 31.0 * sfour) * cc1;
+            t3cof = d2 + 2.0 * cc1sq;
+            t4cof = 0.25 * (3.0 * d3 + cc1 * (12.0 * d2 + 10.0 * cc1sq));
+            t5cof = 0.2 * (3.0 * d4 + 12.0 * cc1 * d3 + 6.0 * d2 * d2 + 15.0 * cc1sq * (2.0 * d2 + cc1sq));
+        }
+
+        let deep_space = if deep {
+            let elements = MeanElements { ecco, inclo, nodeo: tle.raan.to_radians(), argpo, mo, no };
+            Some(Box::new(DeepSpace::new(tle.epoch, &elements, mdot, argpdot, nodedot)))
+        } else {
+            None
+        };
+
+        Ok(Sgp4 {
+            epoch: tle.epoch,
+            bstar,
+            ecco,
+            inclo,
+            nodeo: tle.raan.to_radians(),
+            argpo,
+            mo,
+            no_unkozai: no,
+            isimp,
+            con41,
+            x1mth2,
+            x7thm1,
+            cc1,
+            cc4,
+            cc5,
+            d2,
+            d3,
+            d4,
+            delmo,
+            eta,
+            argpdot,
+            omgcof,
+            sinmao,
+            t2cof,
+            t3cof,
+            t4cof,
+            t5cof,
+            xlcof,
+            aycof,
+            xmcof,
+            mdot,
+            nodecf,
+            nodedot,
+            deep_space,
+        })
+    }
+
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// TEME position and velocity in km and km/s, `minutes` after the element set epoch
+    pub fn propagate(&self, minutes: f64) -> Result<(DVec3, DVec3), Sgp4Error> {
+        let t = minutes;
+
+        // secular gravity and atmospheric drag
+        let xmdf = self.mo + self.mdot * t;
+        let argpdf = self.argpo + self.argpdot * t;
+        let nodedf = self.nodeo + self.nodedot * t;
+        let mut argpm = argpdf;
+        let mut mm = xmdf;
+        let t2 = t * t;
+        let mut nodem = nodedf + self.nodecf * t2;
+        let mut tempa = 1.0 - self.cc1 * t;
+        let mut tempe = self.bstar * self.cc4 * t;
+        let mut templ = self.t2cof * t2;
+
+        if !self.isimp {
+            let delomg = self.omgcof * t;
+            let delm = self.xmcof * ((1.0 + self.eta * xmdf.cos()).powi(3) - self.delmo);
+            let temp = delomg + delm;
+            mm = xmdf + temp;
+            argpm = argpdf - temp;
+            let t3 = t2 * t;
+            let t4 = t3 * t;
+            tempa = tempa - self.d2 * t2 - self.d3 * t3 - self.d4 * t4;
+            tempe += self.bstar * self.cc5 * (mm.sin() - self.sinmao);
+            templ += self.t3cof * t3 + t4 * (self.t4cof + t * self.t5cof);
+        }
+
+        let (mut nm, mut em, mut inclm) = (self.no_unkozai, self.ecco, self.inclo);
+        if let Some(deep_space) = &self.deep_space {
+            deep_space.secular(t, self.argpo, self.argpdot, self.no_unkozai, &mut em, &mut inclm, &mut argpm, &mut nodem, &mut mm, &mut nm);
+        }
+
+        if nm <= 0.0 {
+            return Err(Sgp4Error::MeanMotion(nm))
+        }
+        let am = (XKE / nm).powf(2.0 / 3.0) * tempa * tempa;
+        let nm = XKE / am.powf(1.5);
+        em -= tempe;
+        if !(-0.001..1.0).contains(&em) {
+            return Err(Sgp4Error::Eccentricity(em))
+        }
+        em = em.max(1.0e-6);
+
+        mm += self.no_unkozai * templ;
+        let xlm = (mm + argpm + nodem) % TWO_PI;
+        nodem %= TWO_PI;
+        argpm %= TWO_PI;
+        mm = (xlm - argpm - nodem) % TWO_PI;
+
+        // lunar and solar periodics, which also change the inclination dependent coefficients
+        let (mut ep, mut xincp, mut nodep, mut argpp, mut mp) = (em, inclm, nodem, argpm, mm);
+        let (mut aycof, mut xlcof, mut con41, mut x1mth2, mut x7thm1) = (self.aycof, self.xlcof, self.con41, self.x1mth2, self.x7thm1);
+        if let Some(deep_space) = &self.deep_space {
+            deep_space.periodics(t, &mut ep, &mut xincp, &mut nodep, &mut argpp, &mut mp);
+            if xincp < 0.0 {
+                xincp = -xincp;
+                nodep += PI;
+                argpp -= PI;
+            }
+            if !(0.0..=1.0).contains(&ep) {
+                return Err(Sgp4Error::Eccentricity(ep))
+            }
+
+            let (sinip, cosip) = xincp.sin_cos();
+            aycof = -0.5 * J3OJ2 * sinip;
+            xlcof = -0.25 * J3OJ2 * sinip * (3.0 + 5.0 * cosip) / if (cosip + 1.0).abs() > 1.5e-12 { 1.0 + cosip } else { 1.5e-12 };
+            let cosisq = cosip * cosip;
+            con41 = 3.0 * cosisq - 1.0;
+            x1mth2 = 1.0 - cosisq;
+            x7thm1 = 7.0 * cosisq - 1.0;
+        }
+        let (sinip, cosip) = xincp.sin_cos();
+
+        // long period periodics
+        let axnl = ep * argpp.cos();
+        let temp = 1.0 / (am * (1.0 - ep * ep));
+        let aynl = ep * argpp.sin() + temp * aycof;
+        let xl = mp + argpp + nodep + temp * xlcof * axnl;
+
+        // solve kepler's equation for the eccentric longitude
+        let u = (xl - nodep) % TWO_PI;
+        let mut eo1 = u;
+        let (mut sineo1, mut coseo1) = eo1.sin_cos();
+        for _ in 0..10 {
+            (sineo1, coseo1) = eo1.sin_cos();
+            let mut step = (u - aynl * coseo1 + axnl * sineo1 - eo1) / (1.0 - coseo1 * axnl - sineo1 * aynl);
+            step = step.clamp(-0.95, 0.95);
+            eo1 += step;
+            if step.abs() < 1.0e-12 {
+                break;
+            }
+        }
+
+        // short period preliminary quantities
+        let ecose = axnl * coseo1 + aynl * sineo1;
+        let esine = axnl * sineo1 - aynl * coseo1;
+        let el2 = axnl * axnl + aynl * aynl;
+        let pl = am * (1.0 - el2);
+        if pl < 0.0 {
+            return Err(Sgp4Error::SemiLatusRectum(pl))
+        }
+        let rl = am * (1.0 - ecose);
+        let rdotl = am.sqrt() * esine / rl;
+        let rvdotl = pl.sqrt() / rl;
+        let betal = (1.0 - el2).sqrt();
+        let temp = esine / (1.0 + betal);
+        let sinu = am / rl * (sineo1 - aynl - axnl * temp);
+        let cosu = am / rl * (coseo1 - axnl + aynl * temp);
+        let su = sinu.atan2(cosu);
+        let sin2u = (cosu + cosu) * sinu;
+        let cos2u = 1.0 - 2.0 * sinu * sinu;
+        let temp = 1.0 / pl;
+        let temp1 = 0.5 * J2 * temp;
+        let temp2 = temp1 * temp;
+
+        // short period periodics
+        let mrt = rl * (1.0 - 1.5 * temp2 * betal * con41) + 0.5 * temp1 * x1mth2 * cos2u;
+        let su = su - 0.25 * temp2 * x7thm1 * sin2u;
+        let xnode = nodep + 1.5 * temp2 * cosip * sin2u;
+        let xinc = xincp + 1.5 * temp2 * cosip * sinip * cos2u;
+        let mvt = rdotl - nm * temp1 * x1mth2 * sin2u / XKE;
+        let rvdot = rvdotl + nm * temp1 * (x1mth2 * cos2u + 1.5 * con41) / XKE;
+
+        // orientation vectors
+        let (sinsu, cossu) = su.sin_cos();
+        let (snod, cnod) = xnode.sin_cos();
+        let (sini, cosi) = xinc.sin_cos();
+        let xmx = -snod * cosi;
+        let xmy = cnod * cosi;
+        let u = DVec3::new(xmx * sinsu + cnod * cossu, xmy * sinsu + snod * cossu, sini * sinsu);
+        let v = DVec3::new(xmx * cossu - cnod * sinsu, xmy * cossu - snod * sinsu, sini * cossu);
+
+        if mrt < 1.0 {
+            return Err(Sgp4Error::Decayed { minutes })
+        }
+
+        Ok((u * (mrt * RADIUS_EARTH_KM), (u * mvt + v * rvdot) * VKMPERSEC))
+    }
+
+    /// Geocentric position and velocity at `epoch` in metres and metres per second, in the simulations ecliptic frame
+    pub fn state_at(&self, epoch: Epoch) -> Result<(DVec3, DVec3), Sgp4Error> {
+        let (position, velocity) = self.propagate(epoch.seconds_since(&self.epoch) / 60.0)?;
+        Ok((teme_to_ecliptic(position * 1000.0, self.epoch), teme_to_ecliptic(velocity * 1000.0, self.epoch)))
+    }
+}
+
+/// Mean elements at epoch with the un-kozai'd mean motion, radians and radians per minute
+struct MeanElements {
+    ecco: f64,
+    inclo: f64,
+    nodeo: f64,
+    argpo: f64,
+    mo: f64,
+    no: f64,
+}
+
+// lunar and solar constants
+const ZES: f64 = 0.01675;
+const ZEL: f64 = 0.05490;
+const ZNS: f64 = 1.19459e-5;
+const ZNL: f64 = 1.5835218e-4;
+const RPTIM: f64 = 4.3752690880113e-3; // earth rotation in radians per minute
+
+/// The coefficients of one third body's perturbation, for the sun or the moon
+struct ThirdBody {
+    s1: f64,
+    s2: f64,
+    s3: f64,
+    s4: f64,
+    s5: f64,
+    s6: f64,
+    s7: f64,
+    z1: f64,
+    z2: f64,
+    z3: f64,
+    z11: f64,
+    z12: f64,
+    z13: f64,
+    z21: f64,
+    z22: f64,
+    z23: f64,
+    z31: f64,
+    z32: f64,
+    z33: f64,
+}
+
+impl ThirdBody {
+    /// `zcosg` to `zsinh` orient the perturbing body's orbit, `cc` scales its strength
+    #[allow(clippy::too_many_arguments)]
+    fn new(elements: &MeanElements, zcosg: f64, zsing: f64, zcosi: f64, zsini: f64, zcosh: f64, zsinh: f64, cc: f64) -> ThirdBody {
+        let (sinim, cosim) = elements.inclo.sin_cos();
+        let (sinomm, cosomm) = elements.argpo.sin_cos();
+        let em = elements.ecco;
+        let emsq = em * em;
+        let betasq = 1.0 - emsq;
+        let rtemsq = betasq.sqrt();
+
+        let a1 = zcosg * zcosh + zsing * zcosi * zsinh;
+        let a3 = -zsing * zcosh + zcosg * zcosi * zsinh;
+        let a7 = -zcosg * zsinh + zsing * zcosi * zcosh;
+        let a8 = zsing * zsini;
+        let a9 = zsing * zsinh + zcosg * zcosi * zcosh;
+        let a10 = zcosg * zsini;
+        let a2 = cosim * a7 + sinim * a8;
+        let a4 = cosim * a9 + sinim * a10;
+        let a5 = -sinim * a7 + cosim * a8;
+        let a6 = -sinim * a9 + cosim * a10;
+
+        let x1 = a1 * cosomm + a2 * sinomm;
+        let x2 = a3 * cosomm + a4 * sinomm;
+        let x3 = -a1 * sinomm + a2 * cosomm;
+        let x4 = -a3 * sinomm + a4 * cosomm;
+        let x5 = a5 * sinomm;
+        let x6 = a6 * sinomm;
+        let x7 = a5 * cosomm;
+        let x8 = a6 * cosomm;
+
+        let z31 = 12.0 * x1 * x1 - 3.0 * x3 * x3;
+        let z32 = 24.0 * x1 * x2 - 6.0 * x3 * x4;
+        let z33 = 12.0 * x2 * x2 - 3.0 * x4 * x4;
+        let z1 = 3.0 * (a1 * a1 + a2 * a2) + z31 * emsq;
+        let z2 = 6.0 * (a1 * a3 + a2 * a4) + z32 * emsq;
+        let z3 = 3.0 * (a3 * a3 + a4 * a4) + z33 * emsq;
+        let s3 = cc / elements.no;
+        let s4 = s3 * rtemsq;
+
+        ThirdBody {
+            s1: -15.0 * em * s4,
+            s2: -0.5 * s3 / rtemsq,
+            s3,
+            s4,
+            s5: x1 * x3 + x2 * x4,
+            s6: x2 * x3 + x1 * x4,
+            s7: x2 * x4 - x1 * x3,
+            z1: z1 + z1 + betasq * z31,
+            z2: z2 + z2 + betasq * z32,
+            z3: z3 + z3 + betasq * z33,
+            z11: -6.0 * a1 * a5 + emsq * (-24.0 * x1 * x7 - 6.0 * x3 * x5),
+            z12: -6.0 * (a1 * a6 + a3 * a5) + emsq * (-24.0 * (x2 * x7 + x1 * x8) - 6.0 * (x3 * x6 + x4 * x5)),
+            z13: -6.0 * a3 * a6 + emsq * (-24.0 * x2 * x8 - 6.0 * x4 * x6),
+            z21: 6.0 * a2 * a5 + emsq * (24.0 * x1 * x5 - 6.0 * x3 * x7),
+            z22: 6.0 * (a4 * a5 + a2 * a6) + emsq * (24.0 * (x2 * x5 + x1 * x6) - 6.0 * (x4 * x7 + x3 * x8)),
+            z23: 6.0 * a4 * a6 + emsq * (24.0 * x2 * x6 - 6.0 * x4 * x8),
+            z31,
+            z32,
+            z33,
+        }
+    }
+}
+
+/// Amplitudes of the long period periodics of one third body, the pairs multiply the same functions of its mean
+/// anomaly in `ThirdBodyPeriodics::at`
+#[derive(Debug, Clone)]
+struct ThirdBodyPeriodics {
+    e2: f64,
+    e3: f64,
+    i2: f64,
+    i3: f64,
+    l2: f64,
+    l3: f64,
+    l4: f64,
+    gh2: f64,
+    gh3: f64,
+    gh4: f64,
+    h2: f64,
+    h3: f64,
+    // mean anomaly at epoch, its rate and the eccentricity of the third body's orbit
+    zmo: f64,
+    zn: f64,
+    ze: f64,
+}
+
+impl ThirdBodyPeriodics {
+    fn new(body: &ThirdBody, emsq: f64, zmo: f64, zn: f64, ze: f64) -> ThirdBodyPeriodics {
+        ThirdBodyPeriodics {
+            e2: 2.0 * body.s1 * body.s6,
+            e3: 2.0 * body.s1 * body.s7,
+            i2: 2.0 * body.s2 * body.z12,
+            i3: 2.0 * body.s2 * (body.z13 - body.z11),
+            l2: -2.0 * body.s3 * body.z2,
+            l3: -2.0 * body.s3 * (body.z3 - body.z1),
+            l4: -2.0 * body.s3 * (-21.0 - 9.0 * emsq) * ze,
+            gh2: 2.0 * body.s4 * body.z32,
+            gh3: 2.0 * body.s4 * (body.z33 - body.z31),
+            gh4: -18.0 * body.s4 * ze,
+            h2: -2.0 * body.s2 * body.z22,
+            h3: -2.0 * body.s2 * (body.z23 - body.z21),
+            zmo,
+            zn,
+            ze,
+        }
+    }
+
+    /// Offsets of the eccentricity, inclination, mean longitude, argument of perigee and node `t` minutes after epoch
+    fn at(&self, t: f64) -> [f64; 5] {
+        let zm = self.zmo + self.zn * t;
+        let zf = zm + 2.0 * self.ze * zm.sin();
+        let (sinzf, coszf) = zf.sin_cos();
+        let f2 = 0.5 * sinzf * sinzf - 0.25;
+        let f3 = -0.5 * sinzf * coszf;
+        [
+            self.e2 * f2 + self.e3 * f3,
+            self.i2 * f2 + self.i3 * f3,
+            self.l2 * f2 + self.l3 * f3 + self.l4 * sinzf,
+            self.gh2 * f2 + self.gh3 * f3 + self.gh4 * sinzf,
+            self.h2 * f2 + self.h3 * f3,
+        ]
+    }
+}
+
+/// Terms of the geopotential resonance, for orbits whose period is close to a day or half a day
+#[derive(Debug, Clone)]
+enum Resonance {
+    None,
+    Synchronous { del1: f64, del2: f64, del3: f64 },
+    HalfDay { d2201: f64, d2211: f64, d3210: f64, d3222: f64, d4410: f64, d4422: f64, d5220: f64, d5232: f64, d5421: f64, d5433: f64 },
+}
+
+/// The deep space (SDP4) additions to the model
+#[derive(Debug, Clone)]
+struct DeepSpace {
+    solar: ThirdBodyPeriodics,
+    lunar: ThirdBodyPeriodics,
+
+    // secular rates of the eccentricity, inclination, mean anomaly, argument of perigee and node
+    dedt: f64,
+    didt: f64,
+    dmdt: f64,
+    domdt: f64,
+    dnodt: f64,
+
+    resonance: Resonance,
+    gsto: f64, // greenwich sidereal time at epoch
+    xfact: f64,
+    xlamo: f64,
+}
+
+impl DeepSpace {
+    fn new(epoch: Epoch, elements: &MeanElements, mdot: f64, argpdot: f64, nodedot: f64) -> DeepSpace {
+        // days since 1950 jan 0.0
+        let epoch_days = epoch.julian_date(TimeScale::Utc) - 2433281.5;
+        let (sinim, cosim) = elements.inclo.sin_cos();
+        let (snodm, cnodm) = elements.nodeo.sin_cos();
+        let em = elements.ecco;
+        let emsq = em * em;
+        let nm = elements.no;
+
+        // the sun, and the moon's orbit whose node precesses around the ecliptic
+        let solar = ThirdBody::new(elements, 0.1945905, -0.98088458, 0.91744867, 0.39785416, cnodm, snodm, 2.9864797e-6);
+
+        let day = epoch_days + 18261.5;
+        let xnodce = (4.5236020 - 9.2422029e-4 * day) % TWO_PI;
+        let (stem, ctem) = xnodce.sin_cos();
+        let zcosil = 0.91375164 - 0.03568096 * ctem;
+        let zsinil = (1.0 - zcosil * zcosil).sqrt();
+        let zsinhl = 0.089683511 * stem / zsinil;
+        let zcoshl = (1.0 - zsinhl * zsinhl).sqrt();
+        let gam = 5.8351514 + 0.0019443680 * day;
+        let zx = (0.39785416 * stem / zsinil).atan2(zcoshl * ctem + 0.91744867 * zsinhl * stem);
+        let (zsingl, zcosgl) = (gam + zx - xnodce).sin_cos();
+        let lunar = ThirdBody::new(elements, zcosgl, zsingl, zcosil, zsinil,
+            zcoshl * cnodm + zsinhl * snodm, snodm * zcoshl - cnodm * zsinhl, 4.7968065e-7);
+
+        let zmol = (4.7199672 + 0.22997150 * day - gam) % TWO_PI;
+        let zmos = (6.2565837 + 0.017201977 * day) % TWO_PI;
+
+        // secular rates, the node terms are dropped within 3 degrees of the equator where the node is undefined
+        let equatorial = !(5.2359877e-2..PI - 5.2359877e-2).contains(&elements.inclo);
+        let ses = solar.s1 * ZNS * solar.s5;
+        let sis = solar.s2 * ZNS * (solar.z11 + solar.z13);
+        let sls = -ZNS * solar.s3 * (solar.z1 + solar.z3 - 14.0 - 6.0 * emsq);
+        let sghs = solar.s4 * ZNS * (solar.z31 + solar.z33 - 6.0);
+        let mut shs = if equatorial { 0.0 } else { -ZNS * solar.s2 * (solar.z21 + solar.z23) };
+        if sinim != 0.0 {
+            shs /= sinim;
+        }
+        let sgs = sghs - cosim * shs;
+
+        let dedt = ses + lunar.s1 * ZNL * lunar.s5;
+        let didt = sis + lunar.s2 * ZNL * (lunar.z11 + lunar.z13);
+        let dmdt = sls - ZNL * lunar.s3 * (lunar.z1 + lunar.z3 - 14.0 - 6.0 * emsq);
+        let sghl = lunar.s4 * ZNL * (lunar.z31 + lunar.z33 - 6.0);
+        let shll = if equatorial { 0.0 } else { -ZNL * lunar.s2 * (lunar.z21 + lunar.z23) };
+        let mut domdt = sgs + sghl;
+        let mut dnodt = shs;
+        if sinim != 0.0 {
+            domdt -= cosim / sinim * shll;
+            dnodt += shll / sinim;
+        }
+
+        // resonance terms
+        let gsto = greenwich_sidereal_time(epoch_days + 2433281.5);
+        let theta = gsto % TWO_PI;
+        let aonv = (nm / XKE).powf(2.0 / 3.0);
+        let (mut resonance, mut xfact, mut xlamo) = (Resonance::None, 0.0, 0.0);
+
+        if (8.26e-3..=9.24e-3).contains(&nm) && em >= 0.5 {
+            let cosisq = cosim * cosim;
+            let eoc = em * emsq;
+            let g201 = -0.306 - (em - 0.64) * 0.440;
+            let (g211, g310, g322, g410, g422, g520);
+            if em <= 0.65 {
+                g211 = 3.616 - 13.2470 * em + 16.2900 * emsq;
+                g310 = -19.302 + 117.3900 * em - 228.4190 * emsq + 156.5910 * eoc;
+                g322 = -18.9068 + 109.7927 * em - 214.6334 * emsq + 146.5816 * eoc;
+                g410 = -41.122 + 242.6940 * em - 471.0940 * emsq + 313.9530 * eoc;
+                g422 = -146.407 + 841.8800 * em - 1629.014 * emsq + 1083.4350 * eoc;
+                g520 = -532.114 + 3017.977 * em - 5740.032 * emsq + 3708.2760 * eoc;
+            } else {
+                g211 = -72.099 + 331.819 * em - 508.738 * emsq + 266.724 * eoc;
+                g310 = -346.844 + 1582.851 * em - 2415.925 * emsq + 1246.113 * eoc;
+                g322 = -342.585 + 1554.908 * em - 2366.899 * emsq + 1215.972 * eoc;
+                g410 = -1052.797 + 4758.686 * em - 7193.992 * emsq + 3651.957 * eoc;
+                g422 = -3581.690 + 16178.110 * em - 24462.770 * emsq + 12422.520 * eoc;
+                g520 = if em > 0.715 {
+                    -5149.66 + 29936.92 * em - 54087.36 * emsq + 31324.56 * eoc
+                } else {
+                    1464.74 - 4664.75 * em + 3763.64 * emsq
+                };
+            }
+            let (g533, g521, g532) = if em < 0.7 {
+                (-919.22770 + 4988.6100 * em - 9064.7700 * emsq + 5542.21 * eoc,
+                 -822.71072 + 4568.6173 * em - 8491.4146 * emsq + 5337.524 * eoc,
+                 -853.66600 + 4690.2500 * em - 8624.7700 * emsq + 5341.4 * eoc)
+            } else {
+                (-37995.780 + 161616.52 * em - 229838.20 * emsq + 109377.94 * eoc,
+                 -51752.104 + 218913.95 * em - 309468.16 * emsq + 146349.42 * eoc,
+                 -40023.880 + 170470.89 * em - 242699.48 * emsq + 115605.82 * eoc)
+            };
+
+            let sini2 = sinim * sinim;
+            let f220 = 0.75 * (1.0 + 2.0 * cosim + cosisq);
+            let f221 = 1.5 * sini2;
+            let f321 = 1.875 * sinim * (1.0 - 2.0 * cosim - 3.0 * cosisq);
+            let f322 = -1.875 * sinim * (1.0 + 2.0 * cosim - 3.0 * cosisq);
+            let f441 = 35.0 * sini2 * f220;
+            let f442 = 39.3750 * sini2 * sini2;
+            let f522 = 9.84375 * sinim * (sini2 * (1.0 - 2.0 * cosim - 5.0 * cosisq)
+                + 0.33333333 * (-2.0 + 4.0 * cosim + 6.0 * cosisq));
+            let f523 = sinim * (4.92187512 * sini2 * (-2.0 - 4.0 * cosim + 10.0 * cosisq)
+                + 6.56250012 * (1.0 + 2.0 * cosim - 3.0 * cosisq));
+            let f542 = 29.53125 * sinim * (2.0 - 8.0 * cosim + cosisq * (-12.0 + 8.0 * cosim + 10.0 * cosisq));
+            let f543 = 29.53125 * sinim * (-2.0 - 8.0 * cosim + cosisq * (12.0 + 8.0 * cosim - 10.0 * cosisq));
+
+            let temp1 = 3.0 * nm * nm * aonv * aonv;
+            let temp = temp1 * 1.7891679e-6;
+            let (d2201, d2211) = (temp * f220 * g201, temp * f221 * g211);
+            let temp1 = temp1 * aonv;
+            let temp = temp1 * 3.7393792e-7;
+            let (d3210, d3222) = (temp * f321 * g310, temp * f322 * g322);
+            let temp1 = temp1 * aonv;
+            let temp = 2.0 * temp1 * 7.3636953e-9;
+            let (d4410, d4422) = (temp * f441 * g410, temp * f442 * g422);
+            let temp1 = temp1 * aonv;
+            let temp = temp1 * 1.1428639e-7;
+            let (d5220, d5232) = (temp * f522 * g520, temp * f523 * g532);
+            let temp = 2.0 * temp1 * 2.1765803e-9;
+            let (d5421, d5433) = (temp * f542 * g521, temp * f543 * g533);
+
+            resonance = Resonance::HalfDay {
+                d2201,
+                d2211,
+                d3210,
+                d3222,
+                d4410,
+                d4422,
+                d5220,
+                d5232,
+                d5421,
+                d5433,
+            };
+            xlamo = (elements.mo + elements.nodeo + elements.nodeo - theta - theta) % TWO_PI;
+            xfact = mdot + dmdt + 2.0 * (nodedot + dnodt - RPTIM) - nm;
+        } else if nm > 0.0034906585 && nm < 0.0052359877 {
+            let g200 = 1.0 + emsq * (-2.5 + 0.8125 * emsq);
+            let g310 = 1.0 + 2.0 * emsq;
+            let g300 = 1.0 + emsq * (-6.0 + 6.60937 * emsq);
+            let f220 = 0.75 * (1.0 + cosim) * (1.0 + cosim);
+            let f311 = 0.9375 * sinim * sinim * (1.0 + 3.0 * cosim) - 0.75 * (1.0 + cosim);
+            let f330 = 1.875 * (1.0 + cosim).powi(3);
+            let del1 = 3.0 * nm * nm * aonv * aonv;
+
+            resonance = Resonance::Synchronous {
+                del1: del1 * f311 * g310 * 2.1460748e-6 * aonv,
+                del2: 2.0 * del1 * f220 * g200 * 1.7891679e-6,
+                del3: 3.0 * del1 * f330 * g300 * 2.2123015e-7 * aonv,
+            };
+            xlamo = (elements.mo + elements.nodeo + elements.argpo - theta) % TWO_PI;
+            xfact = mdot + argpdot + nodedot - RPTIM + dmdt + domdt + dnodt - nm;
+        }
+
+        DeepSpace {
+            solar: ThirdBodyPeriodics::new(&solar, emsq, zmos, ZNS, ZES),
+            lunar: ThirdBodyPeriodics::new(&lunar, emsq, zmol, ZNL, ZEL),
+            dedt,
+            didt,
+            dmdt,
+            domdt,
+            dnodt,
+            resonance,
+            gsto,
+            xfact,
+            xlamo,
+        }
+    }
+
+    /// Adds the secular lunar and solar terms to the mean elements `t` minutes after epoch, and integrates the
+    /// resonance for the mean motion and mean anomaly. The integration restarts from the epoch on every call
+    #[allow(clippy::too_many_arguments)]
+    fn secular(&self, t: f64, argpo: f64, argpdot: f64, no: f64, em: &mut f64, inclm: &mut f64, argpm: &mut f64, nodem: &mut f64, mm: &mut f64, nm: &mut f64) {
+        const STEP: f64 = 720.0;
+        const STEP2: f64 = STEP * STEP / 2.0;
+
+        let theta = (self.gsto + t * RPTIM) % TWO_PI;
+        *em += self.dedt * t;
+        *inclm += self.didt * t;
+        *argpm += self.domdt * t;
+        *nodem += self.dnodt * t;
+        *mm += self.dmdt * t;
+
+        if let Resonance::None = self.resonance {
+            return
+        }
+
+        // euler-maclaurin steps of half a day from the epoch, then a taylor expansion over the remainder
+        let delt = if t > 0.0 { STEP } else { -STEP };
+        let (mut atime, mut xli, mut xni) = (0.0, self.xlamo, no);
+        let (xndt, xldot, xnddt) = loop {
+            let (xndt, xnddt) = match self.resonance {
+                Resonance::Synchronous { del1, del2, del3 } => (
+                    del1 * (xli - 0.13130908).sin() + del2 * (2.0 * (xli - 2.8843198)).sin() + del3 * (3.0 * (xli - 0.37448087)).sin(),
+                    del1 * (xli - 0.13130908).cos() + 2.0 * del2 * (2.0 * (xli - 2.8843198)).cos() + 3.0 * del3 * (3.0 * (xli - 0.37448087)).cos(),
+                ),
+                Resonance::HalfDay { d2201, d2211, d3210, d3222, d4410, d4422, d5220, d5232, d5421, d5433 } => {
+                    let (g22, g32, g44, g52, g54) = (5.7686396, 0.95240898, 1.8014998, 1.0508330, 4.4108898);
+                    let xomi = argpo + argpdot * atime;
+                    let x2omi = xomi + xomi;
+                    let x2li = xli + xli;
+                    (
+                        d2201 * (x2omi + xli - g22).sin() + d2211 * (xli - g22).sin()
+                            + d3210 * (xomi + xli - g32).sin() + d3222 * (-xomi + xli - g32).sin()
+                            + d4410 * (x2omi + x2li - g44).sin() + d4422 * (x2li - g44).sin()
+                            + d5220 * (xomi + xli - g52).sin() + d5232 * (-xomi + xli - g52).sin()
+                            + d5421 * (xomi + x2li - g54).sin() + d5433 * (-xomi + x2li - g54).sin(),
+                        d2201 * (x2omi + xli - g22).cos() + d2211 * (xli - g22).cos()
+                            + d3210 * (xomi + xli - g32).cos() + d3222 * (-xomi + xli - g32).cos()
+                            + d5220 * (xomi + xli - g52).cos() + d5232 * (-xomi + xli - g52).cos()
+                            + 2.0 * (d4410 * (x2omi + x2li - g44).cos() + d4422 * (x2li - g44).cos()
+                            + d5421 * (xomi + x2li - g54).cos() + d5433 * (-xomi + x2li - g54).cos()),
+                    )
+                },
+                Resonance::None => unreachable!(),
+            };
+            let xldot = xni + self.xfact;
+            let xnddt = xnddt * xldot;
+
+            if (t - atime).abs() < STEP {
+                break (xndt, xldot, xnddt)
+            }
+            xli += xldot * delt + xndt * STEP2;
+            xni += xndt * delt + xnddt * STEP2;
+            atime += delt;
+        };
+
+        let ft = t - atime;
+        let xl = xli + xldot * ft + xndt * ft * ft * 0.5;
+        *nm = xni + xndt * ft + xnddt * ft * ft * 0.5;
+        *mm = match self.resonance {
+            Resonance::Synchronous { .. } => xl - *nodem - *argpm + theta,
+            _ => xl - 2.0 * *nodem + 2.0 * theta,
+        };
+    }
+
+    /// Applies the long period lunar and solar periodics `t` minutes after epoch to the perturbed elements. Below
+    /// 0.2 radians of inclination the node and perigee terms use Lyddane's modification, which avoids dividing by the
+    /// sine of the inclination
+    fn periodics(&self, t: f64, ep: &mut f64, inclp: &mut f64, nodep: &mut f64, argpp: &mut f64, mp: &mut f64) {
+        let (solar, lunar) = (self.solar.at(t), self.lunar.at(t));
+        let [pe, pinc, pl, pgh, ph] = [0, 1, 2, 3, 4].map(|i| solar[i] + lunar[i]);
+
+        *inclp += pinc;
+        *ep += pe;
+        let (sinip, cosip) = inclp.sin_cos();
+
+        if *inclp >= 0.2 {
+            let ph = ph / sinip;
+            *argpp += pgh - cosip * ph;
+            *nodep += ph;
+            *mp += pl;
+        } else {
+            let (sinop, cosop) = nodep.sin_cos();
+            let alfdp = sinip * sinop + ph * cosop + pinc * cosip * sinop;
+            let betdp = sinip * cosop - ph * sinop + pinc * cosip * cosop;
+            *nodep %= TWO_PI;
+            let xls = *mp + *argpp + cosip * *nodep + pl + pgh - pinc * *nodep * sinip;
+            let xnoh = *nodep;
+            *nodep = alfdp.atan2(betdp);
+            if (xnoh - *nodep).abs() > PI {
+                *nodep += if *nodep < xnoh { TWO_PI } else { -TWO_PI };
+            }
+            *mp += pl;
+            *argpp = xls - *mp - cosip * *nodep;
+        }
+    }
+}
+
+/// Greenwich mean sidereal time in radians at a UT1 julian date, the IAU 1982 model
+fn greenwich_sidereal_time(jdut1: f64) -> f64 {
+    let tut1 = (jdut1 - 2451545.0) / 36525.0;
+    let seconds = -6.2e-6 * tut1 * tut1 * tut1 + 0.093104 * tut1 * tut1 + (876600.0 * 3600.0 + 8640184.812866) * tut1 + 67310.54841;
+    (seconds / 240.0).to_radians().rem_euclid(TWO_PI)
+}
+
+/// Rotates a TEME vector into the ecliptic of J2000. Precession from the element set epoch back to J2000 is applied
+/// with the IAU 1976 angles, nutation (under 20 arcseconds) is neglected so TEME is treated as the mean equator
+pub fn teme_to_ecliptic(v: DVec3, epoch: Epoch) -> DVec3 {
+    let t = epoch.seconds_past_j2000(crate::epoch::TimeScale::Tt) / (36525.0 * 86400.0);
+    let arcsec = |a: f64| (a / 3600.0).to_radians();
+    let zeta = arcsec(2306.2181 * t + 0.30188 * t * t + 0.017998 * t * t * t);
+    let z = arcsec(2306.2181 * t + 1.09468 * t * t + 0.018203 * t * t * t);
+    let theta = arcsec(2004.3109 * t - 0.42665 * t * t - 0.041833 * t * t * t);
+
+    let (sz, cz) = zeta.sin_cos();
+    let (sz2, cz2) = z.sin_cos();
+    let (st, ct) = theta.sin_cos();
+
+    // the precession matrix takes J2000 to the mean equator of date, its transpose takes it back
+    let p = [
+        [cz * ct * cz2 - sz * sz2, -sz * ct * cz2 - cz * sz2, -st * cz2],
+        [cz * ct * sz2 + sz * cz2, -sz * ct * sz2 + cz * cz2, -st * sz2],
+        [cz * st, -sz * st, ct],
+    ];
+    let j2000 = DVec3::new(
+        p[0][0] * v.x + p[1][0] * v.y + p[2][0] * v.z,
+        p[0][1] * v.x + p[1][1] * v.y + p[2][1] * v.z,
+        p[0][2] * v.x + p[1][2] * v.y + p[2][2] * v.z,
+    );
+    equatorial_to_ecliptic(j2000)
+}
+
+impl MemUse for Sgp4 {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the test case from spacetrack report #3
+    const LINE1: &str = "1 88888U          80275.98708465  .00073094  13844-3  66816-4 0    87";
+    const LINE2: &str = "2 88888  72.8435 115.9689 0086731  52.6988 110.5714 16.05824518  1058";
+
+    #[test]
+    fn spacetrack_report_3() {
+        let tle = Tle::parse(LINE1, LINE2).unwrap();
+        let sgp4 = Sgp4::new(&tle).unwrap();
+
+        let (r, v) = sgp4.propagate(0.0).unwrap();
+        assert!((r - DVec3::new(2328.97, -5995.22, 1719.97)).magnitude() < 0.01, "{:?}", r);
+        assert!((v - DVec3::new(2.91207, -0.98342, -7.09082)).magnitude() < 1.0e-4, "{:?}", v);
+
+        let (r, v) = sgp4.propagate(360.0).unwrap();
+        assert!((r - DVec3::new(2456.10, -6071.94, 1222.90)).magnitude() < 1.0, "{:?}", r);
+        assert!((v - DVec3::new(2.67939, -0.44829, -7.22879)).magnitude() < 1.0e-3, "{:?}", v);
+    }
+
+    #[test]
+    fn rotated_state() {
+        let sgp4 = Sgp4::new(&Tle::parse(LINE1, LINE2).unwrap()).unwrap();
+        let (r, _) = sgp4.propagate(0.0).unwrap();
+        let (position, velocity) = sgp4.state_at(sgp4.epoch()).unwrap();
+        assert!((position.magnitude() - r.magnitude() * 1000.0).abs() < 1.0e-6);
+        assert!(velocity.magnitude() > 7000.0 && velocity.magnitude() < 8000.0);
+    }
+
+    #[test]
+    fn spacetrack_report_3_deep_space() {
+        // the deep space test case from spacetrack report #3, also the first deep space set in SGP4-VER.TLE
+        let line1 = "1 11801U          80230.29629788  .01431103  00000-0  14311-1 0    13";
+        let line2 = "2 11801  46.7916 230.4354 7318036  47.4722  10.4117  2.28537848    13";
+        let sgp4 = Sgp4::new(&Tle::parse(line1, line2).unwrap()).unwrap();
+
+        let expected = [
+            (0.0, DVec3::new(7473.37102491, 428.94748312, 5828.74846783), DVec3::new(5.10715539, 6.44468030, -0.18613330)),
+            (360.0, DVec3::new(-3305.22148694, 32410.84323331, -24697.16974954), DVec3::new(-1.30113732, -1.15131560, -0.28333582)),
+            (720.0, DVec3::new(14271.29083858, 24110.44309009, -4725.76320143), DVec3::new(-0.32050453, 2.67984154, -2.08405435)),
+        ];
+        for (minutes, position, velocity) in expected {
+            let (r, v) = sgp4.propagate(minutes).unwrap();
+            assert!((r - position).magnitude() < 1.0e-3, "{} {:?}", minutes, r);
+            assert!((v - velocity).magnitude() < 1.0e-6, "{} {:?}", minutes, v);
+        }
+    }
+
+    #[test]
+    fn sidereal_time() {
+        // vallado example 3-5, 1992 august 20 12:14 ut1
+        let gmst = greenwich_sidereal_time(2448854.5 + (12.0 + 14.0 / 60.0) / 24.0).to_degrees();
+        assert!((gmst - 152.578787810).abs() < 1.0e-6, "{}", gmst);
+    }
+
+    #[test]
+    fn geostationary_resonance() {
+        let line1 = "1 28884U 05041A   20001.00000000 -.00000340  00000-0  00000-0 0  9991";
+        let line2 = "2 28884   0.0300 275.1000 0002000 100.0000 200.0000  1.00270000 52305";
+        let tle = Tle::parse(line1, line2).unwrap();
+        let sgp4 = Sgp4::new(&tle).unwrap();
+        let jd = tle.epoch.julian_date(TimeScale::Utc);
+
+        // the satellite should hold its radius and its longitude over the earth
+        let longitude = |minutes: f64| {
+            let (r, _) = sgp4.propagate(minutes).unwrap();
+            assert!((r.magnitude() - 42164.0).abs() < 20.0, "{} {}", minutes, r.magnitude());
+            (r.y.atan2(r.x) - greenwich_sidereal_time(jd + minutes / 1440.0)).rem_euclid(TWO_PI).to_degrees()
+        };
+        let start = longitude(0.0);
+        for day in 1..=10 {
+            let drift = (longitude(day as f64 * 1440.0) - start + 180.0).rem_euclid(360.0) - 180.0;
+            assert!(drift.abs() < 0.5, "day {} drifted {} degrees", day, drift);
+        }
+    }
+
+    #[test]
+    fn half_day_resonance() {
+        let line1 = "1 23420U 94081A   06176.50000000  .00000100  00000-0  10000-3 0  9993";
+        let line2 = "2 23420  63.4000 120.0000 7400000 270.0000  10.0000  2.00560000 88885";
+        let sgp4 = Sgp4::new(&Tle::parse(line1, line2).unwrap()).unwrap();
+        assert!(matches!(sgp4.deep_space.as_deref().map(|deep_space| &deep_space.resonance), Some(Resonance::HalfDay { .. })));
+
+        // the resonance is integrated in steps of half a day, the state should be continuous across them
+        for minutes in [720.0, 1440.0, -720.0, 7200.0] {
+            let (before, velocity) = sgp4.propagate(minutes - 1.0e-4).unwrap();
+            let (after, _) = sgp4.propagate(minutes + 1.0e-4).unwrap();
+            let jump = after - before - velocity * 2.0e-4 * 60.0;
+            assert!(jump.magnitude() < 1.0e-3, "{} {:?}", minutes, jump);
+        }
+
+        // a molniya orbit, perigee low over the south and apogee high in the north
+        for minutes in (0..10).map(|step| step as f64 * 144.0) {
+            let (r, _) = sgp4.propagate(minutes).unwrap();
+            assert!(r.magnitude() > 6378.0 + 400.0 && r.magnitude() < 47000.0, "{} {}", minutes, r.magnitude());
+        }
+    }
+}
@@ -4,14 +4,20 @@
 #![allow(unused_mut)]
 
 use std::{collections::HashMap, hash::Hash, iter::Zip, slice::{Iter, IterMut}};
-use crate::{ math::*, output::*, constants::* };
+use crate::{ math::*, output::*, constants::*, debug::MemUse, epoch::Epoch, ephemeris::Rails, sgp4::Sgp4, telemetry::{ Phase, Telemetry }, pacing::{ Pacer, TimeWarp }, observer::{ EventDetector, Observer }, forces::{ ForceDependency, ForceModel, Gravity } };
 
+/// Linear motion state of a body
+///
+/// All vectors are double precision, f32 velocities limit long runs to roughly 7 significant digits and the rounding
+/// error quickly dominates the integrators own truncation error, see `tests::kinematic_precision_comparison`
 #[derive(Debug, Clone, Default)]
 pub struct PhysKinematic {
     _physcategory: PhysicsCategory, // the physics processing category
     _position: DVec3, // position in 3D space
-    _velocity: SVec3, // velocity in 3D space
-    _acceleration: SVec3, // acceleration in 3D space
+    _velocity: DVec3, // velocity in 3D space
+    _acceleration: DVec3, // acceleration in 3D space
+    _jerk: DVec3, // estimated rate of change of acceleration, used to select block timesteps
+    _block_level: u32, // block timestep level, the body steps at timestep / 2^level
     _radius: f32, // minimum bounding radius of the body
     _mass: f64, // mass in kg
 }
@@ -21,22 +27,22 @@ impl PhysKinematic {
         self._position += translation
     }
     
-    fn time_adjusted_bounding_radius(&self, dt: f32) -> f64 {
-        ((2.0 * self._radius) + (self._velocity.magnitude() * dt) + (self._acceleration.magnitude() * dt * dt * 0.5)) as f64
+    fn time_adjusted_bounding_radius(&self, dt: f64) -> f64 {
+        (2.0 * self._radius as f64) + (self._velocity.magnitude() * dt) + (self._acceleration.magnitude() * dt * dt * 0.5)
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct PhysDynamic {
-    _f_independent: SVec3, // spatially independent forces
-    _f_spatially_dep: SVec3, // position dependent forces
-    _f_velocity_dep: SVec3, // velocity dependent forces
+    _f_independent: DVec3, // spatially independent forces
+    _f_spatially_dep: DVec3, // position dependent forces
+    _f_velocity_dep: DVec3, // velocity dependent forces
     _f_torque: SVec3, // torque
     _grav_param: f64, // standard gravitational param
 }
 
 impl PhysDynamic {
-    pub fn fnet(&self) -> SVec3 {
+    pub fn fnet(&self) -> DVec3 {
         self._f_independent + self._f_spatially_dep + self._f_velocity_dep
     }
 }
@@ -76,20 +82,20 @@ impl<'a> PhysicsBodyRef<'a> {
         self._kinematic._position
     }
 
-    pub fn velocity(&self) -> SVec3 {
+    pub fn velocity(&self) -> DVec3 {
         self._kinematic._velocity
     }
 
-    pub fn acceleration(&self) -> SVec3 {
+    pub fn acceleration(&self) -> DVec3 {
         self._kinematic._acceleration
     }
 
     pub fn momentum(&self) -> f64 {
-        self._kinematic._velocity.magnitude() as f64 * self._kinematic._mass
+        self._kinematic._velocity.magnitude() * self._kinematic._mass
     }
 
     pub fn kinetic_energy(&self) -> f64 {
-        let velocity = self._kinematic._velocity.magnitude() as f64;
+        let velocity = self._kinematic._velocity.magnitude();
         0.5 * self._kinematic._mass * velocity * velocity
     }
     
@@ -97,9 +103,17 @@ impl<'a> PhysicsBodyRef<'a> {
         self._kinematic._physcategory
     }
 
+    pub fn bounding_radius(&self) -> f64 {
+        self._kinematic._radius as f64
+    }
+
+    pub fn grav_param(&self) -> f64 {
+        self._dynamic._grav_param
+    }
+
     pub fn bounding_distance_to(&self, other: &PhysicsBodyRef) -> f64 {
         let p_this = self._kinematic._position;
-        let r_this = other._kinematic._radius as f64;
+        let r_this = self._kinematic._radius as f64;
         let p_other = other._kinematic._position;
         let r_other = other._kinematic._radius as f64;
         p_this.length_to(&p_other) - r_this - r_other
@@ -120,6 +134,7 @@ pub struct PhysicsFrame {
     frame_number: usize,
     simtime: f64,
     timestep: f64,
+    start_epoch: Option<Epoch>, // the epoch at which simtime is zero, if the simulation is anchored to one
 
     // implement some spatial partitioning structure when necessary
 }
@@ -135,6 +150,7 @@ impl PhysicsFrame {
             frame_number: 0,
             simtime: 0.0,
             timestep: 0.0,
+            start_epoch: None,
         }
     }
 
@@ -150,6 +166,15 @@ impl PhysicsFrame {
         self.timestep
     }
 
+    /// The epoch of this frame, if the simulation is anchored to one
+    pub fn epoch(&self) -> Option<Epoch> {
+        self.start_epoch.map(|epoch| epoch.offset_by(self.simtime))
+    }
+
+    pub fn start_epoch(&self) -> Option<Epoch> {
+        self.start_epoch
+    }
+
     pub fn bodies(&self) -> &Vec<PhysicsBodyRef> {
         unimplemented!() // previous implementation removed for now, turn this into an iterator???
     }
@@ -168,6 +193,11 @@ impl PhysicsFrame {
         }
     }
     
+    /// The name a body was added under, bodies may share names
+    pub fn body_name(&self, id: usize) -> Option<&str> {
+        self.name_index.iter().find(|(_, ids)| ids.contains(&id)).map(|(name, _)| name.as_str())
+    }
+
     pub fn get_named_bodies(&self, name: &str) -> Vec<PhysicsBodyRef> {
         let name = name.to_ascii_uppercase();
         if let Some(ids) = self.name_index.get(name.as_str()) {
@@ -262,6 +292,26 @@ impl PhysicsFrame {
         return id;
     }
 
+    /// Removes a body, the ids of the bodies added after it shift down by one
+    pub fn remove_physics_body(&mut self, id: usize) -> bool {
+        if id >= self.spatial.len() {
+            return false
+        }
+
+        self.spatial.remove(id);
+        self.forces.remove(id);
+        self.rotations.remove(id);
+        self.collisions.remove(id);
+        for ids in self.name_index.values_mut() {
+            ids.retain(|other| *other != id);
+            for other in ids.iter_mut().filter(|other| **other > id) {
+                *other -= 1;
+            }
+        }
+        self.name_index.retain(|_, ids| !ids.is_empty());
+        true
+    }
+
     pub fn physics_data_from_id(&self, id: usize) -> (Option<&PhysKinematic>, Option<&PhysDynamic>, Option<&PhysRotational>, Option<&PhysCollision>) {
         (
             self.spatial.get(id),
@@ -303,6 +353,14 @@ impl<'a> UpdateData<'a> for TranslationSystem {
 pub enum PhysicsCategory {
     Gravitational, // generally large bodies, affected by gravity, and also affect everything else with gravity
     Dynamic, // objects which are affected by gravity but do not have a gravitational influence of their own
+    Scripted, // bodies which follow their rails rather than being integrated, they still pull on everything else
+}
+
+impl PhysicsCategory {
+    /// Whether bodies of this category are a source of gravity
+    pub fn is_gravitational(&self) -> bool {
+        matches!(self, Self::Gravitational | Self::Scripted)
+    }
 }
 
 impl Default for PhysicsCategory {
@@ -311,12 +369,24 @@ impl Default for PhysicsCategory {
     }
 }
 
+impl std::str::FromStr for PhysicsCategory {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "GRAVITATIONAL" => Ok(Self::Gravitational),
+            "DYNAMIC" => Ok(Self::Dynamic),
+            "SCRIPTED" => Ok(Self::Scripted),
+            _ => Err(format!("unknown physics category: '{}'", s)),
+        }
+    }
+}
+
 pub struct PhysicsBodyBuilder<'a> {
     _reference_frame: &'a mut PhysicsFrame,
     _template: Option<PhysicsBodyRef<'a>>,
     _physics_category: Option<PhysicsCategory>,
     _name: Option<String>,
-    _velocity: Option<SVec3>,
+    _velocity: Option<DVec3>,
     _position: Option<DVec3>,
     _orientation: Option<Quat>,
     _angular_velocity: Option<SVec3>,
@@ -339,7 +409,7 @@ impl<'a> PhysicsBodyBuilder<'a> {
         self
     }
 
-    pub fn with_velocity(mut self, vel: SVec3) -> Self {
+    pub fn with_velocity(mut self, vel: DVec3) -> Self {
         self._velocity = Some(vel);
         self
     }
@@ -377,15 +447,17 @@ impl<'a> PhysicsBodyBuilder<'a> {
             _physcategory: self._physics_category.unwrap_or(PhysicsCategory::default()),
             _radius: self._bounding_radius.unwrap_or(0.0f32),
             _position: self._position.unwrap_or(DVec3::default()),
-            _velocity: self._velocity.unwrap_or(SVec3::default()),
-            _acceleration: SVec3::default(),
+            _velocity: self._velocity.unwrap_or(DVec3::default()),
+            _acceleration: DVec3::default(),
+            _jerk: DVec3::default(),
+            _block_level: 0,
             _mass: self._mass.unwrap_or(1.0f64),
         };
         let mut dynamic = PhysDynamic {
             _grav_param: self._grav_param.unwrap_or(self._mass.unwrap_or(1.0f64) * G),
-            _f_independent: SVec3::default(),
-            _f_spatially_dep: SVec3::default(),
-            _f_velocity_dep: SVec3::default(),
+            _f_independent: DVec3::default(),
+            _f_spatially_dep: DVec3::default(),
+            _f_velocity_dep: DVec3::default(),
             _f_torque: SVec3::default(),
         };
         let mut rotation = PhysRotational {
@@ -428,16 +500,111 @@ impl<'a> PhysicsBodyBuilder<'a> {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IntegrationMethod {
     Euler,
     SemiImplicitEuler,
     VelocityVerlet,
+    BlockVelocityVerlet, // velocity verlet with per-body power-of-two timesteps, see BlockTimestep
+}
+
+impl std::str::FromStr for IntegrationMethod {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "EULER" => Ok(Self::Euler),
+            "SEMIIMPLICITEULER" => Ok(Self::SemiImplicitEuler),
+            "VELOCITYVERLET" => Ok(Self::VelocityVerlet),
+            "BLOCKVELOCITYVERLET" => Ok(Self::BlockVelocityVerlet),
+            _ => Err(format!("unknown integration method: '{}'", s)),
+        }
+    }
+}
+
+/// Parameters for hierarchical block timestepping
+///
+/// Each body is integrated with its own step of `timestep / 2^level`, where the level is picked from the bodies
+/// acceleration and jerk. Bodies are synchronised at the end of every simulation frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockTimestep {
+    pub max_level: u32, // the finest step any body can take is timestep / 2^max_level
+    pub eta: f64, // accuracy parameter, the fraction of velocity/acceleration allowed to change over a single step
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockTimestepError {
+    MaxLevel(u32), // deeper than BlockTimestep::MAX_LEVEL
+    Eta(f64), // not a positive finite fraction
+}
+
+impl std::fmt::Display for BlockTimestepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MaxLevel(level) => write!(f, "block timestep level {} is deeper than the supported {}", level, BlockTimestep::MAX_LEVEL),
+            Self::Eta(eta) => write!(f, "block timestep accuracy parameter {} is not positive", eta),
+        }
+    }
+}
+
+impl std::error::Error for BlockTimestepError {}
+
+impl Default for BlockTimestep {
+    fn default() -> Self {
+        BlockTimestep {
+            max_level: 8,
+            eta: 0.01,
+        }
+    }
+}
+
+impl BlockTimestep {
+    /// The deepest level supported, sub-step counts are held in a usize
+    pub const MAX_LEVEL: u32 = 31;
+
+    pub fn validate(&self) -> Result<(), BlockTimestepError> {
+        if self.max_level > Self::MAX_LEVEL {
+            return Err(BlockTimestepError::MaxLevel(self.max_level))
+        }
+        if !(self.eta > 0.0 && self.eta.is_finite()) {
+            return Err(BlockTimestepError::Eta(self.eta))
+        }
+        Ok(())
+    }
+
+    /// Number of the finest sub-steps spanned by a single step at `level`
+    fn block_length(&self, level: u32) -> usize {
+        1usize << (self.max_level - level)
+    }
+
+    /// Chooses the coarsest level whose step satisfies both the acceleration and the jerk criteria
+    fn level_for(&self, timestep: f64, kinematic: &PhysKinematic) -> u32 {
+        let v = kinematic._velocity.magnitude();
+        let a = kinematic._acceleration.magnitude();
+        let j = kinematic._jerk.magnitude();
+
+        let mut dt = timestep;
+        if a > 0.0 {
+            if v > 0.0 {
+                dt = dt.min(self.eta * v / a); // acceleration criterion, limit the fractional change in velocity
+            }
+            if j > 0.0 {
+                dt = dt.min(self.eta * a / j); // jerk criterion, limit the fractional change in acceleration
+            }
+        }
+
+        let mut level = 0;
+        while level < self.max_level && timestep / (1u64 << level) as f64 > dt {
+            level += 1;
+        }
+        level
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TerminationCondition {
-    ElapsedTime(f64),
+    ElapsedTime(f64), // simulated seconds
+    WallTime(f64), // real seconds since the first step
+    Frames(usize),
 }
 
 #[derive(Debug)]
@@ -445,8 +612,15 @@ pub struct Simulation {
     present_state: PhysicsFrame,
     timestep: f64, // seconds
     integration_method: IntegrationMethod,
+    block_timestep: BlockTimestep,
     termination_conditions: Vec<TerminationCondition>,
-    output_device: Option<OutputDevice>,
+    observers: Vec<Box<dyn Observer>>, // passed every committed frame, in the order they were added
+    events: EventDetector,
+    force_models: Vec<Box<dyn ForceModel>>, // every force acting on the bodies, gravity included
+    sgp4_references: Vec<(usize, usize, Sgp4)>, // (body, earth, propagator) for bodies created from element sets
+    rails: Vec<(usize, Option<usize>, Rails)>, // (body, centre, trajectory) for scripted bodies, placed in this order
+    telemetry: Telemetry,
+    pacer: Option<Pacer>, // holds the run to a multiple of wall clock time, flat out if None
 }
 
 impl Simulation {
@@ -455,8 +629,15 @@ impl Simulation {
             present_state: PhysicsFrame::new(),
             timestep: 1.0f64,
             integration_method: IntegrationMethod::VelocityVerlet,
+            block_timestep: BlockTimestep::default(),
             termination_conditions: Vec::new(),
-            output_device: None,
+            observers: Vec::new(),
+            events: EventDetector::default(),
+            force_models: vec![Box::new(Gravity)],
+            sgp4_references: Vec::new(),
+            rails: Vec::new(),
+            telemetry: Telemetry::default(),
+            pacer: None,
         }
     }
 
@@ -468,8 +649,160 @@ impl Simulation {
         &self.present_state
     }
 
+    /// Anchors the simulation to an epoch, the current frame is taken to be at `epoch`
+    pub fn set_epoch(&mut self, epoch: Epoch) {
+        self.present_state.start_epoch = Some(epoch.offset_by(-self.present_state.simtime));
+    }
+
+    pub fn set_timestep(&mut self, timestep: f64) {
+        self.timestep = timestep
+    }
+
+    pub fn timestep(&self) -> f64 {
+        self.timestep
+    }
+
+    pub fn set_integration_method(&mut self, method: IntegrationMethod) {
+        self.integration_method = method
+    }
+
+    pub fn integration_method(&self) -> IntegrationMethod {
+        self.integration_method
+    }
+
+    /// Sets the block timestep parameters, these only take effect with `IntegrationMethod::BlockVelocityVerlet`
+    pub fn set_block_timestep(&mut self, params: BlockTimestep) -> Result<(), BlockTimestepError> {
+        params.validate()?;
+        self.block_timestep = params;
+        Ok(())
+    }
+
+    /// Adds the output device as an observer
     pub fn set_output_device(&mut self, device: OutputDevice) {
-        self.output_device = Some(device)
+        self.add_observer(Box::new(device))
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    /// Removes and returns every observer, finished or not
+    pub fn take_observers(&mut self) -> Vec<Box<dyn Observer>> {
+        std::mem::take(&mut self.observers)
+    }
+
+    /// Wall clock time spent in each phase of a step, accumulated over the run
+    pub fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    /// Removes a body, the ids of the bodies added after it shift down by one. Observers and force models are told so
+    /// they can follow the shift, and force models acting on the removed body are dropped
+    pub fn remove_body(&mut self, id: usize) -> bool {
+        if !self.present_state.remove_physics_body(id) {
+            return false
+        }
+
+        self.sgp4_references.retain(|(body, earth, _)| *body != id && *earth != id);
+        for (body, earth, _) in self.sgp4_references.iter_mut() {
+            if *body > id { *body -= 1; }
+            if *earth > id { *earth -= 1; }
+        }
+        self.rails.retain(|(body, center, _)| *body != id && *center != Some(id));
+        for (body, center, _) in self.rails.iter_mut() {
+            if *body > id { *body -= 1; }
+            if let Some(center) = center.as_mut().filter(|center| **center > id) { *center -= 1; }
+        }
+        self.events.remove_body(id);
+        for observer in self.observers.iter_mut() {
+            observer.remove_body(id);
+        }
+        self.force_models.retain_mut(|model| model.remove_body(id));
+        true
+    }
+
+    /// Offsets the state and mass of a body, as when perturbing initial conditions. The gravitational parameter of a
+    /// gravitational body scales with its mass
+    pub fn perturb_body(&mut self, id: usize, position: DVec3, velocity: DVec3, mass: f64) -> bool {
+        let kinematic = match self.present_state.spatial.get_mut(id) {
+            Some(kinematic) => kinematic,
+            None => return false,
+        };
+        let old_mass = kinematic._mass;
+        kinematic._position += position;
+        kinematic._velocity += velocity;
+        kinematic._mass += mass;
+
+        if kinematic._physcategory.is_gravitational() && old_mass != 0.0 {
+            self.present_state.forces[id]._grav_param *= kinematic._mass / old_mass;
+        }
+        true
+    }
+
+    /// Makes a body scripted and puts it on `rails`, relative to the body `center_id` if given, moving it there at once.
+    /// A centre on rails itself must be put on them first. Off the end of its rails a scripted body coasts
+    pub fn set_rails(&mut self, body_id: usize, center_id: Option<usize>, rails: Rails) -> bool {
+        match self.present_state.spatial.get_mut(body_id) {
+            Some(kinematic) => kinematic._physcategory = PhysicsCategory::Scripted,
+            None => return false,
+        }
+        self.rails.retain(|(id, _, _)| *id != body_id);
+        self.rails.push((body_id, center_id, rails));
+        let sim_time = self.present_state.simtime;
+        Self::place_scripted_bodies(&self.rails, &mut self.present_state, sim_time);
+        true
+    }
+
+    pub fn rails(&self, body_id: usize) -> Option<(Option<usize>, &Rails)> {
+        self.rails.iter().find(|(id, _, _)| *id == body_id).map(|(_, center_id, rails)| (*center_id, rails))
+    }
+
+    /// Moves the scripted bodies to where their rails are at `sim_time`
+    fn place_scripted_bodies(rails: &[(usize, Option<usize>, Rails)], frame: &mut PhysicsFrame, sim_time: f64) {
+        let epoch = frame.start_epoch.map(|start| start.offset_by(sim_time));
+        for (body, center, trajectory) in rails.iter() {
+            let (mut position, mut velocity) = match trajectory.state_at(sim_time, epoch) {
+                Some(state) => state,
+                None => continue,
+            };
+            if let Some(center) = center.and_then(|center| frame.spatial.get(center)) {
+                position += center._position;
+                velocity += center._velocity;
+            }
+            if let Some(kinematic) = frame.spatial.get_mut(*body) {
+                kinematic._position = position;
+                kinematic._velocity = velocity;
+            }
+        }
+    }
+
+    /// Ids of the bodies the integrators move, every body but the scripted ones
+    fn integrated_bodies(frame: &PhysicsFrame) -> Vec<usize> {
+        frame.kinematic_data()
+            .enumerate()
+            .filter(|(_, kinematic)| kinematic._physcategory != PhysicsCategory::Scripted)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Keeps an SGP4 propagation of a body, relative to the earth body `earth_id`, to compare the simulation against
+    pub fn set_sgp4_reference(&mut self, body_id: usize, earth_id: usize, sgp4: Sgp4) {
+        self.sgp4_references.retain(|(id, _, _)| *id != body_id);
+        self.sgp4_references.push((body_id, earth_id, sgp4));
+    }
+
+    pub fn sgp4_reference(&self, body_id: usize) -> Option<(usize, &Sgp4)> {
+        self.sgp4_references.iter().find(|(id, _, _)| *id == body_id).map(|(_, earth_id, sgp4)| (*earth_id, sgp4))
+    }
+
+    /// Distance in metres between the simulated position of a body and its SGP4 reference at the current epoch
+    pub fn sgp4_deviation(&self, body_id: usize) -> Option<f64> {
+        let (earth_id, sgp4) = self.sgp4_reference(body_id)?;
+        let epoch = self.present().epoch()?;
+        let body = self.present().get_body_ref(body_id)?;
+        let earth = self.present().get_body_ref(earth_id)?;
+        let (reference, _) = sgp4.state_at(epoch).ok()?;
+        Some((body.position() - earth.position() - reference).magnitude())
     }
 
     pub fn system_kinetic_energy(&self) -> f64 {
@@ -477,26 +810,42 @@ impl Simulation {
         let data = self.present().kinematic_data();
 
         for body in data {
-            let v = body._velocity.magnitude() as f64;
+            let v = body._velocity.magnitude();
             sum += (body._mass / 2.0) * (v * v);
         }
         sum
     }
     
+    /// Gravitational potential energy, of every pair with at least one gravitational body counted once. A pair of
+    /// gravitational bodies uses the mean of their parameters, which only differ if given inconsistently
     pub fn system_potential_energy(&self) -> f64 {
         let mut sum = 0.0;
-        // calculate gravitational potential energy
-        
-        for (i, body) in self.present().kinematic_data().enumerate() {
-            for (k, other) in self.present().kinematic_data().enumerate() {
-                if i != k {
-                    sum += -(G * other._mass * body._mass) / other._position.length_to(&body._position);
-                }
+        let bodies: Vec<(&PhysKinematic, &PhysDynamic)> = self.present().dynamic_integration_data().collect();
+
+        for (i, (body, body_dynamic)) in bodies.iter().enumerate() {
+            for (other, other_dynamic) in bodies[i + 1..].iter() {
+                let (body_source, other_source) = (body._physcategory.is_gravitational(), other._physcategory.is_gravitational());
+                let mu_m = match (body_source, other_source) {
+                    (true, true) => 0.5 * (body_dynamic._grav_param * other._mass + other_dynamic._grav_param * body._mass),
+                    (true, false) => body_dynamic._grav_param * other._mass,
+                    (false, true) => other_dynamic._grav_param * body._mass,
+                    (false, false) => continue,
+                };
+                sum -= mu_m / other._position.length_to(&body._position);
             }
         }
         sum
     }
 
+    /// Total angular momentum about the origin
+    pub fn system_angular_momentum(&self) -> DVec3 {
+        let mut sum = DVec3::zero();
+        for body in self.present().kinematic_data() {
+            sum += body._position.cross(&body._velocity) * body._mass;
+        }
+        sum
+    }
+
     pub fn system_total_mass(&self) -> f64 {
         let mut sum = 0.0;
         for body in self.present().kinematic_data() {
@@ -505,99 +854,268 @@ impl Simulation {
         sum
     }
     
+    /// Registers a force model, evaluated after those already registered
+    pub fn add_force_model(&mut self, model: Box<dyn ForceModel>) {
+        self.force_models.push(model);
+    }
+
+    /// Unregisters the first force model named `name`, `"gravity"` for the built in gravity
+    pub fn remove_force_model(&mut self, name: &str) -> Option<Box<dyn ForceModel>> {
+        let index = self.force_models.iter().position(|model| model.name() == name)?;
+        Some(self.force_models.remove(index))
+    }
+
+    pub fn force_model_names(&self) -> Vec<&str> {
+        self.force_models.iter().map(|model| model.name()).collect()
+    }
+
     // TODO: DIVIDE AND CONQUER FORCE CALCULATIONS WHERE POSSIBLE
-    pub fn calculate_independent_forces(&self) {
-        unimplemented!();
+    /// Replaces the independent forces in `frame` with those of the independent models, once per step
+    pub fn calculate_independent_forces(&self, frame: &mut PhysicsFrame) {
+        self.telemetry.time(Phase::Clear, || {
+            for body in frame.dynamic_data_mut() {
+                body._f_independent = DVec3::zero();
+            }
+        });
+        let all: Vec<usize> = (0..frame.forces.len()).collect();
+        self.calculate_forces_on(frame, None, ForceDependency::Independent, &all);
     }
 
     pub fn calculate_spatially_dependent_forces(&self, frame: &mut PhysicsFrame) {
-        self.calculate_gravitational_forces(frame);
+        let all: Vec<usize> = (0..frame.forces.len()).collect();
+        self.calculate_forces_on(frame, Some(self.present()), ForceDependency::Spatial, &all);
     }
 
-    pub fn calculate_velocity_dependent_forces(&self) {
-        unimplemented!();
+    pub fn calculate_velocity_dependent_forces(&self, frame: &mut PhysicsFrame) {
+        let all: Vec<usize> = (0..frame.forces.len()).collect();
+        self.calculate_forces_on(frame, Some(self.present()), ForceDependency::Velocity, &all);
     }
-    
-    pub fn calculate_gravitational_forces(&self, frame: &mut PhysicsFrame) {
-        let new_data = frame.dynamic_integration_data_mut();        
-        for (i, (new_body_kinematic, new_body_dynamic)) in new_data.enumerate() {
 
-            let old_data = self.present().dynamic_integration_data();
-            for (k, (old_body_kinematic, old_body_dynamic)) in old_data.enumerate() {
-                
-                // only collect gravitational influences from gravitational bodies
-                if old_body_kinematic._physcategory == PhysicsCategory::Gravitational {
-                    // don't impart forces on yourself
-                    if i != k { 
-                        // F = G ((m1 * m2) / r^2)
-                        let r = DVec3::length_to(&old_body_kinematic._position, &new_body_kinematic._position);
-                        let m2 = new_body_kinematic._mass;
-                        let f = (old_body_dynamic._grav_param * m2) / (r * r);
-                        new_body_dynamic._f_spatially_dep += (new_body_kinematic._position.normal_vector_toward(&old_body_kinematic._position) * f).into();
+    /// Evaluates the dependent models on every integrated body, from the sources in the present frame. Forces on
+    /// scripted bodies would go unused
+    fn calculate_dependent_forces(&self, frame: &mut PhysicsFrame) {
+        let integrated = Self::integrated_bodies(frame);
+        self.calculate_dependent_forces_on(frame, Some(self.present()), &integrated);
+    }
+
+    /// Evaluates the spatially and velocity dependent models on the `targets` bodies, at the state already in `frame`
+    fn calculate_dependent_forces_on(&self, frame: &mut PhysicsFrame, sources: Option<&PhysicsFrame>,
+                                     targets: &[usize]) {
+        self.calculate_forces_on(frame, sources, ForceDependency::Spatial, targets);
+        self.calculate_forces_on(frame, sources, ForceDependency::Velocity, targets);
+    }
+
+    /// Adds the forces of every model with the given dependency into the matching accumulator of the `targets` bodies
+    ///
+    /// Sources are read from `sources`, or from the frame being integrated when there are none, which the block
+    /// integrator relies on since bodies are drifted many times within a single frame
+    fn calculate_forces_on(&self, frame: &mut PhysicsFrame, sources: Option<&PhysicsFrame>, dependency: ForceDependency,
+                           targets: &[usize]) {
+        let models: Vec<&dyn ForceModel> = self.force_models.iter()
+            .filter(|model| model.dependency() == dependency)
+            .map(|model| model.as_ref())
+            .collect();
+        if models.is_empty() {
+            return
+        }
+
+        self.telemetry.time(Phase::Forces, || {
+            let mut forces = vec![DVec3::zero(); targets.len()];
+            let sources = sources.unwrap_or(frame);
+            for model in models {
+                model.accumulate(sources, frame, targets, &mut forces);
+            }
+            for (&i, force) in targets.iter().zip(forces) {
+                let body_dynamic = &mut frame.forces[i];
+                match dependency {
+                    ForceDependency::Independent => body_dynamic._f_independent += force,
+                    ForceDependency::Spatial => body_dynamic._f_spatially_dep += force,
+                    ForceDependency::Velocity => body_dynamic._f_velocity_dep += force,
+                }
+            }
+        });
+    }
+
+    /// Hierarchical block timestep integration in kick-drift-kick form
+    ///
+    /// The frame is divided into 2^max_level sub-steps. Every body drifts to each sub-step on which some block ends,
+    /// but only the bodies whose own block ends there have their forces evaluated and receive their half kicks.
+    /// A body may move to a finer level at any block boundary, but only to a coarser level where the new block lines
+    /// up with the sub-step count, so all bodies meet again at the end of the frame
+    fn integrate_block_velocity_verlet(&self, frame: &mut PhysicsFrame) {
+        let params = self.block_timestep;
+        let substeps = params.block_length(0);
+        let dt_min = self.timestep / substeps as f64;
+        let step_at = |level: u32| self.timestep / (1u64 << level) as f64;
+
+        // all bodies are synchronised at the start of a frame, evaluate them all and pick fresh levels
+        let integrated = Self::integrated_bodies(frame);
+        self.clear_accelerations_and_spatially_dependent_forces(frame);
+        self.calculate_dependent_forces(frame);
+        for &i in integrated.iter() {
+            let (body_kinematic, body_dynamic) = (&mut frame.spatial[i], &frame.forces[i]);
+            body_kinematic._acceleration = body_dynamic.fnet() / body_kinematic._mass;
+            body_kinematic._block_level = params.level_for(self.timestep, body_kinematic);
+            let dt = step_at(body_kinematic._block_level);
+            body_kinematic._velocity += body_kinematic._acceleration * (0.5 * dt); // opening half kick
+        }
+
+        let mut s = 0;
+        while s < substeps {
+            // skip straight to the next sub-step on which any block ends
+            let next = integrated.iter()
+                .map(|&i| params.block_length(frame.spatial[i]._block_level))
+                .map(|length| (s / length + 1) * length)
+                .min()
+                .unwrap_or(substeps);
+            let dt_drift = (next - s) as f64 * dt_min;
+            s = next;
+
+            // drift, inactive bodies coast on their half kicked velocities
+            for &i in integrated.iter() {
+                let body_kinematic = &mut frame.spatial[i];
+                body_kinematic._position += body_kinematic._velocity * dt_drift;
+            }
+            Self::place_scripted_bodies(&self.rails, frame, frame.simtime + s as f64 * dt_min);
+
+            let active: Vec<usize> = integrated.iter()
+                .copied()
+                .filter(|&i| s % params.block_length(frame.spatial[i]._block_level) == 0)
+                .collect();
+
+            self.telemetry.time(Phase::Clear, || {
+                for &i in active.iter() {
+                    frame.forces[i]._f_spatially_dep = DVec3::zero();
+                    frame.forces[i]._f_velocity_dep = DVec3::zero();
+                }
+            });
+            self.calculate_dependent_forces_on(frame, None, &active);
+
+            for &i in active.iter() {
+                let (body_kinematic, body_dynamic) = (&mut frame.spatial[i], &frame.forces[i]);
+                let dt = step_at(body_kinematic._block_level);
+                let a = body_kinematic._acceleration; // acceleration at the start of this bodies block
+                let b = body_dynamic.fnet() / body_kinematic._mass; // acceleration at the end of this bodies block
+
+                body_kinematic._velocity += b * (0.5 * dt); // closing half kick
+                body_kinematic._jerk = (b - a) / dt;
+                body_kinematic._acceleration = b;
+
+                if s < substeps {
+                    // a new block may only start where its length divides the elapsed sub-steps
+                    let mut level = params.level_for(self.timestep, body_kinematic);
+                    while s % params.block_length(level) != 0 {
+                        level += 1;
                     }
+                    body_kinematic._block_level = level;
+                    body_kinematic._velocity += b * (0.5 * step_at(level)); // opening half kick
                 }
             }
         }
     }
-    
+
     fn clear_spatially_dependent_forces(&self, frame: &mut PhysicsFrame) {
-        for body in frame.dynamic_data_mut() {
-            body._f_spatially_dep = SVec3::zero();
-            body._f_velocity_dep = SVec3::zero();
-        }
+        self.telemetry.time(Phase::Clear, || {
+            for body in frame.dynamic_data_mut() {
+                body._f_spatially_dep = DVec3::zero();
+                body._f_velocity_dep = DVec3::zero();
+            }
+        });
     }
 
     fn clear_accelerations_and_spatially_dependent_forces(&self, frame: &mut PhysicsFrame) {
-        let data = frame.dynamic_integration_data_mut();
+        self.telemetry.time(Phase::Clear, || {
+            let data = frame.dynamic_integration_data_mut();
 
-        for (body_kinematic, body_dynamic) in data {
-            body_kinematic._acceleration = SVec3::zero();
-            body_dynamic._f_spatially_dep = SVec3::zero();
-            body_dynamic._f_velocity_dep = SVec3::zero();
-        }
+            for (body_kinematic, body_dynamic) in data {
+                body_kinematic._acceleration = DVec3::zero();
+                body_dynamic._f_spatially_dep = DVec3::zero();
+                body_dynamic._f_velocity_dep = DVec3::zero();
+            }
+        });
     }
 
+    pub fn set_pacer(&mut self, pacer: Option<Pacer>) {
+        self.pacer = pacer;
+    }
+
+    /// Paces runs at `warp` simulated seconds per wall clock second, returning the handle to change it with later
+    pub fn set_time_warp(&mut self, warp: f64) -> TimeWarp {
+        let warp_handle = self.pacer.get_or_insert_with(|| Pacer::new(warp)).time_warp();
+        warp_handle.set(warp);
+        warp_handle
+    }
+
+    pub fn pacer(&self) -> Option<&Pacer> {
+        self.pacer.as_ref()
+    }
+
+    pub fn pacer_mut(&mut self) -> Option<&mut Pacer> {
+        self.pacer.as_mut()
+    }
+
+    /// Adds a condition ending the run, replacing any earlier condition of the same kind
     pub fn set_termination_condition(&mut self, condition: TerminationCondition) {
-        self.termination_conditions.push(condition); // TODO: This is dumb too, test for duplicates and sanity check
+        let kind = std::mem::discriminant(&condition);
+        self.termination_conditions.retain(|existing| std::mem::discriminant(existing) != kind);
+        self.termination_conditions.push(condition);
+    }
+
+    pub fn termination_conditions(&self) -> &[TerminationCondition] {
+        &self.termination_conditions
+    }
 
-        // TODO: Sort the conditions
+    pub fn has_termination_conditions(&self) -> bool {
+        !self.termination_conditions.is_empty()
+    }
+
+    /// Whether any termination condition has been met
+    pub fn finished(&self) -> bool {
+        !self.test_termination_conditions()
     }
 
     pub fn step_simulation(&mut self) {
         // step 1: compute possible collisions and the exact time/position they occur
         //         treat acceleration as being constant during this step. quadratic root finding
-        let mut frame = self.present().clone();
-
-        // step 2: integrate accelerations and velocities
-        match self.integration_method {
+        self.telemetry.begin_step();
+        let mut frame = self.telemetry.time(Phase::Clone, || self.present().clone());
+        self.calculate_independent_forces(&mut frame); // held for the whole step
+        let end_time = frame.simtime + self.timestep;
+        let integrated = Self::integrated_bodies(&frame); // scripted bodies are placed on their rails afterwards
+
+        // step 2: integrate accelerations and velocities, the clears and force calculations are timed separately
+        self.telemetry.time(Phase::Integration, || match self.integration_method {
             IntegrationMethod::Euler => {
                 self.clear_accelerations_and_spatially_dependent_forces(&mut frame);
-                self.calculate_spatially_dependent_forces(&mut frame);
+                self.calculate_dependent_forces(&mut frame);
 
-                for (body_kinematic, body_dynamic) in frame.dynamic_integration_data_mut() {
+                for &i in integrated.iter() {
+                    let (body_kinematic, body_dynamic) = (&mut frame.spatial[i], &frame.forces[i]);
                     body_kinematic._acceleration = body_dynamic.fnet() / body_kinematic._mass;
-                    body_kinematic._position += (body_kinematic._velocity * self.timestep).into(); // position then velocity
+                    body_kinematic._position += body_kinematic._velocity * self.timestep; // position then velocity
                     body_kinematic._velocity += body_kinematic._acceleration * self.timestep;
                 }
             }
 
             IntegrationMethod::SemiImplicitEuler => {
                 self.clear_accelerations_and_spatially_dependent_forces(&mut frame);
-                self.calculate_spatially_dependent_forces(&mut frame);
+                self.calculate_dependent_forces(&mut frame);
 
-                for (body_kinematic, body_dynamic) in frame.dynamic_integration_data_mut() {
+                for &i in integrated.iter() {
+                    let (body_kinematic, body_dynamic) = (&mut frame.spatial[i], &frame.forces[i]);
                     body_kinematic._acceleration = body_dynamic.fnet() / body_kinematic._mass;
                     body_kinematic._velocity += body_kinematic._acceleration * self.timestep;
-                    body_kinematic._position += (body_kinematic._velocity * self.timestep).into(); // velocity then position
+                    body_kinematic._position += body_kinematic._velocity * self.timestep; // velocity then position
                 }
             }
             
             IntegrationMethod::VelocityVerlet => {
                 self.clear_accelerations_and_spatially_dependent_forces(&mut frame);
-                self.calculate_spatially_dependent_forces(&mut frame);
+                self.calculate_dependent_forces(&mut frame);
 
                 // integrate velocities first
-                for (body_kinematic, body_dynamic) in frame.dynamic_integration_data_mut() {
+                for &i in integrated.iter() {
+                    let (body_kinematic, body_dynamic) = (&mut frame.spatial[i], &frame.forces[i]);
                     let dt = self.timestep; // dT
                     let p = body_kinematic._position; // p(T)
                     let v = body_kinematic._velocity; // v(T)
@@ -608,10 +1126,11 @@ impl Simulation {
                 }
                 
                 self.clear_spatially_dependent_forces(&mut frame);
-                self.calculate_spatially_dependent_forces(&mut frame); // recalculate forces for new accelerations
+                self.calculate_dependent_forces(&mut frame); // recalculate forces for new accelerations
 
                 // integrate new accelerations sampled at the beginning and end of the timestep
-                for (body_kinematic, body_dynamic) in frame.dynamic_integration_data_mut() {
+                for &i in integrated.iter() {
+                    let (body_kinematic, body_dynamic) = (&mut frame.spatial[i], &frame.forces[i]);
                     let dt = self.timestep; // dT
                     let v = body_kinematic._velocity; // v(T)
                     let a = body_kinematic._acceleration; // a(T) // we saved the accelerations we calculated initially here
@@ -621,63 +1140,453 @@ impl Simulation {
                     body_kinematic._acceleration = b;
                 }
             }
-        }
+
+            IntegrationMethod::BlockVelocityVerlet => {
+                self.integrate_block_velocity_verlet(&mut frame);
+            }
+        });
+        Self::place_scripted_bodies(&self.rails, &mut frame, end_time); // overrides any integration
         
         frame.timestep = self.timestep;
         frame.simtime += self.timestep;
         frame.frame_number += 1;
-        self.present_state = frame;
+        let commit = std::time::Instant::now();
+        self.present_state = frame; // drops the previous frame
+        self.telemetry.record(Phase::Commit, commit.elapsed());
+        self.telemetry.end_step();
     }
     
     fn test_termination_conditions(&self) -> bool {
-        // return true if we are still running, false if a condition is met
-        if self.termination_conditions.is_empty() {
-            return true
-        } else {
-            for condition in self.termination_conditions.iter() {
-                match condition {
-                    TerminationCondition::ElapsedTime(t) => {
-                        return self.present().simtime < *t
-                    },
-                }
-            }
-            unreachable!()
+        // return true if we are still running, false once any condition is met
+        self.termination_conditions.iter().all(|condition| match condition {
+            TerminationCondition::ElapsedTime(t) => self.present().simtime < *t,
+            TerminationCondition::WallTime(t) => self.telemetry.wall_time().as_secs_f64() < *t,
+            TerminationCondition::Frames(n) => self.present().frame_number < *n,
+        })
+    }
+
+    /// Steps once, passing the committed frame and the events it raised to the observers
+    pub fn step(&mut self) {
+        self.step_observed(true);
+    }
+
+    /// Steps `frames` times regardless of the termination conditions and pacing
+    pub fn step_n(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.step_observed(true);
         }
     }
 
-    // run the simulation for the desired time in seconds
-    pub fn run(&mut self) {
-        while self.test_termination_conditions() {
-            self.step_simulation();
-            if let Some(output) = self.output_device.as_ref() {
-                output.output(&self);
-            }
+    /// Runs, paced if a time warp is set, until the simulation time reaches `sim_time` seconds
+    pub fn run_until(&mut self, sim_time: f64) {
+        self.run_while(|sim| sim.present().sim_time() < sim_time);
+    }
+
+    /// Runs, paced if a time warp is set, for `duration` of wall clock time and returns the number of steps taken
+    pub fn run_for_wall(&mut self, duration: std::time::Duration) -> u64 {
+        let (start, steps) = (std::time::Instant::now(), self.telemetry.steps());
+        self.run_while(|_| start.elapsed() < duration);
+        self.telemetry.steps() - steps
+    }
+
+    /// Runs until a termination condition is met, then finishes the observers
+    pub fn run(&mut self) -> std::io::Result<()> {
+        self.run_while(|sim| sim.test_termination_conditions());
+        self.finish()
+    }
+
+    /// Lets every observer write out what it has collected, as at the end of `run`. The others are still finished
+    /// when one fails, the first error is returned
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        let mut observers = std::mem::take(&mut self.observers);
+        let mut result = Ok(());
+        for observer in observers.iter_mut() {
+            let finished = observer.finish(self);
+            result = result.and(finished);
+        }
+        self.observers = observers;
+        result
+    }
+
+    fn run_while(&mut self, keep_running: impl Fn(&Simulation) -> bool) {
+        while keep_running(self) {
+            // a paced run that has fallen behind skips output until it catches up
+            let (sim_time, timestep) = (self.present().sim_time(), self.timestep);
+            let on_schedule = self.pacer.as_mut().map(|pacer| pacer.pace(sim_time, timestep)).unwrap_or(true);
+            self.step_observed(on_schedule);
         }
     }
+
+    fn step_observed(&mut self, on_schedule: bool) {
+        self.step_simulation();
+        if self.observers.is_empty() {
+            return
+        }
+
+        // the observers are taken out for the duration of the calls, as they need to mutate themselves while reading the sim
+        let mut observers = std::mem::take(&mut self.observers);
+        let (detector, frame) = (&mut self.events, &self.present_state);
+        let events = self.telemetry.time(Phase::Output, || detector.detect(frame));
+        self.telemetry.time(Phase::Output, || {
+            for observer in observers.iter_mut() {
+                for event in events.iter() {
+                    observer.event(self, event);
+                }
+                if on_schedule || !observer.may_skip_frames() {
+                    observer.frame(self);
+                }
+            }
+        });
+        self.observers = observers;
+    }
 }
 
+impl MemUse for PhysKinematic {}
+impl MemUse for PhysDynamic {}
+impl MemUse for PhysRotational {}
+impl MemUse for PhysCollision {}
+impl MemUse for TerminationCondition {}
+
 impl MemUse for PhysicsFrame {
-    fn memory_use(&self) -> usize {
+    fn heap_use(&self) -> usize {
         let mut total = 0;
-        total += ::std::mem::size_of_val(&self.spatial);
-        total += ::std::mem::size_of_val(&self.forces);
-        total += ::std::mem::size_of_val(&self.rotations);
-        total += ::std::mem::size_of_val(&self.collisions);
-        total += ::std::mem::size_of_val(&self.name_index);
-        total += ::std::mem::size_of_val(&self.frame_number);
-        total += ::std::mem::size_of_val(&self.simtime);
+        total += self.spatial.heap_use();
+        total += self.forces.heap_use();
+        total += self.rotations.heap_use();
+        total += self.collisions.heap_use();
+        total += self.name_index.heap_use();
         total
     }
 }
 
 impl MemUse for Simulation {
-    fn memory_use(&self) -> usize {
+    fn heap_use(&self) -> usize {
         let mut total = 0;
-        total += self.present_state.memory_use();
-        total += ::std::mem::size_of_val(&self.timestep);
-        total += ::std::mem::size_of_val(&self.integration_method);
-        total += ::std::mem::size_of_val(&self.termination_conditions);
-        total += if let Some(device) = &self.output_device { device.memory_use() } else { ::std::mem::size_of_val(&self.output_device) };
+        total += self.present_state.heap_use();
+        total += self.termination_conditions.heap_use();
+        total += self.sgp4_references.heap_use();
+        total += self.rails.heap_use();
+        total += self.observers.capacity() * std::mem::size_of::<Box<dyn Observer>>();
+        total += self.observers.iter().map(|observer| observer.heap_use()).sum::<usize>();
+        total += self.events.heap_use();
+        total += self.force_models.capacity() * std::mem::size_of::<Box<dyn ForceModel>>();
+        total += self.telemetry.heap_use();
+        total += self.pacer.heap_use();
         total
-    }    
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Integrates a year of a circular earth orbit about a fixed sun with velocity verlet, keeping positions in f64
+    /// but velocities and accelerations at precision `T`. Returns the largest relative energy error seen
+    fn earth_orbit_energy_error<T: Scalar>(dt: f64) -> f64 {
+        let mu = SOL_GRAV_PARAM.cubic_meters_per_second_squared();
+        let accel = |p: &DVec3| -> Vec3<T> { (*p * (-mu / p.magnitude().powi(3))).cast() };
+        let energy = |p: &DVec3, v: &Vec3<T>| {
+            let v = v.cast::<f64>().magnitude();
+            0.5 * v * v - mu / p.magnitude()
+        };
+
+        let r = EARTH_DIST_TO_SOL.meters();
+        let mut p = DVec3::new(r, 0.0, 0.0);
+        let mut v: Vec3<T> = DVec3::new(0.0, (mu / r).sqrt(), 0.0).cast();
+        let mut a = accel(&p);
+        let e0 = energy(&p, &v);
+        let (dt_t, half) = (T::from_f64(dt), T::from_f64(0.5));
+
+        let mut worst: f64 = 0.0;
+        for _ in 0..(365.25 * 86400.0 / dt) as usize {
+            p += (v * dt_t + a * (half * dt_t * dt_t)).cast();
+            let b = accel(&p);
+            v += (a + b) * (half * dt_t);
+            a = b;
+            worst = worst.max(((energy(&p, &v) - e0) / e0).abs());
+        }
+        worst
+    }
+
+    #[test]
+    fn kinematic_precision_comparison() {
+        // the justification for f64 kinematics, with f32 velocities rounding error swamps the integrators own error
+        let single = earth_orbit_energy_error::<f32>(600.0);
+        let double = earth_orbit_energy_error::<f64>(600.0);
+        // f32 loses about 5e-6 of the orbital energy over the year, f64 stays near its own rounding at about 3e-14
+        assert!(single > 1.0e-7, "f32 energy error {:e}", single);
+        assert!(double < 1.0e-12, "f64 energy error {:e}", double);
+        assert!(double * 1.0e6 < single, "f32 {:e}, f64 {:e}", single, double);
+    }
+
+    #[test]
+    fn memory_use_scales_with_bodies() {
+        let mut sim = Simulation::new();
+        let empty = sim.memory_use();
+        for i in 0..1000 {
+            sim.make_physics_body().named(&format!("Body {}", i)).add();
+        }
+
+        // at least the per body data, plus a name and an id list in the index for each
+        let per_body = std::mem::size_of::<PhysKinematic>() + std::mem::size_of::<PhysDynamic>() + std::mem::size_of::<PhysRotational>();
+        let grown = sim.memory_use() - empty;
+        assert!(grown > 1000 * (per_body + "BODY 999".len() + std::mem::size_of::<usize>()));
+        assert!(grown < 4000 * (per_body + 64));
+    }
+
+    #[test]
+    fn first_termination_condition_met_ends_the_run() {
+        let mut sim = Simulation::new();
+        sim.make_physics_body().named("Probe").add();
+        sim.set_termination_condition(TerminationCondition::ElapsedTime(10.0));
+        sim.set_termination_condition(TerminationCondition::Frames(50));
+        sim.set_termination_condition(TerminationCondition::ElapsedTime(100.0)); // replaces the 10 s limit
+        assert_eq!(sim.termination_conditions().len(), 2);
+
+        sim.run().unwrap();
+        assert_eq!(sim.present().frame_number(), 50);
+        assert_eq!(sim.present().body_name(0), Some("PROBE"));
+    }
+
+    /// Counts the frames it is passed into a shared counter
+    struct FrameCounter(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl Observer for FrameCounter {
+        fn frame(&mut self, _sim: &Simulation) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn stepping_api_notifies_every_observer() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Simulation>();
+
+        let mut sim = Simulation::new();
+        sim.make_physics_body().add();
+        let (first, second) = (std::sync::Arc::default(), std::sync::Arc::default());
+        sim.add_observer(Box::new(FrameCounter(std::sync::Arc::clone(&first))));
+        sim.add_observer(Box::new(FrameCounter(std::sync::Arc::clone(&second))));
+
+        sim.step();
+        sim.step_n(9);
+        sim.run_until(25.0);
+        assert_eq!(sim.present().frame_number(), 25);
+        let steps = sim.run_for_wall(std::time::Duration::from_millis(20));
+        assert!(steps > 0);
+        assert_eq!(sim.present().frame_number() as u64, 25 + steps);
+        assert_eq!(first.load(std::sync::atomic::Ordering::Relaxed), sim.present().frame_number());
+        assert_eq!(second.load(std::sync::atomic::Ordering::Relaxed), sim.present().frame_number());
+        assert_eq!(sim.take_observers().len(), 2);
+    }
+
+    /// Fails to write out when finished, counting how many times it was asked to
+    struct FailingOutput(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl Observer for FailingOutput {
+        fn frame(&mut self, _sim: &Simulation) {}
+
+        fn finish(&mut self, _sim: &Simulation) -> std::io::Result<()> {
+            let count = self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Err(std::io::Error::other(format!("disk full {}", count)))
+        }
+    }
+
+    #[test]
+    fn run_returns_the_first_output_error() {
+        let mut sim = Simulation::new();
+        sim.make_physics_body().add();
+        sim.set_termination_condition(TerminationCondition::Frames(5));
+        let finished = std::sync::Arc::default();
+        sim.add_observer(Box::new(FailingOutput(std::sync::Arc::clone(&finished))));
+        sim.add_observer(Box::new(FailingOutput(std::sync::Arc::clone(&finished))));
+
+        // both are finished, the error is the caller's to report
+        let error = sim.run().unwrap_err();
+        assert_eq!(error.to_string(), "disk full 0");
+        assert_eq!(finished.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn scripted_bodies_follow_their_rails() {
+        let mu = EARTH_GRAV_PARAM.cubic_meters_per_second_squared();
+        let (p0, v0) = (DVec3::new(3.844e8, 0.0, 0.0), DVec3::new(0.0, 1022.0, 0.0));
+        let expected = propagate_kepler(p0, v0, mu, 6000.0).0;
+
+        for &method in [IntegrationMethod::Euler, IntegrationMethod::VelocityVerlet, IntegrationMethod::BlockVelocityVerlet].iter() {
+            // an earth with no rails is never integrated, so stays put however hard the moon pulls on it
+            let mut sim = Simulation::new();
+            let earth = sim.make_physics_body().named("Earth").with_physics_category(PhysicsCategory::Scripted)
+                .with_mass(EARTH_MASS.kilograms()).with_grav_param(mu).add();
+            let moon = sim.make_physics_body().named("Moon").with_mass(7.342e22).relative_to(earth).add();
+            let probe = sim.make_physics_body().named("Probe").with_transform(DVec3::new(3.844e8, -1.0e7, 0.0), None).add();
+            assert!(sim.set_rails(moon, Some(earth), Rails::Kepler { position: p0, velocity: v0, grav_param: mu, sim_time: 0.0 }));
+            assert_eq!(sim.present().get_body_ref(moon).unwrap().physics_category(), PhysicsCategory::Scripted);
+            sim.set_integration_method(method);
+            sim.set_timestep(60.0);
+            sim.step_n(100);
+
+            let frame = sim.present();
+            assert_eq!(frame.get_body_ref(earth).unwrap().position(), DVec3::zero(), "{:?}", method);
+            assert!(frame.get_body_ref(moon).unwrap().position().length_to(&expected) < 1.0e-3, "{:?}", method);
+
+            // the moon pulls the probe along +y harder than the earth pulls it along -x
+            let velocity = frame.get_body_ref(probe).unwrap().velocity();
+            assert!(velocity.y > 0.0 && velocity.y > -velocity.x, "{:?}: {:?}", method, velocity);
+        }
+    }
+
+    #[test]
+    fn integrators_skip_scripted_bodies() {
+        for &method in [IntegrationMethod::Euler, IntegrationMethod::SemiImplicitEuler, IntegrationMethod::VelocityVerlet,
+                        IntegrationMethod::BlockVelocityVerlet].iter() {
+            // a scripted body only moves along its rails, whatever velocity it was given
+            let mut sim = Simulation::new();
+            let beacon = sim.make_physics_body().with_physics_category(PhysicsCategory::Scripted)
+                .with_velocity(DVec3::new(1.0e3, 0.0, 0.0)).add();
+            sim.set_integration_method(method);
+            sim.step_n(10);
+            assert_eq!(sim.present().get_body_ref(beacon).unwrap().position(), DVec3::zero(), "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn perturbed_mass_scales_the_gravitational_parameter() {
+        let mu = EARTH_GRAV_PARAM.cubic_meters_per_second_squared();
+        let mut sim = Simulation::new();
+        let earth = sim.make_physics_body().with_physics_category(PhysicsCategory::Gravitational)
+            .with_mass(EARTH_MASS.kilograms()).with_grav_param(mu).add();
+        let probe = sim.make_physics_body().with_mass(1000.0).with_grav_param(1.0).add();
+
+        assert!(sim.perturb_body(earth, DVec3::zero(), DVec3::zero(), 0.01 * EARTH_MASS.kilograms()));
+        assert!(sim.perturb_body(probe, DVec3::zero(), DVec3::zero(), 10.0));
+        assert!(!sim.perturb_body(2, DVec3::zero(), DVec3::zero(), 1.0));
+
+        // only gravitational bodies pull on others, a dynamic body keeps its parameter
+        let frame = sim.present();
+        assert!((frame.get_body_ref(earth).unwrap().grav_param() / mu - 1.01).abs() < 1.0e-12);
+        assert_eq!(frame.get_body_ref(probe).unwrap().grav_param(), 1.0);
+        assert_eq!(frame.get_body_ref(probe).unwrap().mass(), 1010.0);
+    }
+
+    #[test]
+    fn verlet_reads_sources_from_the_present_frame() {
+        let (mu, d, dt) = (1.0e12, 1.0e6, 10.0);
+        let mut sim = Simulation::new();
+        let a = sim.make_physics_body().with_physics_category(PhysicsCategory::Gravitational).with_grav_param(mu).add();
+        sim.make_physics_body().with_physics_category(PhysicsCategory::Gravitational).with_grav_param(mu)
+            .with_transform(DVec3::new(d, 0.0, 0.0), None).add();
+        sim.set_integration_method(IntegrationMethod::VelocityVerlet);
+        sim.set_timestep(dt);
+        sim.step();
+
+        // both bodies fall 0.5 a dT^2 toward each other, the closing acceleration is taken against where the other was
+        let start = mu / (d * d);
+        let drift = 0.5 * start * dt * dt;
+        let end = mu / ((d - drift) * (d - drift));
+        let body = sim.present().get_body_ref(a).unwrap();
+        assert!((body.position().x - drift).abs() < 1.0e-9, "{:?}", body.position());
+        assert!((body.velocity().x - 0.5 * (start + end) * dt).abs() < 1.0e-12, "{:?}", body.velocity());
+    }
+
+    /// A sun, a body on a wide orbit and one on a tight orbit, integrated in hour long block timestep frames
+    fn block_timestep_system(max_level: u32) -> (Simulation, usize, usize) {
+        let mu = SOL_GRAV_PARAM.cubic_meters_per_second_squared();
+        let (slow_r, fast_r) = (EARTH_DIST_TO_SOL.meters(), 0.05 * EARTH_DIST_TO_SOL.meters());
+        let mut sim = Simulation::new();
+        sim.make_physics_body().named("Sun").with_physics_category(PhysicsCategory::Gravitational).with_grav_param(mu).add();
+        let slow = sim.make_physics_body().named("Slow").with_transform(DVec3::new(slow_r, 0.0, 0.0), None)
+            .with_velocity(DVec3::new(0.0, (mu / slow_r).sqrt(), 0.0)).add();
+        let fast = sim.make_physics_body().named("Fast").with_transform(DVec3::new(-fast_r, 0.0, 0.0), None)
+            .with_velocity(DVec3::new(0.0, -(mu / fast_r).sqrt(), 0.0)).add();
+        sim.set_integration_method(IntegrationMethod::BlockVelocityVerlet);
+        sim.set_block_timestep(BlockTimestep { max_level, ..BlockTimestep::default() }).unwrap();
+        sim.set_timestep(3600.0);
+        (sim, slow, fast)
+    }
+
+    #[test]
+    fn block_levels_follow_the_orbit() {
+        let (mut sim, slow, fast) = block_timestep_system(8);
+        sim.step();
+
+        // the tight orbit turns through a hundredth of a radian in about 560 s, the coarsest step under that is 450 s
+        let levels: Vec<u32> = sim.present().kinematic_data().map(|kinematic| kinematic._block_level).collect();
+        assert_eq!(levels[slow], 0);
+        assert_eq!(levels[fast], 3);
+    }
+
+    /// Records the bodies each evaluation was made for, and adds no force of its own
+    struct EvaluationLog(std::sync::Arc<std::sync::Mutex<Vec<Vec<usize>>>>);
+
+    impl ForceModel for EvaluationLog {
+        fn name(&self) -> &str {
+            "log"
+        }
+
+        fn dependency(&self) -> ForceDependency {
+            ForceDependency::Spatial
+        }
+
+        fn accumulate(&self, _sources: &PhysicsFrame, _frame: &PhysicsFrame, targets: &[usize], _forces: &mut [DVec3]) {
+            self.0.lock().unwrap().push(targets.to_vec());
+        }
+    }
+
+    #[test]
+    fn block_substeps_only_kick_active_bodies() {
+        let (mut sim, slow, fast) = block_timestep_system(3);
+        let log = std::sync::Arc::default();
+        sim.add_force_model(Box::new(EvaluationLog(std::sync::Arc::clone(&log))));
+        let start = sim.present().get_body_ref(slow).unwrap().position();
+        sim.step();
+
+        // every body is evaluated at the start, then the fast body on each of its 8 sub-steps and the slow body at the end
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 9);
+        assert_eq!(log.iter().filter(|targets| targets.contains(&fast)).count(), 9);
+        assert_eq!(log.iter().filter(|targets| targets.contains(&slow)).count(), 2);
+
+        // so the slow body coasts on its opening half kick through the sub-steps, and lands where one verlet step would
+        let mu = SOL_GRAV_PARAM.cubic_meters_per_second_squared();
+        let (dt, r) = (3600.0, start.x);
+        let v = (mu / r).sqrt();
+        let expected = DVec3::new(r - 0.5 * (mu / (r * r)) * dt * dt, v * dt, 0.0);
+        let error = sim.present().get_body_ref(slow).unwrap().position().length_to(&expected);
+        assert!(error < 1.0e-3, "{}", error); // rounding, of a position of 1.5e11 m summed over the sub-steps
+    }
+
+    #[test]
+    fn block_timestep_matches_verlet_at_the_finest_level() {
+        // a tiny eta keeps the body on the finest level, 1/16 of a day, for a year
+        let mu = SOL_GRAV_PARAM.cubic_meters_per_second_squared();
+        let r = EARTH_DIST_TO_SOL.meters();
+        let orbit = |method: IntegrationMethod, timestep: f64, steps: usize| {
+            let mut sim = Simulation::new();
+            sim.make_physics_body().named("Sun").with_physics_category(PhysicsCategory::Gravitational).with_grav_param(mu).add();
+            let earth = sim.make_physics_body().named("Earth").with_transform(DVec3::new(r, 0.0, 0.0), None)
+                .with_velocity(DVec3::new(0.0, (mu / r).sqrt(), 0.0)).add();
+            sim.set_integration_method(method);
+            sim.set_block_timestep(BlockTimestep { max_level: 4, eta: 1.0e-9 }).unwrap();
+            sim.set_timestep(timestep);
+            let e0 = sim.system_kinetic_energy() + sim.system_potential_energy();
+            sim.step_n(steps);
+            let energy_error = ((sim.system_kinetic_energy() + sim.system_potential_energy() - e0) / e0).abs();
+            (sim.present().get_body_ref(earth).unwrap().position(), energy_error)
+        };
+
+        let (block_position, block_energy) = orbit(IntegrationMethod::BlockVelocityVerlet, 86400.0, 365);
+        let (verlet_position, verlet_energy) = orbit(IntegrationMethod::VelocityVerlet, 5400.0, 365 * 16);
+        assert!(block_position.length_to(&verlet_position) < 1.0, "{}", block_position.length_to(&verlet_position));
+        assert!(block_energy < 1.0e-12 && verlet_energy < 1.0e-12, "{} {}", block_energy, verlet_energy);
+        assert!((block_energy - verlet_energy).abs() < 1.0e-13, "{} {}", block_energy, verlet_energy);
+    }
+
+    #[test]
+    fn block_timestep_parameters_are_validated() {
+        let mut sim = Simulation::new();
+        assert_eq!(sim.set_block_timestep(BlockTimestep { max_level: 32, ..BlockTimestep::default() }), Err(BlockTimestepError::MaxLevel(32)));
+        assert_eq!(sim.set_block_timestep(BlockTimestep { eta: 0.0, ..BlockTimestep::default() }), Err(BlockTimestepError::Eta(0.0)));
+        assert!(sim.set_block_timestep(BlockTimestep { max_level: BlockTimestep::MAX_LEVEL, ..BlockTimestep::default() }).is_ok());
+    }
 }
@@ -0,0 +1,329 @@
+use std::{ fmt::Write as FmtWrite, fs::File, io::Write };
+
+use crate::{ debug::MemUse, math::DVec3, observer::id_after_removal, sim::*, output::format_si_value };
+
+// SVG trajectory plots
+//
+// Tracked bodies are recorded relative to a reference body (or the origin) during the run and written as a single SVG
+// at termination. Both axes share one scale so that orbits keep their shape, ticks fall on 1, 2 or 5 times a power of
+// ten and are labelled with SI prefixes
+
+/// The plane trajectories are projected onto, named by its horizontal then vertical axis
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ProjectionPlane {
+    #[default]
+    XY,
+    XZ,
+    YZ,
+}
+
+impl ProjectionPlane {
+    fn project(&self, v: &DVec3) -> (f64, f64) {
+        match self {
+            ProjectionPlane::XY => (v.x, v.y),
+            ProjectionPlane::XZ => (v.x, v.z),
+            ProjectionPlane::YZ => (v.y, v.z),
+        }
+    }
+
+    fn axis_names(&self) -> (&'static str, &'static str) {
+        match self {
+            ProjectionPlane::XY => ("X", "Y"),
+            ProjectionPlane::XZ => ("X", "Z"),
+            ProjectionPlane::YZ => ("Y", "Z"),
+        }
+    }
+}
+
+impl std::str::FromStr for ProjectionPlane {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "XY" => Ok(ProjectionPlane::XY),
+            "XZ" => Ok(ProjectionPlane::XZ),
+            "YZ" => Ok(ProjectionPlane::YZ),
+            _ => Err(()),
+        }
+    }
+}
+
+const COLORS: [&str; 8] = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+
+const MARGIN_LEFT: f64 = 90.0;
+const MARGIN_RIGHT: f64 = 30.0;
+const MARGIN_TOP: f64 = 30.0;
+const MARGIN_BOTTOM: f64 = 60.0;
+
+/// A recorded trajectory, positions are relative to the reference body
+#[derive(Debug, Clone)]
+struct Series {
+    name: String,
+    body_id: Option<usize>, // None once the body is removed, the recorded positions are kept
+    positions: Vec<DVec3>,
+}
+
+enum LegendMarker {
+    Line(&'static str),
+    Periapsis,
+    Apoapsis,
+}
+
+#[derive(Debug, Clone)]
+pub struct SvgPlot {
+    pub path: String,
+    pub plane: ProjectionPlane,
+    pub reference: Option<(String, usize)>, // trajectories are drawn relative to this body, the origin if None
+    pub apsides: bool,
+    pub width: f64,
+    pub height: f64,
+    series: Vec<Series>,
+}
+
+impl SvgPlot {
+    pub fn new(path: &str, tracked: &[(String, usize)]) -> Self {
+        SvgPlot {
+            path: String::from(path),
+            plane: ProjectionPlane::default(),
+            reference: None,
+            apsides: false,
+            width: 800.0,
+            height: 800.0,
+            series: tracked.iter().map(|(name, id)| Series { name: name.clone(), body_id: Some(*id), positions: Vec::new() }).collect(),
+        }
+    }
+
+    pub fn with_plane(mut self, plane: ProjectionPlane) -> Self {
+        self.plane = plane;
+        self
+    }
+
+    pub fn with_reference(mut self, reference: Option<(String, usize)>) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    pub fn with_apsides(mut self, apsides: bool) -> Self {
+        self.apsides = apsides;
+        self
+    }
+
+    /// Records the current position of every tracked body
+    pub fn record(&mut self, sim: &Simulation) {
+        let frame = sim.present();
+        let origin = self.reference.as_ref().and_then(|(_, id)| frame.get_body_ref(*id)).map(|body| body.position()).unwrap_or_default();
+        for series in self.series.iter_mut() {
+            if let Some(body) = series.body_id.and_then(|id| frame.get_body_ref(id)) {
+                series.positions.push(body.position() - origin);
+            }
+        }
+    }
+
+    /// Ends the trajectory of a removed body where it was last recorded, or every trajectory if it was the reference
+    /// they are drawn relative to
+    pub fn remove_body(&mut self, id: usize) {
+        for series in self.series.iter_mut() {
+            series.body_id = series.body_id.and_then(|body| id_after_removal(body, id));
+        }
+        match self.reference.as_mut() {
+            Some((_, reference)) if *reference == id => {
+                for series in self.series.iter_mut() {
+                    series.body_id = None;
+                }
+            },
+            Some((_, reference)) if *reference > id => *reference -= 1,
+            _ => {},
+        }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut file = File::create(&self.path)?;
+        file.write_all(self.render().as_bytes())
+    }
+
+    pub fn render(&self) -> String {
+        let (h_name, v_name) = self.plane.axis_names();
+
+        // one scale for both axes, fitted to the longer side and centred on the shorter
+        let points = self.series.iter().flat_map(|s| s.positions.iter()).map(|p| self.plane.project(p)).chain(std::iter::once((0.0, 0.0)));
+        let (min_x, max_x, min_y, max_y) = points.fold((f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY), |(a, b, c, d), (x, y)| (a.min(x), b.max(x), c.min(y), d.max(y)));
+        let plot_width = self.width - MARGIN_LEFT - MARGIN_RIGHT;
+        let plot_height = self.height - MARGIN_TOP - MARGIN_BOTTOM;
+        let span = ((max_x - min_x) / plot_width).max((max_y - min_y) / plot_height).max(f64::MIN_POSITIVE) * 1.05;
+        let (centre_x, centre_y) = ((min_x + max_x) * 0.5, (min_y + max_y) * 0.5);
+        let (x_range, y_range) = ((centre_x - span * plot_width * 0.5, centre_x + span * plot_width * 0.5), (centre_y - span * plot_height * 0.5, centre_y + span * plot_height * 0.5));
+        let to_svg = |(x, y): (f64, f64)| (MARGIN_LEFT + (x - x_range.0) / span, MARGIN_TOP + (y_range.1 - y) / span);
+
+        let mut svg = String::new();
+        let _ = writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"12\">", self.width, self.height);
+        let _ = writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+        let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>", MARGIN_LEFT, MARGIN_TOP, plot_width, plot_height);
+
+        // ticks and grid lines
+        for tick in nice_ticks(x_range.0, x_range.1) {
+            let (x, _) = to_svg((tick, 0.0));
+            let bottom = MARGIN_TOP + plot_height;
+            let _ = writeln!(svg, "<line x1=\"{0:.2}\" y1=\"{1}\" x2=\"{0:.2}\" y2=\"{2}\" stroke=\"#e0e0e0\"/>", x, MARGIN_TOP, bottom);
+            let _ = writeln!(svg, "<line x1=\"{0:.2}\" y1=\"{1}\" x2=\"{0:.2}\" y2=\"{2}\" stroke=\"black\"/>", x, bottom, bottom + 5.0);
+            let _ = writeln!(svg, "<text x=\"{:.2}\" y=\"{}\" text-anchor=\"middle\">{}</text>", x, bottom + 20.0, tick_label(tick));
+        }
+        for tick in nice_ticks(y_range.0, y_range.1) {
+            let (_, y) = to_svg((0.0, tick));
+            let _ = writeln!(svg, "<line x1=\"{1}\" y1=\"{0:.2}\" x2=\"{2}\" y2=\"{0:.2}\" stroke=\"#e0e0e0\"/>", y, MARGIN_LEFT, MARGIN_LEFT + plot_width);
+            let _ = writeln!(svg, "<line x1=\"{1}\" y1=\"{0:.2}\" x2=\"{2}\" y2=\"{0:.2}\" stroke=\"black\"/>", y, MARGIN_LEFT - 5.0, MARGIN_LEFT);
+            let _ = writeln!(svg, "<text x=\"{}\" y=\"{:.2}\" text-anchor=\"end\" dominant-baseline=\"middle\">{}</text>", MARGIN_LEFT - 8.0, y, tick_label(tick));
+        }
+        let reference_name = self.reference.as_ref().map(|(name, _)| name.as_str()).unwrap_or("origin");
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{} (m, relative to {})</text>", MARGIN_LEFT + plot_width * 0.5, self.height - 15.0, h_name, xml_escape(reference_name));
+        let _ = writeln!(svg, "<text x=\"20\" y=\"{0}\" text-anchor=\"middle\" transform=\"rotate(-90 20 {0})\">{1} (m)</text>", MARGIN_TOP + plot_height * 0.5, v_name);
+
+        // the reference body sits at the origin
+        let (ox, oy) = to_svg((0.0, 0.0));
+        let _ = writeln!(svg, "<path d=\"M {:.2} {:.2} h 10 M {:.2} {:.2} v 10\" stroke=\"black\"/>", ox - 5.0, oy, ox, oy - 5.0);
+        if let Some((name, _)) = &self.reference {
+            let _ = writeln!(svg, "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>", ox + 8.0, oy - 8.0, xml_escape(name));
+        }
+
+        for (index, series) in self.series.iter().enumerate() {
+            let color = COLORS[index % COLORS.len()];
+            let points: Vec<String> = series.positions.iter().map(|p| to_svg(self.plane.project(p))).map(|(x, y)| format!("{:.2},{:.2}", x, y)).collect();
+            let _ = writeln!(svg, "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>", color, points.join(" "));
+
+            if self.apsides {
+                let (periapses, apoapses) = apsides(&series.positions);
+                for i in periapses {
+                    let (x, y) = to_svg(self.plane.project(&series.positions[i]));
+                    let _ = writeln!(svg, "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"4\" fill=\"{}\"/>", x, y, color);
+                }
+                for i in apoapses {
+                    let (x, y) = to_svg(self.plane.project(&series.positions[i]));
+                    let _ = writeln!(svg, "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"8\" height=\"8\" fill=\"white\" stroke=\"{}\"/>", x - 4.0, y - 4.0, color);
+                }
+            }
+        }
+
+        // legend, top right inside the plot area
+        let mut entries: Vec<(String, LegendMarker)> = self.series.iter().enumerate().map(|(i, s)| (xml_escape(&s.name), LegendMarker::Line(COLORS[i % COLORS.len()]))).collect();
+        if self.apsides {
+            entries.push((String::from("periapsis"), LegendMarker::Periapsis));
+            entries.push((String::from("apoapsis"), LegendMarker::Apoapsis));
+        }
+        let legend_x = MARGIN_LEFT + plot_width - 150.0;
+        let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"140\" height=\"{}\" fill=\"white\" fill-opacity=\"0.85\" stroke=\"#808080\"/>", legend_x, MARGIN_TOP + 10.0, entries.len() as f64 * 18.0 + 8.0);
+        for (i, (label, marker)) in entries.iter().enumerate() {
+            let y = MARGIN_TOP + 24.0 + i as f64 * 18.0;
+            let _ = match marker {
+                LegendMarker::Periapsis => writeln!(svg, "<circle cx=\"{}\" cy=\"{}\" r=\"4\" fill=\"black\"/>", legend_x + 20.0, y - 4.0),
+                LegendMarker::Apoapsis => writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"8\" height=\"8\" fill=\"white\" stroke=\"black\"/>", legend_x + 16.0, y - 8.0),
+                LegendMarker::Line(color) => writeln!(svg, "<line x1=\"{}\" y1=\"{2}\" x2=\"{}\" y2=\"{2}\" stroke=\"{3}\" stroke-width=\"2\"/>", legend_x + 8.0, legend_x + 32.0, y - 4.0, color),
+            };
+            let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\">{}</text>", legend_x + 40.0, y, label);
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Tick positions covering [min, max] at 1, 2 or 5 times a power of ten, aiming for five to ten ticks
+fn nice_ticks(min: f64, max: f64) -> Vec<f64> {
+    let range = max - min;
+    if range <= 0.0 || !range.is_finite() {
+        return Vec::new()
+    }
+    let magnitude = 10f64.powf((range / 5.0).log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].iter().map(|m| m * magnitude).find(|step| range / step < 10.0).unwrap_or(magnitude * 10.0);
+    let first = (min / step).ceil() as i64;
+    let last = (max / step).floor() as i64;
+    (first..=last).map(|i| i as f64 * step).collect()
+}
+
+fn tick_label(value: f64) -> String {
+    let (scaled, prefix) = format_si_value(value);
+    // trims the float noise from multiples of the step, e.g. 0.30000000000000004
+    let text = format!("{:.3}", scaled);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    format!("{} {}m", if text == "-0" { "0" } else { text }, prefix)
+}
+
+/// Indices of the local minima and maxima of distance from the reference body, the periapses and apoapses
+fn apsides(positions: &[DVec3]) -> (Vec<usize>, Vec<usize>) {
+    let distances: Vec<f64> = positions.iter().map(|p| p.magnitude()).collect();
+    let mut periapses = Vec::new();
+    let mut apoapses = Vec::new();
+    for i in 1..distances.len().saturating_sub(1) {
+        let (before, here, after) = (distances[i - 1], distances[i], distances[i + 1]);
+        if here < before && here <= after {
+            periapses.push(i);
+        } else if here > before && here >= after {
+            apoapses.push(i);
+        }
+    }
+    (periapses, apoapses)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl MemUse for Series {
+    fn heap_use(&self) -> usize {
+        self.name.heap_use() + self.positions.heap_use()
+    }
+}
+
+impl MemUse for SvgPlot {
+    fn heap_use(&self) -> usize {
+        self.path.heap_use() + self.reference.heap_use() + self.series.heap_use()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks() {
+        assert_eq!(nice_ticks(0.0, 10.0), vec![0.0, 2.0, 4.0, 6.0, 8.0, 10.0]);
+        assert_eq!(nice_ticks(-1.6e11, 1.6e11), vec![-1.5e11, -1.0e11, -0.5e11, 0.0, 0.5e11, 1.0e11, 1.5e11]);
+        assert!(nice_ticks(1.0, 1.0).is_empty());
+        assert_eq!(tick_label(1.5e11), "150 Gm");
+        assert_eq!(tick_label(-4.0e6), "-4 Mm");
+        assert_eq!(tick_label(0.0), "0 m");
+    }
+
+    #[test]
+    fn apsides_of_an_ellipse() {
+        // two revolutions of an ellipse with the focus at the origin, periapsis on +x at steps 0, 100 and 200
+        let (a, e) = (2.0, 0.5);
+        let positions: Vec<DVec3> = (0..=200).map(|i| {
+            let nu = i as f64 * std::f64::consts::PI / 50.0;
+            let r = a * (1.0 - e * e) / (1.0 + e * nu.cos());
+            DVec3::new(r * nu.cos(), r * nu.sin(), 0.0)
+        }).collect();
+        let (periapses, apoapses) = apsides(&positions);
+        assert_eq!(periapses, vec![100]); // the end points can't be classified
+        assert_eq!(apoapses, vec![50, 150]);
+    }
+
+    #[test]
+    fn renders_trajectories() {
+        let mut sim = Simulation::new();
+        let sun = sim.make_physics_body().named("Sun").with_physics_category(PhysicsCategory::Gravitational).add();
+        let probe = sim.make_physics_body().named("Probe").with_transform(DVec3::new(1.0e9, 2.0e9, 3.0e9), None).add();
+        let mut plot = SvgPlot::new("unused.svg", &[(String::from("PROBE <1>"), probe)])
+            .with_plane(ProjectionPlane::XZ)
+            .with_reference(Some((String::from("SUN"), sun)))
+            .with_apsides(true);
+        plot.record(&sim);
+        plot.record(&sim);
+
+        let svg = plot.render();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<polyline").count(), 1);
+        assert!(svg.contains("PROBE &lt;1&gt;"));
+        assert!(svg.contains("X (m, relative to SUN)"));
+        assert!(svg.contains("Z (m)"));
+        assert!(svg.contains(" Gm</text>"));
+    }
+}
@@ -0,0 +1,218 @@
+use std::{error::Error, fmt::Display, time::{Duration, Instant}};
+
+use crate::{math::DVec3, scenario::{Scenario, ScenarioError}, sim::*};
+
+// Timestep and integrator sweeps
+//
+// Runs a scenario for its duration once for every pairing of timestep and integrator, and compares each run with a
+// reference run at a much finer timestep. Runs are made one at a time so that their runtimes are comparable.
+//
+//   energy drift     relative change in total energy from the start to the end of the run
+//   ang mom drift    change in total angular momentum about the origin, relative to its starting magnitude
+//   position error   largest distance between a body and itself in the reference run, at the end
+//
+// Each timestep must divide the duration, so that every run ends at the same time as the reference
+
+/// The reference timestep is the finest timestep swept divided by this
+pub const REFERENCE_DIVISOR: f64 = 16.0;
+
+#[derive(Debug)]
+pub enum SweepError {
+    Scenario(ScenarioError),
+    NoDuration,
+    Indivisible(f64), // a timestep that does not divide the duration
+}
+
+impl Display for SweepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Scenario(inner) => write!(f, "{}", inner),
+            Self::NoDuration => write!(f, "the scenario needs a duration to sweep"),
+            Self::Indivisible(timestep) => write!(f, "a timestep of {} s does not divide the duration", timestep),
+        }
+    }
+}
+
+impl Error for SweepError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Scenario(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScenarioError> for SweepError {
+    fn from(error: ScenarioError) -> Self {
+        SweepError::Scenario(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    scenario: Scenario,
+    timesteps: Vec<f64>,
+    integrators: Vec<IntegrationMethod>,
+    reference: Option<(f64, IntegrationMethod)>,
+}
+
+impl Sweep {
+    pub fn new(scenario: Scenario, timesteps: Vec<f64>, integrators: Vec<IntegrationMethod>) -> Self {
+        Sweep {
+            scenario,
+            timesteps,
+            integrators,
+            reference: None,
+        }
+    }
+
+    /// Replaces the default reference, velocity verlet at the finest timestep over `REFERENCE_DIVISOR`
+    pub fn with_reference(mut self, timestep: f64, integrator: IntegrationMethod) -> Self {
+        self.reference = Some((timestep, integrator));
+        self
+    }
+
+    pub fn run(&self) -> Result<SweepReport, SweepError> {
+        let duration = self.scenario.duration.ok_or(SweepError::NoDuration)?.seconds();
+        let finest = self.timesteps.iter().cloned().fold(f64::INFINITY, f64::min);
+        let (reference_timestep, reference_integrator) = self.reference
+            .unwrap_or((finest / REFERENCE_DIVISOR, IntegrationMethod::VelocityVerlet));
+
+        let (reference_run, reference_frame) = self.run_one(duration, reference_timestep, reference_integrator, None)?;
+        let mut runs = Vec::new();
+        for &integrator in self.integrators.iter() {
+            for &timestep in self.timesteps.iter() {
+                runs.push(self.run_one(duration, timestep, integrator, Some(&reference_frame))?.0);
+            }
+        }
+        Ok(SweepReport { duration, reference: reference_run, runs })
+    }
+
+    fn run_one(&self, duration: f64, timestep: f64, integrator: IntegrationMethod, reference: Option<&PhysicsFrame>) -> Result<(SweepRun, PhysicsFrame), SweepError> {
+        let steps = (duration / timestep).round();
+        if steps < 1.0 || (steps * timestep - duration).abs() > 1.0e-9 * duration {
+            return Err(SweepError::Indivisible(timestep))
+        }
+
+        let mut sim = self.scenario.build()?;
+        sim.set_timestep(timestep);
+        sim.set_integration_method(integrator);
+        let (e0, l0) = (sim.system_kinetic_energy() + sim.system_potential_energy(), sim.system_angular_momentum());
+
+        let start = Instant::now();
+        sim.step_n(steps as usize);
+        let runtime = start.elapsed();
+
+        let (e1, l1) = (sim.system_kinetic_energy() + sim.system_potential_energy(), sim.system_angular_momentum());
+        let frame = sim.present().clone();
+        let position_error = reference.map(|reference| {
+            (0..frame.kinematic_data().len())
+                .filter_map(|id| Some(frame.get_body_ref(id)?.position() - reference.get_body_ref(id)?.position()))
+                .map(|difference: DVec3| difference.magnitude())
+                .fold(0.0, f64::max)
+        });
+
+        let run = SweepRun {
+            integrator,
+            timestep,
+            steps: steps as usize,
+            energy_drift: ((e1 - e0) / e0).abs(),
+            angular_momentum_drift: (l1 - l0).magnitude() / l0.magnitude(),
+            position_error,
+            runtime,
+        };
+        Ok((run, frame))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepRun {
+    pub integrator: IntegrationMethod,
+    pub timestep: f64,
+    pub steps: usize,
+    pub energy_drift: f64,
+    pub angular_momentum_drift: f64,
+    pub position_error: Option<f64>, // m, None for the reference itself
+    pub runtime: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepReport {
+    pub duration: f64,
+    pub reference: SweepRun,
+    pub runs: Vec<SweepRun>,
+}
+
+impl SweepReport {
+    /// The fastest run whose position error is within `tolerance` meters
+    pub fn fastest_within(&self, tolerance: f64) -> Option<&SweepRun> {
+        self.runs.iter()
+            .filter(|run| run.position_error.is_some_and(|error| error <= tolerance))
+            .min_by_key(|run| run.runtime)
+    }
+}
+
+impl Display for SweepReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reference = &self.reference;
+        writeln!(
+            f, "duration {} s, reference {:?} at {} s: {} steps in {:.3} s, energy drift {:.3e}",
+            self.duration, reference.integrator, reference.timestep, reference.steps, reference.runtime.as_secs_f64(), reference.energy_drift
+        )?;
+        writeln!(f, "{:<20} {:>12} {:>10} {:>13} {:>13} {:>16} {:>10}", "integrator", "timestep s", "steps", "energy drift", "ang mom drift", "position error m", "runtime s")?;
+        for run in self.runs.iter() {
+            writeln!(
+                f, "{:<20} {:>12} {:>10} {:>13.3e} {:>13.3e} {:>16.3e} {:>10.3}",
+                format!("{:?}", run.integrator), run.timestep, run.steps, run.energy_drift, run.angular_momentum_drift,
+                run.position_error.unwrap_or(0.0), run.runtime.as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn earth_orbit() -> Scenario {
+        Scenario::parse("
+            duration = 30 d
+            [body Sol]
+            category = gravitational
+            mass = 1 Msun
+            [body Earth]
+            category = gravitational
+            mass = 1 Mearth
+            relative_to = Sol
+            position = 1 AU, 0 m, 0 m
+            velocity = 0 m/s, 29.78 km/s, 0 m/s
+        ").unwrap()
+    }
+
+    #[test]
+    fn errors_shrink_with_the_timestep_and_order() {
+        let sweep = Sweep::new(earth_orbit(), vec![6.0 * 3600.0, 3600.0], vec![IntegrationMethod::Euler, IntegrationMethod::VelocityVerlet]);
+        let report = sweep.run().unwrap();
+        let error = |integrator, timestep| {
+            report.runs.iter().find(|run| run.integrator == integrator && run.timestep == timestep).unwrap().position_error.unwrap()
+        };
+
+        // first order euler gains about 6x from a 6x smaller step, second order verlet about 36x
+        let euler = error(IntegrationMethod::Euler, 6.0 * 3600.0) / error(IntegrationMethod::Euler, 3600.0);
+        let verlet = error(IntegrationMethod::VelocityVerlet, 6.0 * 3600.0) / error(IntegrationMethod::VelocityVerlet, 3600.0);
+        assert!(euler > 4.0 && euler < 8.0, "{}", euler);
+        assert!(verlet > 25.0 && verlet < 50.0, "{}", verlet);
+        assert!(error(IntegrationMethod::VelocityVerlet, 3600.0) < error(IntegrationMethod::Euler, 3600.0) / 100.0);
+
+        // verlet holds the energy far better than euler at the same timestep
+        assert!(report.runs[3].energy_drift < report.runs[1].energy_drift / 100.0, "{}", report);
+        assert!(report.fastest_within(1.0e9).is_some());
+    }
+
+    #[test]
+    fn timesteps_must_divide_the_duration() {
+        let sweep = Sweep::new(earth_orbit(), vec![7.0 * 3600.0], vec![IntegrationMethod::Euler]);
+        assert!(matches!(sweep.run(), Err(SweepError::Indivisible(_))));
+    }
+}
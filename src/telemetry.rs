@@ -0,0 +1,200 @@
+use std::{cell::Cell, time::{Duration, Instant}};
+
+use crate::debug::MemUse;
+
+// Performance telemetry
+//
+// Wall clock time spent in each phase of a simulation step, accumulated over the run. Timed sections may nest, the
+// time of an inner section is only counted against its own phase, so integration excludes the force calculations and
+// clears it makes. The counters use cells so that the `&self` physics methods can be timed from the inside
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Clone, // copying the present frame to integrate into
+    Clear, // zeroing forces and accelerations
+    Forces,
+    Integration,
+    Commit, // replacing the present frame
+    Output,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [Phase::Clone, Phase::Clear, Phase::Forces, Phase::Integration, Phase::Commit, Phase::Output];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Clone => "clone",
+            Phase::Clear => "clear",
+            Phase::Forces => "forces",
+            Phase::Integration => "integration",
+            Phase::Commit => "commit",
+            Phase::Output => "output",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Cumulative counters at an instant, the difference of two gives the rates over the interval between them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TelemetrySnapshot {
+    pub steps: u64,
+    pub wall_time: Duration, // since the first step started
+    pub sim_time: f64,
+    pub phases: [Duration; 6],
+}
+
+impl TelemetrySnapshot {
+    /// Steps per wall clock second between `earlier` and this snapshot
+    pub fn steps_per_second(&self, earlier: &TelemetrySnapshot) -> f64 {
+        (self.steps - earlier.steps) as f64 / (self.wall_time - earlier.wall_time).as_secs_f64()
+    }
+
+    /// Simulated seconds per wall clock second between `earlier` and this snapshot
+    pub fn real_time_factor(&self, earlier: &TelemetrySnapshot) -> f64 {
+        (self.sim_time - earlier.sim_time) / (self.wall_time - earlier.wall_time).as_secs_f64()
+    }
+
+    /// Mean milliseconds per step spent in `phase` between `earlier` and this snapshot
+    pub fn phase_ms_per_step(&self, earlier: &TelemetrySnapshot, phase: Phase) -> f64 {
+        let elapsed = self.phases[phase.index()] - earlier.phases[phase.index()];
+        elapsed.as_secs_f64() * 1000.0 / (self.steps - earlier.steps) as f64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Telemetry {
+    started: Cell<Option<Instant>>,
+    steps: Cell<u64>,
+    phases: [Cell<Duration>; 6],
+    nested: Cell<Duration>, // time spent in timed sections inside the one currently running
+}
+
+impl Telemetry {
+    /// Runs `f`, counting its wall time against `phase` less any time counted by sections timed within it
+    pub fn time<T>(&self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let outer = self.nested.replace(Duration::default());
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        let inner = self.nested.replace(outer + elapsed);
+        self.record(phase, elapsed.saturating_sub(inner));
+        result
+    }
+
+    pub fn record(&self, phase: Phase, elapsed: Duration) {
+        let cell = &self.phases[phase.index()];
+        cell.set(cell.get() + elapsed);
+    }
+
+    /// Marks the start of a step, the first one starts the wall clock
+    pub fn begin_step(&self) {
+        if self.started.get().is_none() {
+            self.started.set(Some(Instant::now()));
+        }
+    }
+
+    pub fn end_step(&self) {
+        self.steps.set(self.steps.get() + 1);
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps.get()
+    }
+
+    /// Wall clock time since the first step started
+    pub fn wall_time(&self) -> Duration {
+        self.started.get().map(|start| start.elapsed()).unwrap_or_default()
+    }
+
+    pub fn snapshot(&self, sim_time: f64) -> TelemetrySnapshot {
+        let mut phases = [Duration::default(); 6];
+        for (total, cell) in phases.iter_mut().zip(self.phases.iter()) {
+            *total = cell.get();
+        }
+        TelemetrySnapshot {
+            steps: self.steps.get(),
+            wall_time: self.wall_time(),
+            sim_time,
+            phases,
+        }
+    }
+
+    /// A table of the time spent in each phase over the whole run
+    pub fn summary(&self, sim_time: f64) -> String {
+        let total = self.snapshot(sim_time);
+        let start = TelemetrySnapshot::default();
+        let measured: Duration = total.phases.iter().sum();
+
+        let mut summary = format!(
+            "profile: {} steps in {:.3} s, {:.1} steps/s, real time factor {:.4e}\n",
+            total.steps, total.wall_time.as_secs_f64(), total.steps_per_second(&start), total.real_time_factor(&start)
+        );
+        summary.push_str(&format!("  {:<12} {:>12} {:>14} {:>7}\n", "phase", "total ms", "ms per step", "share"));
+        for phase in Phase::ALL.iter() {
+            let elapsed = total.phases[phase.index()];
+            summary.push_str(&format!(
+                "  {:<12} {:>12.3} {:>14.6} {:>6.1}%\n",
+                phase.name(), elapsed.as_secs_f64() * 1000.0, total.phase_ms_per_step(&start, *phase),
+                100.0 * elapsed.as_secs_f64() / measured.as_secs_f64().max(f64::MIN_POSITIVE)
+            ));
+        }
+        let untimed = total.wall_time.saturating_sub(measured);
+        summary.push_str(&format!("  {:<12} {:>12.3}\n", "untimed", untimed.as_secs_f64() * 1000.0));
+        summary
+    }
+}
+
+impl MemUse for Telemetry {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spin(duration: Duration) {
+        let start = Instant::now();
+        while start.elapsed() < duration {}
+    }
+
+    #[test]
+    fn nested_sections_are_exclusive() {
+        let telemetry = Telemetry::default();
+        telemetry.begin_step();
+        let start = Instant::now();
+        telemetry.time(Phase::Integration, || {
+            spin(Duration::from_millis(5));
+            telemetry.time(Phase::Forces, || spin(Duration::from_millis(20)));
+            telemetry.time(Phase::Clear, || spin(Duration::from_millis(10)));
+        });
+        let elapsed = start.elapsed();
+        telemetry.end_step();
+
+        // each phase gets at least its own spin, and no time is counted twice however long the section took
+        let snapshot = telemetry.snapshot(60.0);
+        let phase = |phase: Phase| snapshot.phases[phase.index()];
+        assert!(phase(Phase::Forces) >= Duration::from_millis(20) && phase(Phase::Clear) >= Duration::from_millis(10));
+        assert!(phase(Phase::Integration) >= Duration::from_millis(5));
+        assert!(phase(Phase::Integration) + phase(Phase::Forces) + phase(Phase::Clear) <= elapsed);
+        assert_eq!(phase(Phase::Output), Duration::default());
+
+        let summary = telemetry.summary(60.0);
+        assert!(summary.starts_with("profile: 1 steps"));
+        assert!(summary.contains("integration"));
+    }
+
+    #[test]
+    fn rates_between_snapshots() {
+        let earlier = TelemetrySnapshot { steps: 100, wall_time: Duration::from_secs(1), sim_time: 100.0, ..TelemetrySnapshot::default() };
+        let mut later = earlier;
+        later.steps = 300;
+        later.wall_time = Duration::from_secs(3);
+        later.sim_time = 2100.0;
+        later.phases[Phase::Forces.index()] = Duration::from_millis(50);
+
+        assert_eq!(later.steps_per_second(&earlier), 100.0);
+        assert_eq!(later.real_time_factor(&earlier), 1000.0);
+        assert!((later.phase_ms_per_step(&earlier, Phase::Forces) - 0.25).abs() < 1e-12);
+    }
+}
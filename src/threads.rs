@@ -0,0 +1,433 @@
+use std::{error::Error, fmt::Display, sync::{Arc, Mutex, PoisonError, atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError}}, thread::JoinHandle, time::{Duration, Instant}};
+
+use crate::{pacing::Schedule, scenario::{BodySpec, Scenario, ScenarioError}, sim::*};
+
+// Background simulation thread
+//
+// `SimHandle` moves a simulation onto its own thread and drives it with commands sent over a channel, so a front end
+// such as a GUI never waits on a step. Between steps the thread takes any waiting commands, while paused it blocks on
+// the channel and while paced it waits out the schedule on the channel, so commands are handled promptly either way.
+//
+// Completed frames are published as shared snapshots. Publishing swaps an Arc under a lock held for no longer than
+// that, so readers never hold up the simulation and the simulation never holds up readers. Frames are published at
+// most once per publish interval while running, and always after a command changes the state.
+//
+// A panic on the simulation thread is caught and reported by every later call on the handle. Dropping the handle
+// shuts the thread down, `shutdown` does the same and hands the simulation back
+
+/// Frames published by a background simulation, a cheap handle that can be shared between threads
+#[derive(Debug, Clone)]
+pub struct Snapshots(Arc<Mutex<Arc<PhysicsFrame>>>);
+
+impl Snapshots {
+    fn new(frame: PhysicsFrame) -> Self {
+        Snapshots(Arc::new(Mutex::new(Arc::new(frame))))
+    }
+
+    /// The most recently published frame
+    pub fn latest(&self) -> Arc<PhysicsFrame> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn publish(&self, frame: PhysicsFrame) {
+        let frame = Arc::new(frame);
+        let previous = std::mem::replace(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner), frame);
+        drop(previous); // outside the lock, the last reader of a frame frees it
+    }
+}
+
+#[derive(Debug)]
+pub enum SimCommand {
+    Pause,
+    Resume,
+    Step(usize), // steps while paused, ignoring the termination conditions
+    SetTimestep(f64),
+    AddBody(Box<BodySpec>, Sender<Result<usize, ScenarioError>>),
+    RemoveBody(usize, Sender<bool>),
+    Snapshot(Sender<Arc<PhysicsFrame>>), // publishes and replies with the frame as of every earlier command
+    Shutdown,
+}
+
+#[derive(Debug)]
+pub enum ThreadError {
+    Stopped, // the thread has shut down
+    Panicked(String),
+    Scenario(ScenarioError),
+    UnknownBody(usize),
+    Output(std::io::Error), // an observer failed to write out when the thread shut down
+}
+
+impl Display for ThreadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stopped => write!(f, "the simulation thread has shut down"),
+            Self::Panicked(message) => write!(f, "the simulation thread panicked: {}", message),
+            Self::Scenario(inner) => write!(f, "{}", inner),
+            Self::UnknownBody(id) => write!(f, "unknown body: #{}", id),
+            Self::Output(inner) => write!(f, "failed to write output: {}", inner),
+        }
+    }
+}
+
+impl Error for ThreadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Scenario(inner) => Some(inner),
+            Self::Output(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScenarioError> for ThreadError {
+    fn from(error: ScenarioError) -> Self {
+        ThreadError::Scenario(error)
+    }
+}
+
+/// Frames are published at most this often while running, about once per display refresh
+pub const PUBLISH_INTERVAL: Duration = Duration::from_millis(16);
+
+/// What the simulation thread hands back when it exits, nothing if it panicked
+type Finished = Option<(Simulation, std::io::Result<()>)>;
+
+#[derive(Debug)]
+pub struct SimHandle {
+    commands: Sender<SimCommand>,
+    snapshots: Snapshots,
+    running: Arc<AtomicBool>,
+    panic: Arc<Mutex<Option<String>>>,
+    thread: Option<JoinHandle<Finished>>,
+}
+
+impl SimHandle {
+    /// Moves `sim` onto a new thread, paused until `resume`
+    pub fn spawn(sim: Simulation) -> Self {
+        SimHandle::spawn_with_interval(sim, PUBLISH_INTERVAL)
+    }
+
+    /// As `spawn`, publishing frames at most once per `publish_interval` while running
+    pub fn spawn_with_interval(sim: Simulation, publish_interval: Duration) -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let snapshots = Snapshots::new(sim.present().clone());
+        let running = Arc::new(AtomicBool::new(false));
+        let panic = Arc::new(Mutex::new(None));
+
+        let mut worker = Worker {
+            sim,
+            snapshots: snapshots.clone(),
+            running: running.clone(),
+            publish_interval,
+            published: Instant::now(),
+        };
+        let panic_slot = panic.clone();
+        let thread = std::thread::Builder::new()
+            .name(String::from("simulation"))
+            .spawn(move || {
+                match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| worker.run(receiver))) {
+                    Ok(finished) => Some((worker.sim, finished)),
+                    Err(payload) => {
+                        let message = payload.downcast_ref::<&str>().map(|s| String::from(*s))
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| String::from("unknown panic"));
+                        *panic_slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(message);
+                        None
+                    },
+                }
+            })
+            .expect("failed to spawn the simulation thread");
+
+        SimHandle {
+            commands,
+            snapshots,
+            running,
+            panic,
+            thread: Some(thread),
+        }
+    }
+
+    /// A sender for commands from other threads
+    pub fn commands(&self) -> Sender<SimCommand> {
+        self.commands.clone()
+    }
+
+    pub fn send(&self, command: SimCommand) -> Result<(), ThreadError> {
+        self.commands.send(command).map_err(|_| self.stopped())
+    }
+
+    pub fn pause(&self) -> Result<(), ThreadError> {
+        self.send(SimCommand::Pause)
+    }
+
+    pub fn resume(&self) -> Result<(), ThreadError> {
+        self.send(SimCommand::Resume)
+    }
+
+    pub fn step(&self, frames: usize) -> Result<(), ThreadError> {
+        self.send(SimCommand::Step(frames))
+    }
+
+    pub fn set_timestep(&self, timestep: f64) -> Result<(), ThreadError> {
+        self.send(SimCommand::SetTimestep(timestep))
+    }
+
+    /// Adds a body described as in a scenario file, returning its id once it has been added
+    pub fn add_body(&self, body: BodySpec) -> Result<usize, ThreadError> {
+        let (reply, result) = mpsc::channel();
+        self.send(SimCommand::AddBody(Box::new(body), reply))?;
+        Ok(result.recv().map_err(|_| self.stopped())??)
+    }
+
+    /// Removes a body once the thread gets to it, the ids of the bodies added after it shift down by one
+    pub fn remove_body(&self, id: usize) -> Result<(), ThreadError> {
+        let (reply, result) = mpsc::channel();
+        self.send(SimCommand::RemoveBody(id, reply))?;
+        match result.recv().map_err(|_| self.stopped())? {
+            true => Ok(()),
+            false => Err(ThreadError::UnknownBody(id)),
+        }
+    }
+
+    /// The present frame, waiting for the thread to handle every command sent before it
+    pub fn snapshot(&self) -> Result<Arc<PhysicsFrame>, ThreadError> {
+        let (reply, result) = mpsc::channel();
+        self.send(SimCommand::Snapshot(reply))?;
+        result.recv().map_err(|_| self.stopped())
+    }
+
+    /// The most recently published frame, without waiting
+    pub fn latest(&self) -> Arc<PhysicsFrame> {
+        self.snapshots.latest()
+    }
+
+    pub fn snapshots(&self) -> Snapshots {
+        self.snapshots.clone()
+    }
+
+    /// Whether the simulation is running, it pauses itself when a termination condition is met
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Stops the thread after the commands already sent, finishing the observers and returning the simulation
+    pub fn shutdown(mut self) -> Result<Simulation, ThreadError> {
+        let _ = self.commands.send(SimCommand::Shutdown);
+        match self.thread.take().map(|thread| thread.join()) {
+            Some(Ok(Some((sim, Ok(()))))) => Ok(sim),
+            Some(Ok(Some((_, Err(error))))) => Err(ThreadError::Output(error)),
+            _ => Err(self.stopped()),
+        }
+    }
+
+    fn stopped(&self) -> ThreadError {
+        // the channels close as the thread unwinds, before it records the panic
+        while self.thread.as_ref().is_some_and(|thread| !thread.is_finished()) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        match self.panic.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+            Some(message) => ThreadError::Panicked(message.clone()),
+            None => ThreadError::Stopped,
+        }
+    }
+}
+
+impl Drop for SimHandle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.commands.send(SimCommand::Shutdown);
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Worker {
+    sim: Simulation,
+    snapshots: Snapshots,
+    running: Arc<AtomicBool>,
+    publish_interval: Duration,
+    published: Instant,
+}
+
+impl Worker {
+    /// Handles commands and steps until shut down, returning how finishing the observers went
+    fn run(&mut self, commands: Receiver<SimCommand>) -> std::io::Result<()> {
+        loop {
+            let wait = self.next_step_in();
+            let received = match wait {
+                None => commands.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(wait) if wait == Duration::ZERO => commands.try_recv().map_err(|e| match e {
+                    TryRecvError::Empty => RecvTimeoutError::Timeout,
+                    TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+                }),
+                Some(wait) => commands.recv_timeout(wait),
+            };
+
+            match received {
+                Ok(SimCommand::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(command) => self.execute(command),
+                Err(RecvTimeoutError::Timeout) if wait == Some(Duration::ZERO) => {
+                    self.sim.step();
+                    if self.published.elapsed() >= self.publish_interval {
+                        self.publish();
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => (), // waited out the pacing schedule
+            }
+        }
+
+        self.running.store(false, Ordering::Relaxed);
+        self.publish();
+        self.sim.finish()
+    }
+
+    /// How long until the next step is due, None while paused
+    fn next_step_in(&mut self) -> Option<Duration> {
+        if !self.running.load(Ordering::Relaxed) {
+            return None
+        }
+        if self.sim.finished() {
+            self.running.store(false, Ordering::Relaxed);
+            self.publish();
+            return None
+        }
+
+        let (sim_time, timestep) = (self.sim.present().sim_time(), self.sim.timestep());
+        match self.sim.pacer_mut().map(|pacer| pacer.schedule(sim_time, timestep)) {
+            Some(Schedule::Ahead(wait)) => Some(wait),
+            _ => Some(Duration::ZERO),
+        }
+    }
+
+    fn execute(&mut self, command: SimCommand) {
+        match command {
+            SimCommand::Pause => self.running.store(false, Ordering::Relaxed),
+            SimCommand::Resume => {
+                if let Some(pacer) = self.sim.pacer_mut() {
+                    pacer.restart();
+                }
+                self.running.store(true, Ordering::Relaxed);
+            },
+            SimCommand::Step(frames) => self.sim.step_n(frames),
+            SimCommand::SetTimestep(timestep) => self.sim.set_timestep(timestep),
+            SimCommand::AddBody(body, reply) => {
+                let result = Scenario { bodies: vec![*body], ..Scenario::default() }.apply(&mut self.sim)
+                    .map(|_| self.sim.present().kinematic_data().len() - 1);
+                let _ = reply.send(result);
+            },
+            SimCommand::RemoveBody(id, reply) => {
+                let _ = reply.send(self.sim.remove_body(id));
+            },
+            SimCommand::Snapshot(reply) => {
+                self.publish();
+                let _ = reply.send(self.snapshots.latest());
+                return
+            },
+            SimCommand::Shutdown => (),
+        }
+        self.publish();
+    }
+
+    fn publish(&mut self) {
+        self.snapshots.publish(self.sim.present().clone());
+        self.published = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{math::DVec3, observer::Observer};
+
+    fn two_bodies() -> Simulation {
+        let mut sim = Simulation::new();
+        sim.make_physics_body().named("A").add();
+        sim.make_physics_body().named("B").with_transform(DVec3::new(10.0, 0.0, 0.0), None).add();
+        sim
+    }
+
+    /// Panics on the given frame
+    struct Tripwire(usize);
+
+    impl Observer for Tripwire {
+        fn frame(&mut self, sim: &Simulation) {
+            assert!(sim.present().frame_number() != self.0, "tripped on frame {}", self.0);
+        }
+    }
+
+    #[test]
+    fn commands_are_applied_in_order() {
+        let handle = SimHandle::spawn(two_bodies());
+        assert!(!handle.is_running());
+        handle.set_timestep(2.0).unwrap();
+        handle.step(3).unwrap();
+        let frame = handle.snapshot().unwrap();
+        assert_eq!((frame.frame_number(), frame.sim_time()), (3, 6.0));
+
+        let body = BodySpec { name: String::from("C"), ..BodySpec::default() };
+        assert_eq!(handle.add_body(body).unwrap(), 2);
+        handle.remove_body(0).unwrap();
+        assert!(matches!(handle.remove_body(5), Err(ThreadError::UnknownBody(5))));
+        let frame = handle.latest();
+        assert_eq!(frame.kinematic_data().len(), 2);
+        assert_eq!(frame.get_named_bodies("C")[0].id(), 1);
+
+        let sim = handle.shutdown().unwrap();
+        assert_eq!(sim.present().frame_number(), 3);
+    }
+
+    #[test]
+    fn pauses_itself_when_terminated() {
+        let mut sim = two_bodies();
+        sim.set_termination_condition(TerminationCondition::Frames(1000));
+        let handle = SimHandle::spawn_with_interval(sim, Duration::ZERO);
+        handle.resume().unwrap();
+        let start = Instant::now();
+        while handle.latest().frame_number() < 1000 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(handle.snapshot().unwrap().frame_number(), 1000);
+        assert!(!handle.is_running());
+    }
+
+    #[test]
+    fn paced_run_holds_while_paused() {
+        let mut sim = two_bodies();
+        sim.set_time_warp(10.0); // a 1 s step every 100 ms
+        let handle = SimHandle::spawn_with_interval(sim, Duration::ZERO);
+        let start = Instant::now();
+        handle.resume().unwrap();
+        while handle.latest().frame_number() < 2 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        handle.pause().unwrap();
+        let paused = handle.snapshot().unwrap().frame_number();
+        let elapsed = start.elapsed();
+        assert!(paused as u128 <= elapsed.as_millis() / 100, "{} in {:?}", paused, elapsed);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(handle.snapshot().unwrap().frame_number(), paused);
+
+        // pacing restarts on resume rather than catching up on the pause, so it is never ahead of the resumed time
+        let start = Instant::now();
+        handle.resume().unwrap();
+        std::thread::sleep(Duration::from_millis(150));
+        let resumed = handle.snapshot().unwrap().frame_number() - paused;
+        let elapsed = start.elapsed();
+        assert!(resumed as u128 <= elapsed.as_millis() / 100, "{} in {:?}", resumed, elapsed);
+    }
+
+    #[test]
+    fn panics_are_reported() {
+        let mut sim = two_bodies();
+        sim.add_observer(Box::new(Tripwire(2)));
+        let handle = SimHandle::spawn(sim);
+        handle.step(5).unwrap();
+        match handle.snapshot() {
+            Err(ThreadError::Panicked(message)) => assert!(message.contains("tripped on frame 2")),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(handle.resume(), Err(ThreadError::Panicked(_))));
+        assert!(matches!(handle.shutdown(), Err(ThreadError::Panicked(_))));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{error::Error, fmt::Display, path::Path, str::FromStr};
+
+use crate::{epoch::*, sgp4::{Sgp4, Sgp4Error}, sim::*};
+
+// Two-line element sets
+//
+// The fixed column format NORAD publishes earth satellite mean elements in, optionally preceded by a name line
+//
+//   ISS (ZARYA)
+//   1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927
+//   2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537
+//
+// Mean elements are only meaningful to the SGP4 model they were fitted with, so bodies created from an element set
+// take their initial state from an SGP4 propagation to the simulation epoch
+
+#[derive(Debug)]
+pub enum TleError {
+    Io(std::io::Error),
+    Empty,
+    NotFound(String),
+    LineLength { line: usize, length: usize },
+    LineNumber { line: usize },
+    Checksum { line: usize, expected: u32, found: u32 },
+    Field { line: usize, field: &'static str },
+    CatalogMismatch,
+    Propagation(Sgp4Error),
+}
+
+impl Display for TleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(inner) => write!(f, "failed to read element sets: {}", inner),
+            Self::Empty => write!(f, "no element sets found"),
+            Self::NotFound(name) => write!(f, "no element set named '{}'", name),
+            Self::LineLength { line, length } => write!(f, "line {} is {} characters long, expected 69", line, length),
+            Self::LineNumber { line } => write!(f, "line {} does not start with its line number", line),
+            Self::Checksum { line, expected, found } => write!(f, "line {} checksum is {}, computed {}", line, found, expected),
+            Self::Field { line, field } => write!(f, "line {}: malformed {}", line, field),
+            Self::CatalogMismatch => write!(f, "the two lines have different catalog numbers"),
+            Self::Propagation(inner) => write!(f, "sgp4: {}", inner),
+        }
+    }
+}
+
+impl Error for TleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(inner) => Some(inner),
+            Self::Propagation(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TleError {
+    fn from(error: std::io::Error) -> Self {
+        TleError::Io(error)
+    }
+}
+
+impl From<Sgp4Error> for TleError {
+    fn from(error: Sgp4Error) -> Self {
+        TleError::Propagation(error)
+    }
+}
+
+/// A parsed element set, angles in degrees and mean motion in revolutions per day as they appear in the lines
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tle {
+    pub name: Option<String>,
+    pub catalog_number: u32,
+    pub classification: char,
+    pub international_designator: String,
+    pub epoch: Epoch,
+    pub mean_motion_dot: f64, // first derivative of mean motion divided by two, rev/day^2
+    pub mean_motion_ddot: f64, // second derivative of mean motion divided by six, rev/day^3
+    pub bstar: f64, // drag term, inverse earth radii
+    pub element_set_number: u32,
+    pub inclination: f64,
+    pub raan: f64,
+    pub eccentricity: f64,
+    pub arg_perigee: f64,
+    pub mean_anomaly: f64,
+    pub mean_motion: f64,
+    pub revolution_number: u32,
+}
+
+impl Tle {
+    pub fn parse(line1: &str, line2: &str) -> Result<Tle, TleError> {
+        let (line1, line2) = (line1.trim_end(), line2.trim_end());
+        for (number, line) in [(1, line1), (2, line2)] {
+            if line.len() != 69 || !line.is_ascii() {
+                return Err(TleError::LineLength { line: number, length: line.chars().count() })
+            }
+            if !line.starts_with(&format!("{} ", number)) {
+                return Err(TleError::LineNumber { line: number })
+            }
+            let found = line[68..].parse().map_err(|_| TleError::Field { line: number, field: "checksum" })?;
+            let expected = checksum(line);
+            if expected != found {
+                return Err(TleError::Checksum { line: number, expected, found })
+            }
+        }
+
+        let field1 = |range: std::ops::Range<usize>, field: &'static str| field_value::<f64>(line1, 1, range, field);
+        let field2 = |range: std::ops::Range<usize>, field: &'static str| field_value::<f64>(line2, 2, range, field);
+
+        let catalog_number = field_value::<u32>(line1, 1, 2..7, "catalog number")?;
+        if field_value::<u32>(line2, 2, 2..7, "catalog number")? != catalog_number {
+            return Err(TleError::CatalogMismatch)
+        }
+
+        // two digit years from 57 onwards are in the twentieth century, the first satellite launched in 1957
+        let year = field_value::<i32>(line1, 1, 18..20, "epoch year")?;
+        let year = if year >= 57 { 1900 + year } else { 2000 + year };
+        let day = field1(20..32, "epoch day")?;
+        let new_year = CalendarDate { year, month: 1, day: 1, hour: 0, minute: 0, second: 0.0 };
+        let new_year = Epoch::from_calendar(new_year, TimeScale::Utc).map_err(|_| TleError::Field { line: 1, field: "epoch" })?;
+        let epoch = Epoch::from_seconds_past_j2000(new_year.seconds_past_j2000(TimeScale::Utc) + (day - 1.0) * 86400.0, TimeScale::Utc);
+
+        Ok(Tle {
+            name: None,
+            catalog_number,
+            classification: line1[7..8].chars().next().unwrap_or('U'),
+            international_designator: String::from(line1[9..17].trim()),
+            epoch,
+            mean_motion_dot: field1(33..43, "mean motion derivative")?,
+            mean_motion_ddot: exponent_field(&line1[44..52]).ok_or(TleError::Field { line: 1, field: "mean motion second derivative" })?,
+            bstar: exponent_field(&line1[53..61]).ok_or(TleError::Field { line: 1, field: "bstar" })?,
+            element_set_number: field_value::<u32>(line1, 1, 64..68, "element set number").unwrap_or(0),
+            inclination: field2(8..16, "inclination")?,
+            raan: field2(17..25, "right ascension of the ascending node")?,
+            eccentricity: field_value::<f64>(&format!("0.{}", &line2[26..33]), 2, 0..9, "eccentricity")?,
+            arg_perigee: field2(34..42, "argument of perigee")?,
+            mean_anomaly: field2(43..51, "mean anomaly")?,
+            mean_motion: field2(52..63, "mean motion")?,
+            revolution_number: field_value::<u32>(line2, 2, 63..68, "revolution number").unwrap_or(0),
+        })
+    }
+
+    /// Parses every element set in a file, in either the two line or the three line (named) format
+    pub fn parse_all(text: &str) -> Result<Vec<Tle>, TleError> {
+        let mut sets = Vec::new();
+        let mut name = None;
+        let mut lines = text.lines().map(str::trim_end).filter(|line| !line.trim().is_empty());
+
+        while let Some(line) = lines.next() {
+            if line.starts_with("1 ") {
+                let line2 = lines.next().ok_or(TleError::LineNumber { line: 2 })?;
+                let mut tle = Tle::parse(line, line2)?;
+                tle.name = name.take();
+                sets.push(tle);
+            } else {
+                // celestrak style three line sets prefix the name with a zero
+                let line = line.strip_prefix("0 ").unwrap_or(line);
+                name = Some(String::from(line.trim()));
+            }
+        }
+
+        if sets.is_empty() {
+            return Err(TleError::Empty)
+        }
+        Ok(sets)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Tle>, TleError> {
+        let text = std::fs::read_to_string(path)?;
+        Tle::parse_all(&text)
+    }
+
+    /// The name of the satellite, or its catalog number when the set was unnamed
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("{:05}", self.catalog_number))
+    }
+
+    /// Sets the geocentric state of the body at `epoch`. The builder still needs to be made relative to the earth
+    pub fn apply_to<'a>(&self, builder: PhysicsBodyBuilder<'a>, sgp4: &Sgp4, epoch: Epoch) -> Result<PhysicsBodyBuilder<'a>, TleError> {
+        let (position, velocity) = sgp4.state_at(epoch)?;
+        Ok(builder.with_transform(position, None).with_velocity(velocity).named(&self.label()))
+    }
+
+    /// Adds the satellite to the simulation relative to the earth body `earth_id`, and keeps the SGP4 propagation as a
+    /// reference to compare the simulated orbit against. A simulation without an epoch is anchored to the element set
+    pub fn add_to(&self, sim: &mut Simulation, earth_id: usize) -> Result<usize, TleError> {
+        let sgp4 = Sgp4::new(self)?;
+        let epoch = match sim.present().epoch() {
+            Some(epoch) => epoch,
+            None => {
+                sim.set_epoch(self.epoch);
+                self.epoch
+            },
+        };
+
+        let id = self.apply_to(sim.make_physics_body(), &sgp4, epoch)?
+            .with_physics_category(PhysicsCategory::Dynamic)
+            .relative_to(earth_id)
+            .add();
+        sim.set_sgp4_reference(id, earth_id, sgp4);
+        Ok(id)
+    }
+}
+
+impl FromStr for Tle {
+    type Err = TleError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Tle::parse_all(s)?.into_iter().next().ok_or(TleError::Empty)
+    }
+}
+
+/// Modulo 10 sum of the digits in the first 68 columns, with minus signs counting as one
+pub fn checksum(line: &str) -> u32 {
+    line.chars().take(68).map(|c| match c {
+        '-' => 1,
+        c => c.to_digit(10).unwrap_or(0),
+    }).sum::<u32>() % 10
+}
+
+fn field_value<T: FromStr>(line: &str, number: usize, range: std::ops::Range<usize>, field: &'static str) -> Result<T, TleError> {
+    line.get(range).and_then(|text| text.trim().parse().ok()).ok_or(TleError::Field { line: number, field })
+}
+
+/// Parses the assumed decimal point exponent notation, " 13844-3" is 0.13844e-3
+fn exponent_field(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Some(0.0)
+    }
+
+    let (mantissa, exponent) = text.split_at(text.len().checked_sub(2)?);
+    let exponent: i32 = exponent.replace('+', "").parse().ok()?;
+    let (sign, digits) = match mantissa.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+    };
+    let mantissa: f64 = format!("0.{}", digits.trim()).parse().ok()?;
+    Some(sign * mantissa * 10f64.powi(exponent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ISS: &str = "ISS (ZARYA)
+1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927
+2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537
+";
+
+    #[test]
+    fn parse_element_set() {
+        let tle: Tle = ISS.parse().unwrap();
+        assert_eq!(tle.name.as_deref(), Some("ISS (ZARYA)"));
+        assert_eq!(tle.catalog_number, 25544);
+        assert_eq!(tle.international_designator, "98067A");
+        assert_eq!(tle.mean_motion_dot, -0.00002182);
+        assert!((tle.bstar + 0.11606e-4).abs() < 1e-12);
+        assert_eq!(tle.eccentricity, 0.0006703);
+        assert_eq!(tle.mean_motion, 15.72125391);
+        assert_eq!(tle.revolution_number, 56353);
+
+        let date = tle.epoch.calendar(TimeScale::Utc);
+        assert_eq!((date.year, date.month, date.day, date.hour), (2008, 9, 20, 12));
+    }
+
+    #[test]
+    fn checksum_validation() {
+        let lines: Vec<&str> = ISS.lines().collect();
+        let corrupted = lines[1].replace("25544U", "25545U");
+        assert!(matches!(Tle::parse(&corrupted, lines[2]), Err(TleError::Checksum { line: 1, .. })));
+        assert!(matches!(Tle::parse(&lines[1][..60], lines[2]), Err(TleError::LineLength { line: 1, .. })));
+        assert!(matches!(Tle::parse(lines[2], lines[1]), Err(TleError::LineNumber { line: 1 })));
+    }
+
+    #[test]
+    fn exponent_fields() {
+        assert_eq!(exponent_field(" 00000-0"), Some(0.0));
+        assert!((exponent_field(" 13844-3").unwrap() - 0.13844e-3).abs() < 1e-15);
+        assert!((exponent_field("-11606-4").unwrap() + 0.11606e-4).abs() < 1e-15);
+        assert!((exponent_field(" 12345+1").unwrap() - 1.2345).abs() < 1e-15);
+    }
+}
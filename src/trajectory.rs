@@ -0,0 +1,552 @@
+use std::{error::Error, fmt::Display, fs::File, io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write}, path::Path};
+
+use crate::{debug::MemUse, epoch::Epoch, math::DVec3};
+
+// Binary trajectories
+//
+// A compact columnar record of tracked body states. All values are little endian
+//
+//   header  "CRTJ", version u32, has epoch u8, start epoch f64 (TT seconds past J2000)
+//           body count u32, then per body: id u64, name length u32, name utf-8, field count u32, field kinds u8...
+//           frames per chunk u32
+//   chunk   "CHNK", frame count n u32, then columns of n values each: frame numbers u64, sim times f64, and an f64
+//           column per component (x, y, z) of every field of every body, in header order
+//
+// Chunks are written as they fill, so a run that is cut short still leaves a readable file. Every column has a known
+// length, so the reader can index the chunks without reading their data and then read single values directly
+
+const MAGIC: &[u8; 4] = b"CRTJ";
+const CHUNK_MAGIC: &[u8; 4] = b"CHNK";
+pub const TRAJECTORY_VERSION: u32 = 1;
+pub const DEFAULT_CHUNK_FRAMES: u32 = 4096;
+
+#[derive(Debug)]
+pub enum TrajectoryError {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    Corrupt(String),
+}
+
+impl Display for TrajectoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(inner) => write!(f, "failed to access trajectory: {}", inner),
+            Self::BadMagic => write!(f, "not a trajectory file"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported trajectory version {}", version),
+            Self::Corrupt(message) => write!(f, "corrupt trajectory: {}", message),
+        }
+    }
+}
+
+impl Error for TrajectoryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TrajectoryError {
+    fn from(error: std::io::Error) -> Self {
+        TrajectoryError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrajectoryField {
+    Position,
+    Velocity,
+    Acceleration,
+}
+
+impl TrajectoryField {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Position => 0,
+            Self::Velocity => 1,
+            Self::Acceleration => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Position),
+            1 => Some(Self::Velocity),
+            2 => Some(Self::Acceleration),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrajectoryBody {
+    pub id: usize,
+    pub name: String,
+    pub fields: Vec<TrajectoryField>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrajectoryHeader {
+    pub version: u32,
+    pub start_epoch: Option<Epoch>,
+    pub bodies: Vec<TrajectoryBody>,
+    pub chunk_frames: u32,
+}
+
+impl TrajectoryHeader {
+    pub fn new(start_epoch: Option<Epoch>, bodies: Vec<TrajectoryBody>) -> Self {
+        TrajectoryHeader { version: TRAJECTORY_VERSION, start_epoch, bodies, chunk_frames: DEFAULT_CHUNK_FRAMES }
+    }
+
+    /// Number of f64 vector columns (each of three components) per frame
+    fn vector_count(&self) -> usize {
+        self.bodies.iter().map(|body| body.fields.len()).sum()
+    }
+
+    /// Index of the first vector column of a body field
+    fn vector_index(&self, body: usize, field: TrajectoryField) -> Option<usize> {
+        let offset: usize = self.bodies.iter().take(body).map(|body| body.fields.len()).sum();
+        self.bodies.get(body)?.fields.iter().position(|f| *f == field).map(|index| offset + index)
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&self.version.to_le_bytes())?;
+        out.write_all(&[self.start_epoch.is_some() as u8])?;
+        out.write_all(&self.start_epoch.map(|epoch| epoch.seconds_past_j2000(crate::epoch::TimeScale::Tt)).unwrap_or(0.0).to_le_bytes())?;
+        out.write_all(&(self.bodies.len() as u32).to_le_bytes())?;
+        for body in self.bodies.iter() {
+            out.write_all(&(body.id as u64).to_le_bytes())?;
+            out.write_all(&(body.name.len() as u32).to_le_bytes())?;
+            out.write_all(body.name.as_bytes())?;
+            out.write_all(&(body.fields.len() as u32).to_le_bytes())?;
+            for field in body.fields.iter() {
+                out.write_all(&[field.to_byte()])?;
+            }
+        }
+        out.write_all(&self.chunk_frames.to_le_bytes())
+    }
+
+    fn read<R: Read>(input: &mut R) -> Result<Self, TrajectoryError> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic).map_err(|_| TrajectoryError::BadMagic)?;
+        if &magic != MAGIC {
+            return Err(TrajectoryError::BadMagic)
+        }
+
+        let version = read_u32(input)?;
+        if version != TRAJECTORY_VERSION {
+            return Err(TrajectoryError::UnsupportedVersion(version))
+        }
+
+        let has_epoch = read_u8(input)? != 0;
+        let epoch_seconds = read_f64(input)?;
+        let body_count = read_u32(input)?;
+        let mut bodies = Vec::new();
+        for _ in 0..body_count {
+            let id = read_u64(input)? as usize;
+            let mut name = vec![0u8; read_u32(input)? as usize];
+            input.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| TrajectoryError::Corrupt(String::from("body name is not utf-8")))?;
+            let mut fields = Vec::new();
+            for _ in 0..read_u32(input)? {
+                let byte = read_u8(input)?;
+                fields.push(TrajectoryField::from_byte(byte).ok_or_else(|| TrajectoryError::Corrupt(format!("unknown field kind {}", byte)))?);
+            }
+            bodies.push(TrajectoryBody { id, name, fields });
+        }
+        let chunk_frames = read_u32(input)?;
+
+        Ok(TrajectoryHeader {
+            version,
+            start_epoch: if has_epoch { Some(Epoch::from_seconds_past_j2000(epoch_seconds, crate::epoch::TimeScale::Tt)) } else { None },
+            bodies,
+            chunk_frames,
+        })
+    }
+}
+
+/// Streams frames into chunks, a chunk is written each time it fills
+#[derive(Debug)]
+pub struct TrajectoryWriter<W: Write> {
+    out: W,
+    header: TrajectoryHeader,
+    frame_numbers: Vec<u64>,
+    sim_times: Vec<f64>,
+    columns: Vec<Vec<f64>>, // three per vector, x y z
+}
+
+impl TrajectoryWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, header: TrajectoryHeader) -> std::io::Result<Self> {
+        TrajectoryWriter::new(BufWriter::new(File::create(path)?), header)
+    }
+}
+
+impl<W: Write> TrajectoryWriter<W> {
+    pub fn new(mut out: W, header: TrajectoryHeader) -> std::io::Result<Self> {
+        header.write(&mut out)?;
+        let columns = vec![Vec::new(); header.vector_count() * 3];
+        Ok(TrajectoryWriter { out, header, frame_numbers: Vec::new(), sim_times: Vec::new(), columns })
+    }
+
+    pub fn header(&self) -> &TrajectoryHeader {
+        &self.header
+    }
+
+    /// Appends a frame, `vectors` holds a value for every field of every body in header order
+    pub fn write_frame(&mut self, frame_number: usize, sim_time: f64, vectors: &[DVec3]) -> std::io::Result<()> {
+        if vectors.len() * 3 != self.columns.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "frame does not match the trajectory header"))
+        }
+
+        self.frame_numbers.push(frame_number as u64);
+        self.sim_times.push(sim_time);
+        for (i, vector) in vectors.iter().enumerate() {
+            self.columns[i * 3].push(vector.x);
+            self.columns[i * 3 + 1].push(vector.y);
+            self.columns[i * 3 + 2].push(vector.z);
+        }
+
+        if self.frame_numbers.len() >= self.header.chunk_frames as usize {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> std::io::Result<()> {
+        if self.frame_numbers.is_empty() {
+            return Ok(())
+        }
+
+        self.out.write_all(CHUNK_MAGIC)?;
+        self.out.write_all(&(self.frame_numbers.len() as u32).to_le_bytes())?;
+        for n in self.frame_numbers.drain(..) {
+            self.out.write_all(&n.to_le_bytes())?;
+        }
+        for t in self.sim_times.drain(..) {
+            self.out.write_all(&t.to_le_bytes())?;
+        }
+        for column in self.columns.iter_mut() {
+            for value in column.drain(..) {
+                self.out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the partially filled chunk and flushes
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.write_chunk()?;
+        self.out.flush()
+    }
+}
+
+/// A single frame read back from a trajectory
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrajectoryFrame {
+    pub frame_number: usize,
+    pub sim_time: f64,
+    pub vectors: Vec<DVec3>, // every field of every body, in header order
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChunkIndex {
+    offset: u64, // of the first frame number
+    frames: usize,
+    first_frame: u64,
+    last_frame: u64,
+    first_time: f64,
+    last_time: f64,
+}
+
+/// Random access to a trajectory by record index, frame number and time
+#[derive(Debug)]
+pub struct TrajectoryReader<R: Read + Seek> {
+    input: R,
+    header: TrajectoryHeader,
+    chunks: Vec<ChunkIndex>,
+}
+
+impl TrajectoryReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TrajectoryError> {
+        TrajectoryReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> TrajectoryReader<R> {
+    pub fn new(mut input: R) -> Result<Self, TrajectoryError> {
+        let header = TrajectoryHeader::read(&mut input)?;
+        let columns = (header.vector_count() * 3) as u64;
+        let length = input.seek(SeekFrom::End(0))?;
+        let mut position = input.seek(SeekFrom::Start(0)).and_then(|_| skip_header(&mut input, &header))?;
+
+        // index the chunks by walking their headers, a truncated final chunk is ignored
+        let mut chunks = Vec::new();
+        while position + 8 <= length {
+            input.seek(SeekFrom::Start(position))?;
+            let mut magic = [0u8; 4];
+            input.read_exact(&mut magic)?;
+            if &magic != CHUNK_MAGIC {
+                return Err(TrajectoryError::Corrupt(format!("expected a chunk at byte {}", position)))
+            }
+            let frames = read_u32(&mut input)? as u64;
+            let offset = position + 8;
+            let end = offset + frames * 8 * (2 + columns);
+            if frames == 0 || end > length {
+                break;
+            }
+
+            let read_at = |input: &mut R, at: u64| -> Result<[u8; 8], TrajectoryError> {
+                input.seek(SeekFrom::Start(at))?;
+                let mut bytes = [0u8; 8];
+                input.read_exact(&mut bytes)?;
+                Ok(bytes)
+            };
+            chunks.push(ChunkIndex {
+                offset,
+                frames: frames as usize,
+                first_frame: u64::from_le_bytes(read_at(&mut input, offset)?),
+                last_frame: u64::from_le_bytes(read_at(&mut input, offset + (frames - 1) * 8)?),
+                first_time: f64::from_le_bytes(read_at(&mut input, offset + frames * 8)?),
+                last_time: f64::from_le_bytes(read_at(&mut input, offset + (2 * frames - 1) * 8)?),
+            });
+            position = end;
+        }
+
+        Ok(TrajectoryReader { input, header, chunks })
+    }
+
+    pub fn header(&self) -> &TrajectoryHeader {
+        &self.header
+    }
+
+    /// Number of frames recorded
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.frames).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// The `index`th recorded frame
+    pub fn record(&mut self, index: usize) -> Result<Option<TrajectoryFrame>, TrajectoryError> {
+        let mut remaining = index;
+        for i in 0..self.chunks.len() {
+            if remaining < self.chunks[i].frames {
+                return self.read_frame(i, remaining).map(Some)
+            }
+            remaining -= self.chunks[i].frames;
+        }
+        Ok(None)
+    }
+
+    /// The frame with simulation frame number `frame_number`, if it was recorded
+    pub fn frame(&mut self, frame_number: usize) -> Result<Option<TrajectoryFrame>, TrajectoryError> {
+        let target = frame_number as u64;
+        let chunk = match self.chunks.iter().position(|chunk| chunk.first_frame <= target && target <= chunk.last_frame) {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+
+        let numbers = self.read_column::<8>(self.chunks[chunk].offset, self.chunks[chunk].frames)?;
+        match numbers.binary_search_by_key(&target, |bytes| u64::from_le_bytes(*bytes)) {
+            Ok(index) => self.read_frame(chunk, index).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Every frame with a simulation time in [start, end]
+    pub fn frames_in_time_range(&mut self, start: f64, end: f64) -> Result<Vec<TrajectoryFrame>, TrajectoryError> {
+        let mut frames = Vec::new();
+        for chunk in 0..self.chunks.len() {
+            let index = self.chunks[chunk];
+            if index.last_time < start || index.first_time > end {
+                continue;
+            }
+
+            let times = self.read_column::<8>(index.offset + index.frames as u64 * 8, index.frames)?;
+            for (i, bytes) in times.iter().enumerate() {
+                let time = f64::from_le_bytes(*bytes);
+                if start <= time && time <= end {
+                    frames.push(self.read_frame(chunk, i)?);
+                }
+            }
+        }
+        Ok(frames)
+    }
+
+    /// The recorded values of one field of one body across every frame, with the sim time of each
+    pub fn series(&mut self, body: usize, field: TrajectoryField) -> Result<Vec<(f64, DVec3)>, TrajectoryError> {
+        let vector = self.header.vector_index(body, field).ok_or_else(|| TrajectoryError::Corrupt(String::from("no such body field")))?;
+        let mut series = Vec::with_capacity(self.len());
+        for chunk in self.chunks.clone().iter() {
+            let n = chunk.frames as u64;
+            let times = self.read_column::<8>(chunk.offset + n * 8, chunk.frames)?;
+            let column = |c: u64| chunk.offset + n * 8 * (2 + vector as u64 * 3 + c);
+            let (x, y, z) = (self.read_column::<8>(column(0), chunk.frames)?, self.read_column::<8>(column(1), chunk.frames)?, self.read_column::<8>(column(2), chunk.frames)?);
+            for i in 0..chunk.frames {
+                let value = DVec3::new(f64::from_le_bytes(x[i]), f64::from_le_bytes(y[i]), f64::from_le_bytes(z[i]));
+                series.push((f64::from_le_bytes(times[i]), value));
+            }
+        }
+        Ok(series)
+    }
+
+    fn read_column<const N: usize>(&mut self, offset: u64, count: usize) -> Result<Vec<[u8; N]>, TrajectoryError> {
+        self.input.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![0u8; N * count];
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes.chunks_exact(N).map(|chunk| {
+            let mut value = [0u8; N];
+            value.copy_from_slice(chunk);
+            value
+        }).collect())
+    }
+
+    fn read_frame(&mut self, chunk: usize, index: usize) -> Result<TrajectoryFrame, TrajectoryError> {
+        let ChunkIndex { offset, frames, .. } = self.chunks[chunk];
+        let (n, i) = (frames as u64, index as u64);
+        let vector_count = self.header.vector_count() as u64;
+        let input = &mut self.input;
+        let mut value_at = |column: u64| -> Result<[u8; 8], TrajectoryError> {
+            input.seek(SeekFrom::Start(offset + column * n * 8 + i * 8))?;
+            let mut bytes = [0u8; 8];
+            input.read_exact(&mut bytes)?;
+            Ok(bytes)
+        };
+
+        let frame_number = u64::from_le_bytes(value_at(0)?) as usize;
+        let sim_time = f64::from_le_bytes(value_at(1)?);
+        let mut vectors = Vec::new();
+        for v in 0..vector_count {
+            let column = 2 + v * 3;
+            vectors.push(DVec3::new(f64::from_le_bytes(value_at(column)?), f64::from_le_bytes(value_at(column + 1)?), f64::from_le_bytes(value_at(column + 2)?)));
+        }
+        Ok(TrajectoryFrame { frame_number, sim_time, vectors })
+    }
+}
+
+impl TrajectoryFrame {
+    /// The value of a body field in this frame
+    pub fn get(&self, header: &TrajectoryHeader, body: usize, field: TrajectoryField) -> Option<DVec3> {
+        header.vector_index(body, field).and_then(|index| self.vectors.get(index).copied())
+    }
+}
+
+/// Seeks past the header, returning the offset of the first chunk
+fn skip_header<R: Read + Seek>(input: &mut R, header: &TrajectoryHeader) -> std::io::Result<u64> {
+    let mut length = 4 + 4 + 1 + 8 + 4 + 4;
+    for body in header.bodies.iter() {
+        length += 8 + 4 + body.name.len() as u64 + 4 + body.fields.len() as u64;
+    }
+    input.seek(SeekFrom::Start(length))
+}
+
+fn read_u8<R: Read>(input: &mut R) -> std::io::Result<u8> {
+    let mut bytes = [0u8; 1];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32<R: Read>(input: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(input: &mut R) -> std::io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+impl MemUse for TrajectoryField {}
+
+impl MemUse for TrajectoryBody {
+    fn heap_use(&self) -> usize {
+        self.name.heap_use() + self.fields.heap_use()
+    }
+}
+
+impl MemUse for TrajectoryWriter<BufWriter<File>> {
+    fn heap_use(&self) -> usize {
+        let mut total = 0;
+        total += self.out.capacity();
+        total += self.header.bodies.heap_use();
+        total += self.frame_numbers.heap_use();
+        total += self.sim_times.heap_use();
+        total += self.columns.heap_use();
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample(frames: usize, chunk_frames: u32) -> Vec<u8> {
+        let bodies = vec![
+            TrajectoryBody { id: 0, name: String::from("SOL"), fields: vec![TrajectoryField::Position] },
+            TrajectoryBody { id: 1, name: String::from("EARTH"), fields: vec![TrajectoryField::Position, TrajectoryField::Velocity] },
+        ];
+        let mut header = TrajectoryHeader::new(Some(Epoch::J2000), bodies);
+        header.chunk_frames = chunk_frames;
+
+        let mut writer = TrajectoryWriter::new(Vec::new(), header).unwrap();
+        for frame in 0..frames {
+            let t = frame as f64 * 10.0;
+            writer.write_frame(frame * 2, t, &[DVec3::zero(), DVec3::new(t, 1.0, 2.0), DVec3::new(0.0, t * 0.5, 0.0)]).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.out
+    }
+
+    #[test]
+    fn random_access() {
+        let mut reader = TrajectoryReader::new(Cursor::new(sample(100, 16))).unwrap();
+        assert_eq!(reader.header().bodies[1].name, "EARTH");
+        assert_eq!(reader.header().start_epoch, Some(Epoch::J2000));
+        assert_eq!(reader.len(), 100);
+
+        let frame = reader.record(37).unwrap().unwrap();
+        assert_eq!(frame.frame_number, 74);
+        assert_eq!(frame.sim_time, 370.0);
+        assert_eq!(frame.get(reader.header(), 1, TrajectoryField::Velocity), Some(DVec3::new(0.0, 185.0, 0.0)));
+        assert_eq!(frame.get(reader.header(), 0, TrajectoryField::Velocity), None);
+
+        assert_eq!(reader.frame(74).unwrap(), Some(frame));
+        assert_eq!(reader.frame(75).unwrap(), None);
+        assert_eq!(reader.record(100).unwrap(), None);
+
+        let range = reader.frames_in_time_range(155.0, 405.0).unwrap();
+        assert_eq!(range.len(), 25);
+        assert_eq!(range[0].sim_time, 160.0);
+
+        let series = reader.series(1, TrajectoryField::Position).unwrap();
+        assert_eq!(series.len(), 100);
+        assert_eq!(series[99], (990.0, DVec3::new(990.0, 1.0, 2.0)));
+    }
+
+    #[test]
+    fn truncated_and_invalid() {
+        // a run cut short mid chunk keeps every complete chunk
+        let mut bytes = sample(40, 16);
+        bytes.truncate(bytes.len() - 10);
+        assert_eq!(TrajectoryReader::new(Cursor::new(bytes)).unwrap().len(), 32);
+
+        assert!(matches!(TrajectoryReader::new(Cursor::new(b"time,position\n".to_vec())), Err(TrajectoryError::BadMagic)));
+        let mut bytes = sample(1, 16);
+        bytes[4] = 9;
+        assert!(matches!(TrajectoryReader::new(Cursor::new(bytes)), Err(TrajectoryError::UnsupportedVersion(9))));
+    }
+}
@@ -0,0 +1,420 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+// Typed physical quantities
+//
+// Every quantity stores its value in SI base units, conversions to other units are explicit through the named
+// constructors and accessors. Quantities parse from strings such as "1 AU", "29.78 km/s" or "3600s". The unit is
+// required, a bare number is rejected rather than guessed to be SI, except for zero which is the same in every unit
+
+pub const METERS_PER_KILOMETER: f64 = 1000.0;
+pub const METERS_PER_AU: f64 = 149597870700.0; // IAU 2012 exact definition
+pub const SECONDS_PER_MINUTE: f64 = 60.0;
+pub const SECONDS_PER_HOUR: f64 = 3600.0;
+pub const SECONDS_PER_DAY: f64 = 86400.0;
+pub const SECONDS_PER_YEAR: f64 = 365.25 * SECONDS_PER_DAY; // julian year
+pub const KILOGRAMS_PER_SOLAR_MASS: f64 = 1.98847e30;
+pub const KILOGRAMS_PER_EARTH_MASS: f64 = 5.9722e24;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnitParseError {
+    Empty,
+    InvalidNumber(String),
+    UnknownUnit { unit: String, quantity: &'static str },
+    MissingUnit { value: String, quantity: &'static str },
+}
+
+impl Display for UnitParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "expected a value"),
+            Self::InvalidNumber(number) => write!(f, "invalid number: '{}'", number),
+            Self::UnknownUnit { unit, quantity } => write!(f, "unknown {} unit: '{}'", quantity, unit),
+            Self::MissingUnit { value, quantity } => write!(f, "{} '{}' needs a unit", quantity, value),
+        }
+    }
+}
+
+impl Error for UnitParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+/// Splits a string like "29.78 km/s" or "3600s" into its number and unit parts
+fn split_value_and_unit(s: &str) -> Result<(f64, &str), UnitParseError> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(UnitParseError::Empty)
+    }
+
+    // the number ends at the first character that can't be part of a float, taking care with exponents
+    let bytes = s.as_bytes();
+    let mut end = 0;
+    while end < bytes.len() {
+        let c = bytes[end] as char;
+        let is_exponent = (c == 'e' || c == 'E')
+            && end > 0
+            && bytes.get(end + 1).map(|n| (*n as char).is_ascii_digit() || *n == b'-' || *n == b'+').unwrap_or(false);
+        let is_exponent_sign = (c == '-' || c == '+') && end > 0 && (bytes[end - 1] == b'e' || bytes[end - 1] == b'E');
+        if c.is_ascii_digit() || c == '.' || ((c == '-' || c == '+') && end == 0) || is_exponent || is_exponent_sign {
+            end += 1;
+        } else {
+            break;
+        }
+    }
+
+    let (number, unit) = s.split_at(end);
+    let value = number.parse::<f64>().map_err(|_| UnitParseError::InvalidNumber(String::from(number)))?;
+    Ok((value, unit.trim()))
+}
+
+macro_rules! quantity {
+    ($(#[$meta:meta])* $name:ident, $description:expr, $si:ident, $from_si:ident, { $($unit:expr => $factor:expr),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+        pub struct $name(f64);
+
+        impl $name {
+            pub const fn $from_si(value: f64) -> Self {
+                $name(value)
+            }
+
+            pub const fn $si(&self) -> f64 {
+                self.0
+            }
+
+            pub fn abs(&self) -> Self {
+                $name(self.0.abs())
+            }
+
+            /// Looks up the SI conversion factor of a unit symbol for this quantity
+            pub fn unit_factor(unit: &str) -> Option<f64> {
+                match unit {
+                    $($unit => Some($factor),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = UnitParseError;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let (value, unit) = split_value_and_unit(s)?;
+                if unit.is_empty() {
+                    return match value == 0.0 {
+                        true => Ok($name(0.0)),
+                        false => Err(UnitParseError::MissingUnit { value: String::from(s.trim()), quantity: $description }),
+                    }
+                }
+                match Self::unit_factor(unit) {
+                    Some(factor) => Ok($name(value * factor)),
+                    None => Err(UnitParseError::UnknownUnit { unit: String::from(unit), quantity: $description }),
+                }
+            }
+        }
+
+        impl std::ops::Add for $name {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self::Output {
+                $name(self.0 + rhs.0)
+            }
+        }
+
+        impl std::ops::AddAssign for $name {
+            fn add_assign(&mut self, rhs: Self) {
+                self.0 += rhs.0
+            }
+        }
+
+        impl std::ops::Sub for $name {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self::Output {
+                $name(self.0 - rhs.0)
+            }
+        }
+
+        impl std::ops::SubAssign for $name {
+            fn sub_assign(&mut self, rhs: Self) {
+                self.0 -= rhs.0
+            }
+        }
+
+        impl std::ops::Neg for $name {
+            type Output = Self;
+            fn neg(self) -> Self::Output {
+                $name(-self.0)
+            }
+        }
+
+        impl std::ops::Mul<f64> for $name {
+            type Output = Self;
+            fn mul(self, rhs: f64) -> Self::Output {
+                $name(self.0 * rhs)
+            }
+        }
+
+        impl std::ops::Mul<$name> for f64 {
+            type Output = $name;
+            fn mul(self, rhs: $name) -> Self::Output {
+                $name(self * rhs.0)
+            }
+        }
+
+        impl std::ops::Div<f64> for $name {
+            type Output = Self;
+            fn div(self, rhs: f64) -> Self::Output {
+                $name(self.0 / rhs)
+            }
+        }
+
+        impl std::ops::Div for $name {
+            type Output = f64;
+            fn div(self, rhs: Self) -> Self::Output {
+                self.0 / rhs.0
+            }
+        }
+    };
+}
+
+quantity!(
+    /// A distance, stored in metres
+    Length, "length", meters, from_meters, {
+        "m" => 1.0,
+        "km" => METERS_PER_KILOMETER,
+        "AU" => METERS_PER_AU,
+        "au" => METERS_PER_AU,
+    }
+);
+
+quantity!(
+    /// A duration, stored in seconds
+    Time, "time", seconds, from_seconds, {
+        "s" => 1.0,
+        "sec" => 1.0,
+        "min" => SECONDS_PER_MINUTE,
+        "h" => SECONDS_PER_HOUR,
+        "hr" => SECONDS_PER_HOUR,
+        "d" => SECONDS_PER_DAY,
+        "day" => SECONDS_PER_DAY,
+        "days" => SECONDS_PER_DAY,
+        "yr" => SECONDS_PER_YEAR,
+    }
+);
+
+quantity!(
+    /// A mass, stored in kilograms
+    Mass, "mass", kilograms, from_kilograms, {
+        "kg" => 1.0,
+        "t" => 1000.0,
+        "Msun" => KILOGRAMS_PER_SOLAR_MASS,
+        "Msol" => KILOGRAMS_PER_SOLAR_MASS,
+        "Mearth" => KILOGRAMS_PER_EARTH_MASS,
+    }
+);
+
+quantity!(
+    /// A speed, stored in metres per second
+    Velocity, "velocity", meters_per_second, from_meters_per_second, {
+        "m/s" => 1.0,
+        "km/s" => METERS_PER_KILOMETER,
+        "km/h" => METERS_PER_KILOMETER / SECONDS_PER_HOUR,
+        "AU/d" => METERS_PER_AU / SECONDS_PER_DAY,
+        "au/d" => METERS_PER_AU / SECONDS_PER_DAY,
+        "AU/day" => METERS_PER_AU / SECONDS_PER_DAY,
+        "au/day" => METERS_PER_AU / SECONDS_PER_DAY,
+    }
+);
+
+quantity!(
+    /// A standard gravitational parameter (GM), stored in cubic metres per second squared
+    GravParam, "gravitational parameter", cubic_meters_per_second_squared, from_cubic_meters_per_second_squared, {
+        "m^3/s^2" => 1.0,
+        "m3/s2" => 1.0,
+        "km^3/s^2" => METERS_PER_KILOMETER * METERS_PER_KILOMETER * METERS_PER_KILOMETER,
+        "km3/s2" => METERS_PER_KILOMETER * METERS_PER_KILOMETER * METERS_PER_KILOMETER,
+    }
+);
+
+impl Length {
+    pub const fn from_kilometers(km: f64) -> Self {
+        Length(km * METERS_PER_KILOMETER)
+    }
+
+    pub const fn from_au(au: f64) -> Self {
+        Length(au * METERS_PER_AU)
+    }
+
+    pub fn kilometers(&self) -> f64 {
+        self.0 / METERS_PER_KILOMETER
+    }
+
+    pub fn au(&self) -> f64 {
+        self.0 / METERS_PER_AU
+    }
+}
+
+impl Time {
+    pub const fn from_minutes(minutes: f64) -> Self {
+        Time(minutes * SECONDS_PER_MINUTE)
+    }
+
+    pub const fn from_hours(hours: f64) -> Self {
+        Time(hours * SECONDS_PER_HOUR)
+    }
+
+    pub const fn from_days(days: f64) -> Self {
+        Time(days * SECONDS_PER_DAY)
+    }
+
+    pub const fn from_years(years: f64) -> Self {
+        Time(years * SECONDS_PER_YEAR)
+    }
+
+    pub fn minutes(&self) -> f64 {
+        self.0 / SECONDS_PER_MINUTE
+    }
+
+    pub fn hours(&self) -> f64 {
+        self.0 / SECONDS_PER_HOUR
+    }
+
+    pub fn days(&self) -> f64 {
+        self.0 / SECONDS_PER_DAY
+    }
+
+    pub fn years(&self) -> f64 {
+        self.0 / SECONDS_PER_YEAR
+    }
+}
+
+impl Mass {
+    pub const fn from_solar_masses(solar_masses: f64) -> Self {
+        Mass(solar_masses * KILOGRAMS_PER_SOLAR_MASS)
+    }
+
+    pub const fn from_earth_masses(earth_masses: f64) -> Self {
+        Mass(earth_masses * KILOGRAMS_PER_EARTH_MASS)
+    }
+
+    pub fn solar_masses(&self) -> f64 {
+        self.0 / KILOGRAMS_PER_SOLAR_MASS
+    }
+
+    pub fn earth_masses(&self) -> f64 {
+        self.0 / KILOGRAMS_PER_EARTH_MASS
+    }
+}
+
+impl Velocity {
+    pub const fn from_kilometers_per_second(km_s: f64) -> Self {
+        Velocity(km_s * METERS_PER_KILOMETER)
+    }
+
+    pub const fn from_au_per_day(au_d: f64) -> Self {
+        Velocity(au_d * METERS_PER_AU / SECONDS_PER_DAY)
+    }
+
+    pub fn kilometers_per_second(&self) -> f64 {
+        self.0 / METERS_PER_KILOMETER
+    }
+
+    pub fn au_per_day(&self) -> f64 {
+        self.0 * SECONDS_PER_DAY / METERS_PER_AU
+    }
+}
+
+impl GravParam {
+    pub const fn from_cubic_kilometers_per_second_squared(km3_s2: f64) -> Self {
+        GravParam(km3_s2 * METERS_PER_KILOMETER * METERS_PER_KILOMETER * METERS_PER_KILOMETER)
+    }
+
+    pub fn cubic_kilometers_per_second_squared(&self) -> f64 {
+        self.0 / (METERS_PER_KILOMETER * METERS_PER_KILOMETER * METERS_PER_KILOMETER)
+    }
+}
+
+impl std::ops::Div<Time> for Length {
+    type Output = Velocity;
+    fn div(self, rhs: Time) -> Self::Output {
+        Velocity(self.0 / rhs.0)
+    }
+}
+
+impl std::ops::Mul<Time> for Velocity {
+    type Output = Length;
+    fn mul(self, rhs: Time) -> Self::Output {
+        Length(self.0 * rhs.0)
+    }
+}
+
+impl Display for Length {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} m", self.0)
+    }
+}
+
+impl Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} s", self.0)
+    }
+}
+
+impl Display for Mass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} kg", self.0)
+    }
+}
+
+impl Display for Velocity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} m/s", self.0)
+    }
+}
+
+impl Display for GravParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} m^3/s^2", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_with_units() {
+        assert_eq!("1 AU".parse::<Length>().unwrap().meters(), METERS_PER_AU);
+        assert_eq!("1.5km".parse::<Length>().unwrap().meters(), 1500.0);
+        assert_eq!("29.78 km/s".parse::<Velocity>().unwrap().meters_per_second(), 29780.0);
+        assert_eq!("2 h".parse::<Time>().unwrap().seconds(), 7200.0);
+        assert_eq!("1 yr".parse::<Time>().unwrap().days(), 365.25);
+        assert_eq!("1 Msun".parse::<Mass>().unwrap().kilograms(), KILOGRAMS_PER_SOLAR_MASS);
+        assert_eq!("398600.4418 km^3/s^2".parse::<GravParam>().unwrap().cubic_meters_per_second_squared(), 398600441800000.0);
+    }
+
+    #[test]
+    fn units_are_required() {
+        assert_eq!("3600".parse::<Time>(), Err(UnitParseError::MissingUnit { value: String::from("3600"), quantity: "time" }));
+        assert!(matches!("-1.5e3".parse::<Length>(), Err(UnitParseError::MissingUnit { .. })));
+        assert!(matches!("1.5".parse::<Mass>(), Err(UnitParseError::MissingUnit { .. })));
+        assert!(matches!("7.5".parse::<Velocity>(), Err(UnitParseError::MissingUnit { .. })));
+        assert_eq!("-1.5e3 m".parse::<Length>().unwrap().meters(), -1500.0);
+        assert_eq!("6.371e6 m".parse::<Length>().unwrap().kilometers(), 6371.0);
+        assert_eq!("0".parse::<Velocity>(), Ok(Velocity::from_meters_per_second(0.0)));
+        assert!(matches!("1 a".parse::<Time>(), Err(UnitParseError::UnknownUnit { .. })));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!("".parse::<Length>(), Err(UnitParseError::Empty));
+        assert!(matches!("AU".parse::<Length>(), Err(UnitParseError::InvalidNumber(_))));
+        assert_eq!("1 km/s".parse::<Length>(), Err(UnitParseError::UnknownUnit { unit: String::from("km/s"), quantity: "length" }));
+    }
+
+    #[test]
+    fn derived_quantities() {
+        let v = Length::from_kilometers(29.78) / Time::from_seconds(1.0);
+        assert_eq!(v, Velocity::from_kilometers_per_second(29.78));
+        assert_eq!((v * Time::from_seconds(2.0)).kilometers(), 59.56);
+    }
+}
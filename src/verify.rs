@@ -0,0 +1,217 @@
+use std::fmt::Display;
+
+use crate::{constants::*, math::{DVec3, propagate_kepler}, sim::*};
+
+// Integrator verification
+//
+// Runs every integration method on pure two-body problems and measures the position error against the analytic
+// solution of `propagate_kepler` after each of several orbits, so a change to an integrator that costs accuracy shows
+// up as a larger error or a steeper growth. A probe starts at periapsis of a point mass earth that it does not pull on:
+//
+//   circular     e = 0
+//   eccentric    e = 0.7, a Molniya like orbit
+//   parabolic    e = 1
+//   hyperbolic   e = 2
+//
+// Open orbits have no period, their time scale is the period of a circular orbit at periapsis
+
+/// Periapsis radius of every case, m
+pub const PERIAPSIS: f64 = 7.0e6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TwoBodyCase {
+    pub name: &'static str,
+    pub eccentricity: f64,
+}
+
+pub const STANDARD_CASES: [TwoBodyCase; 4] = [
+    TwoBodyCase { name: "circular", eccentricity: 0.0 },
+    TwoBodyCase { name: "eccentric", eccentricity: 0.7 },
+    TwoBodyCase { name: "parabolic", eccentricity: 1.0 },
+    TwoBodyCase { name: "hyperbolic", eccentricity: 2.0 },
+];
+
+impl TwoBodyCase {
+    /// Position and velocity at periapsis
+    pub fn initial_state(&self, mu: f64) -> (DVec3, DVec3) {
+        let speed = (mu * (1.0 + self.eccentricity) / PERIAPSIS).sqrt();
+        (DVec3::new(PERIAPSIS, 0.0, 0.0), DVec3::new(0.0, speed, 0.0))
+    }
+
+    /// The orbital period, or for open orbits the period of a circular orbit at periapsis
+    pub fn time_scale(&self, mu: f64) -> f64 {
+        let a = match self.eccentricity < 1.0 {
+            true => PERIAPSIS / (1.0 - self.eccentricity),
+            false => PERIAPSIS,
+        };
+        2.0 * std::f64::consts::PI * (a * a * a / mu).sqrt()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Verification {
+    pub cases: Vec<TwoBodyCase>,
+    pub integrators: Vec<IntegrationMethod>,
+    pub steps_per_orbit: usize,
+    pub orbits: usize,
+}
+
+impl Default for Verification {
+    fn default() -> Self {
+        Verification {
+            cases: STANDARD_CASES.to_vec(),
+            integrators: vec![IntegrationMethod::Euler, IntegrationMethod::SemiImplicitEuler, IntegrationMethod::VelocityVerlet, IntegrationMethod::BlockVelocityVerlet],
+            steps_per_orbit: 1000,
+            orbits: 4,
+        }
+    }
+}
+
+impl Verification {
+    pub fn run(&self) -> VerifyReport {
+        let mut runs = Vec::new();
+        for case in self.cases.iter() {
+            for &integrator in self.integrators.iter() {
+                runs.push(self.run_one(case, integrator));
+            }
+        }
+        VerifyReport { runs }
+    }
+
+    fn run_one(&self, case: &TwoBodyCase, integrator: IntegrationMethod) -> VerifyRun {
+        let mu = EARTH_GRAV_PARAM.cubic_meters_per_second_squared();
+        let (p0, v0) = case.initial_state(mu);
+        let timestep = case.time_scale(mu) / self.steps_per_orbit as f64;
+
+        let mut sim = Simulation::new();
+        let earth = sim.make_physics_body().named("Earth").with_physics_category(PhysicsCategory::Gravitational)
+            .with_mass(EARTH_MASS.kilograms()).with_grav_param(mu).add();
+        let probe = sim.make_physics_body().named("Probe").with_transform(p0, None).with_velocity(v0).add();
+        sim.set_timestep(timestep);
+        sim.set_integration_method(integrator);
+
+        let mut errors = Vec::new();
+        let mut relative_error = 0.0;
+        for _ in 0..self.orbits {
+            sim.step_n(self.steps_per_orbit);
+            let frame = sim.present();
+            let position = frame.get_body_ref(probe).unwrap().position() - frame.get_body_ref(earth).unwrap().position();
+            let (exact, _) = propagate_kepler(p0, v0, mu, frame.sim_time());
+            let error = position.length_to(&exact);
+            relative_error = error / exact.magnitude();
+            errors.push(error);
+        }
+
+        VerifyRun {
+            case: case.name,
+            integrator,
+            timestep,
+            errors,
+            relative_error,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyRun {
+    pub case: &'static str,
+    pub integrator: IntegrationMethod,
+    pub timestep: f64,
+    pub errors: Vec<f64>, // position error after each orbit, m
+    pub relative_error: f64, // final position error over the distance from the earth
+}
+
+impl VerifyRun {
+    /// The power of time the error grows with, fitted between the first and last orbit
+    pub fn growth(&self) -> f64 {
+        match (self.errors.first(), self.errors.last()) {
+            (Some(first), Some(last)) if self.errors.len() > 1 => (last / first).ln() / (self.errors.len() as f64).ln(),
+            _ => f64::NAN,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub runs: Vec<VerifyRun>,
+}
+
+impl VerifyReport {
+    /// Runs whose final relative error exceeds `tolerance`
+    pub fn failures(&self, tolerance: f64) -> Vec<&VerifyRun> {
+        self.runs.iter().filter(|run| run.relative_error.is_nan() || run.relative_error > tolerance).collect()
+    }
+}
+
+impl Display for VerifyReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let orbits = self.runs.first().map_or(0, |run| run.errors.len());
+        write!(f, "{:<11} {:<20} {:>11}", "case", "integrator", "timestep s")?;
+        for orbit in 1..=orbits {
+            write!(f, " {:>11}", format!("error {} m", orbit))?;
+        }
+        writeln!(f, " {:>10} {:>7}", "relative", "growth")?;
+
+        for run in self.runs.iter() {
+            write!(f, "{:<11} {:<20} {:>11.4}", run.case, format!("{:?}", run.integrator), run.timestep)?;
+            for error in run.errors.iter() {
+                write!(f, " {:>11.3e}", error)?;
+            }
+            writeln!(f, " {:>10.3e} {:>7.2}", run.relative_error, run.growth())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MU: f64 = 3.986004418e14;
+
+    #[test]
+    fn kepler_propagation_is_exact() {
+        // a quarter of a circular orbit turns the state through 90 degrees
+        let circular = STANDARD_CASES[0];
+        let (p0, v0) = circular.initial_state(MU);
+        let (p, v) = propagate_kepler(p0, v0, MU, circular.time_scale(MU) / 4.0);
+        assert!(p.length_to(&DVec3::new(0.0, PERIAPSIS, 0.0)) < 1.0e-6 * PERIAPSIS);
+        assert!(v.length_to(&DVec3::new(-v0.y, 0.0, 0.0)) < 1.0e-6 * v0.y);
+
+        for case in STANDARD_CASES.iter() {
+            let (p0, v0) = case.initial_state(MU);
+            let energy = |p: &DVec3, v: &DVec3| 0.5 * v.dot(v) - MU / p.magnitude();
+            let t = 3.7 * case.time_scale(MU);
+            let (p, v) = propagate_kepler(p0, v0, MU, t);
+
+            // energy and angular momentum are conserved, and propagating back returns to the start
+            assert!((energy(&p, &v) - energy(&p0, &v0)).abs() <= 1.0e-9 * (MU / PERIAPSIS), "{}", case.name);
+            assert!(p.cross(&v).length_to(&p0.cross(&v0)) < 1.0e-9 * p0.cross(&v0).magnitude(), "{}", case.name);
+            let (back, _) = propagate_kepler(p, v, MU, -t);
+            assert!(back.length_to(&p0) < 1.0e-6 * PERIAPSIS, "{}: {}", case.name, back.length_to(&p0));
+        }
+
+        // two whole periods of the eccentric orbit return to periapsis
+        let eccentric = STANDARD_CASES[1];
+        let (p0, v0) = eccentric.initial_state(MU);
+        let (p, _) = propagate_kepler(p0, v0, MU, 2.0 * eccentric.time_scale(MU));
+        assert!(p.length_to(&p0) < 1.0e-6 * PERIAPSIS);
+    }
+
+    #[test]
+    fn verlet_is_second_order() {
+        let coarse = Verification { integrators: vec![IntegrationMethod::Euler, IntegrationMethod::VelocityVerlet], orbits: 2, ..Verification::default() };
+        let fine = Verification { steps_per_orbit: 2000, ..coarse.clone() };
+        let (coarse, fine) = (coarse.run(), fine.run());
+
+        // halving the timestep quarters the error of verlet, while euler is far worse than it in every case
+        for (coarse, fine) in coarse.runs.iter().zip(fine.runs.iter()).filter(|(run, _)| run.integrator == IntegrationMethod::VelocityVerlet) {
+            let gain = coarse.errors[1] / fine.errors[1];
+            assert!(gain > 3.5 && gain < 4.5, "{}: {}", coarse.case, gain);
+        }
+        for pair in fine.runs.chunks(2) {
+            assert!(pair[1].relative_error < pair[0].relative_error / 100.0, "{}", fine);
+        }
+        assert_eq!(fine.failures(1.0e-3).len(), 5, "{}", fine); // euler in every case and verlet on the eccentric orbit
+    }
+}